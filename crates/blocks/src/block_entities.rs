@@ -1,4 +1,6 @@
+use crate::blocks::Block;
 use crate::items::Item;
+use crate::BlockFacing;
 use mchprs_utils::{map, nbt_unwrap_val};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -80,6 +82,14 @@ pub enum BlockEntity {
         ty: ContainerType,
     },
     Sign(Box<SignBlockEntity>),
+    /// A block that is being moved by a piston
+    MovingPiston {
+        /// The state id of the block that will be placed once it has stopped moving
+        moved: u32,
+        /// The direction of the piston that moves the block
+        facing: BlockFacing,
+        extending: bool,
+    },
 }
 
 impl BlockEntity {
@@ -93,6 +103,7 @@ impl BlockEntity {
                 ContainerType::Hopper => 17,
             },
             BlockEntity::Sign(_) => 7,
+            BlockEntity::MovingPiston { .. } => 10,
         }
     }
 
@@ -201,6 +212,37 @@ impl BlockEntity {
                 };
                 Some(BlockEntity::Sign(Box::new(sign)))
             }
+            "piston" => {
+                let state = nbt_unwrap_val!(&nbt["blockState"], Value::Compound);
+                let name = nbt_unwrap_val!(&state["Name"], Value::String);
+                let mut block = Block::from_name(name.trim_start_matches("minecraft:"))?;
+                if let Some(Value::Compound(properties)) = state.get("Properties") {
+                    let properties = properties
+                        .iter()
+                        .filter_map(|(name, value)| match value {
+                            Value::String(value) => Some((name.as_str(), value.as_str())),
+                            _ => None,
+                        })
+                        .collect();
+                    block.set_properties(properties);
+                }
+                let facing = *nbt_unwrap_val!(&nbt["facing"], Value::Int);
+                // The facing is stored as a 3D data value
+                let facing = match facing {
+                    0 => BlockFacing::Down,
+                    1 => BlockFacing::Up,
+                    2 => BlockFacing::North,
+                    3 => BlockFacing::South,
+                    4 => BlockFacing::West,
+                    5 => BlockFacing::East,
+                    _ => return None,
+                };
+                Some(BlockEntity::MovingPiston {
+                    moved: block.get_id(),
+                    facing,
+                    extending: *nbt_unwrap_val!(&nbt["extending"], Value::Byte) != 0,
+                })
+            }
             _ => None,
         }
     }
@@ -255,6 +297,72 @@ impl BlockEntity {
                     "Items" => Value::List(items)
                 })
             }),
+            BlockEntity::MovingPiston {
+                moved,
+                facing,
+                extending,
+            } => Some({
+                let block = Block::from_id(*moved);
+                let properties = block
+                    .properties()
+                    .into_iter()
+                    .map(|(name, value)| (name.to_owned(), Value::String(value)))
+                    .collect();
+                let facing = match facing {
+                    BlockFacing::Down => 0,
+                    BlockFacing::Up => 1,
+                    BlockFacing::North => 2,
+                    BlockFacing::South => 3,
+                    BlockFacing::West => 4,
+                    BlockFacing::East => 5,
+                };
+                nbt::Blob::with_content(map! {
+                    "blockState" => Value::Compound(map! {
+                        "Name" => Value::String("minecraft:".to_owned() + block.get_name()),
+                        "Properties" => Value::Compound(properties)
+                    }),
+                    "facing" => Value::Int(facing),
+                    "extending" => Value::Byte(*extending as i8),
+                    "id" => Value::String("minecraft:piston".to_owned())
+                })
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_piston_nbt() {
+        let moved = Block::Piston {
+            sticky: true,
+            extended: false,
+            facing: BlockFacing::South,
+        };
+        let block_entity = BlockEntity::MovingPiston {
+            moved: moved.get_id(),
+            facing: BlockFacing::Up,
+            extending: true,
+        };
+        let nbt = block_entity.to_nbt(false).unwrap();
+        let Some(nbt::Value::String(id)) = nbt.content.get("id") else {
+            panic!("the id is not a string");
+        };
+        let loaded = BlockEntity::from_nbt(id, &nbt.content).unwrap();
+        match loaded {
+            BlockEntity::MovingPiston {
+                moved: loaded_moved,
+                facing,
+                extending,
+            } => {
+                assert_eq!(Block::from_id(loaded_moved), moved);
+                assert_eq!(facing, BlockFacing::Up);
+                assert!(extending);
+            }
+            _ => panic!("loaded {:?}", loaded),
         }
+        assert!(block_entity.to_nbt(true).is_none());
     }
 }
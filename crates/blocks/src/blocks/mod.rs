@@ -99,6 +99,7 @@ impl Block {
                 | Block::Hopper { .. }
                 | Block::Sign { .. }
                 | Block::WallSign { .. }
+                | Block::MovingPiston { .. }
         )
     }
 
@@ -688,21 +689,25 @@ blocks! {
     },
     TripwireHook {
         props: {
-            direction: BlockDirection
-        },
-        get_id: match direction {
-            BlockDirection::North => 7530,
-            BlockDirection::South => 7532,
-            BlockDirection::West => 7534,
-            BlockDirection::East => 7536,
+            attached: bool,
+            direction: BlockDirection,
+            powered: bool
         },
-        from_id_offset: 7530,
-        from_id(id): 7530..=7536 => {
-            direction: BlockDirection::from_id(id / 2)
+        get_id: ((!attached as u32) << 3)
+            + (direction.get_id() << 1)
+            + !powered as u32
+            + 7521,
+        from_id_offset: 7521,
+        from_id(id): 7521..=7536 => {
+            attached: (id >> 3) == 0,
+            direction: BlockDirection::from_id((id >> 1) & 3),
+            powered: (id & 1) == 0
         },
         from_names(_name): {
             "tripwire_hook" => {
-                direction: Default::default()
+                attached: false,
+                direction: Default::default(),
+                powered: false
             }
         },
         get_name: "tripwire_hook",
@@ -745,22 +750,93 @@ blocks! {
     },
     Observer {
         props: {
-            facing: BlockFacing
+            facing: BlockFacing,
+            powered: bool
         },
-        get_id: (facing.get_id() << 1) + 12551,
-        from_id_offset: 12551,
-        from_id(id): 12551..=12561 => {
-            facing: BlockFacing::from_id(id >> 1)
+        get_id: (facing.get_id() << 1) + !powered as u32 + 12550,
+        from_id_offset: 12550,
+        from_id(id): 12550..=12561 => {
+            facing: BlockFacing::from_id(id >> 1),
+            powered: (id & 1) == 0
         },
         from_names(_name): {
             "observer" => {
-                facing: Default::default()
+                facing: Default::default(),
+                powered: false
             }
         },
         get_name: "observer",
-        solid: true,
+        transparent: true,
         cube: true,
     },
+    Piston {
+        props: {
+            sticky: bool,
+            extended: bool,
+            facing: BlockFacing
+        },
+        get_id: if sticky { 1992 } else { 2011 }
+            + (!extended as u32) * 6
+            + facing.get_id(),
+        from_id(id): 1992..=2003 | 2011..=2022 => {
+            sticky: id <= 2003,
+            extended: (id - if id <= 2003 { 1992 } else { 2011 }) < 6,
+            facing: BlockFacing::from_id((id - if id <= 2003 { 1992 } else { 2011 }) % 6)
+        },
+        from_names(name): {
+            "piston" | "sticky_piston" => {
+                sticky: name == "sticky_piston",
+                extended: false,
+                facing: Default::default()
+            }
+        },
+        get_name: if sticky { "sticky_piston" } else { "piston" },
+        transparent: true,
+        cube: true,
+    },
+    PistonHead {
+        props: {
+            facing: BlockFacing,
+            short: bool,
+            sticky: bool
+        },
+        get_id: (facing.get_id() << 2) + ((!short as u32) << 1) + sticky as u32 + 2023,
+        from_id_offset: 2023,
+        from_id(id): 2023..=2046 => {
+            facing: BlockFacing::from_id(id >> 2),
+            short: (id >> 1) & 1 == 0,
+            sticky: id & 1 == 1
+        },
+        from_names(_name): {
+            "piston_head" => {
+                facing: Default::default(),
+                short: false,
+                sticky: false
+            }
+        },
+        get_name: "piston_head",
+        transparent: true,
+    },
+    MovingPiston {
+        props: {
+            facing: BlockFacing,
+            sticky: bool
+        },
+        get_id: (facing.get_id() << 1) + sticky as u32 + 2063,
+        from_id_offset: 2063,
+        from_id(id): 2063..=2074 => {
+            facing: BlockFacing::from_id(id >> 1),
+            sticky: id & 1 == 1
+        },
+        from_names(_name): {
+            "moving_piston" => {
+                facing: Default::default(),
+                sticky: false
+            }
+        },
+        get_name: "moving_piston",
+        transparent: true,
+    },
     SeaPickle {
         props: {
             pickles: u8
@@ -780,7 +856,7 @@ blocks! {
     Target {
         props: {},
         get_id: 19381,
-        from_id(_id): 19381 => {},
+        from_id(_id): 19381..=19396 => {},
         from_names(_name): {
             "target" => {}
         },
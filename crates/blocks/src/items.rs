@@ -329,6 +329,24 @@ items! {
         from_id(_id): 1193 => {},
         block: true,
     },
+    Piston {
+        props: {},
+        get_id: 661,
+        from_id(_id): 661 => {},
+        block: true,
+    },
+    StickyPiston {
+        props: {},
+        get_id: 662,
+        from_id(_id): 662 => {},
+        block: true,
+    },
+    Observer {
+        props: {},
+        get_id: 665,
+        from_id(_id): 665 => {},
+        block: true,
+    },
     Target {
         props: {},
         get_id: 670,
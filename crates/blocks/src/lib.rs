@@ -150,6 +150,18 @@ impl BlockFace {
         matches!(self, North | South | East | West)
     }
 
+    pub fn opposite(self) -> BlockFace {
+        use BlockFace::*;
+        match self {
+            Top => Bottom,
+            Bottom => Top,
+            North => South,
+            South => North,
+            East => West,
+            West => East,
+        }
+    }
+
     pub fn unwrap_direction(self) -> BlockDirection {
         match self {
             BlockFace::North => BlockDirection::North,
@@ -331,7 +343,7 @@ impl ToString for BlockDirection {
     }
 }
 
-#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BlockFacing {
     North,
     East,
@@ -366,6 +378,30 @@ impl BlockFacing {
         }
     }
 
+    pub fn opposite(self) -> BlockFacing {
+        use BlockFacing::*;
+        match self {
+            North => South,
+            South => North,
+            East => West,
+            West => East,
+            Up => Down,
+            Down => Up,
+        }
+    }
+
+    pub fn block_face(self) -> BlockFace {
+        use BlockFacing::*;
+        match self {
+            North => BlockFace::North,
+            South => BlockFace::South,
+            East => BlockFace::East,
+            West => BlockFace::West,
+            Up => BlockFace::Top,
+            Down => BlockFace::Bottom,
+        }
+    }
+
     pub fn offset_pos(self, mut pos: BlockPos, n: i32) -> BlockPos {
         match self {
             BlockFacing::North => pos.z -= n,
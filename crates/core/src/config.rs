@@ -69,12 +69,17 @@ gen_config! {
     chat_format: String = "<{username}> {message}".to_string(),
     max_players: i64 = 99999,
     view_distance: i64 = 8,
+    chunk_send_budget: i64 = 262144,
     whitelist: bool = false,
     schemati: bool = false,
     luckperms: Option<PermissionsConfig> = None,
     block_in_hitbox: bool = true,
     auto_redpiler: bool = true,
-    velocity: Option<VelocityConfig> = None
+    redpiler_cache: bool = true,
+    velocity: Option<VelocityConfig> = None,
+    bungeecord: Option<BungeeCordConfig> = None,
+    online_mode: bool = false,
+    protocol_reports: String = "./reports".to_string()
 }
 
 #[derive(Serialize, Deserialize)]
@@ -82,3 +87,11 @@ pub struct VelocityConfig {
     pub enabled: bool,
     pub secret: String,
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct BungeeCordConfig {
+    pub enabled: bool,
+    /// If not empty, players also need to be forwarded with one of these BungeeGuard tokens
+    #[serde(default)]
+    pub bungeeguard_tokens: Vec<String>,
+}
@@ -87,7 +87,9 @@ pub fn get_state_for_placement(
         Item::TripwireHook {} => match context.block_face {
             BlockFace::Bottom | BlockFace::Top => Block::Air {},
             direction => Block::TripwireHook {
+                attached: false,
                 direction: direction.unwrap_direction(),
+                powered: false,
             },
         },
         Item::StoneButton {} => {
@@ -143,6 +145,20 @@ pub fn get_state_for_placement(
         },
         Item::Barrel {} => Block::Barrel {},
         Item::Target {} => Block::Target {},
+        Item::Observer {} => Block::Observer {
+            facing: context.player.get_facing(),
+            powered: false,
+        },
+        Item::Piston {} => Block::Piston {
+            sticky: false,
+            extended: false,
+            facing: context.player.get_facing().opposite(),
+        },
+        Item::StickyPiston {} => Block::Piston {
+            sticky: true,
+            extended: false,
+            facing: context.player.get_facing().opposite(),
+        },
         Item::StainedGlass { color } => Block::StainedGlass { color },
         Item::SmoothStoneSlab {} => Block::SmoothStoneSlab {},
         Item::QuartzSlab {} => Block::QuartzSlab {},
@@ -217,13 +233,17 @@ pub fn place_in_world(
             }
         };
     }
-    world.set_block(pos, block);
+    redstone::set_block(world, pos, block);
     change_surrounding_blocks(world, pos);
     if let Block::RedstoneWire { .. } = block {
         redstone::update_wire_neighbors(world, pos);
     } else {
         redstone::update_surrounding_blocks(world, pos);
     }
+    if let Block::Piston { .. } = block {
+        // The piston might already be powered when it gets placed
+        redstone::update(block, world, pos);
+    }
 }
 
 pub fn destroy(block: Block, world: &mut impl World, pos: BlockPos) {
@@ -233,12 +253,12 @@ pub fn destroy(block: Block, world: &mut impl World, pos: BlockPos) {
 
     match block {
         Block::RedstoneWire { .. } => {
-            world.set_block(pos, Block::Air {});
+            redstone::set_block(world, pos, Block::Air {});
             change_surrounding_blocks(world, pos);
             redstone::update_wire_neighbors(world, pos);
         }
         Block::Lever { lever } => {
-            world.set_block(pos, Block::Air {});
+            redstone::set_block(world, pos, Block::Air {});
             // This is a horrible idea, don't do this.
             // One day this will be fixed, but for now... too bad!
             match lever.face {
@@ -262,8 +282,34 @@ pub fn destroy(block: Block, world: &mut impl World, pos: BlockPos) {
                 }
             }
         }
+        Block::Piston {
+            extended: true,
+            facing,
+            ..
+        } => {
+            redstone::set_block(world, pos, Block::Air {});
+            change_surrounding_blocks(world, pos);
+            redstone::update_surrounding_blocks(world, pos);
+            let head_pos = pos.offset(facing.block_face());
+            let head = world.get_block(head_pos);
+            if matches!(head, Block::PistonHead { facing: head_facing, .. } if head_facing == facing)
+            {
+                destroy(head, world, head_pos);
+            }
+        }
+        Block::PistonHead { facing, .. } => {
+            redstone::set_block(world, pos, Block::Air {});
+            change_surrounding_blocks(world, pos);
+            redstone::update_surrounding_blocks(world, pos);
+            let base_pos = pos.offset(facing.opposite().block_face());
+            let base = world.get_block(base_pos);
+            if matches!(base, Block::Piston { extended: true, facing: base_facing, .. } if base_facing == facing)
+            {
+                destroy(base, world, base_pos);
+            }
+        }
         _ => {
-            world.set_block(pos, Block::Air {});
+            redstone::set_block(world, pos, Block::Air {});
             change_surrounding_blocks(world, pos);
             redstone::update_surrounding_blocks(world, pos);
         }
@@ -333,7 +379,7 @@ pub fn change(block: Block, world: &mut impl World, pos: BlockPos, direction: Bl
     }
     if let Block::RedstoneWire { wire } = block {
         let new_state = redstone::wire::on_neighbor_changed(wire, world, pos, direction);
-        if world.set_block(pos, Block::RedstoneWire { wire: new_state }) {
+        if redstone::set_block(world, pos, Block::RedstoneWire { wire: new_state }) {
             redstone::update_wire_neighbors(world, pos);
         }
     }
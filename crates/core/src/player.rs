@@ -1,5 +1,6 @@
 use crate::config::CONFIG;
 use crate::permissions::{self, PlayerPermissionsCache};
+use crate::plot::chunk_queue::ChunkQueue;
 use crate::plot::worldedit::{WorldEditClipboard, WorldEditUndo};
 use crate::plot::PLOT_SCALE;
 use crate::utils::{self, HyphenatedUUID};
@@ -138,6 +139,8 @@ pub struct Player {
     pub last_chunk_x: i32,
     /// The last Z chunk the player was in. This is used for updated view position.
     pub last_chunk_z: i32,
+    /// The chunks in view that still have to be sent to the player.
+    pub chunk_queue: ChunkQueue,
     /// The player's head yaw rotation.
     pub yaw: f32,
     /// The player's head pitch rotation.
@@ -228,6 +231,7 @@ impl Player {
             yaw: player_data.rotation[1],
             last_chunk_x: 0,
             last_chunk_z: 0,
+            chunk_queue: Default::default(),
             entity_id: ENTITY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
             client,
             flying: player_data.flying,
@@ -0,0 +1,207 @@
+//! Sends the chunks around a player bit by bit, the ones closest to what they are looking at
+//! first, so entering a plot doesn't flood the connection.
+
+use super::{Plot, PlotWorld, PLOT_SECTIONS};
+use crate::player::PlayerPos;
+use mchprs_network::packets::PacketEncoder;
+use mchprs_network::PlayerConn;
+use mchprs_world::storage::Chunk;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tracing::error;
+
+/// How many chunks of a player can be encoded at once.
+const MAX_ENCODING: usize = 16;
+const TICK: Duration = Duration::from_millis(50);
+/// Half the horizontal angle the player can see with the default field of view, in radians.
+const HALF_FOV: f64 = PI / 3.0;
+
+struct EncodingChunk {
+    x: i32,
+    z: i32,
+    /// The version of the chunk when it was copied
+    version: u32,
+    packet: Receiver<PacketEncoder>,
+}
+
+impl EncodingChunk {
+    fn start(world: &PlotWorld, rt: &Runtime, x: i32, z: i32) -> EncodingChunk {
+        let (sender, packet) = mpsc::sync_channel(1);
+        if Plot::chunk_in_plot_bounds(world.x, world.z, x, z) {
+            let chunk = world.chunks[world.get_chunk_index_for_chunk(x, z)].clone();
+            rt.spawn_blocking(move || {
+                // The player might have left already
+                let _ = sender.send(chunk.encode_packet());
+            });
+        } else {
+            let _ = sender.send(Chunk::encode_empty_packet(x, z, PLOT_SECTIONS));
+        }
+        EncodingChunk {
+            x,
+            z,
+            version: world.chunk_version(x, z),
+            packet,
+        }
+    }
+}
+
+/// The chunks in view of a player that have not been sent to them yet.
+pub struct ChunkQueue {
+    pending: Vec<(i32, i32)>,
+    /// Chunks being encoded, in the order they are sent in
+    encoding: VecDeque<EncodingChunk>,
+    /// How many bytes can be sent right now. This can go below zero, as chunks are sent whole.
+    budget: f64,
+    last_refill: Instant,
+}
+
+impl Default for ChunkQueue {
+    fn default() -> ChunkQueue {
+        ChunkQueue {
+            pending: Vec::new(),
+            encoding: VecDeque::new(),
+            budget: 0.0,
+            last_refill: Instant::now(),
+        }
+    }
+}
+
+impl ChunkQueue {
+    pub fn push(&mut self, chunk_x: i32, chunk_z: i32) {
+        let queued = self.pending.contains(&(chunk_x, chunk_z))
+            || self
+                .encoding
+                .iter()
+                .any(|chunk| chunk.x == chunk_x && chunk.z == chunk_z);
+        if !queued {
+            self.pending.push((chunk_x, chunk_z));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.encoding.clear();
+    }
+
+    /// Forgets the chunks the player moved too far away from before they were sent.
+    pub fn retain_in_view(&mut self, chunk_x: i32, chunk_z: i32, view_distance: i32) {
+        self.pending.retain(|&(x, z)| {
+            (x - chunk_x).abs() <= view_distance && (z - chunk_z).abs() <= view_distance
+        });
+    }
+
+    /// Sends the chunks that are done encoding as far as the budget of `budget_per_tick` bytes
+    /// allows, and starts encoding the ones that should be sent next.
+    pub fn update(
+        &mut self,
+        world: &PlotWorld,
+        rt: &Runtime,
+        client: &PlayerConn,
+        (pos, yaw, pitch): (PlayerPos, f32, f32),
+        budget_per_tick: u64,
+    ) {
+        if self.pending.is_empty() && self.encoding.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let refill =
+            budget_per_tick as f64 * (now - self.last_refill).as_secs_f64() / TICK.as_secs_f64();
+        self.budget = (self.budget + refill).min(budget_per_tick as f64);
+        self.last_refill = now;
+
+        while self.budget > 0.0 {
+            let Some(chunk) = self.encoding.front() else {
+                break;
+            };
+            let packet = match chunk.packet.try_recv() {
+                Ok(packet) => packet,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    error!("Failed to encode chunk ({}, {})", chunk.x, chunk.z);
+                    self.encoding.pop_front();
+                    continue;
+                }
+            };
+            let chunk = self.encoding.pop_front().unwrap();
+            if world.chunk_version(chunk.x, chunk.z) != chunk.version {
+                // The changes made while it was encoded were sent before the player had the
+                // chunk, so they never saw them
+                self.pending.push((chunk.x, chunk.z));
+                continue;
+            }
+            self.budget -= packet.data_len() as f64;
+            client.send_packet(&packet);
+        }
+
+        if self.encoding.len() < MAX_ENCODING && !self.pending.is_empty() {
+            // The last chunk is the one sent first
+            self.pending.sort_unstable_by(|&(x1, z1), &(x2, z2)| {
+                let priority = |x, z| priority(pos, yaw, pitch, x, z);
+                priority(x2, z2).total_cmp(&priority(x1, z1))
+            });
+            while self.encoding.len() < MAX_ENCODING {
+                let Some((x, z)) = self.pending.pop() else {
+                    break;
+                };
+                self.encoding
+                    .push_back(EncodingChunk::start(world, rt, x, z));
+            }
+        }
+    }
+}
+
+/// Returns how long a chunk can wait before it's sent to the player, the lower the sooner. This
+/// is the distance to the chunk, which counts up to twice as much for chunks behind the player.
+fn priority(pos: PlayerPos, yaw: f32, pitch: f32, chunk_x: i32, chunk_z: i32) -> f64 {
+    let dx = (chunk_x * 16 + 8) as f64 - pos.x;
+    let dz = (chunk_z * 16 + 8) as f64 - pos.z;
+    let distance = dx.hypot(dz) / 16.0;
+    // The player can see the chunks right around them no matter where they look
+    if distance < 2.0 {
+        return distance;
+    }
+
+    let yaw = (yaw as f64).to_radians();
+    let (look_x, look_z) = (-yaw.sin(), yaw.cos());
+    let angle = ((dx * look_x + dz * look_z) / (distance * 16.0))
+        .clamp(-1.0, 1.0)
+        .acos();
+    let outside_view = ((angle - HALF_FOV) / (PI - HALF_FOV)).max(0.0);
+    // Looking straight up or down, every direction is about as visible
+    let facing = (pitch as f64).to_radians().cos();
+    distance * (1.0 + outside_view * facing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(yaw: f32, pitch: f32, chunks: &[(i32, i32)]) -> Vec<(i32, i32)> {
+        let pos = PlayerPos::new(8.0, 64.0, 8.0);
+        let mut chunks = chunks.to_vec();
+        chunks.sort_by(|&(x1, z1), &(x2, z2)| {
+            priority(pos, yaw, pitch, x1, z1).total_cmp(&priority(pos, yaw, pitch, x2, z2))
+        });
+        chunks
+    }
+
+    #[test]
+    fn closest_chunks_first() {
+        let chunks = [(5, 0), (0, 0), (0, -3), (1, 1)];
+        assert_eq!(order(0.0, 0.0, &chunks), [(0, 0), (1, 1), (0, -3), (5, 0)]);
+    }
+
+    #[test]
+    fn chunks_in_view_first() {
+        // Facing south (+z) and then west (-x)
+        let chunks = [(0, -4), (5, 0), (0, 6)];
+        assert_eq!(order(0.0, 0.0, &chunks), [(0, 6), (5, 0), (0, -4)]);
+        assert_eq!(order(90.0, 0.0, &chunks), [(0, -4), (0, 6), (5, 0)]);
+        // Looking down, only the distance matters
+        assert_eq!(order(0.0, 90.0, &chunks), [(0, -4), (5, 0), (0, 6)]);
+    }
+}
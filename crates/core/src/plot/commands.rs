@@ -1,22 +1,28 @@
 use super::{database, worldedit, Plot, PlotWorld};
 use crate::player::{Gamemode, PacketSender, PlayerPos};
-use crate::plot::data::sleep_time_for_tps;
 use crate::profile::PlayerProfile;
 use crate::server::Message;
 use mchprs_blocks::items::ItemStack;
+use mchprs_blocks::BlockPos;
 use mchprs_network::packets::clientbound::{
     CCommands, CCommandsNode as Node, CDeclareCommandsNodeParser as Parser, ClientBoundPacket,
 };
 use mchprs_network::packets::PacketEncoder;
 use mchprs_network::PlayerPacketSender;
-use mchprs_redpiler::CompilerOptions;
+use mchprs_redpiler::{BreakCondition, CompilerOptions, Snapshot, SnapshotError};
 use mchprs_save_data::plot_data::{Tps, WorldSendRate};
 use mchprs_text::TextComponent;
+use mchprs_world::World;
 use once_cell::sync::Lazy;
+use std::fs;
+use std::io;
 use std::ops::Add;
 use std::str::FromStr;
 use std::time::Instant;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
+
+/// The most ticks a single redpiler command can run, so a typo doesn't freeze the plot.
+const MAX_REDPILER_TICKS: u64 = 100_000;
 
 // Parses a relative or absolute coordinate relative to a reference coordinate
 fn parse_relative_coord<F: FromStr + Add + Add<Output = F>>(
@@ -179,6 +185,19 @@ impl Plot {
                 let args = args.join(" ");
                 let options = CompilerOptions::parse(&args);
 
+                let selection = if options.selection {
+                    let player = &self.players[player];
+                    match (player.first_position, player.second_position) {
+                        (Some(first), Some(second)) => Some((first, second)),
+                        _ => {
+                            player.send_error_message("Make a WorldEdit selection first.");
+                            return;
+                        }
+                    }
+                } else {
+                    None
+                };
+
                 if options.optimize {
                     let msg = "Redpiler optimization is highly unstable and can break builds. Use with caution!";
                     warn!("{}", msg);
@@ -186,7 +205,7 @@ impl Plot {
                 }
 
                 self.reset_redpiler();
-                self.start_redpiler(options);
+                self.start_redpiler(options, selection);
 
                 debug!("Compile took {:?}", start_time.elapsed());
             }
@@ -208,10 +227,329 @@ impl Plot {
             "reset" | "r" => {
                 self.reset_redpiler();
             }
+            "report" => {
+                let Some(report) = self.redpiler.report() else {
+                    self.players[player].send_error_message("Nothing has been compiled yet.");
+                    return;
+                };
+                match args.first() {
+                    None => {
+                        for line in report.summary() {
+                            self.players[player].send_system_message(&line);
+                        }
+                    }
+                    Some(&"json") => {
+                        let path =
+                            format!("./redpiler_reports/p{},{}.json", self.world.x, self.world.z);
+                        let written = fs::create_dir_all("./redpiler_reports")
+                            .and_then(|_| fs::write(&path, report.to_json()));
+                        match written {
+                            Ok(()) => self.players[player].send_system_message(&format!(
+                                "Saved the compile report to {}",
+                                path
+                            )),
+                            Err(err) => {
+                                error!("There was an error saving a compile report: {}", err);
+                                self.players[player]
+                                    .send_error_message("There was an error saving the report.");
+                            }
+                        }
+                    }
+                    Some(_) => self.players[player].send_error_message("/redpiler report [json]"),
+                }
+            }
+            "verify" => {
+                let Some(Ok(ticks)) = args.first().map(|arg| arg.parse::<u64>()) else {
+                    self.players[player].send_error_message("/redpiler verify <ticks>");
+                    return;
+                };
+                if ticks > MAX_REDPILER_TICKS {
+                    self.players[player].send_error_message(&format!(
+                        "Cannot verify for more than {} ticks",
+                        MAX_REDPILER_TICKS
+                    ));
+                    return;
+                }
+                self.verify_redpiler(player, ticks);
+            }
+            "break" | "b" => {
+                if args.len() < 3 {
+                    self.players[player]
+                        .send_error_message("/redpiler break <x> <y> <z> [on|off|change|clear]");
+                    return;
+                }
+                let player_pos = self.players[player].pos.block_pos();
+                let coords = (
+                    parse_relative_coord(args[0], player_pos.x),
+                    parse_relative_coord(args[1], player_pos.y),
+                    parse_relative_coord(args[2], player_pos.z),
+                );
+                let (Ok(x), Ok(y), Ok(z)) = coords else {
+                    self.players[player].send_error_message("Unable to parse coordinates!");
+                    return;
+                };
+                let pos = BlockPos::new(x, y, z);
+
+                let condition = match args.get(3) {
+                    None => Some(BreakCondition::Change),
+                    Some(&"clear") => None,
+                    Some(arg) => match arg.parse() {
+                        Ok(condition) => Some(condition),
+                        Err(()) => {
+                            self.players[player].send_error_message(
+                                "The condition must be one of on, off, change or clear",
+                            );
+                            return;
+                        }
+                    },
+                };
+
+                let attached = self.redpiler.set_breakpoint(pos, condition);
+                let msg = match condition {
+                    None => format!("Cleared the breakpoint at {}", pos),
+                    Some(condition) if attached => {
+                        format!("Set a breakpoint at {} ({})", pos, condition)
+                    }
+                    Some(condition) => format!(
+                        "Set a breakpoint at {} ({}), it will take effect once a node there is compiled with the direct backend",
+                        pos, condition
+                    ),
+                };
+                self.players[player].send_system_message(&msg);
+            }
+            "step" | "s" => {
+                let (phases, count) = match args.first() {
+                    Some(&"phase") => (true, args.get(1)),
+                    _ => (false, args.first()),
+                };
+                let count = match count.map(|arg| arg.parse::<u64>()) {
+                    None => 1,
+                    Some(Ok(count)) if count > 0 => count,
+                    _ => {
+                        self.players[player].send_error_message("/redpiler step [phase] [n]");
+                        return;
+                    }
+                };
+                if count > MAX_REDPILER_TICKS {
+                    self.players[player].send_error_message(&format!(
+                        "Cannot step more than {} times at once",
+                        MAX_REDPILER_TICKS
+                    ));
+                    return;
+                }
+
+                // Stepping only makes sense while nothing else is ticking
+                if self.tps != Tps::Limited(0) {
+                    self.set_tps(Tps::Limited(0));
+                    self.players[player].send_system_message("The tps has been set to 0");
+                }
+
+                for _ in 0..count {
+                    if phases {
+                        self.step_phase();
+                    } else {
+                        self.tick();
+                    }
+                    if self.check_breakpoint() {
+                        break;
+                    }
+                }
+                if self.redpiler.is_active() {
+                    self.redpiler.flush(&mut self.world);
+                }
+
+                if self.redpiler.is_mid_tick() {
+                    self.players[player]
+                        .send_system_message("Stepped, the current tick is not finished yet");
+                } else {
+                    self.players[player].send_system_message("Stepped");
+                }
+            }
+            "snapshot" => self.handle_snapshot_command(player, args),
             _ => self.players[player].send_error_message("Invalid argument for /redpiler"),
         }
     }
 
+    /// Handles `/redpiler snapshot`. Snapshots are kept next to the plot file, so they can be
+    /// restored after the plot is loaded and compiled again.
+    fn handle_snapshot_command(&mut self, player: usize, args: &[&str]) {
+        let dir = format!("./world/plots/p{},{}.snapshots", self.world.x, self.world.z);
+        let (action, name) = match args {
+            ["list"] => {
+                let mut names: Vec<_> = fs::read_dir(&dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .filter_map(|entry| entry.file_name().into_string().ok())
+                    .collect();
+                names.sort();
+                let msg = match names.is_empty() {
+                    true => "This plot has no snapshots.".to_string(),
+                    false => format!("Snapshots: {}", names.join(", ")),
+                };
+                self.players[player].send_system_message(&msg);
+                return;
+            }
+            [action @ ("save" | "restore"), name] => (*action, *name),
+            _ => {
+                self.players[player]
+                    .send_error_message("/redpiler snapshot <save|restore|list> [name]");
+                return;
+            }
+        };
+        let valid_name = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            self.players[player].send_error_message("Snapshot name is invalid");
+            return;
+        }
+        if !self.redpiler.is_active() {
+            self.players[player].send_error_message("Redpiler has to be running.");
+            return;
+        }
+
+        let path = format!("{}/{}", dir, name);
+        let result = match action {
+            "save" => self.redpiler.snapshot().and_then(|snapshot| {
+                fs::create_dir_all(&dir)?;
+                snapshot.save(&path)
+            }),
+            _ => {
+                Snapshot::load(&path).and_then(|snapshot| self.redpiler.restore_snapshot(&snapshot))
+            }
+        };
+        match result {
+            Ok(()) if action == "save" => {
+                self.players[player].send_system_message(&format!("Saved snapshot {}", name));
+            }
+            Ok(()) => {
+                self.redpiler.flush(&mut self.world);
+                self.players[player].send_system_message(&format!("Restored snapshot {}", name));
+            }
+            Err(SnapshotError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                self.players[player]
+                    .send_error_message(&format!("There is no snapshot named {}", name));
+            }
+            Err(err @ SnapshotError::Mismatch) => {
+                self.players[player]
+                    .send_error_message(&format!("Cannot restore {}: {}", name, err));
+            }
+            Err(err) => {
+                error!("There was an error with snapshot {}: {}", name, err);
+                self.players[player].send_error_message(&format!("Snapshot {}: {}", name, err));
+            }
+        }
+    }
+
+    /// Handles a command that starts with `/probe`
+    fn handle_probe_command(&mut self, player: usize, command: &str, args: &[&str]) {
+        match command {
+            "add" | "remove" => {
+                if self.probes.is_recording() {
+                    self.players[player]
+                        .send_error_message("Probes cannot be changed while recording.");
+                    return;
+                }
+                let player = &self.players[player];
+                let pos = worldedit::ray_trace_block(
+                    &self.world,
+                    player.pos,
+                    player.pitch as f64,
+                    player.yaw as f64,
+                    10.0,
+                );
+                let Some(pos) = pos else {
+                    player.send_error_message("Trace failed");
+                    return;
+                };
+                if command == "remove" {
+                    if self.probes.remove(pos) {
+                        player.send_system_message(&format!("Removed the probe at {}", pos));
+                    } else {
+                        player.send_error_message(&format!("There is no probe at {}", pos));
+                    }
+                    return;
+                }
+                let name = match args.first() {
+                    Some(name) => name.to_string(),
+                    None => format!(
+                        "{}_{}_{}_{}",
+                        self.world.get_block(pos).get_name(),
+                        pos.x,
+                        pos.y,
+                        pos.z
+                    ),
+                };
+                player.send_system_message(&format!("Added probe {} at {}", name, pos));
+                self.probes.add(pos, name);
+            }
+            "list" | "l" => {
+                let player = &self.players[player];
+                if self.probes.is_empty() {
+                    player.send_system_message("There are no probes.");
+                    return;
+                }
+                for probe in self.probes.iter() {
+                    player.send_system_message(&format!("{}: {}", probe.name, probe.pos));
+                }
+            }
+            "clear" => {
+                if self.probes.is_recording() {
+                    self.players[player]
+                        .send_error_message("Probes cannot be changed while recording.");
+                    return;
+                }
+                self.probes.clear();
+                self.players[player].send_system_message("All probes have been removed.");
+            }
+            "start" => {
+                if self.probes.is_empty() {
+                    self.players[player].send_error_message("There are no probes to record.");
+                    return;
+                }
+                if self.probes.is_recording() {
+                    self.players[player].send_error_message("Probes are already being recorded.");
+                    return;
+                }
+                let file_name = match args.first() {
+                    Some(name) => name.to_string(),
+                    None => format!("p{},{}", self.world.x, self.world.z),
+                };
+                if !file_name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-,".contains(c))
+                {
+                    self.players[player].send_error_message("Filename is invalid");
+                    return;
+                }
+                let path = format!("./probes/{}.vcd", file_name);
+                match self.probes.start(&path, &self.world, &self.redpiler) {
+                    Ok(()) => self.players[player]
+                        .send_system_message(&format!("Recording probes to {}", path)),
+                    Err(err) => {
+                        error!("There was an error starting a probe recording: {}", err);
+                        self.players[player]
+                            .send_error_message("There was an error starting the recording.");
+                    }
+                }
+            }
+            "stop" => match self.probes.stop() {
+                Ok(Some(path)) => self.players[player]
+                    .send_system_message(&format!("Saved probe recording to {}", path.display())),
+                Ok(None) => {
+                    self.players[player].send_error_message("Probes are not being recorded.")
+                }
+                Err(err) => {
+                    error!("There was an error saving a probe recording: {}", err);
+                    self.players[player]
+                        .send_error_message("There was an error saving the recording.");
+                }
+            },
+            _ => self.players[player].send_error_message("Invalid argument for /probe"),
+        }
+    }
+
     // Returns true if packets should stop being handled
     pub(super) fn handle_command(
         &mut self,
@@ -302,10 +640,7 @@ impl Plot {
                     return false;
                 };
 
-                self.sleep_time = sleep_time_for_tps(tps);
-                self.timings.set_tps(tps);
-                self.tps = tps;
-                self.reset_timings();
+                self.set_tps(tps);
                 self.players[player].send_system_message("The rtps was successfully set.");
             }
             "radv" | "radvance" => {
@@ -403,6 +738,14 @@ impl Plot {
                 let command = args.remove(0);
                 self.handle_redpiler_command(player, command, &args);
             }
+            "probe" => {
+                if args.is_empty() {
+                    self.players[player].send_error_message("Invalid number of arguments!");
+                    return false;
+                }
+                let command = args.remove(0);
+                self.handle_probe_command(player, command, &args);
+            }
             "speed" => {
                 if args.len() != 1 {
                     self.players[player].send_error_message("/speed <0-10>");
@@ -542,7 +885,7 @@ pub static DECLARE_COMMANDS: Lazy<PacketEncoder> = Lazy::new(|| {
                 flags: CommandFlags::ROOT.bits() as i8,
                 children: &[
                     1, 4, 5, 6, 11, 12, 14, 16, 18, 19, 20, 21, 22, 23, 24, 26, 29, 31, 32, 34, 36,
-                    47, 49, 53, 60, 61, 63, 65, 66, 67, 71, 73,
+                    47, 49, 53, 60, 61, 63, 65, 66, 67, 71, 73, 76,
                 ],
                 redirect_node: None,
                 name: None,
@@ -1140,7 +1483,7 @@ pub static DECLARE_COMMANDS: Lazy<PacketEncoder> = Lazy::new(|| {
             // 66: /redpiler
             Node {
                 flags: CommandFlags::LITERAL.bits() as i8,
-                children: &[68, 69, 70], // Children are compile, inspect, reset
+                children: &[68, 69, 70, 85, 87, 89, 95, 99], // Children are compile, inspect, reset, report, verify, break, step, snapshot
                 redirect_node: None,
                 name: Some("redpiler"),
                 parser: None,
@@ -1227,6 +1570,267 @@ pub static DECLARE_COMMANDS: Lazy<PacketEncoder> = Lazy::new(|| {
                 parser: None,
                 suggestions_type: None,
             },
+            // 76: /probe
+            Node {
+                flags: (CommandFlags::LITERAL).bits() as i8,
+                children: &[77, 78, 79, 80, 81, 82], // Children are add, remove, list, clear, start, stop
+                redirect_node: None,
+                name: Some("probe"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 77: /probe add
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[83],
+                redirect_node: None,
+                name: Some("add"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 78: /probe remove
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("remove"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 79: /probe list
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("list"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 80: /probe clear
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("clear"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 81: /probe start
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[84],
+                redirect_node: None,
+                name: Some("start"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 82: /probe stop
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("stop"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 83: /probe add [name]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("name"),
+                parser: Some(Parser::String(0)),
+                suggestions_type: None,
+            },
+            // 84: /probe start [filename]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("filename"),
+                parser: Some(Parser::String(0)),
+                suggestions_type: None,
+            },
+            // 85: /redpiler report
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[86],
+                redirect_node: None,
+                name: Some("report"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 86: /redpiler report json
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("json"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 87: /redpiler verify
+            Node {
+                flags: CommandFlags::LITERAL.bits() as i8,
+                children: &[88],
+                redirect_node: None,
+                name: Some("verify"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 88: /redpiler verify [ticks]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("ticks"),
+                parser: Some(Parser::Integer(0, 100000)),
+                suggestions_type: None,
+            },
+            // 89: /redpiler break
+            Node {
+                flags: CommandFlags::LITERAL.bits() as i8,
+                children: &[90],
+                redirect_node: None,
+                name: Some("break"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 90: /redpiler break [pos]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[91, 92, 93, 94],
+                redirect_node: None,
+                name: Some("pos"),
+                parser: Some(Parser::BlockPos),
+                suggestions_type: None,
+            },
+            // 91: /redpiler break [pos] on
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("on"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 92: /redpiler break [pos] off
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("off"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 93: /redpiler break [pos] change
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("change"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 94: /redpiler break [pos] clear
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("clear"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 95: /redpiler step
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[96, 97],
+                redirect_node: None,
+                name: Some("step"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 96: /redpiler step [ticks]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("ticks"),
+                parser: Some(Parser::Integer(1, 100000)),
+                suggestions_type: None,
+            },
+            // 97: /redpiler step phase
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[98],
+                redirect_node: None,
+                name: Some("phase"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 98: /redpiler step phase [phases]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("phases"),
+                parser: Some(Parser::Integer(1, 100000)),
+                suggestions_type: None,
+            },
+            // 99: /redpiler snapshot
+            Node {
+                flags: CommandFlags::LITERAL.bits() as i8,
+                children: &[100, 101, 103],
+                redirect_node: None,
+                name: Some("snapshot"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 100: /redpiler snapshot list
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("list"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 101: /redpiler snapshot save
+            Node {
+                flags: CommandFlags::LITERAL.bits() as i8,
+                children: &[102],
+                redirect_node: None,
+                name: Some("save"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 102: /redpiler snapshot save [name]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("name"),
+                parser: Some(Parser::String(0)),
+                suggestions_type: None,
+            },
+            // 103: /redpiler snapshot restore
+            Node {
+                flags: CommandFlags::LITERAL.bits() as i8,
+                children: &[104],
+                redirect_node: None,
+                name: Some("restore"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 104: /redpiler snapshot restore [name]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: &[],
+                redirect_node: None,
+                name: Some("name"),
+                parser: Some(Parser::String(0)),
+                suggestions_type: None,
+            },
         ],
         root_index: 0,
     }
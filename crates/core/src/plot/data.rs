@@ -47,6 +47,7 @@ static EMPTY_PLOT: Lazy<PlotData> = Lazy::new(|| {
         let mut world = PlotWorld {
             x: 0,
             z: 0,
+            chunk_versions: vec![0; chunks.len()],
             chunks,
             to_be_ticked: Vec::new(),
             packet_senders: Vec::new(),
@@ -1,9 +1,12 @@
+pub mod chunk_queue;
 pub mod commands;
 mod data;
 pub mod database;
 mod monitor;
 mod packet_handlers;
+mod probe;
 mod scoreboard;
+pub mod simulation;
 pub mod worldedit;
 
 use crate::config::CONFIG;
@@ -21,13 +24,17 @@ use mchprs_blocks::{BlockFace, BlockPos};
 use mchprs_network::packets::clientbound::*;
 use mchprs_network::packets::serverbound::SUseItemOn;
 use mchprs_network::PlayerPacketSender;
-use mchprs_redpiler::{Compiler, CompilerOptions};
+use mchprs_redpiler::{
+    step_world_phase, tick_world, verify_configurations, Compiler, CompilerOptions, FallbackWorld,
+    PendingTicks, Verifier,
+};
 use mchprs_save_data::plot_data::{ChunkData, PlotData, Tps, WorldSendRate};
 use mchprs_text::TextComponent;
 use mchprs_world::storage::Chunk;
 use mchprs_world::World;
 use mchprs_world::{TickEntry, TickPriority};
 use monitor::TimingsMonitor;
+use probe::Probes;
 use scoreboard::RedpilerState;
 use std::cmp::Ordering;
 use std::collections::HashSet;
@@ -88,6 +95,7 @@ pub struct Plot {
     /// If true, the plot will remain running even if no players are on for a long time.
     always_running: bool,
     auto_redpiler: bool,
+    probes: Probes,
 
     owner: Option<u128>,
     async_rt: Runtime,
@@ -100,6 +108,9 @@ pub struct PlotWorld {
     pub chunks: Vec<Chunk>,
     pub to_be_ticked: Vec<TickEntry>,
     pub packet_senders: Vec<PlayerPacketSender>,
+    /// Counts how often changes to each chunk were sent to the players, so a copy of a chunk can
+    /// tell whether it is still up to date.
+    chunk_versions: Vec<u32>,
 }
 
 impl PlotWorld {
@@ -118,11 +129,25 @@ impl PlotWorld {
         Some(((chunk_x << PLOT_SCALE) + chunk_z).unsigned_abs() as usize)
     }
 
+    fn chunk_version(&self, chunk_x: i32, chunk_z: i32) -> u32 {
+        if !Plot::chunk_in_plot_bounds(self.x, self.z, chunk_x, chunk_z) {
+            return 0;
+        }
+        self.chunk_versions[self.get_chunk_index_for_chunk(chunk_x, chunk_z)]
+    }
+
     fn flush_block_changes(&mut self) {
-        for packet in self.chunks.iter_mut().flat_map(|c| c.multi_blocks()) {
-            let encoded = packet.encode();
-            for player in &self.packet_senders {
-                player.send_packet(&encoded);
+        for (chunk, version) in self.chunks.iter_mut().zip(&mut self.chunk_versions) {
+            let mut changed = false;
+            for packet in chunk.multi_blocks() {
+                let encoded = packet.encode();
+                for player in &self.packet_senders {
+                    player.send_packet(&encoded);
+                }
+                changed = true;
+            }
+            if changed {
+                *version = version.wrapping_add(1);
             }
         }
         for chunk in &mut self.chunks {
@@ -130,6 +155,36 @@ impl PlotWorld {
         }
     }
 
+    /// Loads the chunks and pending ticks of a plot.
+    pub fn from_data(plot_data: PlotData, x: i32, z: i32) -> PlotWorld {
+        let chunk_x_offset = x << PLOT_SCALE;
+        let chunk_z_offset = z << PLOT_SCALE;
+        let chunks: Vec<Chunk> = plot_data
+            .chunk_data
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| {
+                c.load(
+                    chunk_x_offset + i as i32 / PLOT_WIDTH,
+                    chunk_z_offset + i as i32 % PLOT_WIDTH,
+                )
+            })
+            .collect();
+        if chunks.len() != NUM_CHUNKS {
+            error!("This plot has the wrong number of chunks!");
+            let possible_scale = (chunks.len() as f64).sqrt().log2();
+            error!("Note: it most likely came from a server running plot scale {}, this server is running a plot scale of {}", possible_scale, PLOT_SCALE);
+        }
+        PlotWorld {
+            x,
+            z,
+            chunk_versions: vec![0; chunks.len()],
+            chunks,
+            to_be_ticked: plot_data.pending_ticks,
+            packet_senders: Vec::new(),
+        }
+    }
+
     pub fn get_corners(&self) -> (BlockPos, BlockPos) {
         const W: i32 = PLOT_BLOCK_WIDTH;
         let first_pos = BlockPos::new(self.x * W, 0, self.z * W);
@@ -210,6 +265,7 @@ impl World for PlotWorld {
             for player in &self.packet_senders {
                 player.send_packet(&block_entity_data);
             }
+            self.chunk_versions[chunk_index] = self.chunk_versions[chunk_index].wrapping_add(1);
         }
         let chunk = &mut self.chunks[chunk_index];
         chunk.set_block_entity(BlockPos::new(pos.x & 0xF, pos.y, pos.z & 0xF), block_entity);
@@ -236,6 +292,10 @@ impl World for PlotWorld {
         self.to_be_ticked.iter().any(|e| e.pos == pos)
     }
 
+    fn cancel_ticks(&mut self, pos: BlockPos) {
+        self.to_be_ticked.retain(|e| e.pos != pos);
+    }
+
     fn play_sound(
         &mut self,
         pos: BlockPos,
@@ -268,26 +328,51 @@ impl World for PlotWorld {
     }
 }
 
+impl PendingTicks for PlotWorld {
+    fn pending_ticks(&mut self) -> &mut Vec<TickEntry> {
+        &mut self.to_be_ticked
+    }
+}
+
+fn set_pressure_plate(world: &mut impl World, pos: BlockPos, powered: bool) {
+    let block = world.get_block(pos);
+    match block {
+        Block::StonePressurePlate { .. } => {
+            world.set_block(pos, Block::StonePressurePlate { powered });
+            mchprs_redstone::update_surrounding_blocks(world, pos);
+            mchprs_redstone::update_surrounding_blocks(world, pos.offset(BlockFace::Bottom));
+        }
+        _ => warn!("Block at {} is not a pressure plate", pos),
+    }
+}
+
 impl Plot {
     fn tick(&mut self) {
         self.timings.tick();
-        if self.redpiler.is_active() {
-            self.redpiler.tick();
-            return;
+        // Probes only record whole ticks
+        if tick_world(&mut self.world, &mut self.redpiler) {
+            self.probes.record(&self.world, &self.redpiler);
         }
+    }
 
-        self.world
-            .to_be_ticked
-            .sort_by_key(|e| (e.ticks_left, e.tick_priority));
-        for pending in &mut self.world.to_be_ticked {
-            pending.ticks_left = pending.ticks_left.saturating_sub(1);
-        }
-        while self.world.to_be_ticked.first().map_or(1, |e| e.ticks_left) == 0 {
-            let entry = self.world.to_be_ticked.remove(0);
-            mchprs_redstone::tick(self.world.get_block(entry.pos), &mut self.world, entry.pos);
+    /// Runs a single tick priority on redpiler, see [`Compiler::step_phase`].
+    fn step_phase(&mut self) {
+        if step_world_phase(&mut self.world, &mut self.redpiler) {
+            self.probes.record(&self.world, &self.redpiler);
         }
     }
 
+    /// Stops ticking and tells the players which breakpoint was hit, if there was one.
+    fn check_breakpoint(&mut self) -> bool {
+        let Some(hit) = self.redpiler.take_breakpoint_hit() else {
+            return false;
+        };
+        self.set_tps(Tps::Limited(0));
+        self.redpiler.flush(&mut self.world);
+        self.broadcast_plot_chat_message(&format!("&6{}", hit));
+        true
+    }
+
     /// Send a block change to all connected players
     pub fn send_block_change(&mut self, pos: BlockPos, id: u32) {
         let block_change = CBlockUpdate {
@@ -343,24 +428,21 @@ impl Plot {
     }
 
     fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool) {
+        if let Some(region) = self.redpiler.fallback_region() {
+            if region.contains(pos) {
+                let mut world = FallbackWorld::new(&mut self.world, region);
+                set_pressure_plate(&mut world, pos, powered);
+                self.redpiler.read_interface(&self.world);
+                self.redpiler.flush(&mut self.world);
+                return;
+            }
+        }
         if self.redpiler.is_active() {
             self.redpiler.set_pressure_plate(pos, powered);
             return;
         }
 
-        let block = self.world.get_block(pos);
-        match block {
-            Block::StonePressurePlate { .. } => {
-                self.world
-                    .set_block(pos, Block::StonePressurePlate { powered });
-                mchprs_redstone::update_surrounding_blocks(&mut self.world, pos);
-                mchprs_redstone::update_surrounding_blocks(
-                    &mut self.world,
-                    pos.offset(BlockFace::Bottom),
-                );
-            }
-            _ => warn!("Block at {} is not a pressure plate", pos),
-        }
+        set_pressure_plate(&mut self.world, pos, powered);
     }
 
     fn are_players_on_block(&mut self, pos: BlockPos) -> bool {
@@ -425,19 +507,22 @@ impl Plot {
             // let unload_chunk = CUnloadChunk { chunk_x, chunk_z }.encode();
             // self.players[player_idx].client.send_packet(&unload_chunk);
         } else if !was_loaded && should_be_loaded {
-            if !Plot::chunk_in_plot_bounds(self.world.x, self.world.z, chunk_x, chunk_z) {
-                self.players[player_idx]
-                    .client
-                    .send_packet(&Chunk::encode_empty_packet(chunk_x, chunk_z, PLOT_SECTIONS));
-            } else {
-                let chunk_data = self.world.chunks
-                    [self.world.get_chunk_index_for_chunk(chunk_x, chunk_z)]
-                .encode_packet();
-                self.players[player_idx].client.send_packet(&chunk_data);
-            }
+            self.players[player_idx].chunk_queue.push(chunk_x, chunk_z);
         }
     }
 
+    /// Sends the player as many of the chunks queued for them as the budget allows.
+    fn send_queued_chunks(&mut self, player_idx: usize) {
+        let player = &mut self.players[player_idx];
+        player.chunk_queue.update(
+            &self.world,
+            &self.async_rt,
+            &player.client,
+            (player.pos, player.yaw, player.pitch),
+            CONFIG.chunk_send_budget.max(0) as u64,
+        );
+    }
+
     pub fn update_view_pos_for_player(&mut self, player_idx: usize, force_load: bool) {
         let view_distance = CONFIG.view_distance as i32;
         let (chunk_x, chunk_z) = self.players[player_idx].pos.chunk_pos();
@@ -465,6 +550,7 @@ impl Plot {
                 }
             }
         } else {
+            self.players[player_idx].chunk_queue.clear();
             for x in last_chunk_x - view_distance..=last_chunk_x + view_distance {
                 for z in last_chunk_z - view_distance..=last_chunk_z + view_distance {
                     self.set_chunk_loaded_at_player(player_idx, x, z, true, false);
@@ -476,6 +562,9 @@ impl Plot {
                 }
             }
         }
+        self.players[player_idx]
+            .chunk_queue
+            .retain_in_view(chunk_x, chunk_z, view_distance);
         self.players[player_idx].last_chunk_x = chunk_x;
         self.players[player_idx].last_chunk_z = chunk_z;
     }
@@ -539,6 +628,16 @@ impl Plot {
             let block = self.world.get_block(block_pos);
             let lever_or_button = matches!(block, Block::Lever { .. } | Block::StoneButton { .. });
             if lever_or_button && !self.players[player].crouching {
+                if let Some(region) = self.redpiler.fallback_region() {
+                    if region.contains(block_pos) {
+                        let mut world = FallbackWorld::new(&mut self.world, region);
+                        mchprs_redstone::on_use(block, &mut world, block_pos);
+                        self.redpiler.read_interface(&self.world);
+                        self.redpiler.flush(&mut self.world);
+                        self.world.flush_block_changes();
+                        return;
+                    }
+                }
                 self.redpiler.on_use_block(block_pos);
                 self.redpiler.flush(&mut self.world);
                 self.world.flush_block_changes();
@@ -552,7 +651,9 @@ impl Plot {
                     }
                     _ => {}
                 }
-                self.reset_redpiler();
+                if !self.redpiler.can_recompile() {
+                    self.reset_redpiler();
+                }
             }
         }
 
@@ -570,6 +671,7 @@ impl Plot {
             if cancelled {
                 cancel(self);
             }
+            self.recompile_redpiler(block_pos);
             self.world.flush_block_changes();
             return;
         }
@@ -583,6 +685,7 @@ impl Plot {
                 block_pos,
                 None,
             );
+            self.recompile_redpiler(block_pos);
             self.world.flush_block_changes();
         }
     }
@@ -635,9 +738,12 @@ impl Plot {
             _ => {}
         }
 
-        self.reset_redpiler();
+        if !self.redpiler.can_recompile() {
+            self.reset_redpiler();
+        }
 
         interaction::destroy(block, &mut self.world, block_pos);
+        self.recompile_redpiler(block_pos);
         self.world.flush_block_changes();
 
         let effect = CWorldEvent {
@@ -667,23 +773,45 @@ impl Plot {
         self.timings.reset_timings();
     }
 
-    fn start_redpiler(&mut self, options: CompilerOptions) {
+    fn set_tps(&mut self, tps: Tps) {
+        self.sleep_time = sleep_time_for_tps(tps);
+        self.timings.set_tps(tps);
+        self.tps = tps;
+        self.reset_timings();
+    }
+
+    /// Compiles the whole plot, or only `selection` if there is one.
+    fn start_redpiler(
+        &mut self,
+        options: CompilerOptions,
+        selection: Option<(BlockPos, BlockPos)>,
+    ) {
         debug!("Starting redpiler");
+        let bounds = self.world.get_corners();
+
         self.scoreboard
             .set_redpiler_state(&self.players, RedpilerState::Compiling);
         self.scoreboard
             .set_redpiler_options(&self.players, &options);
 
-        let bounds = self.world.get_corners();
         // TODO: use monitor
         let monitor = Default::default();
-        let ticks = self.world.to_be_ticked.drain(..).collect();
+        let ticks = self.world.to_be_ticked.clone();
 
         let mut players_need_updates = HashSet::new();
         thread::scope(|s| {
-            let handle = s.spawn(|| {
-                self.redpiler
-                    .compile(&mut self.world, bounds, options, ticks, monitor)
+            let handle = s.spawn(|| match selection {
+                Some(selection) => self.redpiler.compile_selection(
+                    &self.world,
+                    bounds,
+                    selection,
+                    options,
+                    ticks,
+                    monitor,
+                ),
+                None => self
+                    .redpiler
+                    .compile(&self.world, bounds, options, ticks, monitor),
             });
             while !handle.is_finished() {
                 // We'll update the players so that they don't time out.
@@ -699,6 +827,12 @@ impl Plot {
             }
         });
 
+        // Ticks in the fallback region keep running on the world, redpiler took over the rest
+        let redpiler = &self.redpiler;
+        self.world
+            .to_be_ticked
+            .retain(|entry| redpiler.is_fallback(entry.pos));
+
         // Now that we have ownership of the world again, we can update player view positions
         for player_idx in players_need_updates {
             self.update_view_pos_for_player(player_idx, false);
@@ -710,7 +844,70 @@ impl Plot {
         self.reset_timings();
     }
 
-    /// Redpiler needs to reset implicitly in the case of any block changes done by a player. This can be
+    /// Runs a copy of the plot for `ticks` ticks on every redpiler backend, with and without
+    /// optimizations, and tells the player where each of them stopped matching the default
+    /// redstone implementation.
+    fn verify_redpiler(&mut self, player: usize, ticks: u64) {
+        if self.redpiler.is_mid_tick() {
+            self.players[player]
+                .send_error_message("Finish the current tick with `/redpiler step` first");
+            return;
+        }
+        // The blocks in the world have to be up to date to be copied
+        if self.redpiler.is_active() {
+            self.redpiler.flush(&mut self.world);
+        }
+        self.players[player]
+            .send_system_message(&format!("Verifying redpiler for {} ticks...", ticks));
+
+        let mut verifier = Verifier::new(
+            &self.world,
+            self.world.get_corners(),
+            self.world.to_be_ticked.clone(),
+        );
+        // Blocks that are not flushed with `--io-only`, and the ticks pending in redpiler, are
+        // only known to redpiler
+        verifier.copy_compiler_state(&self.redpiler);
+        let mut players_need_updates = HashSet::new();
+        let results = thread::scope(|s| {
+            let handle = s.spawn(|| verifier.verify(&verify_configurations(), ticks));
+            while !handle.is_finished() {
+                // Keeps the players from timing out, like while compiling
+                for player_idx in 0..self.players.len() {
+                    if self.players[player_idx].update() {
+                        players_need_updates.insert(player_idx);
+                    }
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            handle.join().unwrap()
+        });
+        for player_idx in players_need_updates {
+            self.update_view_pos_for_player(player_idx, false);
+        }
+
+        for result in results {
+            self.players[player].send_system_message(&result.to_string());
+        }
+        self.reset_timings();
+    }
+
+    /// Patches the running redpiler after the blocks around `pos` were edited, if it is active.
+    fn recompile_redpiler(&mut self, pos: BlockPos) {
+        if !self.redpiler.is_active() {
+            return;
+        }
+        // The edit was done by the default redstone implementation, which may have scheduled ticks
+        // for blocks that redpiler is in charge of
+        let redpiler = &self.redpiler;
+        self.world
+            .to_be_ticked
+            .retain(|entry| redpiler.is_fallback(entry.pos));
+        if !self.redpiler.recompile_around(&mut self.world, pos) {
+            self.reset_redpiler();
+        }
+    }
+
     fn reset_redpiler(&mut self) {
         if self.redpiler.is_active() {
             debug!("Discarding redpiler");
@@ -739,7 +936,8 @@ impl Plot {
     fn leave_plot(&mut self, uuid: u128) -> Player {
         let player_idx = self.players.iter().position(|p| p.uuid == uuid).unwrap();
         self.world.packet_senders.remove(player_idx);
-        let player = self.players.remove(player_idx);
+        let mut player = self.players.remove(player_idx);
+        player.chunk_queue.clear();
 
         let destroy_other_entities = CRemoveEntities {
             entity_ids: self.players.iter().map(|p| p.entity_id as i32).collect(),
@@ -964,6 +1162,7 @@ impl Plot {
             if self.players[player_idx].update() {
                 self.update_view_pos_for_player(player_idx, false);
             }
+            self.send_queued_chunks(player_idx);
         }
         // Handle received packets
         for player_idx in 0..self.players.len() {
@@ -1012,8 +1211,12 @@ impl Plot {
                 let batch_size = batch_size.min(50_000) as u32;
                 let mut ticks_completed = batch_size;
                 if self.redpiler.is_active() {
-                    for _ in 0..batch_size {
+                    for i in 0..batch_size {
                         self.tick();
+                        if self.check_breakpoint() {
+                            ticks_completed = i + 1;
+                            break;
+                        }
                     }
                     self.redpiler.flush(&mut self.world);
                 } else {
@@ -1032,7 +1235,7 @@ impl Plot {
                 && !self.redpiler.is_active()
                 && (self.tps == Tps::Unlimited || self.timings.is_running_behind())
             {
-                self.start_redpiler(Default::default());
+                self.start_redpiler(Default::default(), None);
             }
 
             let now = Instant::now();
@@ -1097,33 +1300,13 @@ impl Plot {
         priv_rx: Receiver<PrivMessage>,
         always_running: bool,
     ) -> Plot {
-        let chunk_x_offset = x << PLOT_SCALE;
-        let chunk_z_offset = z << PLOT_SCALE;
-        let chunks: Vec<Chunk> = plot_data
-            .chunk_data
-            .into_iter()
-            .enumerate()
-            .map(|(i, c)| {
-                c.load(
-                    chunk_x_offset + i as i32 / PLOT_WIDTH,
-                    chunk_z_offset + i as i32 % PLOT_WIDTH,
-                )
-            })
-            .collect();
-        if chunks.len() != NUM_CHUNKS {
-            error!("This plot has the wrong number of chunks!");
-            let possible_scale = (chunks.len() as f64).sqrt().log2();
-            error!("Note: it most likely came from a server running plot scale {}, this server is running a plot scale of {}", possible_scale, PLOT_SCALE);
-        }
-        let world = PlotWorld {
-            x,
-            z,
-            chunks,
-            to_be_ticked: plot_data.pending_ticks,
-            packet_senders: Vec::new(),
-        };
         let tps = plot_data.tps;
         let world_send_rate = plot_data.world_send_rate;
+        let world = PlotWorld::from_data(plot_data, x, z);
+        let mut redpiler = Compiler::default();
+        if CONFIG.redpiler_cache {
+            redpiler.use_cache("./world/redpiler_cache");
+        }
         Plot {
             last_player_time: Instant::now(),
             last_update_time: Instant::now(),
@@ -1138,10 +1321,11 @@ impl Plot {
             locked_players: HashSet::new(),
             running: true,
             auto_redpiler: CONFIG.auto_redpiler,
+            probes: Default::default(),
             tps,
             world_send_rate,
             always_running,
-            redpiler: Default::default(),
+            redpiler,
             timings: TimingsMonitor::new(tps),
             owner: database::get_plot_owner(x, z).map(|s| s.parse::<HyphenatedUUID>().unwrap().0),
             async_rt: Plot::create_async_rt(),
@@ -1219,6 +1403,9 @@ impl Plot {
             }
         }
 
+        if let Err(err) = self.probes.stop() {
+            error!("Error finishing probe recording: {}", err);
+        }
         self.save();
     }
 
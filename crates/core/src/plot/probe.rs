@@ -0,0 +1,286 @@
+//! Probes record the power of a few blocks every tick into a Value Change Dump, which can be
+//! viewed with waveform viewers such as GTKWave.
+
+use super::PlotWorld;
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use mchprs_redpiler::Compiler;
+use mchprs_redstone::bool_to_ss;
+use mchprs_world::World;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::error;
+
+/// Bits used for every signal, comparators can go above 15 with high signal strength
+const SIGNAL_WIDTH: usize = 8;
+
+pub struct Probe {
+    pub pos: BlockPos,
+    pub name: String,
+}
+
+fn is_powered(block: Block) -> bool {
+    match block {
+        Block::RedstoneTorch { lit } | Block::RedstoneWallTorch { lit, .. } => lit,
+        Block::RedstoneLamp { lit } => lit,
+        Block::RedstoneRepeater { repeater } => repeater.powered,
+        Block::Lever { lever } => lever.powered,
+        Block::StoneButton { button } => button.powered,
+        Block::StonePressurePlate { powered }
+        | Block::IronTrapdoor { powered, .. }
+        | Block::NoteBlock { powered, .. }
+        | Block::Observer { powered, .. } => powered,
+        Block::TripwireHook { powered, .. } => powered,
+        Block::RedstoneBlock {} => true,
+        _ => false,
+    }
+}
+
+/// Returns the power of the block at `pos`, taking it from redpiler if the block is compiled.
+/// Wires and comparators have their signal strength, while everything else is either 0 or 15.
+fn probe_power(world: &PlotWorld, redpiler: &Compiler, pos: BlockPos) -> u8 {
+    let block = world.get_block(pos);
+    let state = redpiler.node_state(pos);
+    match block {
+        Block::RedstoneWire { wire } => state.map_or(wire.power, |state| state.power),
+        Block::RedstoneComparator { .. } => match state {
+            Some(state) => state.power,
+            None => match world.get_block_entity(pos) {
+                Some(BlockEntity::Comparator { output_strength }) => *output_strength,
+                _ => 0,
+            },
+        },
+        _ => bool_to_ss(state.map_or_else(|| is_powered(block), |state| state.powered)),
+    }
+}
+
+/// Returns the short identifier VCD uses to refer to the signal with the given index.
+fn identifier(mut idx: usize) -> String {
+    const FIRST: u8 = b'!';
+    const COUNT: usize = (b'~' - FIRST + 1) as usize;
+    let mut id = String::new();
+    loop {
+        id.push((FIRST + (idx % COUNT) as u8) as char);
+        idx /= COUNT;
+        if idx == 0 {
+            return id;
+        }
+        idx -= 1;
+    }
+}
+
+struct VcdWriter<W: Write> {
+    out: W,
+    tick: u64,
+    values: Vec<u8>,
+}
+
+impl<W: Write> VcdWriter<W> {
+    fn new(mut out: W, plot: (i32, i32), probes: &[Probe], values: Vec<u8>) -> io::Result<Self> {
+        writeln!(out, "$version MCHPRS $end")?;
+        writeln!(out, "$comment Plot {},{} $end", plot.0, plot.1)?;
+        // There is no real time unit for a tick, so this is what it would be at 10 rtps
+        writeln!(out, "$timescale 100 ms $end")?;
+        writeln!(out, "$scope module plot $end")?;
+        for (idx, probe) in probes.iter().enumerate() {
+            writeln!(
+                out,
+                "$var wire {} {} {} $end",
+                SIGNAL_WIDTH,
+                identifier(idx),
+                probe.name
+            )?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+        writeln!(out, "#0")?;
+        writeln!(out, "$dumpvars")?;
+        for (idx, value) in values.iter().enumerate() {
+            Self::write_value(&mut out, idx, *value)?;
+        }
+        writeln!(out, "$end")?;
+        Ok(VcdWriter {
+            out,
+            tick: 0,
+            values,
+        })
+    }
+
+    fn write_value(out: &mut W, idx: usize, value: u8) -> io::Result<()> {
+        writeln!(
+            out,
+            "b{:0width$b} {}",
+            value,
+            identifier(idx),
+            width = SIGNAL_WIDTH
+        )
+    }
+
+    /// Writes the values after another tick, if any of them changed.
+    fn tick(&mut self, values: impl Iterator<Item = u8>) -> io::Result<()> {
+        self.tick += 1;
+        let mut time_written = false;
+        for (idx, value) in values.enumerate() {
+            if self.values[idx] == value {
+                continue;
+            }
+            if !time_written {
+                writeln!(self.out, "#{}", self.tick)?;
+                time_written = true;
+            }
+            self.values[idx] = value;
+            Self::write_value(&mut self.out, idx, value)?;
+        }
+        Ok(())
+    }
+
+    /// Marks the end of the recording, so the last values show up for a tick in viewers.
+    fn finish(mut self) -> io::Result<W> {
+        writeln!(self.out, "#{}", self.tick + 1)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+struct Recording {
+    path: PathBuf,
+    writer: VcdWriter<BufWriter<File>>,
+}
+
+#[derive(Default)]
+pub struct Probes {
+    probes: Vec<Probe>,
+    recording: Option<Recording>,
+}
+
+impl Probes {
+    pub fn iter(&self) -> impl Iterator<Item = &Probe> {
+        self.probes.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Adds a probe, replacing the name of any probe that is already at `pos`.
+    pub fn add(&mut self, pos: BlockPos, name: String) {
+        match self.probes.iter_mut().find(|probe| probe.pos == pos) {
+            Some(probe) => probe.name = name,
+            None => self.probes.push(Probe { pos, name }),
+        }
+    }
+
+    /// Returns false if there was no probe at `pos`.
+    pub fn remove(&mut self, pos: BlockPos) -> bool {
+        let len = self.probes.len();
+        self.probes.retain(|probe| probe.pos != pos);
+        self.probes.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.probes.clear();
+    }
+
+    fn values<'a>(
+        &'a self,
+        world: &'a PlotWorld,
+        redpiler: &'a Compiler,
+    ) -> impl Iterator<Item = u8> + 'a {
+        self.probes
+            .iter()
+            .map(|probe| probe_power(world, redpiler, probe.pos))
+    }
+
+    /// Starts recording into the file at `path`, beginning with the current power of every probe.
+    pub fn start(
+        &mut self,
+        path: impl AsRef<Path>,
+        world: &PlotWorld,
+        redpiler: &Compiler,
+    ) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let out = BufWriter::new(File::create(&path)?);
+        let values = self.values(world, redpiler).collect();
+        let writer = VcdWriter::new(out, (world.x, world.z), &self.probes, values)?;
+        self.recording = Some(Recording { path, writer });
+        Ok(())
+    }
+
+    /// Records the power of every probe after a tick has passed.
+    pub fn record(&mut self, world: &PlotWorld, redpiler: &Compiler) {
+        let Some(recording) = &mut self.recording else {
+            return;
+        };
+        let values = self
+            .probes
+            .iter()
+            .map(|probe| probe_power(world, redpiler, probe.pos));
+        if let Err(err) = recording.writer.tick(values) {
+            error!(
+                "Error writing probe recording {}: {}",
+                recording.path.display(),
+                err
+            );
+            self.recording = None;
+        }
+    }
+
+    /// Stops recording and returns the path of the file it was written to.
+    pub fn stop(&mut self) -> io::Result<Option<PathBuf>> {
+        let Some(recording) = self.recording.take() else {
+            return Ok(None);
+        };
+        recording.writer.finish()?;
+        Ok(Some(recording.path))
+    }
+}
+
+#[test]
+fn vcd_writer_test() {
+    let probes = [
+        Probe {
+            pos: BlockPos::new(0, 0, 0),
+            name: "clock".to_string(),
+        },
+        Probe {
+            pos: BlockPos::new(1, 0, 0),
+            name: "data".to_string(),
+        },
+    ];
+    let mut writer = VcdWriter::new(Vec::new(), (0, 0), &probes, vec![0, 15]).unwrap();
+    writer.tick([0, 15].into_iter()).unwrap();
+    writer.tick([15, 3].into_iter()).unwrap();
+    let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+    assert_eq!(
+        out,
+        "$version MCHPRS $end
+$comment Plot 0,0 $end
+$timescale 100 ms $end
+$scope module plot $end
+$var wire 8 ! clock $end
+$var wire 8 \" data $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+b00000000 !
+b00001111 \"
+$end
+#2
+b00001111 !
+b00000011 \"
+#3
+"
+    );
+    assert_eq!(identifier(93), "~");
+    assert_eq!(identifier(94), "!!");
+}
@@ -3,7 +3,7 @@ use mchprs_network::packets::clientbound::{
     CDisplayObjective, CResetScore, CUpdateObjectives, CUpdateScore, ClientBoundPacket,
     ObjectiveNumberFormat,
 };
-use mchprs_redpiler::CompilerOptions;
+use mchprs_redpiler::{BackendVariant, CompilerOptions};
 use mchprs_text::{ColorCode, TextComponentBuilder};
 
 #[derive(PartialEq, Eq, Default, Clone, Copy)]
@@ -138,6 +138,14 @@ impl Scoreboard {
         if options.wire_dot_out {
             flags.push("§b- wire dot out");
         }
+        if options.selection {
+            flags.push("§b- selection");
+        }
+        match options.backend_variant {
+            BackendVariant::Direct => {}
+            BackendVariant::Cranelift => flags.push("§b- cranelift backend"),
+            BackendVariant::Parallel => flags.push("§b- parallel backend"),
+        }
 
         if !flags.is_empty() {
             new_lines.push("§7Flags:".to_string());
@@ -0,0 +1,223 @@
+//! Running a plot without a server, for testing builds outside of the game.
+
+use super::worldedit::{paste_clipboard, WorldEditClipboard};
+use super::{set_pressure_plate, tick_world, PlotWorld, PLOT_SECTIONS, PLOT_WIDTH};
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use mchprs_redpiler::{Compiler, CompilerOptions, FallbackWorld};
+use mchprs_save_data::plot_data::PlotData;
+use mchprs_world::storage::Chunk;
+use mchprs_world::World;
+use tracing::warn;
+
+/// A plot along with everything needed to tick it. Nobody can be on the plot, so any interaction
+/// has to be done through the methods here.
+pub struct Simulation {
+    world: PlotWorld,
+    redpiler: Compiler,
+}
+
+impl Simulation {
+    pub fn new(world: PlotWorld) -> Simulation {
+        Simulation {
+            world,
+            redpiler: Default::default(),
+        }
+    }
+
+    /// Creates the plot at 0,0 filled with nothing but air.
+    pub fn empty() -> Simulation {
+        let mut chunks = Vec::new();
+        for chunk_x in 0..PLOT_WIDTH {
+            for chunk_z in 0..PLOT_WIDTH {
+                chunks.push(Chunk::empty(chunk_x, chunk_z, PLOT_SECTIONS));
+            }
+        }
+        Simulation::new(PlotWorld {
+            x: 0,
+            z: 0,
+            chunk_versions: vec![0; chunks.len()],
+            chunks,
+            to_be_ticked: Vec::new(),
+            packet_senders: Vec::new(),
+        })
+    }
+
+    pub fn from_plot_data(data: PlotData, x: i32, z: i32) -> Simulation {
+        Simulation::new(PlotWorld::from_data(data, x, z))
+    }
+
+    /// Pastes the schematic into an empty plot at 0,0 with its lowest corner at 0,0,0.
+    pub fn from_schematic(clipboard: &WorldEditClipboard) -> Simulation {
+        let mut simulation = Simulation::empty();
+        let pos = BlockPos::new(clipboard.offset_x, clipboard.offset_y, clipboard.offset_z);
+        paste_clipboard(&mut simulation.world, clipboard, pos, true);
+        simulation
+    }
+
+    pub fn world(&self) -> &PlotWorld {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut PlotWorld {
+        &mut self.world
+    }
+
+    pub fn get_block(&self, pos: BlockPos) -> Block {
+        self.world.get_block(pos)
+    }
+
+    pub fn is_redpiler_active(&self) -> bool {
+        self.redpiler.is_active()
+    }
+
+    pub fn start_redpiler(&mut self, options: CompilerOptions) {
+        let bounds = self.world.get_corners();
+        let ticks = self.world.to_be_ticked.clone();
+        self.redpiler
+            .compile(&self.world, bounds, options, ticks, Default::default());
+        let redpiler = &self.redpiler;
+        self.world
+            .to_be_ticked
+            .retain(|entry| redpiler.is_fallback(entry.pos));
+    }
+
+    pub fn tick(&mut self) {
+        tick_world(&mut self.world, &mut self.redpiler);
+        if self.redpiler.is_active() {
+            self.redpiler.flush(&mut self.world);
+        }
+    }
+
+    /// Passes changes made inside the fallback region on to the compiled blocks around it.
+    fn sync_fallback(&mut self) {
+        self.redpiler.read_interface(&self.world);
+        self.redpiler.flush(&mut self.world);
+    }
+
+    /// Flips a lever or presses a button.
+    pub fn use_block(&mut self, pos: BlockPos) {
+        let block = self.world.get_block(pos);
+        if let Some(region) = self.redpiler.fallback_region() {
+            if region.contains(pos) {
+                let mut world = FallbackWorld::new(&mut self.world, region);
+                mchprs_redstone::on_use(block, &mut world, pos);
+                self.sync_fallback();
+                return;
+            }
+        }
+        if self.redpiler.is_active() {
+            match block {
+                Block::Lever { .. } | Block::StoneButton { .. } => {
+                    self.redpiler.on_use_block(pos);
+                    self.redpiler.flush(&mut self.world);
+                }
+                _ => warn!(
+                    "Cannot use the {} at {} while redpiler is active",
+                    block.get_name(),
+                    pos
+                ),
+            }
+            return;
+        }
+        mchprs_redstone::on_use(block, &mut self.world, pos);
+    }
+
+    pub fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool) {
+        if let Some(region) = self.redpiler.fallback_region() {
+            if region.contains(pos) {
+                let mut world = FallbackWorld::new(&mut self.world, region);
+                set_pressure_plate(&mut world, pos, powered);
+                self.sync_fallback();
+                return;
+            }
+        }
+        if self.redpiler.is_active() {
+            match self.world.get_block(pos) {
+                Block::StonePressurePlate { .. } => {
+                    self.redpiler.set_pressure_plate(pos, powered);
+                    self.redpiler.flush(&mut self.world);
+                }
+                block => warn!(
+                    "The {} at {} is not a pressure plate",
+                    block.get_name(),
+                    pos
+                ),
+            }
+            return;
+        }
+        set_pressure_plate(&mut self.world, pos, powered);
+    }
+}
+
+#[test]
+fn simulation_lever_lamp_test() {
+    use mchprs_blocks::blocks::{Lever, LeverFace};
+
+    for redpiler in [false, true] {
+        let mut simulation = Simulation::empty();
+        let world = simulation.world_mut();
+        world.set_block(BlockPos::new(0, 0, 0), Block::Sandstone {});
+        world.set_block(
+            BlockPos::new(0, 1, 0),
+            Block::Lever {
+                lever: Lever {
+                    face: LeverFace::Floor,
+                    ..Default::default()
+                },
+            },
+        );
+        world.set_block(BlockPos::new(1, 0, 0), Block::RedstoneLamp { lit: false });
+        if redpiler {
+            simulation.start_redpiler(Default::default());
+        }
+
+        simulation.use_block(BlockPos::new(0, 1, 0));
+        assert_eq!(
+            simulation.get_block(BlockPos::new(1, 0, 0)),
+            Block::RedstoneLamp { lit: true }
+        );
+        simulation.use_block(BlockPos::new(0, 1, 0));
+        simulation.tick();
+        assert_eq!(
+            simulation.get_block(BlockPos::new(1, 0, 0)),
+            Block::RedstoneLamp { lit: true }
+        );
+        simulation.tick();
+        assert_eq!(
+            simulation.get_block(BlockPos::new(1, 0, 0)),
+            Block::RedstoneLamp { lit: false }
+        );
+    }
+}
+
+#[test]
+fn simulation_pressure_plate_test() {
+    for redpiler in [false, true] {
+        let mut simulation = Simulation::empty();
+        let world = simulation.world_mut();
+        world.set_block(BlockPos::new(0, 0, 0), Block::Sandstone {});
+        world.set_block(
+            BlockPos::new(0, 1, 0),
+            Block::StonePressurePlate { powered: false },
+        );
+        world.set_block(BlockPos::new(1, 0, 0), Block::RedstoneLamp { lit: false });
+        if redpiler {
+            simulation.start_redpiler(Default::default());
+        }
+
+        // Pressing something that is not a pressure plate does nothing
+        simulation.set_pressure_plate(BlockPos::new(1, 0, 0), true);
+        simulation.set_pressure_plate(BlockPos::new(5, 5, 5), true);
+        assert_eq!(
+            simulation.get_block(BlockPos::new(1, 0, 0)),
+            Block::RedstoneLamp { lit: false }
+        );
+
+        simulation.set_pressure_plate(BlockPos::new(0, 1, 0), true);
+        assert_eq!(
+            simulation.get_block(BlockPos::new(1, 0, 0)),
+            Block::RedstoneLamp { lit: true }
+        );
+    }
+}
@@ -3,6 +3,8 @@
 mod execute;
 mod schematic;
 
+pub use schematic::{load_schematic_file, save_schematic_file};
+
 use super::{Plot, PlotWorld};
 use crate::player::{PacketSender, Player, PlayerPos};
 use execute::*;
@@ -970,8 +972,10 @@ fn worldedit_start_operation(player: &mut Player) -> WorldEditOperation {
     WorldEditOperation::new(first_pos, second_pos)
 }
 
-fn create_clipboard(
-    plot: &mut PlotWorld,
+/// Copies the blocks between `first_pos` and `second_pos` out of any world, with `origin` being
+/// where the clipboard is pasted from.
+pub fn create_clipboard(
+    plot: &impl World,
     origin: BlockPos,
     first_pos: BlockPos,
     second_pos: BlockPos,
@@ -1036,7 +1040,12 @@ fn clear_area(plot: &mut PlotWorld, first_pos: BlockPos, second_pos: BlockPos) {
     }
 }
 
-fn paste_clipboard(plot: &mut PlotWorld, cb: &WorldEditClipboard, pos: BlockPos, ignore_air: bool) {
+pub(super) fn paste_clipboard(
+    plot: &mut PlotWorld,
+    cb: &WorldEditClipboard,
+    pos: BlockPos,
+    ignore_air: bool,
+) {
     let offset_x = pos.x - cb.offset_x;
     let offset_y = pos.y - cb.offset_y;
     let offset_z = pos.z - cb.offset_z;
@@ -14,7 +14,7 @@ use regex::Regex;
 use rustc_hash::FxHashMap;
 use serde::Serialize;
 use std::fs::{self, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 macro_rules! nbt_as {
     // I'm not sure if path is the right type here.
@@ -44,7 +44,12 @@ fn parse_block(str: &str) -> Option<Block> {
 }
 
 pub fn load_schematic(file_name: &str) -> Result<WorldEditClipboard> {
-    let mut file = File::open("./schems/".to_owned() + file_name)?;
+    load_schematic_file("./schems/".to_owned() + file_name)
+}
+
+/// Loads a schematic from anywhere, rather than from the schematics folder.
+pub fn load_schematic_file(path: impl AsRef<Path>) -> Result<WorldEditClipboard> {
+    let mut file = File::open(path)?;
     let nbt = nbt::Blob::from_gzip_reader(&mut file)?;
 
     let root = if nbt.content.contains_key("Schematic") {
@@ -211,8 +216,12 @@ pub fn save_schematic(file_name: &str, clipboard: &WorldEditClipboard) -> Result
     let mut path = PathBuf::from("./schems");
     path.push(file_name);
     fs::create_dir_all(path.parent().unwrap())?;
+    save_schematic_file("./schems/".to_owned() + file_name, clipboard)
+}
 
-    let mut file = File::create("./schems/".to_owned() + file_name)?;
+/// Saves a schematic anywhere, rather than in the schematics folder.
+pub fn save_schematic_file(path: impl AsRef<Path>, clipboard: &WorldEditClipboard) -> Result<()> {
+    let mut file = File::create(path)?;
     let size_x = clipboard.size_x;
     let size_y = clipboard.size_y;
     let size_z = clipboard.size_z;
@@ -2,25 +2,29 @@ use crate::config::CONFIG;
 use crate::player::{Gamemode, PacketSender, Player};
 use crate::plot::commands::DECLARE_COMMANDS;
 use crate::plot::{self, database, Plot, PLOT_BLOCK_HEIGHT};
+use crate::session::{GameProfile, HttpSessionVerifier, SessionVerifier, MOJANG_SESSION_SERVER};
 use crate::utils::HyphenatedUUID;
 use crate::{permissions, utils};
 use backtrace::Backtrace;
 use bus::Bus;
 use hmac::{Hmac, Mac};
+use mchprs_network::encryption::{self, ServerKey};
 use mchprs_network::packets::clientbound::{
-    CConfigurationPluginMessage, CDisconnectLogin, CFinishConfiguration, CGameEvent,
-    CGameEventType, CLogin, CLoginPluginRequest, CLoginSuccess, CPlayerInfoActions,
+    CConfigurationPluginMessage, CDisconnectLogin, CEncryptionRequest, CFinishConfiguration,
+    CGameEvent, CGameEventType, CLogin, CLoginPluginRequest, CLoginSuccess, CPlayerInfoActions,
     CPlayerInfoAddPlayer, CPlayerInfoUpdate, CPlayerInfoUpdatePlayer, CPong, CRegistryBiome,
     CRegistryBiomeEffects, CRegistryData, CRegistryDataCodec, CRegistryDimensionType, CResponse,
     CSetCompression, CSetContainerContent, CSetHeldItem, CSynchronizePlayerPosition,
     ClientBoundPacket, UpdateTime,
 };
 use mchprs_network::packets::serverbound::{
-    SAcknowledgeFinishConfiguration, SHandshake, SLoginAcknowledged, SLoginPluginResponse,
-    SLoginStart, SPing, SRequest, ServerBoundPacketHandler, VelocityResponseData,
+    BungeeCordForwardingData, SAcknowledgeFinishConfiguration, SEncryptionResponse, SHandshake,
+    SLoginAcknowledged, SLoginPluginResponse, SLoginStart, SPing, SRequest,
+    ServerBoundPacketHandler, VelocityResponseData,
 };
 use mchprs_network::packets::{PacketEncoderExt, PlayerProperty, SlotData, COMPRESSION_THRESHOLD};
-use mchprs_network::{NetworkServer, NetworkState, PlayerPacketSender};
+use mchprs_network::translation::Translations;
+use mchprs_network::{HandshakingConn, NetworkServer, NetworkState, PlayerPacketSender};
 use mchprs_text::TextComponent;
 use mchprs_utils::map;
 use rustc_hash::FxHashMap;
@@ -31,12 +35,15 @@ use std::fs::{self, File};
 use std::io::Cursor;
 use std::path::Path;
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 
 pub const MC_VERSION: &str = "1.20.4";
 pub const MC_DATA_VERSION: i32 = 3700;
 pub const PROTOCOL_VERSION: i32 = 765;
+/// Keeps a client that is logging in from holding up the others by flooding the server
+const HANDSHAKING_PACKETS_PER_UPDATE: usize = 64;
 
 /// `Message` gets send from a plot thread to the server thread.
 #[derive(Debug)]
@@ -133,6 +140,13 @@ pub struct MinecraftServer {
     online_players: FxHashMap<u128, PlayerListEntry>,
     running_plots: Vec<PlotListEntry>,
     whitelist: Option<Vec<WhitelistEntry>>,
+    /// Only present in online mode
+    server_key: Option<ServerKey>,
+    session_verifier: Arc<dyn SessionVerifier>,
+    /// Session verification runs on its own thread, the results are sent back here along with
+    /// the id of the client.
+    auth_sender: Sender<(u32, anyhow::Result<Option<GameProfile>>)>,
+    auth_receiver: Receiver<(u32, anyhow::Result<Option<GameProfile>>)>,
 }
 
 impl MinecraftServer {
@@ -179,15 +193,28 @@ impl MinecraftServer {
             permissions::init(permissions_config.clone()).unwrap();
         }
 
+        if let Some(bungeecord_config) = &CONFIG.bungeecord {
+            if bungeecord_config.enabled && bungeecord_config.bungeeguard_tokens.is_empty() {
+                warn!("BungeeCord forwarding is enabled without BungeeGuard, make sure the server can only be reached through the proxy");
+            }
+        }
+
+        let server_key = CONFIG.online_mode.then(ServerKey::generate);
+        let (auth_sender, auth_receiver) = mpsc::channel();
+
         // Create server struct
         let mut server = MinecraftServer {
-            network: NetworkServer::new(bind_addr),
+            network: NetworkServer::new(bind_addr, Translations::load(&CONFIG.protocol_reports)),
             broadcaster: bus,
             receiver: server_rx,
             plot_sender: plot_tx,
             online_players: FxHashMap::default(),
             running_plots: Vec::new(),
             whitelist,
+            server_key,
+            session_verifier: Arc::new(HttpSessionVerifier::new(MOJANG_SESSION_SERVER)),
+            auth_sender,
+            auth_receiver,
         };
 
         // Load the spawn area plot on server start
@@ -450,9 +477,88 @@ impl MinecraftServer {
             }
         }
 
+        // The proxy already authenticated the player, if the forwarding was accepted
+        if bungeecord_enabled() {
+            if clients[client_idx].uuid.is_some() {
+                self.complete_player_login(client_idx);
+            } else {
+                self.disconnect_login(client_idx, "Unable to authenticate");
+            }
+            return;
+        }
+
+        if let Some(server_key) = &self.server_key {
+            let verify_token: [u8; 4] = rand::random();
+            clients[client_idx].verify_token = Some(verify_token.to_vec());
+            let encryption_request = CEncryptionRequest {
+                server_id: String::new(),
+                public_key: server_key.public_key_der().to_vec(),
+                verify_token: verify_token.to_vec(),
+            }
+            .encode();
+            clients[client_idx].send_packet(&encryption_request);
+            return;
+        }
+
         self.complete_player_login(client_idx);
     }
 
+    fn disconnect_login(&mut self, client_idx: usize, reason: &str) {
+        let client = &mut self.network.handshaking_clients[client_idx];
+        let disconnect = CDisconnectLogin {
+            reason: json!({ "text": reason }).to_string(),
+        }
+        .encode();
+        client.send_packet(&disconnect);
+        client.close_connection();
+    }
+
+    fn handle_bungeecord_forwarding(&mut self, client_idx: usize, server_address: &str) {
+        let Some(mut forwarding_data) = BungeeCordForwardingData::parse(server_address) else {
+            error!("Received a handshake without BungeeCord forwarding data");
+            self.disconnect_login(
+                client_idx,
+                "If you wish to use IP forwarding, please enable it in your BungeeCord config as well!",
+            );
+            return;
+        };
+
+        let tokens = &CONFIG.bungeecord.as_ref().unwrap().bungeeguard_tokens;
+        if !forwarding_data.verify_bungeeguard_token(tokens) {
+            error!("Failed to verify BungeeGuard token!");
+            self.disconnect_login(client_idx, "Unable to authenticate");
+            return;
+        }
+
+        let client = &mut self.network.handshaking_clients[client_idx];
+        client.uuid = Some(forwarding_data.uuid);
+        client.properties = forwarding_data.properties;
+    }
+
+    /// Handles the result of verifying the session of a client in online mode.
+    fn handle_auth_result(&mut self, client_id: u32, result: anyhow::Result<Option<GameProfile>>) {
+        let clients = &mut self.network.handshaking_clients;
+        // The client might have disconnected while waiting
+        let Some(client_idx) = clients.iter().position(|client| client.id() == client_id) else {
+            return;
+        };
+
+        match result {
+            Ok(Some(profile)) => {
+                let client = &mut clients[client_idx];
+                client.uuid = Some(profile.uuid.0);
+                client.username = Some(profile.name);
+                client.properties = profile.properties.into_iter().map(Into::into).collect();
+                self.complete_player_login(client_idx);
+            }
+            Ok(None) => self.disconnect_login(client_idx, "Failed to verify username!"),
+            Err(err) => {
+                error!("Could not verify session: {:?}", err);
+                self.disconnect_login(client_idx, "Authentication servers are down");
+            }
+        }
+    }
+
     fn complete_player_login(&mut self, client_idx: usize) {
         let clients = &mut self.network.handshaking_clients;
         let username = clients[client_idx].username.clone().unwrap();
@@ -633,32 +739,59 @@ impl MinecraftServer {
         while let Ok(message) = self.receiver.try_recv() {
             self.handle_message(message);
         }
+        while let Ok((client_id, result)) = self.auth_receiver.try_recv() {
+            self.handle_auth_result(client_id, result);
+        }
         self.network.update();
 
         let mut client_idx = 0;
-        let mut clients_len = self.network.handshaking_clients.len();
-        loop {
-            if client_idx >= clients_len {
-                break;
-            }
-
-            let packets = self.network.handshaking_clients[client_idx].receive_packets();
-            for packet in packets {
+        while client_idx < self.network.handshaking_clients.len() {
+            let client_id = self.network.handshaking_clients[client_idx].id();
+            for _ in 0..HANDSHAKING_PACKETS_PER_UPDATE {
+                let clients = &self.network.handshaking_clients;
+                let Some(packet) = handshaking_client(clients, client_idx, client_id)
+                    .and_then(HandshakingConn::receive_packet)
+                else {
+                    break;
+                };
                 packet.handle(self, client_idx);
             }
 
-            let new_len = self.network.handshaking_clients.len();
-
-            if clients_len == new_len {
+            let clients = &self.network.handshaking_clients;
+            if handshaking_client(clients, client_idx, client_id).is_some() {
                 client_idx += 1;
             }
-            clients_len = new_len;
         }
     }
 }
 
+/// Returns the client at `idx` if it is still the one with this id. Clients are removed once
+/// they join the game.
+fn handshaking_client(
+    clients: &[HandshakingConn],
+    idx: usize,
+    id: u32,
+) -> Option<&HandshakingConn> {
+    clients.get(idx).filter(|client| client.id() == id)
+}
+
+/// Velocity forwarding takes precedence if both are enabled.
+fn bungeecord_enabled() -> bool {
+    let velocity_enabled = CONFIG
+        .velocity
+        .as_ref()
+        .is_some_and(|config| config.enabled);
+    let bungeecord_enabled = CONFIG
+        .bungeecord
+        .as_ref()
+        .is_some_and(|config| config.enabled);
+    bungeecord_enabled && !velocity_enabled
+}
+
 impl ServerBoundPacketHandler for MinecraftServer {
     fn handle_handshake(&mut self, handshake: SHandshake, client_idx: usize) {
+        let supported = handshake.protocol_version == PROTOCOL_VERSION
+            || self.network.supports_protocol(handshake.protocol_version);
         let clients = &mut self.network.handshaking_clients;
         let client = &mut clients[client_idx];
         let next_state = match handshake.next_state {
@@ -667,7 +800,8 @@ impl ServerBoundPacketHandler for MinecraftServer {
             // TODO: Handle invalid next state
             _ => return,
         };
-        if next_state == NetworkState::Login && handshake.protocol_version != PROTOCOL_VERSION {
+        client.protocol_version = handshake.protocol_version;
+        if next_state == NetworkState::Login && !supported {
             warn!("A player tried to connect using the wrong version");
             let disconnect = CDisconnectLogin {
                 reason: json!({ "text": format!("Version mismatch, I'm on {}!", MC_VERSION) })
@@ -676,16 +810,27 @@ impl ServerBoundPacketHandler for MinecraftServer {
             .encode();
             client.send_packet(&disconnect);
             client.close_connection();
+            return;
+        }
+
+        if next_state == NetworkState::Login && bungeecord_enabled() {
+            self.handle_bungeecord_forwarding(client_idx, &handshake.server_address);
         }
     }
 
     fn handle_request(&mut self, _request: SRequest, client_idk: usize) {
+        // Clients that can join through translation are told the server is on their version
+        let client_protocol = self.network.handshaking_clients[client_idk].protocol_version;
+        let protocol = match self.network.supports_protocol(client_protocol) {
+            true => client_protocol,
+            false => PROTOCOL_VERSION,
+        };
         let client = &mut self.network.handshaking_clients[client_idk];
         let response = CResponse {
             json_response: json!({
                 "version": {
                     "name": MC_VERSION,
-                    "protocol": PROTOCOL_VERSION
+                    "protocol": protocol
                 },
                 "players": {
                     "max": CONFIG.max_players,
@@ -803,6 +948,44 @@ impl ServerBoundPacketHandler for MinecraftServer {
         self.handle_player_enter_play(client_idx);
     }
 
+    fn handle_encryption_response(&mut self, packet: SEncryptionResponse, client_idx: usize) {
+        let Some(server_key) = &self.server_key else {
+            error!("Received an encryption response while not in online mode");
+            return;
+        };
+        let client = &mut self.network.handshaking_clients[client_idx];
+
+        let verify_token = server_key.decrypt(&packet.verify_token);
+        let shared_secret = server_key.decrypt(&packet.shared_secret);
+        let shared_secret = match (verify_token, shared_secret) {
+            (Ok(verify_token), Ok(shared_secret))
+                if client.verify_token.take().as_ref() == Some(&verify_token) =>
+            {
+                shared_secret
+            }
+            _ => {
+                warn!("A player sent an invalid encryption response");
+                self.disconnect_login(client_idx, "Invalid encryption response");
+                return;
+            }
+        };
+        if let Err(err) = client.enable_encryption(&shared_secret) {
+            warn!("Could not enable encryption: {}", err);
+            self.disconnect_login(client_idx, "Invalid encryption response");
+            return;
+        }
+
+        let server_hash = encryption::server_hash("", &shared_secret, server_key.public_key_der());
+        let username = client.username.clone().unwrap();
+        let client_id = client.id();
+        let session_verifier = self.session_verifier.clone();
+        let auth_sender = self.auth_sender.clone();
+        std::thread::spawn(move || {
+            let result = session_verifier.has_joined(&username, &server_hash);
+            let _ = auth_sender.send((client_id, result));
+        });
+    }
+
     fn handle_login_plugin_response(&mut self, packet: SLoginPluginResponse, client_idx: usize) {
         let clients = &mut self.network.handshaking_clients;
 
@@ -0,0 +1,148 @@
+//! Checking with a session server that players logging in own the account they claim, which is
+//! what online mode is about.
+
+use crate::utils::HyphenatedUUID;
+use anyhow::Result;
+use mchprs_network::packets::PlayerProperty;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+pub const MOJANG_SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+
+#[derive(Debug, Deserialize)]
+pub struct GameProfile {
+    #[serde(rename = "id")]
+    pub uuid: HyphenatedUUID,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<ProfileProperty>,
+}
+
+/// A property of a profile, such as the skin of the player.
+#[derive(Debug, Deserialize)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+impl From<ProfileProperty> for PlayerProperty {
+    fn from(property: ProfileProperty) -> PlayerProperty {
+        PlayerProperty {
+            name: property.name,
+            value: property.value,
+            signature: property.signature,
+        }
+    }
+}
+
+pub trait SessionVerifier: Send + Sync {
+    /// Asks whether `username` joined a server with `server_hash`, and returns their profile if
+    /// they did. This blocks until there is an answer.
+    fn has_joined(&self, username: &str, server_hash: &str) -> Result<Option<GameProfile>>;
+}
+
+/// Verifies sessions with a session server that speaks the same HTTP API as Mojang's.
+pub struct HttpSessionVerifier {
+    url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl HttpSessionVerifier {
+    pub fn new(url: impl Into<String>) -> HttpSessionVerifier {
+        HttpSessionVerifier {
+            url: url.into(),
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+}
+
+impl SessionVerifier for HttpSessionVerifier {
+    fn has_joined(&self, username: &str, server_hash: &str) -> Result<Option<GameProfile>> {
+        let res = self
+            .client
+            .get(format!("{}/session/minecraft/hasJoined", self.url))
+            .query(&[("username", username), ("serverId", server_hash)])
+            .send()?;
+        // Players that did not join get an empty response
+        if res.status() == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        Ok(Some(res.error_for_status()?.json()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answers one request for each response with it, returning the url it can be reached at and
+    /// the request lines it received.
+    fn session_server(responses: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                requests.push(line.trim_end().to_string());
+                // Skip the headers
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim_end().is_empty() {
+                        break;
+                    }
+                }
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn response(status: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+
+    #[test]
+    fn has_joined() {
+        let profile = r#"{
+            "id": "069a79f444e94726a5befca90e38aaf5",
+            "name": "Notch",
+            "properties": [{ "name": "textures", "value": "e30=", "signature": "c2ln" }]
+        }"#;
+        let (url, server) = session_server(vec![
+            response("200 OK", profile),
+            response("204 No Content", ""),
+            response("500 Internal Server Error", ""),
+        ]);
+        let verifier = HttpSessionVerifier::new(url);
+
+        let profile = verifier.has_joined("Notch", "-1a2b").unwrap().unwrap();
+        assert_eq!(profile.uuid.0, 0x069a79f444e94726a5befca90e38aaf5);
+        assert_eq!(profile.name, "Notch");
+        assert_eq!(profile.properties[0].name, "textures");
+        assert_eq!(profile.properties[0].signature.as_deref(), Some("c2ln"));
+
+        assert!(verifier.has_joined("Notch", "-1a2b").unwrap().is_none());
+        assert!(verifier.has_joined("Notch", "-1a2b").is_err());
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests[0],
+            "GET /session/minecraft/hasJoined?username=Notch&serverId=-1a2b HTTP/1.1"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Protocol encryption for online mode.
+//!
+//! The server sends its public key to the client, which answers with a shared secret encrypted
+//! using that key. From then on, everything sent either way is encrypted with AES/CFB8, using the
+//! shared secret as both the key and the IV.
+
+use aes::Aes128;
+use cfb8::cipher::generic_array::GenericArray;
+use cfb8::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use rsa::pkcs8::EncodePublicKey;
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+pub(crate) type Encryptor = cfb8::Encryptor<Aes128>;
+pub(crate) type Decryptor = cfb8::Decryptor<Aes128>;
+
+#[derive(Error, Debug)]
+pub enum EncryptionError {
+    #[error("rsa error: {0}")]
+    Rsa(#[from] rsa::Error),
+
+    #[error("the shared secret has to be 16 bytes long")]
+    InvalidSecretLength,
+}
+
+/// The key pair used to receive the shared secret of every connection.
+pub struct ServerKey {
+    private_key: RsaPrivateKey,
+    /// The public key, encoded the way the client expects it in the encryption request
+    public_key_der: Vec<u8>,
+}
+
+impl ServerKey {
+    /// Generates a new 1024 bit key, which is what the vanilla server uses.
+    pub fn generate() -> ServerKey {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024)
+            .expect("failed to generate server key");
+        let public_key_der = private_key
+            .to_public_key()
+            .to_public_key_der()
+            .expect("failed to encode server key")
+            .into_vec();
+        ServerKey {
+            private_key,
+            public_key_der,
+        }
+    }
+
+    pub fn public_key_der(&self) -> &[u8] {
+        &self.public_key_der
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        Ok(self.private_key.decrypt(Pkcs1v15Encrypt, data)?)
+    }
+}
+
+pub(crate) fn new_ciphers(shared_secret: &[u8]) -> Result<(Encryptor, Decryptor), EncryptionError> {
+    let encryptor = Encryptor::new_from_slices(shared_secret, shared_secret)
+        .map_err(|_| EncryptionError::InvalidSecretLength)?;
+    let decryptor = Decryptor::new_from_slices(shared_secret, shared_secret)
+        .map_err(|_| EncryptionError::InvalidSecretLength)?;
+    Ok((encryptor, decryptor))
+}
+
+// CFB8 works on one byte at a time, so every byte is a block of its own
+pub(crate) fn encrypt(cipher: &mut Encryptor, data: &mut [u8]) {
+    for byte in data.chunks_mut(1) {
+        cipher.encrypt_block_mut(GenericArray::from_mut_slice(byte));
+    }
+}
+
+pub(crate) fn decrypt(cipher: &mut Decryptor, data: &mut [u8]) {
+    for byte in data.chunks_mut(1) {
+        cipher.decrypt_block_mut(GenericArray::from_mut_slice(byte));
+    }
+}
+
+/// Computes the server id hash the client and the session server agree on. It is the SHA-1 digest
+/// read as a signed big endian number, written in hex without leading zeros.
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key_der: &[u8]) -> String {
+    let mut hash: [u8; 20] = Sha1::new()
+        .chain_update(server_id)
+        .chain_update(shared_secret)
+        .chain_update(public_key_der)
+        .finalize()
+        .into();
+
+    let negative = hash[0] & 0x80 != 0;
+    if negative {
+        // Two's complement, to get the magnitude
+        let mut carry = true;
+        for byte in hash.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                (*byte, carry) = byte.overflowing_add(1);
+            }
+        }
+    }
+
+    let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let hex = hex.trim_start_matches('0');
+    match negative {
+        true => format!("-{}", hex),
+        false => hex.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name_hash(name: &str) -> String {
+        server_hash(name, &[], &[])
+    }
+
+    #[test]
+    fn server_hash_sign() {
+        assert_eq!(
+            name_hash("Notch"),
+            "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48"
+        );
+        assert_eq!(
+            name_hash("jeb_"),
+            "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1"
+        );
+        assert_eq!(
+            name_hash("simon"),
+            "88e16a1019277b15d58faf0541e11910eb756f6"
+        );
+    }
+
+    #[test]
+    fn cipher_round_trip() {
+        let secret = [7; 16];
+        let (mut encryptor, _) = new_ciphers(&secret).unwrap();
+        let (_, mut decryptor) = new_ciphers(&secret).unwrap();
+
+        let message = b"the stream is encrypted across packets".to_vec();
+        let mut data = message.clone();
+        // Encrypted in two parts, but decrypted as one
+        let (first, second) = data.split_at_mut(10);
+        encrypt(&mut encryptor, first);
+        encrypt(&mut encryptor, second);
+        assert_ne!(data, message);
+        decrypt(&mut decryptor, &mut data);
+        assert_eq!(data, message);
+
+        assert!(new_ciphers(&[0; 8]).is_err());
+    }
+}
@@ -1,35 +1,122 @@
+pub mod encryption;
 mod nbt_util;
 pub mod packets;
+pub mod translation;
 
+use encryption::{Decryptor, EncryptionError, Encryptor};
 use packets::serverbound::ServerBoundPacket;
-use packets::{read_packet, PacketEncoder, PlayerProperty};
-use std::net::{Shutdown, TcpListener, TcpStream};
+use packets::{decode_packet, read_packet_data, PacketEncoder, PlayerProperty};
+use std::io::{self, Cursor};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{mpsc, Arc};
-use std::thread;
+use std::sync::{mpsc, Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::sync::{Semaphore, TryAcquireError};
+use tokio::task::AbortHandle;
 use tracing::warn;
+use translation::{ClientboundTranslator, Translation, Translations};
 
 pub use nbt_util::NBTCompound;
 
+/// The number of bytes of packets that can wait to be sent to a client. Clients that fall this far
+/// behind get disconnected, so they can't hold up whoever is sending them packets. A plot changing
+/// blocks in every chunk section sends a few thousand packets in one tick, which has to fit
+/// comfortably.
+const SEND_QUEUE_BYTES: usize = 32 << 20;
+
 #[derive(Debug)]
 pub struct PlayerPacketSender {
-    stream: Option<TcpStream>,
+    queue: PacketQueue,
 }
 
 impl PlayerPacketSender {
     pub fn new(conn: &PlayerConn) -> PlayerPacketSender {
-        let stream = conn.client.stream.try_clone().ok();
-        if stream.is_none() {
-            warn!("Creating PlayerPacketSender with dead stream")
+        PlayerPacketSender {
+            queue: conn.client.queue.clone(),
         }
-        PlayerPacketSender { stream }
     }
 
     pub fn send_packet(&self, data: &PacketEncoder) {
-        if let Some(stream) = &self.stream {
-            // Going to assume stream is compressed since it should be after login
-            let _ = data.write_compressed(stream);
+        self.queue.push(Outgoing::Packet(data.clone()));
+    }
+}
+
+/// Packets are encoded by the task writing them, so compressing them doesn't hold up whoever is
+/// sending them.
+enum Outgoing {
+    Packet(PacketEncoder),
+    /// Everything after this gets encrypted
+    EnableEncryption(Box<Encryptor>),
+    SetCompressed(bool),
+    /// Everything after this gets translated for a client on another release
+    Translate(Box<dyn ClientboundTranslator>),
+    /// Closes the connection once everything before this was sent
+    Close,
+}
+
+/// The queue of packets waiting to be written to the client, shared by everything that sends
+/// packets to it. Sending never blocks, instead the client is disconnected if the queue is full.
+#[derive(Debug, Clone)]
+struct PacketQueue {
+    sender: tokio_mpsc::UnboundedSender<Outgoing>,
+    /// Holds a permit for every byte that can still be queued. Packets take as many as they are
+    /// long, and give them back once they were written.
+    space: Arc<Semaphore>,
+    reader: AbortHandle,
+    writer: AbortHandle,
+}
+
+impl PacketQueue {
+    fn push(&self, outgoing: Outgoing) {
+        if let Outgoing::Packet(packet) = &outgoing {
+            match self.space.try_acquire_many(queued_bytes(packet)) {
+                Ok(permit) => permit.forget(),
+                Err(TryAcquireError::NoPermits) => {
+                    warn!("Disconnecting a client that can't keep up with the packets sent to it");
+                    self.abort();
+                    return;
+                }
+                // The semaphore is never closed
+                Err(TryAcquireError::Closed) => return,
+            }
         }
+        // This fails if the connection is closed already
+        let _ = self.sender.send(outgoing);
+    }
+
+    /// Drops the connection right away, without sending what is left in the queue.
+    fn abort(&self) {
+        self.reader.abort();
+        self.writer.abort();
+    }
+}
+
+/// The bytes a packet takes up in the send queue.
+fn queued_bytes(packet: &PacketEncoder) -> u32 {
+    u32::try_from(packet.data_len()).unwrap_or(u32::MAX)
+}
+
+/// Returns the length of the packet at the start of `buf` including its length prefix, or `None`
+/// if it was not received completely yet.
+fn frame_length(buf: &[u8]) -> io::Result<Option<usize>> {
+    let mut length = 0;
+    // Three bytes are enough for the largest packet the client is allowed to send
+    for (i, byte) in buf.iter().take(3).enumerate() {
+        length |= ((byte & 0x7F) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            let frame_length = i + 1 + length;
+            return Ok((buf.len() >= frame_length).then_some(frame_length));
+        }
+    }
+    match buf.len() >= 3 {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "packet too large",
+        )),
+        false => Ok(None),
     }
 }
 
@@ -45,26 +132,67 @@ pub enum NetworkState {
 
 pub struct HandshakingConn {
     client: NetworkClient,
+    /// Set once the client was disconnected, anything it sent after that is ignored
+    closed: bool,
+    /// The protocol version the client sent in its handshake
+    pub protocol_version: i32,
     pub username: Option<String>,
     pub uuid: Option<u128>,
     pub forwarding_message_id: Option<i32>,
+    /// Sent in the encryption request, the client has to send it back encrypted
+    pub verify_token: Option<Vec<u8>>,
     pub properties: Vec<PlayerProperty>,
 }
 
 impl HandshakingConn {
+    fn new(client: NetworkClient) -> HandshakingConn {
+        HandshakingConn {
+            client,
+            closed: false,
+            protocol_version: 0,
+            username: None,
+            uuid: None,
+            forwarding_message_id: None,
+            verify_token: None,
+            properties: vec![],
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.client.id
+    }
+
     pub fn send_packet(&self, data: &PacketEncoder) {
         self.client.send_packet(data);
     }
 
-    pub fn receive_packets(&self) -> Vec<Box<dyn ServerBoundPacket>> {
-        self.client.receive_packets(&mut true)
+    /// Encrypts everything sent and received from now on using the shared secret the client sent
+    /// in its encryption response, already decrypted with the server key.
+    pub fn enable_encryption(&self, shared_secret: &[u8]) -> Result<(), EncryptionError> {
+        let (encryptor, decryptor) = encryption::new_ciphers(shared_secret)?;
+        *self.client.decryptor.lock().unwrap() = Some(decryptor);
+        self.client
+            .queue
+            .push(Outgoing::EnableEncryption(Box::new(encryptor)));
+        Ok(())
+    }
+
+    /// Returns the next packet the client sent, unless it was disconnected. Packets are handled
+    /// one by one, as handling one might disconnect the client.
+    pub fn receive_packet(&self) -> Option<Box<dyn ServerBoundPacket>> {
+        if self.closed {
+            return None;
+        }
+        self.client.packets.try_recv().ok()
     }
 
     pub fn set_compressed(&self, compressed: bool) {
-        self.client.compressed.store(compressed, Ordering::Relaxed)
+        self.client.compressed.store(compressed, Ordering::Relaxed);
+        self.client.queue.push(Outgoing::SetCompressed(compressed));
     }
 
-    pub fn close_connection(&self) {
+    pub fn close_connection(&mut self) {
+        self.closed = true;
         self.client.close_connection();
     }
 }
@@ -107,28 +235,178 @@ pub struct NetworkClient {
     /// All NetworkClients are identified by this id.
     /// If the client is a player, the player's entitiy id becomes the same.
     pub id: u32,
-    stream: TcpStream,
+    queue: PacketQueue,
+    decryptor: Arc<Mutex<Option<Decryptor>>>,
     packets: mpsc::Receiver<Box<dyn ServerBoundPacket>>,
     compressed: Arc<AtomicBool>,
 }
 
 impl NetworkClient {
-    fn listen(
-        mut stream: TcpStream,
+    /// Spawns the tasks reading and writing packets of the client. This has to be called from
+    /// within the runtime.
+    fn spawn(
+        stream: TcpStream,
+        id: u32,
+        queue_bytes: usize,
+        translations: Arc<Translations>,
+    ) -> NetworkClient {
+        let (packet_sender, packet_receiver) = mpsc::channel();
+        let (queue_sender, queue_receiver) = tokio_mpsc::unbounded_channel();
+        let space = Arc::new(Semaphore::new(queue_bytes));
+        let compressed = Arc::new(AtomicBool::new(false));
+        let decryptor = Arc::new(Mutex::new(None));
+
+        let (read_half, write_half) = stream.into_split();
+        let reader = tokio::spawn(NetworkClient::listen(
+            read_half,
+            decryptor.clone(),
+            packet_sender,
+            compressed.clone(),
+            translations,
+            queue_sender.clone(),
+        ));
+        let writer = tokio::spawn(NetworkClient::write_packets(
+            write_half,
+            queue_receiver,
+            space.clone(),
+        ));
+
+        NetworkClient {
+            id,
+            queue: PacketQueue {
+                sender: queue_sender,
+                space,
+                reader: reader.abort_handle(),
+                writer: writer.abort_handle(),
+            },
+            decryptor,
+            packets: packet_receiver,
+            compressed,
+        }
+    }
+
+    async fn listen(
+        mut stream: OwnedReadHalf,
+        decryptor: Arc<Mutex<Option<Decryptor>>>,
         sender: mpsc::Sender<Box<dyn ServerBoundPacket>>,
         compressed: Arc<AtomicBool>,
+        translations: Arc<Translations>,
+        queue: tokio_mpsc::UnboundedSender<Outgoing>,
     ) {
         let mut state = NetworkState::Handshaking;
+        let mut translation: Option<Arc<dyn Translation>> = None;
+        let mut buf = Vec::new();
         loop {
-            let packet = match read_packet(&mut stream, &compressed, &mut state) {
-                Ok(packet) => packet,
-                // This will cause the client to disconnect
-                Err(_) => return,
-            };
-            if sender.send(packet).is_err() {
+            let received = buf.len();
+            buf.reserve(4096);
+            match stream.read_buf(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            // The decryptor is set before the server sends the packets the client answers with
+            // encrypted ones, so it is always there by the time encrypted data is read
+            if let Some(decryptor) = &mut *decryptor.lock().unwrap() {
+                encryption::decrypt(decryptor, &mut buf[received..]);
+            }
+
+            let mut decoded = 0;
+            loop {
+                let frame_length = match frame_length(&buf[decoded..]) {
+                    Ok(Some(frame_length)) => frame_length,
+                    Ok(None) => break,
+                    // This will cause the client to disconnect
+                    Err(_) => return,
+                };
+                let mut frame = Cursor::new(&buf[decoded..decoded + frame_length]);
+                decoded += frame_length;
+                let data = match read_packet_data(&mut frame, &compressed) {
+                    Ok(data) => data,
+                    Err(_) => return,
+                };
+                let data = match &translation {
+                    Some(translation) => match translation.serverbound(&state, data) {
+                        Ok(Some(data)) => data,
+                        Ok(None) => continue,
+                        Err(_) => return,
+                    },
+                    None => data,
+                };
+
+                let handshaking = state == NetworkState::Handshaking;
+                let packet = match decode_packet(&data, &mut state) {
+                    Ok(packet) => packet,
+                    Err(_) => return,
+                };
+                if handshaking {
+                    // The handshake tells what release the client is on, and the server only
+                    // answers it after it was passed on
+                    translation = translations.for_handshake(&data);
+                    if let Some(translation) = &translation {
+                        let translator = translation.clone().clientbound(state.clone());
+                        if queue.send(Outgoing::Translate(translator)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                if sender.send(packet).is_err() {
+                    return;
+                }
+            }
+            buf.drain(..decoded);
+        }
+    }
+
+    async fn write_packets(
+        stream: OwnedWriteHalf,
+        mut queue: tokio_mpsc::UnboundedReceiver<Outgoing>,
+        space: Arc<Semaphore>,
+    ) {
+        let mut stream = BufWriter::new(stream);
+        let mut encryptor = None;
+        let mut compressed = false;
+        let mut translator: Option<Box<dyn ClientboundTranslator>> = None;
+        let mut packets = Vec::new();
+        let mut data = Vec::new();
+        while let Some(outgoing) = queue.recv().await {
+            match outgoing {
+                Outgoing::Packet(packet) => {
+                    let bytes = queued_bytes(&packet);
+                    match &mut translator {
+                        Some(translator) => {
+                            let id = packet.packet_id;
+                            if let Err(err) = translator.translate(packet, &mut packets) {
+                                warn!("Failed to translate packet with id {:#04x}: {:?}", id, err);
+                            }
+                        }
+                        None => packets.push(packet),
+                    }
+                    for packet in packets.drain(..) {
+                        data.clear();
+                        // Writing to a `Vec` can't fail
+                        match compressed {
+                            true => packet.write_compressed(&mut data).unwrap(),
+                            false => packet.write_uncompressed(&mut data).unwrap(),
+                        }
+                        if let Some(encryptor) = &mut encryptor {
+                            encryption::encrypt(encryptor, &mut data);
+                        }
+                        if stream.write_all(&data).await.is_err() {
+                            return;
+                        }
+                    }
+                    space.add_permits(bytes as usize);
+                }
+                Outgoing::EnableEncryption(new_encryptor) => encryptor = Some(*new_encryptor),
+                Outgoing::SetCompressed(new_compressed) => compressed = new_compressed,
+                Outgoing::Translate(new_translator) => translator = Some(new_translator),
+                Outgoing::Close => break,
+            }
+            // Packets sent together get written together
+            if queue.is_empty() && stream.flush().await.is_err() {
                 return;
             }
         }
+        let _ = stream.shutdown().await;
     }
 
     pub fn receive_packets(&self, alive: &mut bool) -> Vec<Box<dyn ServerBoundPacket>> {
@@ -150,70 +428,84 @@ impl NetworkClient {
     pub fn send_packet(&self, data: &PacketEncoder) {
         // TODO: every call to `send_packet` with the same PacketEncoder will
         // lead to re-encoding the packet. It might be good to cache this.
-        if self.compressed.load(Ordering::Relaxed) {
-            let _ = data.write_compressed(&self.stream);
-        } else {
-            let _ = data.write_uncompressed(&self.stream);
-        }
+        self.queue.push(Outgoing::Packet(data.clone()));
     }
 
     pub fn close_connection(&self) {
-        let _ = self.stream.shutdown(Shutdown::Both);
+        self.queue.push(Outgoing::Close);
+        self.queue.reader.abort();
     }
 }
 
 /// This represents the network portion of a minecraft server
 pub struct NetworkServer {
+    /// Runs the tasks accepting clients and reading and writing their packets
+    _runtime: Runtime,
     client_receiver: mpsc::Receiver<NetworkClient>,
+    translations: Arc<Translations>,
     /// These clients are either in the handshake, login, or ping state, once they shift to play, they will be moved to a plot
     pub handshaking_clients: Vec<HandshakingConn>,
 }
 
 impl NetworkServer {
-    fn listen(bind_address: &str, sender: mpsc::Sender<NetworkClient>) {
-        let listener = TcpListener::bind(bind_address).unwrap();
-
-        for (index, stream) in listener.incoming().enumerate() {
-            let stream = stream.unwrap();
-            let (packet_sender, packet_receiver) = mpsc::channel();
-            let compressed = Arc::new(AtomicBool::new(false));
-            let client_stream = stream.try_clone().unwrap();
-            let client_compressed = compressed.clone();
-            thread::spawn(move || {
-                NetworkClient::listen(client_stream, packet_sender, client_compressed);
-            });
-            sender
-                .send(NetworkClient {
-                    // The index will increment after each client making it unique. We'll just use this as the enitity id.
-                    id: index as u32,
-                    stream,
-                    packets: packet_receiver,
-                    compressed,
-                })
-                .unwrap();
-        }
-    }
-
-    /// Creates a new `NetworkServer`. The server will then start accepting TCP clients.
-    pub fn new(bind_address: String) -> NetworkServer {
+    async fn listen(
+        listener: TcpListener,
+        sender: mpsc::Sender<NetworkClient>,
+        translations: Arc<Translations>,
+    ) {
+        // The id will increment after each client making it unique. We'll just use this as the enitity id.
+        let mut id = 0;
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    warn!("Failed to accept a connection: {}", err);
+                    continue;
+                }
+            };
+            let _ = stream.set_nodelay(true);
+            let client = NetworkClient::spawn(stream, id, SEND_QUEUE_BYTES, translations.clone());
+            if sender.send(client).is_err() {
+                return;
+            }
+            id += 1;
+        }
+    }
+
+    /// Creates a new `NetworkServer`. The server will then start accepting TCP clients, including
+    /// those on the releases `translations` has a translation for.
+    pub fn new(bind_address: String, translations: Translations) -> NetworkServer {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .thread_name("network")
+            .enable_io()
+            .build()
+            .unwrap();
+        let listener = runtime.block_on(TcpListener::bind(bind_address)).unwrap();
         let (sender, receiver) = mpsc::channel();
-        thread::spawn(move || NetworkServer::listen(&bind_address, sender));
+        let translations = Arc::new(translations);
+        runtime.spawn(NetworkServer::listen(
+            listener,
+            sender,
+            translations.clone(),
+        ));
         NetworkServer {
+            _runtime: runtime,
             client_receiver: receiver,
+            translations,
             handshaking_clients: Vec::new(),
         }
     }
 
+    /// Returns true if clients on this protocol version can join, besides those on the one the
+    /// server speaks.
+    pub fn supports_protocol(&self, protocol_version: i32) -> bool {
+        self.translations.supports(protocol_version)
+    }
+
     pub fn update(&mut self) {
         loop {
             match self.client_receiver.try_recv() {
-                Ok(client) => self.handshaking_clients.push(HandshakingConn {
-                    client,
-                    username: None,
-                    uuid: None,
-                    forwarding_message_id: None,
-                    properties: vec![],
-                }),
+                Ok(client) => self.handshaking_clients.push(HandshakingConn::new(client)),
                 Err(mpsc::TryRecvError::Empty) => break,
                 Err(mpsc::TryRecvError::Disconnected) => {
                     panic!("Client receiver channel disconnected!");
@@ -222,3 +514,159 @@ impl NetworkServer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packets::clientbound::{CLoginPluginRequest, ClientBoundPacket};
+    use packets::serverbound::{
+        BungeeCordForwardingData, SHandshake, SLoginStart, ServerBoundPacketHandler,
+    };
+    use packets::PacketEncoderExt;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn frame_lengths() {
+        assert_eq!(frame_length(&[]).unwrap(), None);
+        assert_eq!(frame_length(&[2, 0]).unwrap(), None);
+        assert_eq!(frame_length(&[2, 0, 0, 5]).unwrap(), Some(3));
+        // 200 in two bytes
+        assert_eq!(frame_length(&[0xC8, 0x01]).unwrap(), None);
+        assert_eq!(frame_length(&[0xC8, 0x01, 0x00]).unwrap(), None);
+        assert_eq!(frame_length(&[0xC8, 0x01].repeat(101)).unwrap(), Some(202));
+        assert!(frame_length(&[0x80, 0x80, 0x80, 0x01]).is_err());
+    }
+
+    fn plugin_request(len: usize) -> PacketEncoder {
+        CLoginPluginRequest {
+            message_id: 0,
+            channel: "mchprs:test".to_string(),
+            data: vec![0; len],
+        }
+        .encode()
+    }
+
+    #[test]
+    fn send_queue_overflow() {
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        // This never reads anything, so the send queue fills up once the socket buffers did
+        let _stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let client = runtime.block_on(async {
+            let (stream, _) = listener.accept().await.unwrap();
+            NetworkClient::spawn(stream, 0, 1 << 20, Default::default())
+        });
+
+        let packet = plugin_request(1 << 16);
+        let start = Instant::now();
+        let mut alive = true;
+        while alive {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            client.send_packet(&packet);
+            client.receive_packets(&mut alive);
+        }
+
+        // Nothing is written to the client, so only the size of the packets can fill the queue
+        let task = || runtime.spawn(std::future::pending::<()>());
+        let writer = task();
+        let (sender, _receiver) = tokio_mpsc::unbounded_channel();
+        let queue = PacketQueue {
+            sender,
+            space: Arc::new(Semaphore::new(1 << 16)),
+            reader: task().abort_handle(),
+            writer: writer.abort_handle(),
+        };
+        let (finished, writer_finished) = mpsc::channel();
+        runtime.spawn(async move {
+            let _ = writer.await;
+            let _ = finished.send(());
+        });
+        let small = plugin_request(0);
+        for _ in 0..(1 << 16) / small.data_len() {
+            queue.push(Outgoing::Packet(small.clone()));
+        }
+        // Thousands of packets fit, as long as they are small
+        assert!(queue.space.available_permits() < small.data_len());
+        assert!(!queue.writer.is_finished());
+        queue.push(Outgoing::Packet(small));
+        writer_finished
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap();
+    }
+
+    /// Rejects handshakes without the right BungeeGuard token, like the server does.
+    struct LoginHandler {
+        client: HandshakingConn,
+        logins: usize,
+    }
+
+    impl ServerBoundPacketHandler for LoginHandler {
+        fn handle_handshake(&mut self, handshake: SHandshake, _: usize) {
+            let mut data = BungeeCordForwardingData::parse(&handshake.server_address).unwrap();
+            if !data.verify_bungeeguard_token(&["secret".to_string()]) {
+                self.client.close_connection();
+            }
+        }
+
+        fn handle_login_start(&mut self, _: SLoginStart, _: usize) {
+            self.logins += 1;
+        }
+    }
+
+    fn login(token: &str) -> usize {
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let mut stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut client = runtime.block_on(async {
+            let (stream, _) = listener.accept().await.unwrap();
+            NetworkClient::spawn(stream, 0, SEND_QUEUE_BYTES, Default::default())
+        });
+
+        let mut handshake = Vec::new();
+        handshake.write_varint(0x00);
+        handshake.write_varint(765);
+        let properties = format!(r#"[{{"name":"bungeeguard-token","value":"{}"}}]"#, token);
+        let address = format!(
+            "localhost\0127.0.0.1\0069a79f444e94726a5befca90e38aaf5\0{}",
+            properties
+        );
+        handshake.write_string(32767, &address);
+        handshake.write_unsigned_short(25565);
+        handshake.write_varint(2);
+        let mut login_start = Vec::new();
+        login_start.write_varint(0x00);
+        login_start.write_string(16, "Notch");
+        login_start.write_uuid(0x069a79f444e94726a5befca90e38aaf5);
+        // Clients send both at once
+        let mut data = Vec::new();
+        for packet in [handshake, login_start] {
+            data.write_varint(packet.len() as i32);
+            data.extend(packet);
+        }
+        stream.write_all(&data).unwrap();
+        // Wait until both packets were received, so the login start is already waiting when the
+        // handshake gets rejected
+        let (sender, receiver) = mpsc::channel();
+        let received = std::mem::replace(&mut client.packets, receiver);
+        for _ in 0..2 {
+            let packet = received.recv_timeout(Duration::from_secs(10)).unwrap();
+            sender.send(packet).unwrap();
+        }
+
+        let mut handler = LoginHandler {
+            client: HandshakingConn::new(client),
+            logins: 0,
+        };
+        while let Some(packet) = handler.client.receive_packet() {
+            packet.handle(&mut handler, 0);
+        }
+        handler.logins
+    }
+
+    #[test]
+    fn rejected_login() {
+        assert_eq!(login("secret"), 1);
+        assert_eq!(login("wrong"), 0);
+    }
+}
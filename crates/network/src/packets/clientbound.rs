@@ -57,6 +57,24 @@ impl ClientBoundPacket for CPong {
     }
 }
 
+pub struct CEncryptionRequest {
+    pub server_id: String,
+    pub public_key: Vec<u8>,
+    pub verify_token: Vec<u8>,
+}
+
+impl ClientBoundPacket for CEncryptionRequest {
+    fn encode(&self) -> PacketEncoder {
+        let mut buf = Vec::new();
+        buf.write_string(20, &self.server_id);
+        buf.write_varint(self.public_key.len() as i32);
+        buf.write_bytes(&self.public_key);
+        buf.write_varint(self.verify_token.len() as i32);
+        buf.write_bytes(&self.verify_token);
+        PacketEncoder::new(buf, 0x01)
+    }
+}
+
 pub struct CLoginSuccess {
     pub uuid: u128,
     pub username: String,
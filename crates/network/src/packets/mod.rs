@@ -9,7 +9,7 @@ use flate2::bufread::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use mchprs_text::TextComponent;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serverbound::*;
 use std::io::{self, Cursor, Read, Write};
 use std::net::TcpStream;
@@ -26,7 +26,7 @@ pub struct SlotData {
     pub nbt: Option<NBTCompound>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct PlayerProperty {
     pub name: String,
     pub value: String,
@@ -70,27 +70,26 @@ impl From<std::string::FromUtf8Error> for PacketDecodeError {
 #[derive(Debug)]
 pub enum PacketEncodeError {}
 
-fn read_compressed<T: PacketDecoderExt>(
-    reader: &mut T,
-    network_state: &mut NetworkState,
-) -> DecodeResult<Box<dyn ServerBoundPacket>> {
+fn read_compressed<T: PacketDecoderExt>(reader: &mut T) -> DecodeResult<Vec<u8>> {
     let decompressed_length = reader.read_varint()? as usize;
     let data = PacketDecoderExt::read_to_end(reader)?;
     // `data` is not compressed if `decompressed_length` is 0
     if decompressed_length == 0 {
-        read_decompressed(&mut Cursor::new(data), network_state)
+        Ok(data)
     } else {
         let mut decompresser = ZlibDecoder::new(data.as_slice());
         let mut decompressed_data = Vec::with_capacity(decompressed_length);
         decompresser.read_to_end(&mut decompressed_data)?;
-        read_decompressed(&mut Cursor::new(decompressed_data), network_state)
+        Ok(decompressed_data)
     }
 }
 
-fn read_decompressed<T: PacketDecoderExt>(
-    reader: &mut T,
+/// Decodes a packet from its id followed by its data, as returned by [`read_packet_data`].
+pub fn decode_packet(
+    data: &[u8],
     state: &mut NetworkState,
 ) -> DecodeResult<Box<dyn ServerBoundPacket>> {
+    let reader = &mut Cursor::new(data);
     let packet_id = reader.read_varint()?;
     let packet: Box<dyn ServerBoundPacket> = match *state {
         NetworkState::Handshaking if packet_id == 0x00 => {
@@ -105,6 +104,7 @@ fn read_decompressed<T: PacketDecoderExt>(
         NetworkState::Status if packet_id == 0x00 => Box::new(SRequest::decode(reader)?),
         NetworkState::Status if packet_id == 0x01 => Box::new(SPing::decode(reader)?),
         NetworkState::Login if packet_id == 0x00 => Box::new(SLoginStart::decode(reader)?),
+        NetworkState::Login if packet_id == 0x01 => Box::new(SEncryptionResponse::decode(reader)?),
         NetworkState::Login if packet_id == 0x02 => Box::new(SLoginPluginResponse::decode(reader)?),
         NetworkState::Login if packet_id == 0x03 => {
             *state = NetworkState::Configuration;
@@ -144,21 +144,29 @@ fn read_decompressed<T: PacketDecoderExt>(
     Ok(packet)
 }
 
-pub fn read_packet<T: PacketDecoderExt>(
+/// Reads a packet and returns its id followed by its data, decompressed if necessary.
+pub fn read_packet_data<T: PacketDecoderExt>(
     reader: &mut T,
     compressed: &Arc<AtomicBool>,
-    network_state: &mut NetworkState,
-) -> DecodeResult<Box<dyn ServerBoundPacket>> {
+) -> DecodeResult<Vec<u8>> {
     let length = reader.read_varint()?;
     let data = reader.read_bytes(length as usize)?;
-    let mut cursor = Cursor::new(data);
     if compressed.load(Ordering::Relaxed) {
-        read_compressed(&mut cursor, network_state)
+        read_compressed(&mut Cursor::new(data))
     } else {
-        read_decompressed(&mut cursor, network_state)
+        Ok(data)
     }
 }
 
+pub fn read_packet<T: PacketDecoderExt>(
+    reader: &mut T,
+    compressed: &Arc<AtomicBool>,
+    network_state: &mut NetworkState,
+) -> DecodeResult<Box<dyn ServerBoundPacket>> {
+    let data = read_packet_data(reader, compressed)?;
+    decode_packet(&data, network_state)
+}
+
 impl<T: std::convert::AsRef<[u8]>> PacketDecoderExt for Cursor<T> {}
 impl PacketDecoderExt for TcpStream {}
 
@@ -435,17 +443,23 @@ pub trait PacketEncoderExt: Write {
 
 impl PacketEncoderExt for Vec<u8> {}
 
+#[derive(Clone)]
 pub struct PacketEncoder {
-    buffer: Vec<u8>,
-    packet_id: u32,
+    pub(crate) buffer: Vec<u8>,
+    pub(crate) packet_id: u32,
 }
 
 impl PacketEncoder {
-    fn new(buffer: Vec<u8>, packet_id: u32) -> PacketEncoder {
+    pub(crate) fn new(buffer: Vec<u8>, packet_id: u32) -> PacketEncoder {
         trace!("Encoding packet with id {:#02x}", packet_id);
         PacketEncoder { buffer, packet_id }
     }
 
+    /// The size of the packet data, before it is compressed.
+    pub fn data_len(&self) -> usize {
+        self.buffer.len()
+    }
+
     // This function is separate because it is needed when writing packet headers
     fn varint(val: i32) -> Vec<u8> {
         let mut val = val as u32;
@@ -8,6 +8,7 @@ pub trait ServerBoundPacketHandler {
     fn handle_ping(&mut self, _packet: SPing, _player_idx: usize) {}
     // Login
     fn handle_login_start(&mut self, _packet: SLoginStart, _player_idx: usize) {}
+    fn handle_encryption_response(&mut self, _packet: SEncryptionResponse, _player_idx: usize) {}
     fn handle_login_plugin_response(&mut self, _packet: SLoginPluginResponse, _player_idx: usize) {}
     fn handle_login_acknowledged(&mut self, _packet: SLoginAcknowledged, _player_idx: usize) {}
     // Configuration
@@ -141,6 +142,28 @@ impl ServerBoundPacket for SLoginStart {
     }
 }
 
+#[derive(Debug)]
+pub struct SEncryptionResponse {
+    pub shared_secret: Vec<u8>,
+    pub verify_token: Vec<u8>,
+}
+
+impl ServerBoundPacket for SEncryptionResponse {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let shared_secret_len = decoder.read_varint()?;
+        let shared_secret = decoder.read_bytes(shared_secret_len as usize)?;
+        let verify_token_len = decoder.read_varint()?;
+        Ok(SEncryptionResponse {
+            shared_secret,
+            verify_token: decoder.read_bytes(verify_token_len as usize)?,
+        })
+    }
+
+    fn handle(self: Box<Self>, handler: &mut dyn ServerBoundPacketHandler, player_idx: usize) {
+        handler.handle_encryption_response(*self, player_idx);
+    }
+}
+
 #[derive(Debug)]
 pub struct SLoginPluginResponse {
     pub message_id: i32,
@@ -198,6 +221,61 @@ impl VelocityResponseData {
     }
 }
 
+/// The player info BungeeCord appends to the server address of the handshake when ip forwarding
+/// is enabled, separated by null characters.
+#[derive(Debug)]
+pub struct BungeeCordForwardingData {
+    pub host: String,
+    pub address: String,
+    pub uuid: u128,
+    pub properties: Vec<PlayerProperty>,
+}
+
+impl BungeeCordForwardingData {
+    /// Returns `None` if the server address does not contain forwarded player info.
+    pub fn parse(server_address: &str) -> Option<Self> {
+        let mut parts = server_address.split('\0');
+        let host = parts.next()?.to_string();
+        let address = parts.next()?.to_string();
+        let uuid = u128::from_str_radix(parts.next()?, 16).ok()?;
+        let properties = match parts.next() {
+            Some(properties) => serde_json::from_str(properties).ok()?,
+            None => Vec::new(),
+        };
+        Some(BungeeCordForwardingData {
+            host,
+            address,
+            uuid,
+            properties,
+        })
+    }
+
+    /// Takes the BungeeGuard token out of the properties, so it isn't sent to other players
+    /// along with the rest of them, and checks that it is one of `tokens`. Any token is accepted
+    /// if `tokens` is empty.
+    pub fn verify_bungeeguard_token(&mut self, tokens: &[String]) -> bool {
+        let token_idx = self
+            .properties
+            .iter()
+            .position(|property| property.name == "bungeeguard-token");
+        let token = token_idx.map(|idx| self.properties.remove(idx).value);
+        if tokens.is_empty() {
+            return true;
+        }
+        let Some(token) = token else {
+            return false;
+        };
+        // Every token is compared in full, so how long this takes doesn't give any of them away
+        tokens.iter().fold(false, |valid, expected| {
+            valid | constant_time_eq(token.as_bytes(), expected.as_bytes())
+        })
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
 #[derive(Debug)]
 pub struct SLoginAcknowledged;
 
@@ -686,3 +764,48 @@ impl ServerBoundPacket for SUpdateSign {
         handler.handle_update_sign(*self, player_idx);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bungeecord_forwarding_data() {
+        let data = BungeeCordForwardingData::parse(
+            "localhost\x00127.0.0.1\x00069a79f444e94726a5befca90e38aaf5\x00[{\"name\":\"textures\",\"value\":\"e30=\",\"signature\":\"c2ln\"}]",
+        )
+        .unwrap();
+        assert_eq!(data.host, "localhost");
+        assert_eq!(data.address, "127.0.0.1");
+        assert_eq!(data.uuid, 0x069a79f444e94726a5befca90e38aaf5);
+        assert_eq!(data.properties[0].name, "textures");
+        assert_eq!(data.properties[0].signature.as_deref(), Some("c2ln"));
+
+        let data = BungeeCordForwardingData::parse(
+            "localhost\x00127.0.0.1\x00069a79f444e94726a5befca90e38aaf5",
+        )
+        .unwrap();
+        assert!(data.properties.is_empty());
+
+        assert!(BungeeCordForwardingData::parse("localhost").is_none());
+        assert!(BungeeCordForwardingData::parse("localhost\x00127.0.0.1\x00notch").is_none());
+    }
+
+    #[test]
+    fn bungeeguard_token() {
+        let address = "localhost\x00127.0.0.1\x00069a79f444e94726a5befca90e38aaf5\x00[{\"name\":\"bungeeguard-token\",\"value\":\"secret\"}]";
+        let tokens = ["other".to_string(), "secret".to_string()];
+        let mut data = BungeeCordForwardingData::parse(address).unwrap();
+        assert!(data.verify_bungeeguard_token(&tokens));
+        assert!(data.properties.is_empty());
+
+        let mut data = BungeeCordForwardingData::parse(address).unwrap();
+        assert!(!data.verify_bungeeguard_token(&["secreT".to_string(), "secrets".to_string()]));
+        let mut data = BungeeCordForwardingData::parse(address).unwrap();
+        assert!(data.verify_bungeeguard_token(&[]));
+
+        let address = "localhost\x00127.0.0.1\x00069a79f444e94726a5befca90e38aaf5";
+        let mut data = BungeeCordForwardingData::parse(address).unwrap();
+        assert!(!data.verify_bungeeguard_token(&tokens));
+    }
+}
@@ -0,0 +1,338 @@
+//! Translating packets between the protocol the server speaks and the protocols of newer releases,
+//! so their clients can join as well.
+//!
+//! Every release numbers its block states, items and other registries differently, so ids are
+//! mapped by name using the reports the vanilla server generates with
+//! `java -DbundlerMainClass=net.minecraft.data.Main -jar server.jar --reports`. The `blocks.json` and
+//! `registries.json` reports of each release go into a directory named after it, next to the ones
+//! of the release the server speaks (see [`NATIVE_VERSION`]).
+
+mod raw_nbt;
+mod v1_20_5;
+
+use crate::packets::{DecodeResult, PacketDecoderExt, PacketEncoder};
+use crate::NetworkState;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+/// The release the server speaks, whose reports every translation needs.
+pub const NATIVE_VERSION: &str = "1.20.4";
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("invalid report: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Translates the packets of the clients on one release.
+pub(crate) trait Translation: Send + Sync {
+    /// Translates a packet sent by the client into the one the server understands, or returns
+    /// `None` if the server has no use for it. `state` is the state the packet was received in.
+    fn serverbound(&self, state: &NetworkState, data: Vec<u8>) -> DecodeResult<Option<Vec<u8>>>;
+
+    /// Creates what translates the packets sent to a client, starting in `state`.
+    fn clientbound(self: Arc<Self>, state: NetworkState) -> Box<dyn ClientboundTranslator>;
+}
+
+/// Translates the packets sent to one client, keeping track of what later packets depend on.
+pub(crate) trait ClientboundTranslator: Send {
+    /// Pushes the packets that have to be sent instead of `packet` to `out`.
+    fn translate(
+        &mut self,
+        packet: PacketEncoder,
+        out: &mut Vec<PacketEncoder>,
+    ) -> DecodeResult<()>;
+}
+
+/// The translations for all releases whose reports were found, by protocol version.
+#[derive(Default)]
+pub struct Translations {
+    translations: HashMap<i32, Arc<dyn Translation>>,
+}
+
+impl Translations {
+    /// Loads the translations of every supported release whose reports are in `dir`.
+    pub fn load(dir: impl AsRef<Path>) -> Translations {
+        let dir = dir.as_ref();
+        let mut translations = Translations::default();
+        if !dir.exists() {
+            return translations;
+        }
+        let native = match Reports::load(&dir.join(NATIVE_VERSION)) {
+            Ok(native) => native,
+            Err(err) => {
+                warn!(
+                    "Failed to load the {} reports, so only {} clients can join: {}",
+                    NATIVE_VERSION, NATIVE_VERSION, err
+                );
+                return translations;
+            }
+        };
+
+        let Some(version) = v1_20_5::VERSIONS
+            .into_iter()
+            .find(|version| dir.join(version).exists())
+        else {
+            return translations;
+        };
+        match Reports::load(&dir.join(version)) {
+            Ok(reports) => {
+                translations.translations.insert(
+                    v1_20_5::PROTOCOL_VERSION,
+                    Arc::new(v1_20_5::V1_20_5::new(&native, &reports)),
+                );
+                info!(
+                    "Clients on {} can join through protocol translation",
+                    version
+                );
+            }
+            Err(err) => warn!("Failed to load the {} reports: {}", version, err),
+        }
+        translations
+    }
+
+    pub fn supports(&self, protocol_version: i32) -> bool {
+        self.translations.contains_key(&protocol_version)
+    }
+
+    /// Returns the translation for the client that sent this handshake, if it is on a release the
+    /// server does not speak itself.
+    pub(crate) fn for_handshake(&self, data: &[u8]) -> Option<Arc<dyn Translation>> {
+        let mut reader = Cursor::new(data);
+        match reader.read_varint() {
+            Ok(0x00) => {}
+            _ => return None,
+        }
+        let protocol_version = reader.read_varint().ok()?;
+        self.translations.get(&protocol_version).cloned()
+    }
+}
+
+#[derive(Deserialize)]
+struct BlockReport {
+    states: Vec<BlockStateReport>,
+}
+
+#[derive(Deserialize)]
+struct BlockStateReport {
+    id: u32,
+    #[serde(default)]
+    default: bool,
+    #[serde(default)]
+    properties: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct RegistryReport {
+    default: Option<String>,
+    entries: HashMap<String, RegistryEntryReport>,
+}
+
+#[derive(Deserialize)]
+struct RegistryEntryReport {
+    protocol_id: u32,
+}
+
+/// The reports of one release.
+pub(crate) struct Reports {
+    blocks: HashMap<String, BlockReport>,
+    registries: HashMap<String, RegistryReport>,
+}
+
+impl Reports {
+    fn load(dir: &Path) -> Result<Reports, ReportError> {
+        let blocks = fs::read_to_string(dir.join("blocks.json"))?;
+        let registries = fs::read_to_string(dir.join("registries.json"))?;
+        Reports::parse(&blocks, &registries)
+    }
+
+    fn parse(blocks: &str, registries: &str) -> Result<Reports, ReportError> {
+        Ok(Reports {
+            blocks: serde_json::from_str(blocks)?,
+            registries: serde_json::from_str(registries)?,
+        })
+    }
+
+    fn block_state_count(&self) -> usize {
+        self.blocks
+            .values()
+            .flat_map(|block| &block.states)
+            .map(|state| state.id as usize + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the names of the entries of a registry, by id.
+    fn registry_names(&self, registry: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        if let Some(report) = self.registries.get(registry) {
+            names.resize(report.entries.len(), String::new());
+            for (name, entry) in &report.entries {
+                if let Some(slot) = names.get_mut(entry.protocol_id as usize) {
+                    *slot = name.clone();
+                }
+            }
+        }
+        names
+    }
+}
+
+/// Maps the ids of one release to those of another. Ids without an equivalent are mapped to a
+/// fallback, such as air for block states.
+#[derive(Debug, Default)]
+pub(crate) struct IdMap {
+    ids: Vec<u32>,
+    fallback: u32,
+}
+
+impl IdMap {
+    pub(crate) fn get(&self, id: i32) -> i32 {
+        let id = usize::try_from(id).ok().and_then(|id| self.ids.get(id));
+        *id.unwrap_or(&self.fallback) as i32
+    }
+
+    /// Maps the entries of a registry by name.
+    fn registry(from: &Reports, to: &Reports, registry: &str) -> IdMap {
+        let (Some(from), Some(to)) = (from.registries.get(registry), to.registries.get(registry))
+        else {
+            warn!("The reports are missing the {} registry", registry);
+            return IdMap::default();
+        };
+        let fallback = to
+            .default
+            .as_ref()
+            .and_then(|name| to.entries.get(name))
+            .map_or(0, |entry| entry.protocol_id);
+        let mut ids = vec![fallback; from.entries.len()];
+        let mut missing = 0;
+        for (name, entry) in &from.entries {
+            let Some(id) = ids.get_mut(entry.protocol_id as usize) else {
+                continue;
+            };
+            match to.entries.get(name) {
+                Some(to) => *id = to.protocol_id,
+                None => missing += 1,
+            }
+        }
+        if missing > 0 {
+            debug!("{} entries of {} have no equivalent", missing, registry);
+        }
+        IdMap { ids, fallback }
+    }
+
+    /// Maps block states by the name of their block and their properties. Properties that only
+    /// exist in `to` take the value they have in the default state of the block there.
+    fn block_states(from: &Reports, to: &Reports) -> IdMap {
+        let mut ids = vec![0; from.block_state_count()];
+        let mut missing = 0;
+        for (name, block) in &from.blocks {
+            let Some(to_block) = to.blocks.get(name) else {
+                missing += block.states.len();
+                continue;
+            };
+            let to_default = to_block
+                .states
+                .iter()
+                .find(|state| state.default)
+                .unwrap_or(&to_block.states[0]);
+            let to_states: HashMap<&BTreeMap<String, String>, u32> = to_block
+                .states
+                .iter()
+                .map(|state| (&state.properties, state.id))
+                .collect();
+            for state in &block.states {
+                let mut properties = to_default.properties.clone();
+                for (property, value) in properties.iter_mut() {
+                    if let Some(from_value) = state.properties.get(property) {
+                        value.clone_from(from_value);
+                    }
+                }
+                ids[state.id as usize] = match to_states.get(&properties) {
+                    Some(&id) => id,
+                    None => {
+                        missing += 1;
+                        to_default.id
+                    }
+                };
+            }
+        }
+        if missing > 0 {
+            debug!("{} block states have no equivalent", missing);
+        }
+        IdMap { ids, fallback: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) const NATIVE_BLOCKS: &str = r#"{
+        "minecraft:air": { "states": [{ "id": 0, "default": true }] },
+        "minecraft:lever": { "states": [
+            { "id": 1, "properties": { "powered": "true" } },
+            { "id": 2, "default": true, "properties": { "powered": "false" } }
+        ] },
+        "minecraft:grass": { "states": [{ "id": 3, "default": true }] }
+    }"#;
+
+    pub(super) const BLOCKS: &str = r#"{
+        "minecraft:air": { "states": [{ "id": 0, "default": true }] },
+        "minecraft:short_grass": { "states": [{ "id": 1, "default": true }] },
+        "minecraft:lever": { "states": [
+            { "id": 2, "properties": { "powered": "true", "waterlogged": "true" } },
+            { "id": 3, "properties": { "powered": "true", "waterlogged": "false" } },
+            { "id": 4, "properties": { "powered": "false", "waterlogged": "true" } },
+            { "id": 5, "default": true, "properties": { "powered": "false", "waterlogged": "false" } }
+        ] }
+    }"#;
+
+    pub(super) const NATIVE_REGISTRIES: &str = r#"{
+        "minecraft:item": { "default": "minecraft:air", "entries": {
+            "minecraft:air": { "protocol_id": 0 },
+            "minecraft:stone": { "protocol_id": 1 },
+            "minecraft:redstone": { "protocol_id": 2 }
+        } }
+    }"#;
+
+    pub(super) const REGISTRIES: &str = r#"{
+        "minecraft:item": { "default": "minecraft:air", "entries": {
+            "minecraft:air": { "protocol_id": 0 },
+            "minecraft:armadillo_scute": { "protocol_id": 1 },
+            "minecraft:redstone": { "protocol_id": 2 },
+            "minecraft:stone": { "protocol_id": 3 }
+        } }
+    }"#;
+
+    #[test]
+    fn block_state_map() {
+        let native = Reports::parse(NATIVE_BLOCKS, "{}").unwrap();
+        let reports = Reports::parse(BLOCKS, "{}").unwrap();
+        let map = IdMap::block_states(&native, &reports);
+        // The new property takes its default value
+        assert_eq!(map.get(1), 3);
+        assert_eq!(map.get(2), 5);
+        // Renamed blocks become air
+        assert_eq!(map.get(3), 0);
+        assert_eq!(map.get(100), 0);
+    }
+
+    #[test]
+    fn registry_map() {
+        let native = Reports::parse("{}", NATIVE_REGISTRIES).unwrap();
+        let reports = Reports::parse("{}", REGISTRIES).unwrap();
+        let map = IdMap::registry(&native, &reports, "minecraft:item");
+        assert_eq!([0, 1, 2, 3].map(|id| map.get(id)), [0, 3, 2, 0]);
+        let map = IdMap::registry(&reports, &native, "minecraft:item");
+        assert_eq!([0, 1, 2, 3].map(|id| map.get(id)), [0, 0, 2, 1]);
+    }
+}
@@ -0,0 +1,159 @@
+//! Walking NBT without decoding it, to pass it along or pick parts of it out as they are.
+
+use crate::packets::{DecodeResult, PacketDecodeError, PacketDecoderExt};
+use std::io::{self, Cursor};
+
+const TAG_END: u8 = 0;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+
+fn invalid(message: &str) -> PacketDecodeError {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string()).into()
+}
+
+fn skip(reader: &mut Cursor<&[u8]>, bytes: usize) -> DecodeResult<()> {
+    let pos = reader.position() as usize + bytes;
+    if pos > reader.get_ref().len() {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    reader.set_position(pos as u64);
+    Ok(())
+}
+
+fn read_length(reader: &mut Cursor<&[u8]>) -> DecodeResult<usize> {
+    usize::try_from(reader.read_int()?).map_err(|_| invalid("negative nbt length"))
+}
+
+/// Reads a string, which is also how names are written.
+fn read_string(reader: &mut Cursor<&[u8]>) -> DecodeResult<String> {
+    let length = reader.read_unsigned_short()?;
+    let bytes = reader.read_bytes(length as usize)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn skip_payload(reader: &mut Cursor<&[u8]>, tag: u8) -> DecodeResult<()> {
+    match tag {
+        1 => skip(reader, 1),
+        2 => skip(reader, 2),
+        3 | 5 => skip(reader, 4),
+        4 | 6 => skip(reader, 8),
+        7 => {
+            let length = read_length(reader)?;
+            skip(reader, length)
+        }
+        TAG_STRING => {
+            let length = reader.read_unsigned_short()?;
+            skip(reader, length as usize)
+        }
+        TAG_LIST => {
+            let tag = reader.read_unsigned_byte()?;
+            for _ in 0..read_length(reader)? {
+                skip_payload(reader, tag)?;
+            }
+            Ok(())
+        }
+        TAG_COMPOUND => loop {
+            let tag = reader.read_unsigned_byte()?;
+            if tag == TAG_END {
+                return Ok(());
+            }
+            read_string(reader)?;
+            skip_payload(reader, tag)?;
+        },
+        11 => {
+            let length = read_length(reader)?;
+            skip(reader, length * 4)
+        }
+        12 => {
+            let length = read_length(reader)?;
+            skip(reader, length * 8)
+        }
+        _ => Err(invalid("invalid nbt tag")),
+    }
+}
+
+/// Skips a tag as it is sent over the network, without a name.
+pub(super) fn skip_nbt(reader: &mut Cursor<&[u8]>) -> DecodeResult<()> {
+    let tag = reader.read_unsigned_byte()?;
+    match tag {
+        TAG_END => Ok(()),
+        tag => skip_payload(reader, tag),
+    }
+}
+
+/// An entry of a registry sent in the registry data.
+pub(super) struct RegistryEntry {
+    pub name: String,
+    /// The compound describing the entry, as it is sent over the network
+    pub element: Vec<u8>,
+}
+
+/// Reads the registries out of the compound sent in the registry data, which holds a compound for
+/// each registry with its entries in `value`.
+pub(super) fn read_registries(data: &[u8]) -> DecodeResult<Vec<(String, Vec<RegistryEntry>)>> {
+    let reader = &mut Cursor::new(data);
+    if reader.read_unsigned_byte()? != TAG_COMPOUND {
+        return Err(invalid("registry data is not a compound"));
+    }
+    let mut registries = Vec::new();
+    loop {
+        let tag = reader.read_unsigned_byte()?;
+        if tag == TAG_END {
+            return Ok(registries);
+        }
+        let name = read_string(reader)?;
+        if tag != TAG_COMPOUND {
+            skip_payload(reader, tag)?;
+            continue;
+        }
+        let mut entries = Vec::new();
+        loop {
+            let tag = reader.read_unsigned_byte()?;
+            if tag == TAG_END {
+                break;
+            }
+            let field = read_string(reader)?;
+            if field != "value" || tag != TAG_LIST {
+                skip_payload(reader, tag)?;
+                continue;
+            }
+            let entry_tag = reader.read_unsigned_byte()?;
+            let length = read_length(reader)?;
+            if length > 0 && entry_tag != TAG_COMPOUND {
+                return Err(invalid("registry entries are not compounds"));
+            }
+            for _ in 0..length {
+                entries.push(read_registry_entry(reader)?);
+            }
+        }
+        registries.push((name, entries));
+    }
+}
+
+fn read_registry_entry(reader: &mut Cursor<&[u8]>) -> DecodeResult<RegistryEntry> {
+    let mut name = None;
+    let mut element = None;
+    loop {
+        let tag = reader.read_unsigned_byte()?;
+        if tag == TAG_END {
+            break;
+        }
+        match (tag, read_string(reader)?.as_str()) {
+            (TAG_STRING, "name") => name = Some(read_string(reader)?),
+            (TAG_COMPOUND, "element") => {
+                let start = reader.position() as usize;
+                skip_payload(reader, TAG_COMPOUND)?;
+                let end = reader.position() as usize;
+                let mut data = vec![TAG_COMPOUND];
+                data.extend_from_slice(&reader.get_ref()[start..end]);
+                element = Some(data);
+            }
+            (tag, _) => skip_payload(reader, tag)?,
+        }
+    }
+    match (name, element) {
+        (Some(name), Some(element)) => Ok(RegistryEntry { name, element }),
+        _ => Err(invalid("registry entry without name or element")),
+    }
+}
@@ -0,0 +1,872 @@
+//! Translation for 1.20.5 and 1.20.6 clients, which speak the same protocol.
+//!
+//! Besides renumbering packets, these releases split the registry data into a packet for every
+//! registry and replaced item NBT with data components. Items lose their NBT on the way, since
+//! there is nothing to translate it into without knowing what every tag means.
+
+use super::raw_nbt::{read_registries, skip_nbt, RegistryEntry};
+use super::{ClientboundTranslator, IdMap, Reports, Translation};
+use crate::packets::{DecodeResult, PacketDecoderExt, PacketEncoder, PacketEncoderExt};
+use crate::NetworkState;
+use std::io::Cursor;
+use std::sync::Arc;
+
+pub(super) const PROTOCOL_VERSION: i32 = 766;
+/// The releases speaking this protocol, the reports of either will do.
+pub(super) const VERSIONS: [&str; 2] = ["1.20.6", "1.20.5"];
+
+type Reader<'a> = Cursor<&'a [u8]>;
+
+pub(super) struct V1_20_5 {
+    block_states: IdMap,
+    items: IdMap,
+    /// Maps the items the client sends back
+    items_from_client: IdMap,
+    entity_types: IdMap,
+    block_entity_types: IdMap,
+    sounds: IdMap,
+    menus: IdMap,
+    argument_types: IdMap,
+    /// The names of the command argument types of the server by id, which tell what properties
+    /// follow them
+    argument_type_names: Vec<String>,
+    /// Bits per entry of chunk sections using the global palette
+    global_palette_bits: u8,
+}
+
+impl V1_20_5 {
+    pub(super) fn new(native: &Reports, reports: &Reports) -> V1_20_5 {
+        let block_state_count = reports.block_state_count().max(2);
+        V1_20_5 {
+            block_states: IdMap::block_states(native, reports),
+            items: IdMap::registry(native, reports, "minecraft:item"),
+            items_from_client: IdMap::registry(reports, native, "minecraft:item"),
+            entity_types: IdMap::registry(native, reports, "minecraft:entity_type"),
+            block_entity_types: IdMap::registry(native, reports, "minecraft:block_entity_type"),
+            sounds: IdMap::registry(native, reports, "minecraft:sound_event"),
+            menus: IdMap::registry(native, reports, "minecraft:menu"),
+            argument_types: IdMap::registry(native, reports, "minecraft:command_argument_type"),
+            argument_type_names: native.registry_names("minecraft:command_argument_type"),
+            global_palette_bits: (usize::BITS - (block_state_count - 1).leading_zeros()) as u8,
+        }
+    }
+
+    /// The old chat command packet, which is only used for signed commands now.
+    fn chat_command(&self, reader: &mut Reader) -> DecodeResult<Vec<u8>> {
+        let command = reader.read_string()?;
+        let mut buf = Vec::new();
+        buf.write_varint(0x04);
+        buf.write_string(32767, &command);
+        buf.write_long(0); // Timestamp
+        buf.write_long(0); // Salt
+        buf.write_varint(0); // Argument signatures
+        buf.write_varint(0); // Message count
+        buf.write_bytes(&[0; 3]); // Acknowledged
+        Ok(buf)
+    }
+
+    fn creative_mode_slot(&self, reader: &mut Reader) -> DecodeResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        buf.write_varint(0x2F);
+        buf.write_short(reader.read_short()?);
+        let count = reader.read_varint()?;
+        if count > 0 {
+            buf.write_bool(true);
+            buf.write_varint(self.items_from_client.get(reader.read_varint()?));
+            buf.write_byte(count as i8);
+            // The components that follow are dropped
+            buf.write_byte(0);
+        } else {
+            buf.write_bool(false);
+        }
+        Ok(buf)
+    }
+
+    fn slot(&self, r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+        if !r.read_bool()? {
+            w.write_varint(0);
+            return Ok(());
+        }
+        let item = r.read_varint()?;
+        let count = r.read_byte()?;
+        skip_nbt(r)?;
+        if count <= 0 {
+            w.write_varint(0);
+            return Ok(());
+        }
+        w.write_varint(count as i32);
+        w.write_varint(self.items.get(item));
+        // No components to add or remove
+        w.write_varint(0);
+        w.write_varint(0);
+        Ok(())
+    }
+
+    fn commands(&self, r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+        let count = copy_varint(r, w)?;
+        for _ in 0..count {
+            let flags = r.read_byte()?;
+            w.write_byte(flags);
+            let children = copy_varint(r, w)?;
+            for _ in 0..children {
+                copy_varint(r, w)?;
+            }
+            if flags & 0x08 != 0 {
+                // Redirect
+                copy_varint(r, w)?;
+            }
+            let node_type = flags & 0x03;
+            if node_type != 0 {
+                copy_string(r, w)?;
+            }
+            if node_type == 2 {
+                let parser = r.read_varint()?;
+                w.write_varint(self.argument_types.get(parser));
+                let name = self
+                    .argument_type_names
+                    .get(parser as usize)
+                    .map_or("", String::as_str);
+                copy_parser_properties(name, r, w)?;
+            }
+            if flags & 0x10 != 0 {
+                // Suggestions type
+                copy_string(r, w)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn chunk_data(&self, r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+        copy(r, w, 8)?;
+        copy_nbt(r, w)?;
+        let size = r.read_varint()?;
+        let sections = r.read_bytes(size as usize)?;
+        let sections_reader = &mut Cursor::new(sections.as_slice());
+        let mut data = Vec::with_capacity(sections.len());
+        while (sections_reader.position() as usize) < sections.len() {
+            copy(sections_reader, &mut data, 2)?;
+            self.block_states_container(sections_reader, &mut data)?;
+            copy_biomes_container(sections_reader, &mut data)?;
+        }
+        w.write_varint(data.len() as i32);
+        w.write_bytes(&data);
+
+        let block_entities = copy_varint(r, w)?;
+        for _ in 0..block_entities {
+            copy(r, w, 3)?;
+            w.write_varint(self.block_entity_types.get(r.read_varint()?));
+            copy_nbt(r, w)?;
+        }
+        Ok(())
+    }
+
+    fn block_states_container(&self, r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+        let bits = r.read_unsigned_byte()?;
+        match bits {
+            0 => {
+                w.write_unsigned_byte(0);
+                w.write_varint(self.block_states.get(r.read_varint()?));
+                copy_longs(r, w)
+            }
+            1..=8 => {
+                w.write_unsigned_byte(bits);
+                let palette_len = copy_varint(r, w)?;
+                for _ in 0..palette_len {
+                    w.write_varint(self.block_states.get(r.read_varint()?));
+                }
+                copy_longs(r, w)
+            }
+            _ => {
+                // The global palette, which has to be repacked if it needs more bits now
+                let len = r.read_varint()?;
+                let mut longs = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    longs.push(r.read_long()? as u64);
+                }
+                let states: Vec<u32> = unpack(&longs, bits)
+                    .map(|state| self.block_states.get(state as i32) as u32)
+                    .collect();
+                let longs = pack(&states, self.global_palette_bits);
+                w.write_unsigned_byte(self.global_palette_bits);
+                w.write_varint(longs.len() as i32);
+                for long in longs {
+                    w.write_long(long as i64);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn entity_metadata(&self, r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+        copy_varint(r, w)?;
+        loop {
+            let start = w.len();
+            let index = r.read_unsigned_byte()?;
+            w.write_unsigned_byte(index);
+            if index == 0xFF {
+                return Ok(());
+            }
+            let ty = r.read_varint()?;
+            w.write_varint(metadata_type(ty));
+            match ty {
+                0 | 8 => copy(r, w, 1)?,
+                1 | 12 | 19..=22 | 24 | 25 => {
+                    copy_varint(r, w)?;
+                }
+                2 => w.write_varlong(r.read_varlong()?),
+                3 => copy(r, w, 4)?,
+                4 => copy_string(r, w)?,
+                5 | 16 => copy_nbt(r, w)?,
+                6 => {
+                    if copy_bool(r, w)? {
+                        copy_nbt(r, w)?;
+                    }
+                }
+                7 => self.slot(r, w)?,
+                9 | 26 => copy(r, w, 12)?,
+                10 => copy(r, w, 8)?,
+                11 => {
+                    if copy_bool(r, w)? {
+                        copy(r, w, 8)?;
+                    }
+                }
+                13 => {
+                    if copy_bool(r, w)? {
+                        copy(r, w, 16)?;
+                    }
+                }
+                14 | 15 => w.write_varint(self.block_states.get(r.read_varint()?)),
+                18 => {
+                    for _ in 0..3 {
+                        copy_varint(r, w)?;
+                    }
+                }
+                23 => {
+                    if copy_bool(r, w)? {
+                        copy_string(r, w)?;
+                        copy(r, w, 8)?;
+                    }
+                }
+                27 => copy(r, w, 16)?,
+                // Particles were reworked, so everything from here on is left out
+                _ => {
+                    w.truncate(start);
+                    w.write_unsigned_byte(0xFF);
+                    r.set_position(r.get_ref().len() as u64);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl Translation for V1_20_5 {
+    fn serverbound(&self, state: &NetworkState, data: Vec<u8>) -> DecodeResult<Option<Vec<u8>>> {
+        let reader = &mut Cursor::new(data.as_slice());
+        let id = reader.read_varint()?;
+        let id = match state {
+            NetworkState::Handshaking | NetworkState::Status => id,
+            NetworkState::Login => match id {
+                // Cookie response
+                0x04 => return Ok(None),
+                _ => id,
+            },
+            NetworkState::Configuration => match id {
+                0x00 => id,
+                0x02..=0x06 => id - 1,
+                // Cookie response and known packs
+                _ => return Ok(None),
+            },
+            NetworkState::Play => match id {
+                0x00..=0x03 => id,
+                0x04 => return self.chat_command(reader).map(Some),
+                // Signed chat command
+                0x05 => 0x04,
+                0x06..=0x10 => id - 1,
+                // Cookie response
+                0x11 => return Ok(None),
+                0x12 => 0x10,
+                // Debug sample subscription
+                0x13 => return Ok(None),
+                0x32 => return self.creative_mode_slot(reader).map(Some),
+                _ => id - 3,
+            },
+        };
+        let mut buf = Vec::new();
+        buf.write_varint(id);
+        copy_rest(reader, &mut buf);
+        Ok(Some(buf))
+    }
+
+    fn clientbound(self: Arc<Self>, state: NetworkState) -> Box<dyn ClientboundTranslator> {
+        Box::new(Clientbound {
+            translation: self,
+            state,
+            dimension_types: Vec::new(),
+        })
+    }
+}
+
+struct Clientbound {
+    translation: Arc<V1_20_5>,
+    state: NetworkState,
+    /// The names of the dimension types in the order they were sent, which is how the client
+    /// refers to them now
+    dimension_types: Vec<String>,
+}
+
+impl Clientbound {
+    fn registry_data(&mut self, data: &[u8], out: &mut Vec<PacketEncoder>) -> DecodeResult<()> {
+        let mut registries = read_registries(data)?;
+        for (registry, entries) in &mut registries {
+            match registry.as_str() {
+                "minecraft:dimension_type" => {
+                    self.dimension_types = entries.iter().map(|entry| entry.name.clone()).collect();
+                }
+                // Campfires got a damage type of their own
+                "minecraft:damage_type"
+                    if !entries
+                        .iter()
+                        .any(|entry| entry.name == "minecraft:campfire") =>
+                {
+                    entries.push(RegistryEntry {
+                        name: "minecraft:campfire".to_string(),
+                        element: compound([
+                            ("message_id", nbt::Value::String("generic".to_string())),
+                            ("scaling", nbt::Value::String("always".to_string())),
+                            ("exhaustion", nbt::Value::Float(0.0)),
+                        ]),
+                    });
+                }
+                _ => {}
+            }
+        }
+        // The client needs at least one wolf variant, even if there are no wolves
+        let texture = |name: &str| nbt::Value::String(format!("minecraft:entity/wolf/{}", name));
+        registries.push((
+            "minecraft:wolf_variant".to_string(),
+            vec![RegistryEntry {
+                name: "minecraft:pale".to_string(),
+                element: compound([
+                    ("wild_texture", texture("wolf")),
+                    ("tame_texture", texture("wolf_tame")),
+                    ("angry_texture", texture("wolf_angry")),
+                    ("biomes", nbt::Value::String("minecraft:taiga".to_string())),
+                ]),
+            }],
+        ));
+
+        for (registry, entries) in registries {
+            let mut buf = Vec::new();
+            buf.write_string(32767, &registry);
+            buf.write_varint(entries.len() as i32);
+            for entry in entries {
+                buf.write_string(32767, &entry.name);
+                buf.write_bool(true);
+                buf.write_bytes(&entry.element);
+            }
+            out.push(PacketEncoder::new(buf, 0x07));
+        }
+        Ok(())
+    }
+
+    fn login(&self, r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+        copy(r, w, 5)?;
+        let dimensions = copy_varint(r, w)?;
+        for _ in 0..dimensions {
+            copy_string(r, w)?;
+        }
+        for _ in 0..3 {
+            copy_varint(r, w)?;
+        }
+        copy(r, w, 3)?;
+        let dimension_type = r.read_string()?;
+        let dimension_type = self
+            .dimension_types
+            .iter()
+            .position(|name| *name == dimension_type)
+            .unwrap_or(0);
+        w.write_varint(dimension_type as i32);
+        copy_rest(r, w);
+        // Enforces secure chat
+        w.write_bool(false);
+        Ok(())
+    }
+
+    fn play(&self, packet: &PacketEncoder) -> DecodeResult<PacketEncoder> {
+        let translation = &*self.translation;
+        let id = packet.packet_id;
+        let r = &mut Cursor::new(packet.buffer.as_slice());
+        let mut buf = Vec::with_capacity(packet.buffer.len());
+        let w = &mut buf;
+        match id {
+            // Spawn entity
+            0x01 => {
+                copy_varint(r, w)?;
+                copy(r, w, 16)?;
+                w.write_varint(translation.entity_types.get(r.read_varint()?));
+            }
+            // Block entity data
+            0x07 => {
+                copy(r, w, 8)?;
+                w.write_varint(translation.block_entity_types.get(r.read_varint()?));
+            }
+            // Block update
+            0x09 => {
+                copy(r, w, 8)?;
+                w.write_varint(translation.block_states.get(r.read_varint()?));
+            }
+            0x11 => translation.commands(r, w)?,
+            // Set container content
+            0x13 => {
+                copy(r, w, 1)?;
+                copy_varint(r, w)?;
+                let count = copy_varint(r, w)?;
+                // And the carried item
+                for _ in 0..=count {
+                    translation.slot(r, w)?;
+                }
+            }
+            // Set container slot
+            0x15 => {
+                copy(r, w, 1)?;
+                copy_varint(r, w)?;
+                copy(r, w, 2)?;
+                translation.slot(r, w)?;
+            }
+            0x25 => translation.chunk_data(r, w)?,
+            // World event
+            0x26 => {
+                let event = r.read_int()?;
+                w.write_int(event);
+                copy(r, w, 8)?;
+                let data = r.read_int()?;
+                // Block break effects carry the block state
+                match event {
+                    2001 => w.write_int(translation.block_states.get(data)),
+                    _ => w.write_int(data),
+                }
+            }
+            0x29 => self.login(r, w)?,
+            // Open screen
+            0x31 => {
+                copy_varint(r, w)?;
+                w.write_varint(translation.menus.get(r.read_varint()?));
+            }
+            // Update section blocks
+            0x47 => {
+                copy(r, w, 8)?;
+                let count = copy_varint(r, w)?;
+                for _ in 0..count {
+                    let entry = r.read_varlong()?;
+                    let state = translation.block_states.get((entry >> 12) as i32);
+                    w.write_varlong(((state as i64) << 12) | (entry & 0xFFF));
+                }
+            }
+            0x56 => translation.entity_metadata(r, w)?,
+            // Set equipment
+            0x59 => {
+                copy_varint(r, w)?;
+                loop {
+                    let slot = r.read_byte()?;
+                    w.write_byte(slot);
+                    translation.slot(r, w)?;
+                    // The top bit is set on all but the last entry
+                    if slot >= 0 {
+                        break;
+                    }
+                }
+            }
+            // Sound effect
+            0x66 => match r.read_varint()? {
+                // The sound follows by name
+                0 => w.write_varint(0),
+                sound => w.write_varint(translation.sounds.get(sound - 1) + 1),
+            },
+            _ => {}
+        }
+        copy_rest(r, w);
+        Ok(PacketEncoder::new(buf, play_id(id)))
+    }
+}
+
+impl ClientboundTranslator for Clientbound {
+    fn translate(
+        &mut self,
+        packet: PacketEncoder,
+        out: &mut Vec<PacketEncoder>,
+    ) -> DecodeResult<()> {
+        let id = packet.packet_id;
+        match self.state {
+            NetworkState::Handshaking | NetworkState::Status => out.push(packet),
+            NetworkState::Login => {
+                let mut buf = packet.buffer;
+                match id {
+                    // Encryption request, the client only authenticates if it is told to
+                    0x01 => buf.write_bool(true),
+                    // Login success
+                    0x02 => {
+                        // Strict error handling
+                        buf.write_bool(false);
+                        self.state = NetworkState::Configuration;
+                    }
+                    _ => {}
+                }
+                out.push(PacketEncoder::new(buf, id));
+            }
+            NetworkState::Configuration => match id {
+                0x05 => self.registry_data(&packet.buffer, out)?,
+                _ => {
+                    if id == 0x02 {
+                        self.state = NetworkState::Play;
+                    }
+                    out.push(PacketEncoder::new(packet.buffer, config_id(id)));
+                }
+            },
+            NetworkState::Play => out.push(self.play(&packet)?),
+        }
+        Ok(())
+    }
+}
+
+fn config_id(id: u32) -> u32 {
+    match id {
+        0x00..=0x04 => id + 1,
+        0x05 => 0x07,
+        0x06 | 0x07 => id + 2,
+        _ => id + 4,
+    }
+}
+
+fn play_id(id: u32) -> u32 {
+    match id {
+        0x00..=0x15 => id,
+        0x16..=0x19 => id + 1,
+        0x1A..=0x68 => id + 2,
+        0x69..=0x6F => id + 3,
+        _ => id + 4,
+    }
+}
+
+/// New metadata types were added for particle lists, wolf variants and armadillo states.
+fn metadata_type(ty: i32) -> i32 {
+    match ty {
+        0..=17 => ty,
+        18..=21 => ty + 1,
+        22..=25 => ty + 2,
+        _ => ty + 3,
+    }
+}
+
+/// Copies the properties of a command argument, which depend on its type.
+fn copy_parser_properties(name: &str, r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+    match name {
+        "brigadier:float" | "brigadier:integer" | "brigadier:double" | "brigadier:long" => {
+            let size = match name {
+                "brigadier:double" | "brigadier:long" => 8,
+                _ => 4,
+            };
+            let flags = r.read_byte()?;
+            w.write_byte(flags);
+            // The minimum and maximum follow if they are set
+            let bounds = (flags & 0x01) + ((flags >> 1) & 0x01);
+            copy(r, w, size * bounds as usize)
+        }
+        "brigadier:string" => copy_varint(r, w).map(drop),
+        "minecraft:entity" | "minecraft:score_holder" => copy(r, w, 1),
+        "minecraft:time" => copy(r, w, 4),
+        "minecraft:resource_or_tag"
+        | "minecraft:resource_or_tag_key"
+        | "minecraft:resource"
+        | "minecraft:resource_key" => copy_string(r, w),
+        _ => Ok(()),
+    }
+}
+
+fn copy_biomes_container(r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+    let bits = r.read_unsigned_byte()?;
+    w.write_unsigned_byte(bits);
+    match bits {
+        0 => {
+            copy_varint(r, w)?;
+        }
+        1..=3 => {
+            let palette_len = copy_varint(r, w)?;
+            for _ in 0..palette_len {
+                copy_varint(r, w)?;
+            }
+        }
+        _ => {}
+    }
+    copy_longs(r, w)
+}
+
+/// Unpacks the 4096 entries of a paletted container.
+fn unpack(longs: &[u64], bits: u8) -> impl Iterator<Item = u32> + '_ {
+    let per_long = 64 / bits as usize;
+    let mask = (1 << bits) - 1;
+    (0..4096).map(move |i| {
+        let long = longs.get(i / per_long).copied().unwrap_or(0);
+        ((long >> ((i % per_long) * bits as usize)) & mask) as u32
+    })
+}
+
+fn pack(entries: &[u32], bits: u8) -> Vec<u64> {
+    let per_long = 64 / bits as usize;
+    let mut longs = vec![0; entries.len().div_ceil(per_long)];
+    for (i, &entry) in entries.iter().enumerate() {
+        longs[i / per_long] |= (entry as u64) << ((i % per_long) * bits as usize);
+    }
+    longs
+}
+
+/// Encodes a compound the way it is sent over the network.
+fn compound<const N: usize>(fields: [(&str, nbt::Value); N]) -> Vec<u8> {
+    let compound = nbt::Value::Compound(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    );
+    let mut buf = vec![compound.id()];
+    // Writing to a `Vec` can't fail
+    compound.to_writer(&mut buf).unwrap();
+    buf
+}
+
+fn copy(r: &mut Reader, w: &mut Vec<u8>, bytes: usize) -> DecodeResult<()> {
+    w.write_bytes(&r.read_bytes(bytes)?);
+    Ok(())
+}
+
+fn copy_bool(r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<bool> {
+    let value = r.read_bool()?;
+    w.write_bool(value);
+    Ok(value)
+}
+
+fn copy_varint(r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<i32> {
+    let value = r.read_varint()?;
+    w.write_varint(value);
+    Ok(value)
+}
+
+fn copy_string(r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+    let value = r.read_string()?;
+    w.write_string(32767, &value);
+    Ok(())
+}
+
+fn copy_longs(r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+    let len = copy_varint(r, w)?;
+    copy(r, w, len as usize * 8)
+}
+
+fn copy_nbt(r: &mut Reader, w: &mut Vec<u8>) -> DecodeResult<()> {
+    let start = r.position() as usize;
+    skip_nbt(r)?;
+    w.write_bytes(&r.get_ref()[start..r.position() as usize]);
+    Ok(())
+}
+
+fn copy_rest(r: &mut Reader, w: &mut Vec<u8>) {
+    w.write_bytes(&r.get_ref()[r.position() as usize..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{BLOCKS, NATIVE_BLOCKS, NATIVE_REGISTRIES, REGISTRIES};
+    use super::*;
+    use crate::packets::clientbound::{
+        CBlockUpdate, CFinishConfiguration, CLogin, CSetContainerSlot, ClientBoundPacket,
+    };
+    use crate::packets::serverbound::{SChatCommand, SSetCreativeModeSlot, ServerBoundPacket};
+    use crate::packets::SlotData;
+
+    fn translation() -> Arc<V1_20_5> {
+        let native = Reports::parse(NATIVE_BLOCKS, NATIVE_REGISTRIES).unwrap();
+        let reports = Reports::parse(BLOCKS, REGISTRIES).unwrap();
+        Arc::new(V1_20_5::new(&native, &reports))
+    }
+
+    fn translate(
+        translator: &mut dyn ClientboundTranslator,
+        packet: PacketEncoder,
+    ) -> Vec<PacketEncoder> {
+        let mut out = Vec::new();
+        translator.translate(packet, &mut out).unwrap();
+        out
+    }
+
+    fn registry_entry(name: &str, id: i32) -> nbt::Value {
+        let fields = [
+            ("name", nbt::Value::String(name.to_string())),
+            ("id", nbt::Value::Int(id)),
+            ("element", nbt::Value::Compound(Default::default())),
+        ];
+        nbt::Value::Compound(
+            fields
+                .map(|(k, v)| (k.to_string(), v))
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn configuration_and_login() {
+        let mut translator = translation().clientbound(NetworkState::Configuration);
+        let dimension_types = [
+            (
+                "type",
+                nbt::Value::String("minecraft:dimension_type".to_string()),
+            ),
+            (
+                "value",
+                nbt::Value::List(vec![
+                    registry_entry("minecraft:overworld", 0),
+                    registry_entry("mchprs:plot", 1),
+                ]),
+            ),
+        ];
+        let registry_data = compound([(
+            "minecraft:dimension_type",
+            nbt::Value::Compound(
+                dimension_types
+                    .map(|(k, v)| (k.to_string(), v))
+                    .into_iter()
+                    .collect(),
+            ),
+        )]);
+
+        let out = translate(&mut *translator, PacketEncoder::new(registry_data, 0x05));
+        assert_eq!(out.len(), 2);
+        assert!(out.iter().all(|packet| packet.packet_id == 0x07));
+        let reader = &mut Cursor::new(out[0].buffer.as_slice());
+        assert_eq!(reader.read_string().unwrap(), "minecraft:dimension_type");
+        assert_eq!(reader.read_varint().unwrap(), 2);
+        assert_eq!(reader.read_string().unwrap(), "minecraft:overworld");
+        assert!(reader.read_bool().unwrap());
+        skip_nbt(reader).unwrap();
+        assert_eq!(reader.read_string().unwrap(), "mchprs:plot");
+
+        let out = translate(&mut *translator, CFinishConfiguration.encode());
+        assert_eq!(out[0].packet_id, 0x03);
+
+        let login = CLogin {
+            entity_id: 0,
+            is_hardcore: false,
+            dimension_names: vec!["mchprs:world".to_string()],
+            max_players: 1,
+            view_distance: 8,
+            simulation_distance: 8,
+            reduced_debug_info: false,
+            enable_respawn_screen: false,
+            do_limited_crafting: false,
+            dimension_type: "mchprs:plot".to_string(),
+            dimension_name: "mchprs:world".to_string(),
+            hashed_seed: 0,
+            gamemode: 1,
+            previous_gamemode: -1,
+            is_debug: false,
+            is_flat: true,
+            death_location: None,
+            portal_cooldown: 0,
+        };
+        let out = translate(&mut *translator, login.encode());
+        assert_eq!(out[0].packet_id, 0x2B);
+        let buffer = &out[0].buffer;
+        let dimension_type = 5 + 1 + 13 + 3 + 3;
+        assert_eq!(buffer[dimension_type], 1);
+        assert_eq!(buffer[dimension_type + 1], 12);
+        assert_eq!(buffer.last(), Some(&0));
+    }
+
+    #[test]
+    fn play_packets() {
+        let mut translator = translation().clientbound(NetworkState::Play);
+        let block_update = CBlockUpdate {
+            x: 0,
+            y: 0,
+            z: 0,
+            block_id: 1,
+        };
+        let out = translate(&mut *translator, block_update.encode());
+        assert_eq!(out[0].packet_id, 0x09);
+        assert_eq!(out[0].buffer[8..], [3]);
+
+        let set_slot = CSetContainerSlot {
+            window_id: 0,
+            state_id: 0,
+            slot: 36,
+            slot_data: Some(SlotData {
+                item_id: 1,
+                item_count: 5,
+                nbt: None,
+            }),
+        };
+        let out = translate(&mut *translator, set_slot.encode());
+        assert_eq!(out[0].packet_id, 0x15);
+        assert_eq!(out[0].buffer, [0, 0, 0, 36, 5, 3, 0, 0]);
+    }
+
+    #[test]
+    fn global_palette() {
+        let translation = translation();
+        let states: Vec<u32> = (0..4096).map(|i| i % 3).collect();
+        let longs = pack(&states, 15);
+        assert!(unpack(&longs, 15).eq(states.iter().copied()));
+
+        let mut section = vec![15];
+        section.write_varint(longs.len() as i32);
+        longs
+            .iter()
+            .for_each(|&long| section.write_long(long as i64));
+        let mut out = Vec::new();
+        translation
+            .block_states_container(&mut Cursor::new(section.as_slice()), &mut out)
+            .unwrap();
+        let reader = &mut Cursor::new(out.as_slice());
+        let bits = reader.read_unsigned_byte().unwrap();
+        assert_eq!(bits, 3);
+        let longs: Vec<u64> = (0..reader.read_varint().unwrap())
+            .map(|_| reader.read_long().unwrap() as u64)
+            .collect();
+        assert!(unpack(&longs, bits).eq(states.iter().map(|&state| [0, 3, 5][state as usize])));
+    }
+
+    #[test]
+    fn serverbound_packets() {
+        let translation = translation();
+
+        let mut chat_command = vec![0x04];
+        chat_command.write_string(32767, "plot info");
+        let data = translation
+            .serverbound(&NetworkState::Play, chat_command)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data[0], 0x04);
+        let command = SChatCommand::decode(&mut Cursor::new(&data[1..])).unwrap();
+        assert_eq!(command.command, "plot info");
+
+        let mut creative_slot = vec![0x32];
+        creative_slot.write_short(36);
+        creative_slot.write_varint(2);
+        creative_slot.write_varint(3);
+        creative_slot.write_bytes(&[0, 0]);
+        let data = translation
+            .serverbound(&NetworkState::Play, creative_slot)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data[0], 0x2F);
+        let slot = SSetCreativeModeSlot::decode(&mut Cursor::new(&data[1..])).unwrap();
+        let item = slot.clicked_item.unwrap();
+        assert_eq!((slot.slot, item.item_id, item.item_count), (36, 1, 2));
+
+        let keep_alive = vec![0x18, 0, 0, 0, 0, 0, 0, 0, 1];
+        let data = translation.serverbound(&NetworkState::Play, keep_alive);
+        assert_eq!(data.unwrap().unwrap(), [0x15, 0, 0, 0, 0, 0, 0, 0, 1]);
+        // Cookie response
+        let data = translation.serverbound(&NetworkState::Configuration, vec![0x01]);
+        assert!(data.unwrap().is_none());
+    }
+}
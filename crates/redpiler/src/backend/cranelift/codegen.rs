@@ -0,0 +1,792 @@
+use super::{NodeState, Runtime};
+use crate::backend::direct::{Event, NodeId, TickScheduler};
+use crate::compile_graph::{CompileGraph, LinkType, NodeType};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, InstBuilder, MemFlags, Signature, UserFuncName, Value,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use itertools::Itertools;
+use mchprs_blocks::blocks::ComparatorMode;
+use mchprs_world::TickPriority;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use rustc_hash::FxHashMap;
+use std::mem::{self, offset_of, size_of};
+use tracing::trace;
+
+pub(super) type NodeFn = unsafe extern "C" fn(*mut NodeState, *mut Runtime);
+pub(super) type SetFn = unsafe extern "C" fn(*mut NodeState, *mut Runtime, u32, u32);
+
+const OUTPUT_POWER: usize = offset_of!(NodeState, output_power);
+const POWERED: usize = offset_of!(NodeState, powered);
+const LOCKED: usize = offset_of!(NodeState, locked);
+const PENDING_TICK: usize = offset_of!(NodeState, pending_tick);
+const CHANGED: usize = offset_of!(NodeState, changed);
+
+pub(super) struct CompiledCode {
+    module: Option<Box<JITModule>>,
+    pub update_fns: Vec<Option<NodeFn>>,
+    pub tick_fns: Vec<Option<NodeFn>>,
+    pub set_fns: Vec<Option<SetFn>>,
+}
+
+// Safety: the module only owns the memory holding the generated code, which is never mutated
+// after it has been finalized.
+unsafe impl Send for CompiledCode {}
+
+impl Drop for CompiledCode {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Safety: the function pointers into this module are dropped along with it
+            unsafe { (*module).free_memory() };
+        }
+    }
+}
+
+extern "C" fn schedule_tick(runtime: *mut Runtime, node: u32, delay: u32, priority: u32) {
+    // Safety: the generated code only ever passes along the runtime it was called with
+    let runtime = unsafe { &mut *runtime };
+    let priority = TickScheduler::priorities()[priority as usize];
+    // Safety: node ids are baked into the generated code from the same graph
+    let node = unsafe { NodeId::from_index(node as usize) };
+    runtime
+        .scheduler
+        .schedule_tick(node, delay as usize, priority);
+}
+
+extern "C" fn play_note(runtime: *mut Runtime, noteblock_id: u32) {
+    // Safety: the generated code only ever passes along the runtime it was called with
+    let runtime = unsafe { &mut *runtime };
+    runtime.events.push(Event::NoteBlockPlay {
+        noteblock_id: noteblock_id as u16,
+    });
+}
+
+struct LoweredNode {
+    ty: NodeType,
+    /// The output strength of constant nodes, which is folded directly into the inputs of the
+    /// nodes they link to.
+    constant: Option<u8>,
+    default_inputs: Vec<(usize, u8)>,
+    side_inputs: Vec<(usize, u8)>,
+    updates: Vec<(usize, u8)>,
+    /// Observers that pulse when the block state of this node changes
+    observers: Vec<usize>,
+    noteblock_id: Option<u32>,
+}
+
+fn has_update_fn(ty: &NodeType) -> bool {
+    matches!(
+        ty,
+        NodeType::Repeater { .. }
+            | NodeType::Torch
+            | NodeType::Comparator { .. }
+            | NodeType::Lamp
+            | NodeType::Trapdoor
+            | NodeType::Wire
+            | NodeType::NoteBlock { .. }
+    )
+}
+
+fn has_tick_fn(ty: &NodeType) -> bool {
+    matches!(
+        ty,
+        NodeType::Repeater { .. }
+            | NodeType::Torch
+            | NodeType::Comparator { .. }
+            | NodeType::Lamp
+            | NodeType::Button
+            | NodeType::Observer
+    )
+}
+
+fn has_set_fn(ty: &NodeType) -> bool {
+    has_tick_fn(ty)
+        || matches!(
+            ty,
+            NodeType::Lever | NodeType::PressurePlate | NodeType::Interface
+        )
+}
+
+fn lower_nodes(graph: &CompileGraph) -> Vec<LoweredNode> {
+    let nodes_map: FxHashMap<_, _> = graph
+        .node_indices()
+        .enumerate()
+        .map(|(i, idx)| (idx, i))
+        .collect();
+
+    let mut noteblock_count = 0;
+    graph
+        .node_indices()
+        .map(|idx| {
+            let node = &graph[idx];
+            let mut default_inputs = Vec::new();
+            let mut side_inputs = Vec::new();
+            // The incoming links of an observer only say which node it is watching
+            let incoming = graph
+                .edges_directed(idx, Direction::Incoming)
+                .filter(|_| node.ty != NodeType::Observer);
+            for edge in incoming {
+                let input = (nodes_map[&edge.source()], edge.weight().ss);
+                match edge.weight().ty {
+                    LinkType::Default => default_inputs.push(input),
+                    LinkType::Side => side_inputs.push(input),
+                }
+            }
+
+            let (observers, outgoing): (Vec<_>, Vec<_>) = graph
+                .edges_directed(idx, Direction::Outgoing)
+                .partition(|edge| graph[edge.target()].ty == NodeType::Observer);
+            let observers = observers
+                .into_iter()
+                .map(|edge| nodes_map[&edge.target()])
+                .collect();
+            let updates = if node.ty != NodeType::Constant {
+                outgoing
+                    .into_iter()
+                    .sorted_by_key(|edge| nodes_map[&edge.target()])
+                    .into_group_map_by(|edge| mem::discriminant(&graph[edge.target()].ty))
+                    .into_values()
+                    .flatten()
+                    .map(|edge| (nodes_map[&edge.target()], edge.weight().ss))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            let noteblock_id = matches!(node.ty, NodeType::NoteBlock { .. }).then(|| {
+                noteblock_count += 1;
+                noteblock_count - 1
+            });
+
+            LoweredNode {
+                ty: node.ty.clone(),
+                constant: (node.ty == NodeType::Constant).then_some(node.state.output_strength),
+                default_inputs,
+                side_inputs,
+                updates,
+                observers,
+                noteblock_id,
+            }
+        })
+        .collect()
+}
+
+struct Lowering {
+    nodes: Vec<LoweredNode>,
+    update_ids: Vec<Option<FuncId>>,
+    tick_ids: Vec<Option<FuncId>>,
+    set_ids: Vec<Option<FuncId>>,
+    schedule_tick: FuncId,
+    play_note: FuncId,
+    node_sig: Signature,
+    set_sig: Signature,
+}
+
+impl Lowering {
+    fn declare(module: &mut JITModule, nodes: Vec<LoweredNode>) -> Lowering {
+        let ptr = module.target_config().pointer_type();
+
+        let mut node_sig = module.make_signature();
+        node_sig.params.push(AbiParam::new(ptr));
+        node_sig.params.push(AbiParam::new(ptr));
+        let mut set_sig = node_sig.clone();
+        set_sig.params.push(AbiParam::new(types::I32));
+        set_sig.params.push(AbiParam::new(types::I32));
+
+        let mut schedule_tick_sig = module.make_signature();
+        schedule_tick_sig.params.push(AbiParam::new(ptr));
+        schedule_tick_sig
+            .params
+            .extend([AbiParam::new(types::I32); 3]);
+        let schedule_tick = module
+            .declare_function("schedule_tick", Linkage::Import, &schedule_tick_sig)
+            .unwrap();
+
+        let mut play_note_sig = module.make_signature();
+        play_note_sig.params.push(AbiParam::new(ptr));
+        play_note_sig.params.push(AbiParam::new(types::I32));
+        let play_note = module
+            .declare_function("play_note", Linkage::Import, &play_note_sig)
+            .unwrap();
+
+        let mut declare = |prefix: &str, filter: fn(&NodeType) -> bool, sig: &Signature| {
+            nodes
+                .iter()
+                .enumerate()
+                .map(|(i, node)| {
+                    filter(&node.ty).then(|| {
+                        module
+                            .declare_function(&format!("{}_{}", prefix, i), Linkage::Local, sig)
+                            .unwrap()
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let update_ids = declare("update", has_update_fn, &node_sig);
+        let tick_ids = declare("tick", has_tick_fn, &node_sig);
+        let set_ids = declare("set", has_set_fn, &set_sig);
+
+        Lowering {
+            nodes,
+            update_ids,
+            tick_ids,
+            set_ids,
+            schedule_tick,
+            play_note,
+            node_sig,
+            set_sig,
+        }
+    }
+
+    fn define(&self, module: &mut JITModule) {
+        let mut ctx = module.make_context();
+        let mut builder_ctx = FunctionBuilderContext::new();
+
+        let functions = [
+            (&self.update_ids, &self.node_sig, FunctionKind::Update),
+            (&self.tick_ids, &self.node_sig, FunctionKind::Tick),
+            (&self.set_ids, &self.set_sig, FunctionKind::Set),
+        ];
+        for (ids, sig, kind) in functions {
+            for (node, func_id) in ids.iter().enumerate() {
+                let Some(func_id) = *func_id else {
+                    continue;
+                };
+                ctx.func.signature = sig.clone();
+                ctx.func.name = UserFuncName::user(0, func_id.as_u32());
+
+                let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+                let entry = builder.create_block();
+                builder.append_block_params_for_function_params(entry);
+                builder.switch_to_block(entry);
+                builder.seal_block(entry);
+                let params = builder.block_params(entry).to_vec();
+
+                let mut emitter = Emitter {
+                    lowering: self,
+                    module,
+                    builder,
+                    states: params[0],
+                    runtime: params[1],
+                };
+                match kind {
+                    FunctionKind::Update => emitter.emit_update(node),
+                    FunctionKind::Tick => emitter.emit_tick(node),
+                    FunctionKind::Set => emitter.emit_set(node, params[2], params[3]),
+                }
+                emitter.builder.ins().return_(&[]);
+                emitter.builder.finalize();
+
+                module.define_function(func_id, &mut ctx).unwrap();
+                module.clear_context(&mut ctx);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FunctionKind {
+    Update,
+    Tick,
+    Set,
+}
+
+struct Emitter<'a, 'f> {
+    lowering: &'a Lowering,
+    module: &'a mut JITModule,
+    builder: FunctionBuilder<'f>,
+    states: Value,
+    runtime: Value,
+}
+
+impl Emitter<'_, '_> {
+    fn offset(node: usize, field: usize) -> i32 {
+        (node * size_of::<NodeState>() + field)
+            .try_into()
+            .expect("node state offset out of range")
+    }
+
+    fn load(&mut self, node: usize, field: usize) -> Value {
+        self.builder.ins().uload8(
+            types::I32,
+            MemFlags::trusted(),
+            self.states,
+            Self::offset(node, field),
+        )
+    }
+
+    fn store(&mut self, node: usize, field: usize, value: Value) {
+        self.builder.ins().istore8(
+            MemFlags::trusted(),
+            value,
+            self.states,
+            Self::offset(node, field),
+        );
+    }
+
+    fn store_imm(&mut self, node: usize, field: usize, value: i64) {
+        let value = self.iconst(value);
+        self.store(node, field, value);
+    }
+
+    fn iconst(&mut self, value: i64) -> Value {
+        self.builder.ins().iconst(types::I32, value)
+    }
+
+    /// Compares two values, producing 0 or 1
+    fn cmp(&mut self, cc: IntCC, x: Value, y: Value) -> Value {
+        let cmp = self.builder.ins().icmp(cc, x, y);
+        self.builder.ins().uextend(types::I32, cmp)
+    }
+
+    fn cmp_imm(&mut self, cc: IntCC, x: Value, y: i64) -> Value {
+        let cmp = self.builder.ins().icmp_imm(cc, x, y);
+        self.builder.ins().uextend(types::I32, cmp)
+    }
+
+    fn saturating_sub(&mut self, x: Value, distance: u8) -> Value {
+        if distance == 0 {
+            return x;
+        }
+        let zero = self.iconst(0);
+        let sub = self.builder.ins().iadd_imm(x, -(distance as i64));
+        let in_range = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::UnsignedGreaterThan, x, distance as i64);
+        self.builder.ins().select(in_range, sub, zero)
+    }
+
+    fn max(&mut self, x: Value, y: Value) -> Value {
+        let greater = self.builder.ins().icmp(IntCC::UnsignedGreaterThan, x, y);
+        self.builder.ins().select(greater, x, y)
+    }
+
+    fn input_power(&mut self, inputs: &[(usize, u8)]) -> Value {
+        let lowering = self.lowering;
+        let nodes = &lowering.nodes;
+        let constant = inputs
+            .iter()
+            .filter_map(|&(source, distance)| {
+                nodes[source].constant.map(|ss| ss.saturating_sub(distance))
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut power = self.iconst(constant as i64);
+        for &(source, distance) in inputs {
+            if nodes[source].constant.is_some() {
+                continue;
+            }
+            let output = self.load(source, OUTPUT_POWER);
+            let ss = self.saturating_sub(output, distance);
+            power = self.max(power, ss);
+        }
+        power
+    }
+
+    fn bool_input(&mut self, inputs: &[(usize, u8)]) -> Value {
+        let power = self.input_power(inputs);
+        self.cmp_imm(IntCC::NotEqual, power, 0)
+    }
+
+    fn if_then(&mut self, cond: Value, then: impl FnOnce(&mut Self)) {
+        let then_block = self.builder.create_block();
+        let merge_block = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(cond, then_block, &[], merge_block, &[]);
+
+        self.builder.switch_to_block(then_block);
+        self.builder.seal_block(then_block);
+        then(self);
+        self.builder.ins().jump(merge_block, &[]);
+
+        self.builder.switch_to_block(merge_block);
+        self.builder.seal_block(merge_block);
+    }
+
+    fn if_else(&mut self, cond: Value, then: impl FnOnce(&mut Self), or: impl FnOnce(&mut Self)) {
+        let then_block = self.builder.create_block();
+        let else_block = self.builder.create_block();
+        let merge_block = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(cond, then_block, &[], else_block, &[]);
+
+        self.builder.switch_to_block(then_block);
+        self.builder.seal_block(then_block);
+        then(self);
+        self.builder.ins().jump(merge_block, &[]);
+
+        self.builder.switch_to_block(else_block);
+        self.builder.seal_block(else_block);
+        or(self);
+        self.builder.ins().jump(merge_block, &[]);
+
+        self.builder.switch_to_block(merge_block);
+        self.builder.seal_block(merge_block);
+    }
+
+    fn return_if(&mut self, cond: Value) {
+        self.if_then(cond, |e| {
+            e.builder.ins().return_(&[]);
+            // Everything emitted after the return is unreachable, but the block still has to be
+            // terminated by the jump `if_then` inserts.
+            let dead_block = e.builder.create_block();
+            e.builder.switch_to_block(dead_block);
+            e.builder.seal_block(dead_block);
+        });
+    }
+
+    fn schedule_tick(&mut self, node: usize, delay: u8, priority: Value) {
+        self.store_imm(node, PENDING_TICK, 1);
+        let func = self
+            .module
+            .declare_func_in_func(self.lowering.schedule_tick, self.builder.func);
+        let node = self.iconst(node as i64);
+        let delay = self.iconst(delay as i64);
+        self.builder
+            .ins()
+            .call(func, &[self.runtime, node, delay, priority]);
+    }
+
+    fn schedule_tick_imm(&mut self, node: usize, delay: u8, priority: TickPriority) {
+        let priority = self.iconst(priority as i64);
+        self.schedule_tick(node, delay, priority);
+    }
+
+    /// Schedules a pulse on every observer watching the node. This has to be emitted wherever the
+    /// block state of the node changes.
+    fn notify_observers(&mut self, node: usize) {
+        let lowering = self.lowering;
+        for &observer in &lowering.nodes[node].observers {
+            let powered = self.load(observer, POWERED);
+            let pending_tick = self.load(observer, PENDING_TICK);
+            let busy = self.builder.ins().bor(powered, pending_tick);
+            let idle = self.cmp_imm(IntCC::Equal, busy, 0);
+            self.if_then(idle, |e| {
+                e.schedule_tick_imm(observer, 1, TickPriority::Normal);
+            });
+        }
+    }
+
+    fn call_set(&mut self, node: usize, powered: Value, power: Value) {
+        let func_id = self.lowering.set_ids[node].unwrap();
+        let func = self.module.declare_func_in_func(func_id, self.builder.func);
+        self.builder
+            .ins()
+            .call(func, &[self.states, self.runtime, powered, power]);
+    }
+
+    fn call_set_imm(&mut self, node: usize, powered: bool, power: u8) {
+        let powered = self.iconst(powered as i64);
+        let power = self.iconst(power as i64);
+        self.call_set(node, powered, power);
+    }
+
+    fn comparator_output(
+        &mut self,
+        node: usize,
+        mode: ComparatorMode,
+        far_input: Option<u8>,
+    ) -> Value {
+        let lowering = self.lowering;
+        let lowered = &lowering.nodes[node];
+        let mut input_power = self.input_power(&lowered.default_inputs);
+        let side_input_power = self.input_power(&lowered.side_inputs);
+        if let Some(far_input) = far_input {
+            let far_input = self.iconst(far_input as i64);
+            let weak = self
+                .builder
+                .ins()
+                .icmp_imm(IntCC::UnsignedLessThan, input_power, 15);
+            input_power = self.builder.ins().select(weak, far_input, input_power);
+        }
+        let zero = self.iconst(0);
+        let in_range = self.builder.ins().icmp(
+            IntCC::UnsignedGreaterThanOrEqual,
+            input_power,
+            side_input_power,
+        );
+        let output = match mode {
+            ComparatorMode::Compare => input_power,
+            ComparatorMode::Subtract => self.builder.ins().isub(input_power, side_input_power),
+        };
+        self.builder.ins().select(in_range, output, zero)
+    }
+
+    fn emit_update(&mut self, node: usize) {
+        let lowering = self.lowering;
+        let lowered = &lowering.nodes[node];
+        match lowered.ty {
+            NodeType::Repeater {
+                delay,
+                facing_diode,
+            } => {
+                let should_be_locked = self.bool_input(&lowered.side_inputs);
+                let locked = self.load(node, LOCKED);
+                let lock_changed = self.cmp(IntCC::NotEqual, should_be_locked, locked);
+                self.if_then(lock_changed, |e| {
+                    e.store(node, LOCKED, should_be_locked);
+                    e.store_imm(node, CHANGED, 1);
+                    e.notify_observers(node);
+                });
+                let pending_tick = self.load(node, PENDING_TICK);
+                let skip = self.builder.ins().bor(should_be_locked, pending_tick);
+                self.return_if(skip);
+
+                let should_be_powered = self.bool_input(&lowered.default_inputs);
+                let powered = self.load(node, POWERED);
+                let changed = self.cmp(IntCC::NotEqual, should_be_powered, powered);
+                self.if_then(changed, |e| {
+                    let priority = if facing_diode {
+                        e.iconst(TickPriority::Highest as i64)
+                    } else {
+                        let high = e.iconst(TickPriority::High as i64);
+                        let higher = e.iconst(TickPriority::Higher as i64);
+                        e.builder.ins().select(should_be_powered, high, higher)
+                    };
+                    e.schedule_tick(node, delay, priority);
+                });
+            }
+            NodeType::Torch => {
+                let pending_tick = self.load(node, PENDING_TICK);
+                self.return_if(pending_tick);
+                let input = self.bool_input(&lowered.default_inputs);
+                let powered = self.load(node, POWERED);
+                // A torch should be powered exactly when its input is not
+                let changed = self.cmp(IntCC::Equal, input, powered);
+                self.if_then(changed, |e| {
+                    e.schedule_tick_imm(node, 1, TickPriority::Normal);
+                });
+            }
+            NodeType::Comparator {
+                mode,
+                far_input,
+                facing_diode,
+            } => {
+                let pending_tick = self.load(node, PENDING_TICK);
+                self.return_if(pending_tick);
+                let output_power = self.comparator_output(node, mode, far_input);
+                let old_strength = self.load(node, OUTPUT_POWER);
+                let changed = self.cmp(IntCC::NotEqual, output_power, old_strength);
+                self.if_then(changed, |e| {
+                    let priority = if facing_diode {
+                        TickPriority::High
+                    } else {
+                        TickPriority::Normal
+                    };
+                    e.schedule_tick_imm(node, 1, priority);
+                });
+            }
+            NodeType::Lamp => {
+                let should_be_lit = self.bool_input(&lowered.default_inputs);
+                let lit = self.load(node, POWERED);
+                self.if_else(
+                    lit,
+                    |e| {
+                        let should_be_unlit = e.cmp_imm(IntCC::Equal, should_be_lit, 0);
+                        e.if_then(should_be_unlit, |e| {
+                            e.schedule_tick_imm(node, 2, TickPriority::Normal);
+                        });
+                    },
+                    |e| {
+                        e.if_then(should_be_lit, |e| {
+                            e.store_imm(node, POWERED, 1);
+                            e.store_imm(node, CHANGED, 1);
+                            e.notify_observers(node);
+                        });
+                    },
+                );
+            }
+            NodeType::Trapdoor => {
+                let should_be_powered = self.bool_input(&lowered.default_inputs);
+                let powered = self.load(node, POWERED);
+                let changed = self.cmp(IntCC::NotEqual, should_be_powered, powered);
+                self.if_then(changed, |e| {
+                    e.store(node, POWERED, should_be_powered);
+                    e.store_imm(node, CHANGED, 1);
+                    e.notify_observers(node);
+                });
+            }
+            NodeType::Wire => {
+                let input_power = self.input_power(&lowered.default_inputs);
+                let output_power = self.load(node, OUTPUT_POWER);
+                let changed = self.cmp(IntCC::NotEqual, input_power, output_power);
+                self.if_then(changed, |e| {
+                    e.store(node, OUTPUT_POWER, input_power);
+                    e.store_imm(node, CHANGED, 1);
+                    e.notify_observers(node);
+                });
+            }
+            NodeType::NoteBlock { .. } => {
+                let noteblock_id = lowered.noteblock_id.unwrap();
+                let should_be_powered = self.bool_input(&lowered.default_inputs);
+                let powered = self.load(node, POWERED);
+                let changed = self.cmp(IntCC::NotEqual, should_be_powered, powered);
+                self.if_then(changed, |e| {
+                    e.store(node, POWERED, should_be_powered);
+                    e.store_imm(node, CHANGED, 1);
+                    e.if_then(should_be_powered, |e| {
+                        let func = e
+                            .module
+                            .declare_func_in_func(e.lowering.play_note, e.builder.func);
+                        let noteblock_id = e.iconst(noteblock_id as i64);
+                        e.builder.ins().call(func, &[e.runtime, noteblock_id]);
+                    });
+                    e.notify_observers(node);
+                });
+            }
+            _ => unreachable!("Node {:?} should not be updated!", lowered.ty),
+        }
+    }
+
+    fn emit_tick(&mut self, node: usize) {
+        let lowering = self.lowering;
+        let lowered = &lowering.nodes[node];
+        self.store_imm(node, PENDING_TICK, 0);
+        match lowered.ty {
+            NodeType::Repeater { delay, .. } => {
+                let locked = self.load(node, LOCKED);
+                self.return_if(locked);
+
+                let should_be_powered = self.bool_input(&lowered.default_inputs);
+                let powered = self.load(node, POWERED);
+                let should_be_unpowered = self.cmp_imm(IntCC::Equal, should_be_powered, 0);
+                self.if_else(
+                    powered,
+                    |e| {
+                        e.if_then(should_be_unpowered, |e| e.call_set_imm(node, false, 0));
+                    },
+                    |e| {
+                        e.if_then(should_be_unpowered, |e| {
+                            e.schedule_tick_imm(node, delay, TickPriority::Higher);
+                        });
+                        e.call_set_imm(node, true, 15);
+                    },
+                );
+            }
+            NodeType::Torch => {
+                let input = self.bool_input(&lowered.default_inputs);
+                let should_be_powered = self.cmp_imm(IntCC::Equal, input, 0);
+                let powered = self.load(node, POWERED);
+                let changed = self.cmp(IntCC::NotEqual, should_be_powered, powered);
+                self.if_then(changed, |e| {
+                    let power = e.builder.ins().imul_imm(should_be_powered, 15);
+                    e.call_set(node, should_be_powered, power);
+                });
+            }
+            NodeType::Comparator {
+                mode, far_input, ..
+            } => {
+                let new_strength = self.comparator_output(node, mode, far_input);
+                let old_strength = self.load(node, OUTPUT_POWER);
+                let changed = self.cmp(IntCC::NotEqual, new_strength, old_strength);
+                self.if_then(changed, |e| {
+                    let powered = e.cmp_imm(IntCC::NotEqual, new_strength, 0);
+                    e.call_set(node, powered, new_strength);
+                });
+            }
+            NodeType::Lamp => {
+                let should_be_lit = self.bool_input(&lowered.default_inputs);
+                let lit = self.load(node, POWERED);
+                let should_unlight = self.cmp(IntCC::UnsignedGreaterThan, lit, should_be_lit);
+                self.if_then(should_unlight, |e| e.call_set_imm(node, false, 0));
+            }
+            NodeType::Button => {
+                let powered = self.load(node, POWERED);
+                self.if_then(powered, |e| e.call_set_imm(node, false, 0));
+            }
+            NodeType::Observer => {
+                let powered = self.load(node, POWERED);
+                self.if_else(
+                    powered,
+                    |e| e.call_set_imm(node, false, 0),
+                    |e| {
+                        e.call_set_imm(node, true, 15);
+                        e.schedule_tick_imm(node, 1, TickPriority::Normal);
+                    },
+                );
+            }
+            _ => unreachable!("Node {:?} should not be ticked!", lowered.ty),
+        }
+    }
+
+    fn emit_set(&mut self, node: usize, powered: Value, new_power: Value) {
+        let old_power = self.load(node, OUTPUT_POWER);
+        let old_powered = self.load(node, POWERED);
+        self.store_imm(node, CHANGED, 1);
+        self.store(node, POWERED, powered);
+        self.store(node, OUTPUT_POWER, new_power);
+
+        let lowering = self.lowering;
+        if !lowering.nodes[node].observers.is_empty() {
+            let state_changed = self.cmp(IntCC::NotEqual, old_powered, powered);
+            self.if_then(state_changed, |e| e.notify_observers(node));
+        }
+        for &(target, distance) in &lowering.nodes[node].updates {
+            let Some(update_id) = lowering.update_ids[target] else {
+                continue;
+            };
+            let old_power = self.saturating_sub(old_power, distance);
+            let new_power = self.saturating_sub(new_power, distance);
+            let changed = self.cmp(IntCC::NotEqual, old_power, new_power);
+            self.if_then(changed, |e| {
+                let func = e.module.declare_func_in_func(update_id, e.builder.func);
+                e.builder.ins().call(func, &[e.states, e.runtime]);
+            });
+        }
+    }
+}
+
+pub(super) fn compile(graph: &CompileGraph) -> CompiledCode {
+    let mut builder = JITBuilder::with_flags(&[("opt_level", "speed")], default_libcall_names())
+        .expect("failed to create cranelift jit builder");
+    builder.symbol("schedule_tick", schedule_tick as *const u8);
+    builder.symbol("play_note", play_note as *const u8);
+    let mut module = JITModule::new(builder);
+
+    let lowering = Lowering::declare(&mut module, lower_nodes(graph));
+    lowering.define(&mut module);
+    module.finalize_definitions().unwrap();
+    trace!(
+        "Generated {} update, {} tick and {} set functions",
+        lowering.update_ids.iter().flatten().count(),
+        lowering.tick_ids.iter().flatten().count(),
+        lowering.set_ids.iter().flatten().count()
+    );
+
+    // Safety: the functions were declared with the signatures of `NodeFn` and `SetFn`
+    let node_fns = |ids: &[Option<FuncId>]| {
+        ids.iter()
+            .map(|id| {
+                id.map(|id| unsafe {
+                    mem::transmute::<*const u8, NodeFn>(module.get_finalized_function(id))
+                })
+            })
+            .collect()
+    };
+    let update_fns = node_fns(&lowering.update_ids);
+    let tick_fns = node_fns(&lowering.tick_ids);
+    let set_fns = lowering
+        .set_ids
+        .iter()
+        .map(|id| {
+            id.map(|id| unsafe {
+                mem::transmute::<*const u8, SetFn>(module.get_finalized_function(id))
+            })
+        })
+        .collect();
+
+    CompiledCode {
+        module: Some(Box::new(module)),
+        update_fns,
+        tick_fns,
+        set_fns,
+    }
+}
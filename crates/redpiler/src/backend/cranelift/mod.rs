@@ -0,0 +1,414 @@
+//! The cranelift backend lowers the `CompileGraph` into native machine code.
+//!
+//! Every node is given its own `update`, `tick` and `set` functions. Inputs are read straight out
+//! of the state of the nodes linking into it, and a `set` function calls directly into the
+//! `update` functions of the nodes it links to, so no graph has to be walked at runtime. Scheduling
+//! ticks is done by calling back into the host, which reuses the scheduler of the direct backend.
+
+mod codegen;
+
+use super::direct::{Event, NodeId, TickScheduler};
+use super::JITBackend;
+use crate::compile_graph::{CompileGraph, NodeType};
+use crate::snapshot::{graph_hash, BackendState, NodeSnapshot, Snapshot, SnapshotError};
+use crate::task_monitor::TaskMonitor;
+use crate::{block_powered_mut, BackendVariant, CompilerOptions};
+use codegen::CompiledCode;
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::{Block, Instrument};
+use mchprs_blocks::BlockPos;
+use mchprs_redstone::{bool_to_ss, noteblock};
+use mchprs_world::{TickEntry, TickPriority, World};
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// The state of a single node, as it is read and written by the generated code.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct NodeState {
+    output_power: u8,
+    /// Powered or lit
+    powered: bool,
+    /// Only for repeaters
+    locked: bool,
+    pending_tick: bool,
+    changed: bool,
+}
+
+/// Everything the generated code can reach through host calls.
+#[derive(Default)]
+struct Runtime {
+    scheduler: TickScheduler,
+    events: Vec<Event>,
+}
+
+#[derive(Debug)]
+struct NodeInfo {
+    ty: NodeType,
+    is_io: bool,
+}
+
+#[derive(Default)]
+pub struct CraneliftBackend {
+    code: Option<CompiledCode>,
+    states: Box<[NodeState]>,
+    nodes: Vec<NodeInfo>,
+    blocks: Vec<Option<(BlockPos, Block)>>,
+    pos_map: FxHashMap<BlockPos, NodeId>,
+    runtime: Runtime,
+    noteblock_info: Vec<(BlockPos, Instrument, u32)>,
+    /// The hash of the graph that was compiled, see [`crate::snapshot::graph_hash`]
+    graph_hash: u64,
+}
+
+impl CraneliftBackend {
+    fn positions(&self) -> impl ExactSizeIterator<Item = Option<BlockPos>> + '_ {
+        self.blocks.iter().map(|block| block.map(|(pos, _)| pos))
+    }
+
+    fn code(&self) -> &CompiledCode {
+        self.code
+            .as_ref()
+            .expect("cranelift backend used before compilation")
+    }
+
+    fn set_node(&mut self, node_id: NodeId, powered: bool, new_power: u8) {
+        let Some(set_fn) = self.code().set_fns[node_id.index()] else {
+            warn!("Node {:?} cannot be set", node_id);
+            return;
+        };
+        // Safety: the generated code only accesses nodes that are part of this graph
+        unsafe {
+            set_fn(
+                self.states.as_mut_ptr(),
+                &mut self.runtime,
+                powered as u32,
+                new_power as u32,
+            );
+        }
+    }
+}
+
+impl JITBackend for CraneliftBackend {
+    fn inspect(&mut self, pos: BlockPos) {
+        let Some(node_id) = self.pos_map.get(&pos) else {
+            debug!("could not find node at pos {}", pos);
+            return;
+        };
+
+        debug!(
+            "Node {:?}: {:#?} {:#?}",
+            node_id,
+            self.nodes[node_id.index()],
+            self.states[node_id.index()]
+        );
+    }
+
+    fn reset<W: World>(&mut self, world: &mut W, io_only: bool) {
+        self.runtime.scheduler.reset(world, &self.blocks);
+
+        let states = std::mem::take(&mut self.states);
+        let nodes = std::mem::take(&mut self.nodes);
+        for (i, (state, node)) in states.iter().zip(nodes).enumerate() {
+            let Some((pos, block)) = self.blocks[i] else {
+                continue;
+            };
+            if node.ty == NodeType::Interface {
+                continue;
+            }
+            if matches!(node.ty, NodeType::Comparator { .. }) {
+                let block_entity = BlockEntity::Comparator {
+                    output_strength: state.output_power,
+                };
+                world.set_block_entity(pos, block_entity);
+            }
+
+            if io_only && !node.is_io {
+                world.set_block(pos, block);
+            }
+        }
+
+        self.code = None;
+        self.pos_map.clear();
+        self.noteblock_info.clear();
+        self.runtime.events.clear();
+    }
+
+    fn copy_into<W: World>(&self, world: &mut W) {
+        self.runtime
+            .scheduler
+            .copy_into(world, &self.blocks, &Default::default());
+        for ((state, node), entry) in self.states.iter().zip(&self.nodes).zip(&self.blocks) {
+            let Some((pos, mut block)) = *entry else {
+                continue;
+            };
+            if node.ty == NodeType::Interface {
+                continue;
+            }
+            if matches!(node.ty, NodeType::Comparator { .. }) {
+                let block_entity = BlockEntity::Comparator {
+                    output_strength: state.output_power,
+                };
+                world.set_block_entity(pos, block_entity);
+            }
+            write_block(world, pos, &mut block, state);
+        }
+    }
+
+    fn update_nodes(&mut self, positions: &[BlockPos]) {
+        let code = self
+            .code
+            .as_ref()
+            .expect("cranelift backend used before compilation");
+        for pos in positions {
+            let Some(node_id) = self.pos_map.get(pos) else {
+                continue;
+            };
+            if let Some(update_fn) = code.update_fns[node_id.index()] {
+                // Safety: the generated code only accesses nodes that are part of this graph
+                unsafe { update_fn(self.states.as_mut_ptr(), &mut self.runtime) };
+            }
+        }
+    }
+
+    fn on_use_block(&mut self, pos: BlockPos) {
+        let node_id = self.pos_map[&pos];
+        let powered = self.states[node_id.index()].powered;
+        match self.nodes[node_id.index()].ty {
+            NodeType::Button => {
+                if powered {
+                    return;
+                }
+                self.runtime
+                    .scheduler
+                    .schedule_tick(node_id, 10, TickPriority::Normal);
+                self.set_node(node_id, true, 15);
+            }
+            NodeType::Lever => {
+                self.set_node(node_id, !powered, bool_to_ss(!powered));
+            }
+            ref ty => warn!("Tried to use a {:?} redpiler node", ty),
+        }
+    }
+
+    fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool) {
+        let node_id = self.pos_map[&pos];
+        match self.nodes[node_id.index()].ty {
+            NodeType::PressurePlate => {
+                self.set_node(node_id, powered, bool_to_ss(powered));
+            }
+            ref ty => warn!("Tried to set pressure plate state for a {:?}", ty),
+        }
+    }
+
+    fn tick(&mut self) {
+        let mut queues = self.runtime.scheduler.queues_this_tick();
+
+        let code = self
+            .code
+            .as_ref()
+            .expect("cranelift backend used before compilation");
+        let states = self.states.as_mut_ptr();
+        let runtime: *mut Runtime = &mut self.runtime;
+        for node_id in queues.drain_iter() {
+            match code.tick_fns[node_id.index()] {
+                // Safety: the generated code only accesses nodes that are part of this graph
+                Some(tick_fn) => unsafe { tick_fn(states, runtime) },
+                None => self.states[node_id.index()].pending_tick = false,
+            }
+        }
+
+        self.runtime.scheduler.end_tick(queues);
+    }
+
+    fn flush<W: World>(&mut self, world: &mut W, io_only: bool) {
+        for event in self.runtime.events.drain(..) {
+            match event {
+                Event::NoteBlockPlay { noteblock_id } => {
+                    let (pos, instrument, note) = self.noteblock_info[noteblock_id as usize];
+                    noteblock::play_note(world, pos, instrument, note);
+                }
+            }
+        }
+        for (i, state) in self.states.iter_mut().enumerate() {
+            let Some((pos, block)) = &mut self.blocks[i] else {
+                continue;
+            };
+            let node = &self.nodes[i];
+            if state.changed && (!io_only || node.is_io) && node.ty != NodeType::Interface {
+                write_block(world, *pos, block, state);
+            }
+            state.changed = false;
+        }
+    }
+
+    fn flush_interface<W: World>(
+        &mut self,
+        world: &mut W,
+        positions: &[BlockPos],
+    ) -> Vec<BlockPos> {
+        let mut changed = Vec::new();
+        for &pos in positions {
+            let Some(node_id) = self.pos_map.get(&pos) else {
+                continue;
+            };
+            let state = &mut self.states[node_id.index()];
+            let Some((pos, block)) = &mut self.blocks[node_id.index()] else {
+                continue;
+            };
+            if state.changed {
+                write_block(world, *pos, block, state);
+                state.changed = false;
+                changed.push(*pos);
+            }
+        }
+        changed
+    }
+
+    fn set_interface_power(&mut self, pos: BlockPos, power: u8) {
+        let node_id = self.pos_map[&pos];
+        match self.nodes[node_id.index()].ty {
+            NodeType::Interface => self.set_node(node_id, power > 0, power),
+            ref ty => warn!("Tried to set interface power for a {:?}", ty),
+        }
+    }
+
+    fn compile(
+        &mut self,
+        graph: CompileGraph,
+        ticks: Vec<TickEntry>,
+        _options: &CompilerOptions,
+        _monitor: Arc<TaskMonitor>,
+    ) {
+        let code = codegen::compile(&graph);
+        self.graph_hash = graph_hash(&graph);
+
+        self.states = graph
+            .node_weights()
+            .map(|node| NodeState {
+                output_power: node.state.output_strength,
+                powered: node.state.powered,
+                locked: node.state.repeater_locked,
+                pending_tick: false,
+                changed: false,
+            })
+            .collect();
+        self.nodes = graph
+            .node_weights()
+            .map(|node| NodeInfo {
+                ty: node.ty.clone(),
+                is_io: node.is_input || node.is_output,
+            })
+            .collect();
+        self.blocks = graph
+            .node_weights()
+            .map(|node| node.block.map(|(pos, id)| (pos, Block::from_id(id))))
+            .collect();
+        // Noteblock ids are handed out in node order by the code generator
+        for node in graph.node_weights() {
+            if let NodeType::NoteBlock { instrument, note } = node.ty {
+                self.noteblock_info
+                    .push((node.block.unwrap().0, instrument, note));
+            }
+        }
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            if let Some((pos, _)) = block {
+                // Safety: `i` is in bounds of the nodes array
+                self.pos_map.insert(*pos, unsafe { NodeId::from_index(i) });
+            }
+        }
+
+        for entry in ticks {
+            if let Some(node) = self.pos_map.get(&entry.pos) {
+                self.runtime.scheduler.schedule_tick(
+                    *node,
+                    entry.ticks_left as usize,
+                    entry.tick_priority,
+                );
+                self.states[node.index()].pending_tick = true;
+            }
+        }
+
+        self.code = Some(code);
+    }
+
+    fn has_pending_ticks(&self) -> bool {
+        self.runtime.scheduler.has_pending_ticks()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        // Inputs are read from the nodes linking in, so there are none to keep
+        let nodes = self
+            .states
+            .iter()
+            .map(|state| NodeSnapshot {
+                powered: state.powered,
+                locked: state.locked,
+                output_power: state.output_power,
+                pending_tick: state.pending_tick,
+                ..Default::default()
+            })
+            .collect();
+        Snapshot {
+            backend: BackendVariant::Cranelift,
+            parts: vec![BackendState {
+                positions: self.positions().collect(),
+                graph_hash: self.graph_hash,
+                nodes,
+                ticks: self.runtime.scheduler.snapshot(None),
+                buses: Vec::new(),
+            }],
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let [state] = &snapshot.parts[..] else {
+            return Err(SnapshotError::Mismatch);
+        };
+        let fits = snapshot.backend == BackendVariant::Cranelift
+            && state.fits(self.positions(), self.graph_hash)
+            && TickScheduler::fits(&state.ticks, self.states.len())
+            && state.ticks.mid_tick.is_none();
+        if !fits {
+            return Err(SnapshotError::Mismatch);
+        }
+
+        self.runtime.scheduler.restore(&state.ticks);
+        for (state, snapshot) in self.states.iter_mut().zip(&state.nodes) {
+            *state = NodeState {
+                output_power: snapshot.output_power,
+                powered: snapshot.powered,
+                locked: snapshot.locked,
+                pending_tick: snapshot.pending_tick,
+                changed: true,
+            };
+        }
+        self.runtime.events.clear();
+        Ok(())
+    }
+
+    fn node_state(&self, pos: BlockPos) -> Option<crate::NodeState> {
+        let state = &self.states[self.pos_map.get(&pos)?.index()];
+        Some(crate::NodeState {
+            powered: state.powered,
+            locked: state.locked,
+            power: state.output_power,
+        })
+    }
+}
+
+/// Writes the current state of a node into its block in the world.
+fn write_block<W: World>(world: &mut W, pos: BlockPos, block: &mut Block, state: &NodeState) {
+    if let Some(powered) = block_powered_mut(block) {
+        *powered = state.powered
+    }
+    if let Block::RedstoneWire { wire, .. } = block {
+        wire.power = state.output_power
+    };
+    if let Block::RedstoneRepeater { repeater } = block {
+        repeater.locked = state.locked;
+    }
+    world.set_block(pos, *block);
+}
@@ -0,0 +1,287 @@
+//! Buses found by the `DetectBuses` pass are ticked a whole stage at a time. The lanes of a stage
+//! keep their state as bits of a mask, so turning on one bit or all 64 of them costs the same.
+//!
+//! Only the last stage of a bus updates other nodes. Every other stage powers the stage after it
+//! directly, without going through the inputs of its nodes.
+
+use super::node::{Node, NodeId, NodeInput, NodeType};
+use super::*;
+use crate::snapshot::BusSnapshot;
+use smallvec::SmallVec;
+
+pub(super) struct BusStage {
+    /// The type every lane had before it was lowered
+    ty: NodeType,
+    /// Scheduled in place of the lanes, whenever any of them has a tick pending
+    node: NodeId,
+    lanes: Box<[NodeId]>,
+    first: bool,
+    last: bool,
+    powered: u64,
+    pending: u64,
+    /// The lanes that will be ticked, by queue and priority
+    scheduled: [[u64; TickScheduler::NUM_PRIORITIES]; TickScheduler::NUM_QUEUES],
+}
+
+/// Turns the nodes of every bus into lanes, adding a node for every stage. `buses` holds the
+/// indices of the nodes of every stage of every bus, ordered by lane.
+pub(super) fn lower(
+    nodes: &mut Vec<Node>,
+    blocks: &mut Vec<Option<(BlockPos, Block)>>,
+    buses: Vec<Vec<Vec<usize>>>,
+) -> Vec<BusStage> {
+    let mut stages = Vec::new();
+    for bus in buses {
+        let num_stages = bus.len();
+        for (stage_num, lanes) in bus.into_iter().enumerate() {
+            let stage = stages.len() as u32;
+            let ty = nodes[lanes[0]].ty;
+            let mut powered = 0;
+            for (lane, &idx) in lanes.iter().enumerate() {
+                let node = &mut nodes[idx];
+                powered |= (node.powered as u64) << lane;
+                node.ty = NodeType::BusLane {
+                    stage,
+                    lane: lane as u8,
+                };
+                if stage_num + 1 < num_stages {
+                    node.updates.clear();
+                }
+            }
+
+            // Safety: the node is pushed right below, and the lanes are indices into `nodes`
+            let node = unsafe { NodeId::from_index(nodes.len()) };
+            nodes.push(Node {
+                ty: NodeType::BusStage { stage },
+                default_inputs: NodeInput::default(),
+                side_inputs: NodeInput::default(),
+                high_inputs: None,
+                updates: SmallVec::new(),
+                observers: Box::default(),
+                powered: false,
+                output_power: 0,
+                locked: false,
+                pending_tick: false,
+                changed: false,
+                is_io: false,
+            });
+            blocks.push(None);
+            let lanes = lanes
+                .into_iter()
+                .map(|idx| {
+                    assert!(idx < nodes.len());
+                    unsafe { NodeId::from_index(idx) }
+                })
+                .collect();
+            stages.push(BusStage {
+                ty,
+                node,
+                lanes,
+                first: stage_num == 0,
+                last: stage_num + 1 == num_stages,
+                powered,
+                pending: 0,
+                scheduled: Default::default(),
+            });
+        }
+    }
+    stages
+}
+
+impl BusStage {
+    pub(super) fn snapshot(&self) -> BusSnapshot {
+        BusSnapshot {
+            powered: self.powered,
+            pending: self.pending,
+            scheduled: self.scheduled.to_vec(),
+        }
+    }
+
+    pub(super) fn fits(snapshot: &BusSnapshot) -> bool {
+        snapshot.scheduled.len() == TickScheduler::NUM_QUEUES
+    }
+
+    /// Takes the state of the snapshot, which has to be checked with [`BusStage::fits`] first.
+    pub(super) fn restore(&mut self, snapshot: &BusSnapshot) {
+        self.powered = snapshot.powered;
+        self.pending = snapshot.pending;
+        self.scheduled.copy_from_slice(&snapshot.scheduled);
+    }
+}
+
+/// Iterates over the lanes that are set in `mask`.
+fn lanes_of(mut mask: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if mask == 0 {
+            return None;
+        }
+        let lane = mask.trailing_zeros() as usize;
+        mask &= mask - 1;
+        Some(lane)
+    })
+}
+
+fn schedule_lanes(
+    scheduler: &mut TickScheduler,
+    stage: &mut BusStage,
+    lanes: u64,
+    delay: usize,
+    priority: TickPriority,
+) {
+    if lanes == 0 {
+        return;
+    }
+    stage.pending |= lanes;
+    let scheduled = &mut stage.scheduled[scheduler.slot(delay)][priority as usize];
+    if *scheduled == 0 {
+        scheduler.schedule_tick(stage.node, delay, priority);
+    }
+    *scheduled |= lanes;
+}
+
+/// Does what `update_node` does for each of `lanes`, given the input of every lane as a mask.
+pub(super) fn update_lanes(
+    scheduler: &mut TickScheduler,
+    stage: &mut BusStage,
+    lanes: u64,
+    input: u64,
+) {
+    let lanes = lanes & !stage.pending;
+    match stage.ty {
+        NodeType::Repeater {
+            delay,
+            facing_diode,
+        } => {
+            let changed = lanes & (input ^ stage.powered);
+            let delay = delay as usize;
+            if facing_diode {
+                schedule_lanes(scheduler, stage, changed, delay, TickPriority::Highest);
+            } else {
+                schedule_lanes(
+                    scheduler,
+                    stage,
+                    changed & !input,
+                    delay,
+                    TickPriority::Higher,
+                );
+                schedule_lanes(scheduler, stage, changed & input, delay, TickPriority::High);
+            }
+        }
+        NodeType::Torch => {
+            let changed = lanes & !(input ^ stage.powered);
+            schedule_lanes(scheduler, stage, changed, 1, TickPriority::Normal);
+        }
+        ty => unreachable!("Bus lanes cannot be a {:?}", ty),
+    }
+}
+
+impl DirectBackend {
+    /// Schedules a tick that was scheduled for the node of a lane before it was compiled.
+    pub(super) fn schedule_lane_tick(
+        &mut self,
+        node_id: NodeId,
+        delay: usize,
+        priority: TickPriority,
+    ) {
+        let NodeType::BusLane { stage, lane } = self.nodes[node_id].ty else {
+            unreachable!("Node {:?} is not a bus lane", node_id);
+        };
+        let stage = &mut self.bus_stages[stage as usize];
+        schedule_lanes(&mut self.scheduler, stage, 1 << lane, delay, priority);
+    }
+
+    /// Ticks the lanes of the stage that were scheduled for the highest priority left this tick.
+    pub(super) fn tick_stage(&mut self, stage_id: usize) {
+        let slot = self.scheduler.current_slot();
+        let stage = &mut self.bus_stages[stage_id];
+        let Some(lanes) = stage.scheduled[slot].iter_mut().find(|lanes| **lanes != 0) else {
+            return;
+        };
+        let lanes = mem::take(lanes);
+        stage.pending &= !lanes;
+
+        let stage = &self.bus_stages[stage_id];
+        let input = if stage.first {
+            lanes_of(lanes).fold(0, |input, lane| {
+                input | (get_bool_input(&self.nodes[stage.lanes[lane]]) as u64) << lane
+            })
+        } else {
+            self.bus_stages[stage_id - 1].powered
+        };
+
+        let stage = &mut self.bus_stages[stage_id];
+        let changed = match stage.ty {
+            NodeType::Repeater { delay, .. } => {
+                let turned_on = lanes & !stage.powered;
+                let delay = delay as usize;
+                let pulse = turned_on & !input;
+                schedule_lanes(
+                    &mut self.scheduler,
+                    stage,
+                    pulse,
+                    delay,
+                    TickPriority::Higher,
+                );
+                (lanes & stage.powered & !input) | turned_on
+            }
+            NodeType::Torch => lanes & !(input ^ stage.powered),
+            ty => unreachable!("Bus lanes cannot be a {:?}", ty),
+        };
+        self.set_lanes(stage_id, changed);
+    }
+
+    /// Flips the power of the `changed` lanes of a stage.
+    fn set_lanes(&mut self, stage_id: usize, changed: u64) {
+        if changed == 0 {
+            return;
+        }
+        let stage = &mut self.bus_stages[stage_id];
+        stage.powered ^= changed;
+        let powered = stage.powered;
+        if stage.last {
+            for lane in lanes_of(changed) {
+                let node_id = self.bus_stages[stage_id].lanes[lane];
+                let lane_powered = powered >> lane & 1 != 0;
+                self.set_node(node_id, lane_powered, bool_to_ss(lane_powered));
+            }
+            return;
+        }
+
+        for lane in lanes_of(changed) {
+            let node = &mut self.nodes[stage.lanes[lane]];
+            node.powered = powered >> lane & 1 != 0;
+            node.output_power = bool_to_ss(node.powered);
+            node.changed = true;
+        }
+        let next = &mut self.bus_stages[stage_id + 1];
+        update_lanes(&mut self.scheduler, next, changed, powered);
+    }
+
+    /// Schedules the pending ticks of every lane in the world, before the scheduler does the same
+    /// for the other nodes. Stage nodes have no block to schedule a tick for.
+    pub(super) fn reset_buses<W: World>(&mut self, world: &mut W) {
+        let stage_nodes = self.copy_bus_ticks(world);
+        self.scheduler.cancel(&stage_nodes);
+        self.bus_stages.clear();
+    }
+
+    /// Schedules the ticks of the lanes of every bus in `world`, and returns the nodes of the
+    /// stages, which stand in for those ticks in the scheduler.
+    pub(super) fn copy_bus_ticks<W: World>(&self, world: &mut W) -> FxHashSet<NodeId> {
+        let mut stage_nodes = FxHashSet::default();
+        for stage in &self.bus_stages {
+            stage_nodes.insert(stage.node);
+            for (slot, scheduled) in stage.scheduled.iter().enumerate() {
+                let delay = self.scheduler.delay_of(slot) as u32;
+                for (&lanes, priority) in scheduled.iter().zip(TickScheduler::priorities()) {
+                    for lane in lanes_of(lanes) {
+                        if let Some((pos, _)) = self.blocks[stage.lanes[lane].index()] {
+                            world.schedule_tick(pos, delay, priority);
+                        }
+                    }
+                }
+            }
+        }
+        stage_nodes
+    }
+}
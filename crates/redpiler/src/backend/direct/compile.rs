@@ -1,4 +1,8 @@
 use crate::compile_graph::{CompileGraph, LinkType, NodeIdx};
+use crate::incremental::GraphPatch;
+use crate::passes::bus_stages;
+use crate::snapshot::graph_hash;
+use crate::trace::{TraceNode, TraceWriter, TRACE_PATH};
 use crate::{CompilerOptions, TaskMonitor};
 use itertools::Itertools;
 use mchprs_blocks::blocks::{Block, Instrument};
@@ -6,13 +10,17 @@ use mchprs_blocks::BlockPos;
 use mchprs_world::TickEntry;
 use petgraph::visit::EdgeRef;
 use petgraph::Direction;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use smallvec::SmallVec;
 use std::sync::Arc;
-use tracing::trace;
+use tracing::{error, trace};
 
-use super::node::{ForwardLink, Node, NodeId, NodeInput, NodeType, Nodes, NonMaxU8};
-use super::DirectBackend;
+use super::node::{ForwardLink, HighInputs, Node, NodeId, NodeInput, NodeType, Nodes, NonMaxU8};
+use super::{bus, node_state, update, DirectBackend};
+
+/// How many free slots are always fine to keep around. Past this, the backend is compiled again
+/// once at least half of its slots are free.
+const MIN_FREE_SLOTS: usize = 64;
 
 #[derive(Debug, Default)]
 struct FinalGraphStats {
@@ -37,13 +45,25 @@ fn compile_node(
     let mut default_input_count = 0;
     let mut side_input_count = 0;
 
+    use crate::compile_graph::NodeType as CNodeType;
+
     let mut default_inputs = NodeInput { ss_counts: [0; 16] };
     let mut side_inputs = NodeInput { ss_counts: [0; 16] };
-    for edge in graph.edges_directed(node_idx, Direction::Incoming) {
+    let mut high_inputs: Option<Box<HighInputs>> = None;
+    // The incoming links of an observer are lowered into the `observers` of the observed node
+    let incoming = graph
+        .edges_directed(node_idx, Direction::Incoming)
+        .filter(|_| node.ty != CNodeType::Observer);
+    for edge in incoming {
         let weight = edge.weight();
-        let distance = weight.ss;
-        let source = edge.source();
-        let ss = graph[source].state.output_strength.saturating_sub(distance);
+        let ss = weight.received(graph[edge.source()].state.output_strength);
+        if ss > 15 {
+            high_inputs.get_or_insert_with(Default::default).replace(
+                weight.ty == LinkType::Side,
+                0,
+                ss,
+            );
+        }
         match weight.ty {
             LinkType::Default => {
                 if default_input_count >= MAX_INPUTS {
@@ -53,24 +73,26 @@ fn compile_node(
                     );
                 }
                 default_input_count += 1;
-                default_inputs.ss_counts[ss as usize] += 1;
+                default_inputs.ss_counts[ss.min(15) as usize] += 1;
             }
             LinkType::Side => {
                 if side_input_count >= MAX_INPUTS {
                     panic!("Exceeded the maximum number of side inputs {}", MAX_INPUTS);
                 }
                 side_input_count += 1;
-                side_inputs.ss_counts[ss as usize] += 1;
+                side_inputs.ss_counts[ss.min(15) as usize] += 1;
             }
         }
     }
     stats.default_link_count += default_input_count;
     stats.side_link_count += side_input_count;
 
-    use crate::compile_graph::NodeType as CNodeType;
+    let (observers, outgoing): (Vec<_>, Vec<_>) = graph
+        .edges_directed(node_idx, Direction::Outgoing)
+        .partition(|edge| graph[edge.target()].ty == CNodeType::Observer);
     let updates = if node.ty != CNodeType::Constant {
-        graph
-            .edges_directed(node_idx, Direction::Outgoing)
+        outgoing
+            .into_iter()
             .sorted_by_key(|edge| nodes_map[&edge.target()])
             .into_group_map_by(|edge| std::mem::discriminant(&graph[edge.target()].ty))
             .into_values()
@@ -83,15 +105,55 @@ fn compile_node(
                 let target_id = NodeId::from_index(idx);
 
                 let weight = edge.weight();
-                ForwardLink::new(target_id, weight.ty == LinkType::Side, weight.ss)
+                ForwardLink::new(
+                    target_id,
+                    weight.ty == LinkType::Side,
+                    weight.ss,
+                    weight.through_wire,
+                )
             })
             .collect()
     } else {
         SmallVec::new()
     };
     stats.update_link_count += updates.len();
+    let observers = observers
+        .into_iter()
+        .map(|edge| {
+            let idx = nodes_map[&edge.target()];
+            assert!(idx < nodes_len);
+            // Safety: bounds checked
+            unsafe { NodeId::from_index(idx) }
+        })
+        .collect();
+
+    let ty = lower_type(graph, node_idx, noteblock_info);
+
+    Node {
+        ty,
+        default_inputs,
+        side_inputs,
+        high_inputs,
+        updates,
+        observers,
+        powered: node.state.powered,
+        output_power: node.state.output_strength,
+        locked: node.state.repeater_locked,
+        pending_tick: false,
+        changed: false,
+        is_io: node.is_input || node.is_output,
+    }
+}
 
-    let ty = match &node.ty {
+fn lower_type(
+    graph: &CompileGraph,
+    node_idx: NodeIdx,
+    noteblock_info: &mut Vec<(BlockPos, Instrument, u32)>,
+) -> NodeType {
+    use crate::compile_graph::NodeType as CNodeType;
+
+    let node = &graph[node_idx];
+    match &node.ty {
         CNodeType::Repeater {
             delay,
             facing_diode,
@@ -106,7 +168,8 @@ fn compile_node(
             facing_diode,
         } => NodeType::Comparator {
             mode: *mode,
-            far_input: far_input.map(|value| NonMaxU8::new(value).unwrap()),
+            // 255 cannot be stored, but no container gets there without commands
+            far_input: far_input.map(|value| NonMaxU8::new(value.min(u8::MAX - 1)).unwrap()),
             facing_diode: *facing_diode,
         },
         CNodeType::Lamp => NodeType::Lamp,
@@ -121,19 +184,8 @@ fn compile_node(
             noteblock_info.push((node.block.unwrap().0, *instrument, *note));
             NodeType::NoteBlock { noteblock_id }
         }
-    };
-
-    Node {
-        ty,
-        default_inputs,
-        side_inputs,
-        updates,
-        powered: node.state.powered,
-        output_power: node.state.output_strength,
-        locked: node.state.repeater_locked,
-        pending_tick: false,
-        changed: false,
-        is_io: node.is_input || node.is_output,
+        CNodeType::Observer => NodeType::Observer,
+        CNodeType::Interface => NodeType::Interface,
     }
 }
 
@@ -144,6 +196,7 @@ pub fn compile(
     options: &CompilerOptions,
     _monitor: Arc<TaskMonitor>,
 ) {
+    backend.graph_hash = graph_hash(&graph);
     // Create a mapping from compile to backend node indices
     let mut nodes_map = FxHashMap::with_capacity_and_hasher(graph.node_count(), Default::default());
     for node in graph.node_indices() {
@@ -153,7 +206,7 @@ pub fn compile(
 
     // Lower nodes
     let mut stats = FinalGraphStats::default();
-    let nodes = graph
+    let mut nodes: Vec<Node> = graph
         .node_indices()
         .map(|idx| {
             compile_node(
@@ -166,14 +219,29 @@ pub fn compile(
             )
         })
         .collect();
-    stats.nodes_bytes = nodes_len * std::mem::size_of::<Node>();
-    trace!("{:#?}", stats);
-
-    backend.blocks = graph
+    let mut blocks = graph
         .node_weights()
         .map(|node| node.block.map(|(pos, id)| (pos, Block::from_id(id))))
         .collect();
-    backend.nodes = Nodes::new(nodes);
+
+    // The trace records every node on its own, so buses are only lowered without it
+    if !options.trace {
+        let buses = bus_stages(&graph)
+            .into_iter()
+            .map(|stages| {
+                stages
+                    .into_iter()
+                    .map(|lanes| lanes.into_iter().map(|idx| nodes_map[&idx]).collect())
+                    .collect()
+            })
+            .collect();
+        backend.bus_stages = bus::lower(&mut nodes, &mut blocks, buses);
+    }
+    stats.nodes_bytes = nodes.len() * std::mem::size_of::<Node>();
+    trace!("{:#?}", stats);
+
+    backend.blocks = blocks;
+    backend.nodes = Nodes::new(nodes.into_boxed_slice());
 
     // Create a mapping from block pos to backend NodeId
     for i in 0..backend.blocks.len() {
@@ -184,11 +252,33 @@ pub fn compile(
 
     // Schedule backend ticks
     for entry in ticks {
-        if let Some(node) = backend.pos_map.get(&entry.pos) {
+        if let Some(&node) = backend.pos_map.get(&entry.pos) {
+            let delay = entry.ticks_left as usize;
+            if matches!(backend.nodes[node].ty, NodeType::BusLane { .. }) {
+                backend.schedule_lane_tick(node, delay, entry.tick_priority);
+                continue;
+            }
             backend
                 .scheduler
-                .schedule_tick(*node, entry.ticks_left as usize, entry.tick_priority);
-            backend.nodes[*node].pending_tick = true;
+                .schedule_tick(node, delay, entry.tick_priority);
+            backend.nodes[node].pending_tick = true;
+        }
+    }
+
+    if options.trace {
+        let nodes = backend
+            .nodes
+            .inner()
+            .iter()
+            .zip(&backend.blocks)
+            .map(|(node, block)| TraceNode {
+                pos: block.map(|(pos, _)| pos),
+                block: block.map_or("", |(_, block)| block.get_name()).to_string(),
+                initial: node_state(node),
+            });
+        match TraceWriter::create(TRACE_PATH, nodes) {
+            Ok(trace) => backend.trace = Some(trace),
+            Err(err) => error!("Could not create redpiler trace: {}", err),
         }
     }
 
@@ -197,3 +287,310 @@ pub fn compile(
         std::fs::write("backend_graph.dot", format!("{}", backend)).unwrap();
     }
 }
+
+/// A node that does nothing, left in the slot of a removed node until an added node takes it.
+fn free_slot() -> Node {
+    Node {
+        ty: NodeType::Constant,
+        default_inputs: NodeInput::default(),
+        side_inputs: NodeInput::default(),
+        high_inputs: None,
+        updates: SmallVec::new(),
+        observers: Box::default(),
+        powered: false,
+        output_power: 0,
+        locked: false,
+        pending_tick: false,
+        changed: false,
+        is_io: false,
+    }
+}
+
+/// Applies the changes made to `graph` since it was compiled, keeping the state of every node that
+/// is still around. Returns false without changing anything if so many nodes were removed that
+/// the backend should rather be compiled again, to get rid of their slots.
+pub fn patch(backend: &mut DirectBackend, graph: &CompileGraph, patch: &GraphPatch) -> bool {
+    let removed: Vec<NodeId> = patch
+        .removed
+        .iter()
+        .filter_map(|pos| backend.pos_map.get(pos).copied())
+        .collect();
+    let free = (backend.free_slots.len() + removed.len()).saturating_sub(patch.added.len());
+    if free > MIN_FREE_SLOTS && free * 2 > backend.blocks.len() {
+        return false;
+    }
+
+    backend.graph_hash = graph_hash(graph);
+    // Nodes that nothing should link to anymore
+    let mut stale = FxHashSet::default();
+
+    for (pos, &node_id) in patch.removed.iter().zip(&removed) {
+        backend.pos_map.remove(pos);
+        let node = std::mem::replace(&mut backend.nodes[node_id], free_slot());
+        if let NodeType::NoteBlock { noteblock_id } = node.ty {
+            backend.free_noteblocks.push(noteblock_id);
+        }
+        backend.blocks[node_id.index()] = None;
+        backend.free_slots.push(node_id);
+        stale.insert(node_id);
+    }
+    backend.scheduler.cancel(&stale);
+
+    let missing = patch.added.len().saturating_sub(backend.free_slots.len());
+    if missing > 0 {
+        // Slots are added in bulk, so adding nodes one by one doesn't copy all of them every time
+        let len = backend.blocks.len();
+        let grow = missing.max(len / 8);
+        let mut nodes = std::mem::take(&mut backend.nodes).into_inner().into_vec();
+        nodes.extend((0..grow).map(|_| free_slot()));
+        backend.nodes = Nodes::new(nodes.into_boxed_slice());
+        backend.blocks.resize(len + grow, None);
+        // Taken from the back, so the new slots are used in order
+        backend
+            .free_slots
+            .extend((len..len + grow).rev().map(|i| backend.nodes.get(i)));
+    }
+    for &idx in &patch.added {
+        let node = &graph[idx];
+        let (pos, block) = node.block.unwrap();
+        let mut ty = lower_type(graph, idx, &mut backend.noteblock_info);
+        if let NodeType::NoteBlock { noteblock_id } = &mut ty {
+            if let Some(free_id) = backend.free_noteblocks.pop() {
+                let info = backend.noteblock_info.pop().unwrap();
+                backend.noteblock_info[free_id as usize] = info;
+                *noteblock_id = free_id;
+            }
+        }
+        let node_id = backend.free_slots.pop().unwrap();
+        backend.nodes[node_id] = Node {
+            ty,
+            default_inputs: NodeInput::default(),
+            side_inputs: NodeInput::default(),
+            high_inputs: None,
+            updates: SmallVec::new(),
+            observers: Box::default(),
+            powered: node.state.powered,
+            output_power: node.state.output_strength,
+            locked: node.state.repeater_locked,
+            pending_tick: false,
+            changed: false,
+            is_io: node.is_input || node.is_output,
+        };
+        backend.blocks[node_id.index()] = Some((pos, Block::from_id(block)));
+        backend.pos_map.insert(pos, node_id);
+    }
+
+    let relinked: Vec<NodeId> = patch
+        .relinked
+        .iter()
+        .map(|&idx| backend.pos_map[&graph[idx].block.unwrap().0])
+        .collect();
+    stale.extend(relinked.iter().copied());
+    // Only the nodes that linked to the stale ones before the edit have links to drop
+    let unlinked: FxHashSet<NodeId> = patch
+        .unlinked
+        .iter()
+        .filter_map(|pos| backend.pos_map.get(pos).copied())
+        .collect();
+    for node_id in unlinked {
+        let node = &mut backend.nodes[node_id];
+        node.updates.retain(|link| !stale.contains(&link.node()));
+        if node.observers.iter().any(|id| stale.contains(id)) {
+            node.observers = node
+                .observers
+                .iter()
+                .copied()
+                .filter(|id| !stale.contains(id))
+                .collect();
+        }
+    }
+
+    use crate::compile_graph::NodeType as CNodeType;
+
+    for (&idx, &node_id) in patch.relinked.iter().zip(&relinked) {
+        let (pos, block) = graph[idx].block.unwrap();
+        backend.blocks[node_id.index()] = Some((pos, Block::from_id(block)));
+
+        let mut default_inputs = NodeInput::default();
+        let mut side_inputs = NodeInput::default();
+        for edge in graph.edges_directed(idx, Direction::Incoming) {
+            let source = &graph[edge.source()];
+            let source_id = backend.pos_map[&source.block.unwrap().0];
+            let source_node = &mut backend.nodes[source_id];
+            if graph[idx].ty == CNodeType::Observer {
+                let mut observers = source_node.observers.to_vec();
+                observers.push(node_id);
+                source_node.observers = observers.into_boxed_slice();
+                continue;
+            }
+
+            let weight = edge.weight();
+            // Graphs with high signal strength are never patched
+            let ss = weight.received(source_node.output_power);
+            let side = weight.ty == LinkType::Side;
+            let inputs = if side {
+                &mut side_inputs
+            } else {
+                &mut default_inputs
+            };
+            inputs.ss_counts[ss as usize] += 1;
+            if source.ty != CNodeType::Constant {
+                source_node.updates.push(ForwardLink::new(
+                    node_id,
+                    side,
+                    weight.ss,
+                    weight.through_wire,
+                ));
+            }
+        }
+
+        let node = &mut backend.nodes[node_id];
+        node.default_inputs = default_inputs;
+        node.side_inputs = side_inputs;
+        // The block in the world may have been changed by the edit, so it is written again
+        node.changed = true;
+    }
+
+    // Like a block update, this lets the nodes react to their new inputs
+    for &node_id in &relinked {
+        update::update_node(
+            &mut backend.scheduler,
+            &mut backend.events,
+            &mut backend.nodes,
+            &mut backend.bus_stages,
+            node_id,
+        );
+    }
+    backend.remap_breakpoints();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::JITBackend;
+    use crate::breakpoint::BreakCondition;
+    use crate::compile_graph::{CompileLink, CompileNode, NodeState, NodeType as CNodeType};
+
+    fn add_node(graph: &mut CompileGraph, ty: CNodeType, x: i32) -> NodeIdx {
+        graph.add_node(CompileNode {
+            ty,
+            block: Some((BlockPos::new(x, 0, 0), 0)),
+            state: NodeState::default(),
+            is_input: false,
+            is_output: false,
+            annotations: Default::default(),
+        })
+    }
+
+    fn compiled(graph: &CompileGraph) -> DirectBackend {
+        let mut backend = DirectBackend::default();
+        compile(
+            &mut backend,
+            graph.clone(),
+            Vec::new(),
+            &Default::default(),
+            Default::default(),
+        );
+        backend
+    }
+
+    #[test]
+    fn patch_reuses_slots() {
+        let mut graph = CompileGraph::new();
+        let lever = add_node(&mut graph, CNodeType::Lever, 0);
+        let mut lamp = add_node(&mut graph, CNodeType::Lamp, 1);
+        graph.add_edge(lever, lamp, CompileLink::default(0));
+        let mut backend = compiled(&graph);
+        let lamp_pos = BlockPos::new(1, 0, 0);
+
+        for _ in 0..100 {
+            graph.remove_node(lamp);
+            let removed = GraphPatch {
+                removed: vec![lamp_pos],
+                unlinked: vec![BlockPos::new(0, 0, 0)],
+                ..Default::default()
+            };
+            assert!(patch(&mut backend, &graph, &removed));
+
+            lamp = add_node(&mut graph, CNodeType::Lamp, 1);
+            graph.add_edge(lever, lamp, CompileLink::default(0));
+            let added = GraphPatch {
+                added: vec![lamp],
+                relinked: vec![lamp],
+                ..Default::default()
+            };
+            assert!(patch(&mut backend, &graph, &added));
+        }
+        assert_eq!(backend.nodes.inner().len(), 2);
+
+        backend.on_use_block(BlockPos::new(0, 0, 0));
+        backend.tick();
+        assert!(backend.node_state(lamp_pos).unwrap().powered);
+    }
+
+    #[test]
+    fn patch_remaps_breakpoints() {
+        let mut graph = CompileGraph::new();
+        let lever = add_node(&mut graph, CNodeType::Lever, 0);
+        let torch = add_node(&mut graph, CNodeType::Torch, 1);
+        let lamp = add_node(&mut graph, CNodeType::Lamp, 2);
+        graph.add_edge(lever, torch, CompileLink::default(0));
+        graph.add_edge(torch, lamp, CompileLink::default(0));
+        let mut backend = compiled(&graph);
+        let (torch_pos, lamp_pos) = (BlockPos::new(1, 0, 0), BlockPos::new(2, 0, 0));
+        assert!(backend.set_breakpoint(lamp_pos, Some(BreakCondition::On)));
+
+        // The new torch takes the slot of the lamp, and the new lamp the one of the torch
+        graph.remove_node(torch);
+        graph.remove_node(lamp);
+        let torch = add_node(&mut graph, CNodeType::Torch, 1);
+        let lamp = add_node(&mut graph, CNodeType::Lamp, 2);
+        graph.add_edge(lever, torch, CompileLink::default(0));
+        graph.add_edge(torch, lamp, CompileLink::default(0));
+        let replaced = GraphPatch {
+            removed: vec![torch_pos, lamp_pos],
+            added: vec![torch, lamp],
+            relinked: vec![torch, lamp],
+            unlinked: vec![BlockPos::new(0, 0, 0), torch_pos],
+        };
+        assert!(patch(&mut backend, &graph, &replaced));
+        assert_ne!(backend.pos_map[&lamp_pos], backend.pos_map[&torch_pos]);
+        assert_eq!(backend.breakpoints[0].node, backend.pos_map[&lamp_pos]);
+
+        graph.remove_node(lamp);
+        let removed = GraphPatch {
+            removed: vec![lamp_pos],
+            unlinked: vec![torch_pos],
+            ..Default::default()
+        };
+        assert!(patch(&mut backend, &graph, &removed));
+        assert!(backend.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn patch_refuses_to_leave_most_slots_free() {
+        let mut graph = CompileGraph::new();
+        let lever = add_node(&mut graph, CNodeType::Lever, 0);
+        let lamps: Vec<_> = (1..=100)
+            .map(|x| add_node(&mut graph, CNodeType::Lamp, x))
+            .collect();
+        for &lamp in &lamps {
+            graph.add_edge(lever, lamp, CompileLink::default(0));
+        }
+        let mut backend = compiled(&graph);
+
+        for &lamp in &lamps[..80] {
+            graph.remove_node(lamp);
+        }
+        let removed = GraphPatch {
+            removed: (1..=80).map(|x| BlockPos::new(x, 0, 0)).collect(),
+            unlinked: vec![BlockPos::new(0, 0, 0)],
+            ..Default::default()
+        };
+        assert!(!patch(&mut backend, &graph, &removed));
+        // Nothing was changed, so the backend can still be reset into the world
+        assert!(backend.pos_map.contains_key(&BlockPos::new(1, 0, 0)));
+        assert!(backend.free_slots.is_empty());
+    }
+}
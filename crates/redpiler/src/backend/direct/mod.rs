@@ -1,39 +1,72 @@
 //! The direct backend does not do code generation and operates on the `CompileNode` graph directly
 
+mod bus;
 mod compile;
 mod node;
 mod tick;
 mod update;
 
 use super::JITBackend;
+use crate::breakpoint::{BreakCondition, BreakpointHit};
 use crate::compile_graph::CompileGraph;
+use crate::incremental::GraphPatch;
+use crate::snapshot::{BackendState, Snapshot, SnapshotError, TickSnapshot};
 use crate::task_monitor::TaskMonitor;
-use crate::{block_powered_mut, CompilerOptions};
+use crate::trace::{NodeState, TraceCause, TraceWriter};
+use crate::{block_powered_mut, BackendVariant, CompilerOptions};
+use bus::BusStage;
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::{Block, ComparatorMode, Instrument};
 use mchprs_blocks::BlockPos;
 use mchprs_redstone::{bool_to_ss, noteblock};
 use mchprs_world::World;
 use mchprs_world::{TickEntry, TickPriority};
-use node::{Node, NodeId, NodeType, Nodes};
-use rustc_hash::FxHashMap;
+pub(super) use node::NodeId;
+use node::{Node, NodeType, Nodes};
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::sync::Arc;
 use std::{fmt, mem};
 use tracing::{debug, warn};
 
 #[derive(Default, Clone)]
-struct Queues([Vec<NodeId>; TickScheduler::NUM_PRIORITIES]);
+pub(super) struct Queues([Vec<NodeId>; TickScheduler::NUM_PRIORITIES]);
 
 impl Queues {
-    fn drain_iter(&mut self) -> impl Iterator<Item = NodeId> + '_ {
+    pub(super) fn drain_iter(&mut self) -> impl Iterator<Item = NodeId> + '_ {
         let [q0, q1, q2, q3] = &mut self.0;
         let [q0, q1, q2, q3] = [q0, q1, q2, q3].map(|q| q.drain(..));
         q0.chain(q1).chain(q2).chain(q3)
     }
+
+    fn snapshot(&self) -> Vec<Vec<u32>> {
+        self.0
+            .iter()
+            .map(|queue| queue.iter().map(|node| node.index() as u32).collect())
+            .collect()
+    }
+
+    fn fits(queues: &[Vec<u32>], len: usize) -> bool {
+        queues.len() == TickScheduler::NUM_PRIORITIES
+            && queues.iter().flatten().all(|&node| (node as usize) < len)
+    }
+
+    /// The snapshot has to be checked with [`Queues::fits`] first.
+    fn from_snapshot(queues: &[Vec<u32>]) -> Queues {
+        let mut result = Queues::default();
+        for (queue, nodes) in result.0.iter_mut().zip(queues) {
+            // Safety: the nodes were checked to be in bounds
+            queue.extend(
+                nodes
+                    .iter()
+                    .map(|&node| unsafe { NodeId::from_index(node as usize) }),
+            );
+        }
+        result
+    }
 }
 
 #[derive(Default)]
-struct TickScheduler {
+pub(super) struct TickScheduler {
     queues_deque: [Queues; Self::NUM_QUEUES],
     pos: usize,
 }
@@ -42,15 +75,26 @@ impl TickScheduler {
     const NUM_PRIORITIES: usize = 4;
     const NUM_QUEUES: usize = 16;
 
-    fn reset<W: World>(&mut self, world: &mut W, blocks: &[Option<(BlockPos, Block)>]) {
+    pub(super) fn reset<W: World>(&mut self, world: &mut W, blocks: &[Option<(BlockPos, Block)>]) {
+        self.copy_into(world, blocks, &FxHashSet::default());
+        for queues in self.queues_deque.iter_mut() {
+            for queue in queues.0.iter_mut() {
+                queue.clear();
+            }
+        }
+    }
+
+    /// Schedules the pending ticks in `world`, except for those of the nodes in `skip`.
+    pub(super) fn copy_into<W: World>(
+        &self,
+        world: &mut W,
+        blocks: &[Option<(BlockPos, Block)>],
+        skip: &FxHashSet<NodeId>,
+    ) {
         for (idx, queues) in self.queues_deque.iter().enumerate() {
-            let delay = if self.pos >= idx {
-                idx + Self::NUM_QUEUES
-            } else {
-                idx
-            } - self.pos;
+            let delay = self.delay_of(idx);
             for (entries, priority) in queues.0.iter().zip(Self::priorities()) {
-                for node in entries {
+                for node in entries.iter().filter(|node| !skip.contains(node)) {
                     let Some((pos, _)) = blocks[node.index()] else {
                         warn!("Cannot schedule tick for node {:?} because block information is missing", node);
                         continue;
@@ -59,30 +103,53 @@ impl TickScheduler {
                 }
             }
         }
+    }
+
+    pub(super) fn schedule_tick(&mut self, node: NodeId, delay: usize, priority: TickPriority) {
+        self.queues_deque[self.slot(delay)].0[priority as usize].push(node);
+    }
+
+    /// Returns the queues that ticks scheduled with `delay` end up in.
+    pub(super) fn slot(&self, delay: usize) -> usize {
+        (self.pos + delay) % Self::NUM_QUEUES
+    }
+
+    /// Returns the queues of the tick that is currently running.
+    pub(super) fn current_slot(&self) -> usize {
+        self.pos
+    }
+
+    /// Returns how many ticks from now the queues in `slot` will run.
+    pub(super) fn delay_of(&self, slot: usize) -> usize {
+        if self.pos >= slot {
+            slot + Self::NUM_QUEUES - self.pos
+        } else {
+            slot - self.pos
+        }
+    }
+
+    /// Removes every pending tick of `nodes`.
+    pub(super) fn cancel(&mut self, nodes: &FxHashSet<NodeId>) {
         for queues in self.queues_deque.iter_mut() {
             for queue in queues.0.iter_mut() {
-                queue.clear();
+                queue.retain(|node| !nodes.contains(node));
             }
         }
     }
 
-    fn schedule_tick(&mut self, node: NodeId, delay: usize, priority: TickPriority) {
-        self.queues_deque[(self.pos + delay) % Self::NUM_QUEUES].0[priority as usize].push(node);
-    }
-
-    fn queues_this_tick(&mut self) -> Queues {
+    pub(super) fn queues_this_tick(&mut self) -> Queues {
         self.pos = (self.pos + 1) % Self::NUM_QUEUES;
         mem::take(&mut self.queues_deque[self.pos])
     }
 
-    fn end_tick(&mut self, mut queues: Queues) {
+    pub(super) fn end_tick(&mut self, mut queues: Queues) {
         for queue in &mut queues.0 {
             queue.clear();
         }
         self.queues_deque[self.pos] = queues;
     }
 
-    fn priorities() -> [TickPriority; Self::NUM_PRIORITIES] {
+    pub(super) fn priorities() -> [TickPriority; Self::NUM_PRIORITIES] {
         [
             TickPriority::Highest,
             TickPriority::Higher,
@@ -91,7 +158,43 @@ impl TickScheduler {
         ]
     }
 
-    fn has_pending_ticks(&self) -> bool {
+    pub(super) fn snapshot(&self, mid_tick: Option<&(Queues, usize)>) -> TickSnapshot {
+        TickSnapshot {
+            pos: self.pos,
+            queues: self.queues_deque.iter().map(Queues::snapshot).collect(),
+            mid_tick: mid_tick.map(|(queues, idx)| (queues.snapshot(), *idx)),
+        }
+    }
+
+    /// Returns true if the snapshot can be restored into a scheduler for `len` nodes.
+    pub(super) fn fits(snapshot: &TickSnapshot, len: usize) -> bool {
+        let mid_tick_fits = match &snapshot.mid_tick {
+            Some((queues, idx)) => *idx < Self::NUM_PRIORITIES && Queues::fits(queues, len),
+            None => true,
+        };
+        snapshot.pos < Self::NUM_QUEUES
+            && snapshot.queues.len() == Self::NUM_QUEUES
+            && snapshot
+                .queues
+                .iter()
+                .all(|queues| Queues::fits(queues, len))
+            && mid_tick_fits
+    }
+
+    /// Replaces every pending tick with the ones in the snapshot, which has to be checked with
+    /// [`TickScheduler::fits`] first. Returns the tick that was stopped halfway, if there was one.
+    pub(super) fn restore(&mut self, snapshot: &TickSnapshot) -> Option<(Queues, usize)> {
+        for (queues, nodes) in self.queues_deque.iter_mut().zip(&snapshot.queues) {
+            *queues = Queues::from_snapshot(nodes);
+        }
+        self.pos = snapshot.pos;
+        snapshot
+            .mid_tick
+            .as_ref()
+            .map(|(queues, idx)| (Queues::from_snapshot(queues), *idx))
+    }
+
+    pub(super) fn has_pending_ticks(&self) -> bool {
         for queues in &self.queues_deque {
             for queue in &queues.0 {
                 if !queue.is_empty() {
@@ -103,7 +206,7 @@ impl TickScheduler {
     }
 }
 
-enum Event {
+pub(super) enum Event {
     NoteBlockPlay { noteblock_id: u16 },
 }
 
@@ -115,6 +218,26 @@ pub struct DirectBackend {
     scheduler: TickScheduler,
     events: Vec<Event>,
     noteblock_info: Vec<(BlockPos, Instrument, u32)>,
+    /// Slots of nodes removed by a patch, which nodes added by later patches are put in
+    free_slots: Vec<NodeId>,
+    /// Ids of note blocks removed by a patch, which note blocks added later are given
+    free_noteblocks: Vec<u16>,
+    trace: Option<TraceWriter>,
+    bus_stages: Vec<BusStage>,
+    breakpoints: Vec<Breakpoint>,
+    breakpoint_hit: Option<BreakpointHit>,
+    /// The queues of a tick that was stopped halfway, along with the priority that runs next
+    mid_tick: Option<(Queues, usize)>,
+    /// The hash of the graph that was compiled, see [`crate::snapshot::graph_hash`]
+    graph_hash: u64,
+}
+
+struct Breakpoint {
+    node: NodeId,
+    pos: BlockPos,
+    condition: BreakCondition,
+    /// The state of the node when breakpoints were last checked
+    last_state: NodeState,
 }
 
 impl DirectBackend {
@@ -125,43 +248,194 @@ impl DirectBackend {
     fn set_node(&mut self, node_id: NodeId, powered: bool, new_power: u8) {
         let node = &mut self.nodes[node_id];
         let old_power = node.output_power;
+        let state_changed = node.powered != powered;
 
         node.changed = true;
         node.powered = powered;
         node.output_power = new_power;
-        for i in 0..node.updates.len() {
+        let num_updates = node.updates.len();
+        if let Some(trace) = &mut self.trace {
+            if state_changed || old_power != new_power {
+                let cause = trace.cause;
+                trace.record(node_id.index() as u32, cause, node_state(node));
+            }
+        }
+        if state_changed {
+            notify_observers(&mut self.scheduler, &mut self.nodes, node_id);
+        }
+        for i in 0..num_updates {
             let node = &self.nodes[node_id];
             let update_link = unsafe { *node.updates.get_unchecked(i) };
             let side = update_link.side();
-            let distance = update_link.ss();
             let update = update_link.node();
 
             let update_ref = &mut self.nodes[update];
-            let inputs = if side {
-                &mut update_ref.side_inputs
-            } else {
-                &mut update_ref.default_inputs
-            };
 
-            let old_power = old_power.saturating_sub(distance);
-            let new_power = new_power.saturating_sub(distance);
+            let old_power = update_link.received(old_power);
+            let new_power = update_link.received(new_power);
 
             if old_power == new_power {
                 continue;
             }
 
-            // Safety: signal strength is never larger than 15
+            if old_power > 15 || new_power > 15 {
+                update_ref
+                    .high_inputs
+                    .get_or_insert_with(Default::default)
+                    .replace(side, old_power, new_power);
+            }
+            let inputs = if side {
+                &mut update_ref.side_inputs
+            } else {
+                &mut update_ref.default_inputs
+            };
+
+            // Safety: the index is clamped to 15
             unsafe {
-                *inputs.ss_counts.get_unchecked_mut(old_power as usize) -= 1;
-                *inputs.ss_counts.get_unchecked_mut(new_power as usize) += 1;
+                *inputs
+                    .ss_counts
+                    .get_unchecked_mut(old_power.min(15) as usize) -= 1;
+                *inputs
+                    .ss_counts
+                    .get_unchecked_mut(new_power.min(15) as usize) += 1;
             }
 
+            let old_state = self
+                .trace
+                .is_some()
+                .then(|| node_state(&self.nodes[update]));
             update::update_node(
                 &mut self.scheduler,
                 &mut self.events,
                 &mut self.nodes,
+                &mut self.bus_stages,
                 update,
             );
+            if let (Some(trace), Some(old_state)) = (&mut self.trace, old_state) {
+                let new_state = node_state(&self.nodes[update]);
+                if new_state != old_state {
+                    let from = node_id.index() as u32;
+                    trace.record(
+                        update.index() as u32,
+                        TraceCause::Update { from },
+                        new_state,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Runs the ticks of the next priority, so the trace knows which priority each change happened
+    /// in and breakpoints can stop in between. Returns true if that finished the tick.
+    fn tick_priority(&mut self) -> bool {
+        let (mut queues, idx) = match self.mid_tick.take() {
+            Some(mid_tick) => mid_tick,
+            None => {
+                if let Some(trace) = &mut self.trace {
+                    trace.next_tick();
+                }
+                (self.scheduler.queues_this_tick(), 0)
+            }
+        };
+
+        let priority = TickScheduler::priorities()[idx];
+        if let Some(trace) = &mut self.trace {
+            trace.cause = TraceCause::Tick;
+            trace.priority = Some(priority);
+        }
+        for node_id in queues.0[idx].drain(..) {
+            self.tick_node(node_id);
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.cause = TraceCause::Input;
+            trace.priority = None;
+        }
+        self.check_breakpoints(priority);
+
+        if idx + 1 < TickScheduler::NUM_PRIORITIES {
+            self.mid_tick = Some((queues, idx + 1));
+            false
+        } else {
+            self.scheduler.end_tick(queues);
+            true
+        }
+    }
+
+    /// Runs the rest of a tick that was stopped halfway.
+    fn finish_tick(&mut self) {
+        while self.mid_tick.is_some() {
+            self.tick_priority();
+        }
+    }
+
+    pub(super) fn backend_state(&self) -> BackendState {
+        BackendState {
+            positions: self.positions().collect(),
+            graph_hash: self.graph_hash,
+            nodes: self.nodes.inner().iter().map(Node::snapshot).collect(),
+            ticks: self.scheduler.snapshot(self.mid_tick.as_ref()),
+            buses: self.bus_stages.iter().map(BusStage::snapshot).collect(),
+        }
+    }
+
+    /// Returns true if the state was taken of this graph and can be restored.
+    pub(super) fn fits(&self, state: &BackendState) -> bool {
+        let len = self.nodes.inner().len();
+        state.fits(self.positions(), self.graph_hash)
+            && TickScheduler::fits(&state.ticks, len)
+            && state.buses.len() == self.bus_stages.len()
+            && state.buses.iter().all(BusStage::fits)
+    }
+
+    /// Restores a state, which has to be checked with [`DirectBackend::fits`] first.
+    pub(super) fn restore_state(&mut self, state: &BackendState) {
+        self.mid_tick = self.scheduler.restore(&state.ticks);
+        for (node, snapshot) in self.nodes.inner_mut().iter_mut().zip(&state.nodes) {
+            node.restore(snapshot);
+        }
+        for (stage, snapshot) in self.bus_stages.iter_mut().zip(&state.buses) {
+            stage.restore(snapshot);
+        }
+        // Rewinding is not a change that should hit a breakpoint
+        for breakpoint in &mut self.breakpoints {
+            breakpoint.last_state = node_state(&self.nodes[breakpoint.node]);
+        }
+        self.events.clear();
+    }
+
+    /// Points the breakpoints at the nodes now at their positions, after a patch moved nodes to
+    /// other slots. Breakpoints of nodes that were removed are dropped.
+    fn remap_breakpoints(&mut self) {
+        let (nodes, pos_map) = (&self.nodes, &self.pos_map);
+        self.breakpoints.retain_mut(|breakpoint| {
+            let Some(&node) = pos_map.get(&breakpoint.pos) else {
+                return false;
+            };
+            if node != breakpoint.node {
+                // The edit replaced the node, which is not a change that should hit a breakpoint
+                breakpoint.node = node;
+                breakpoint.last_state = node_state(&nodes[node]);
+            }
+            true
+        });
+    }
+
+    fn positions(&self) -> impl ExactSizeIterator<Item = Option<BlockPos>> + '_ {
+        self.blocks.iter().map(|block| block.map(|(pos, _)| pos))
+    }
+
+    fn check_breakpoints(&mut self, priority: TickPriority) {
+        for breakpoint in &mut self.breakpoints {
+            let state = node_state(&self.nodes[breakpoint.node]);
+            let old_state = mem::replace(&mut breakpoint.last_state, state);
+            if self.breakpoint_hit.is_none() && breakpoint.condition.is_met(old_state, state) {
+                self.breakpoint_hit = Some(BreakpointHit {
+                    pos: breakpoint.pos,
+                    condition: breakpoint.condition,
+                    state,
+                    priority,
+                });
+            }
         }
     }
 }
@@ -177,6 +451,9 @@ impl JITBackend for DirectBackend {
     }
 
     fn reset<W: World>(&mut self, world: &mut W, io_only: bool) {
+        // The world cannot hold on to a tick that is only partly done
+        self.finish_tick();
+        self.reset_buses(world);
         self.scheduler.reset(world, &self.blocks);
 
         let nodes = std::mem::take(&mut self.nodes);
@@ -185,6 +462,10 @@ impl JITBackend for DirectBackend {
             let Some((pos, block)) = self.blocks[i] else {
                 continue;
             };
+            // The fallback region was never taken over, so its blocks are already up to date
+            if matches!(node.ty, NodeType::Interface) {
+                continue;
+            }
             if matches!(node.ty, NodeType::Comparator { .. }) {
                 let block_entity = BlockEntity::Comparator {
                     output_strength: node.output_power,
@@ -199,7 +480,48 @@ impl JITBackend for DirectBackend {
 
         self.pos_map.clear();
         self.noteblock_info.clear();
+        self.free_slots.clear();
+        self.free_noteblocks.clear();
         self.events.clear();
+        self.breakpoints.clear();
+        self.breakpoint_hit = None;
+        // Dropping the trace flushes whatever is left of it
+        self.trace = None;
+    }
+
+    fn copy_into<W: World>(&self, world: &mut W) {
+        let stage_nodes = self.copy_bus_ticks(world);
+        self.scheduler.copy_into(world, &self.blocks, &stage_nodes);
+        for (node, entry) in self.nodes.inner().iter().zip(&self.blocks) {
+            let Some((pos, mut block)) = *entry else {
+                continue;
+            };
+            if matches!(node.ty, NodeType::Interface) {
+                continue;
+            }
+            if matches!(node.ty, NodeType::Comparator { .. }) {
+                let block_entity = BlockEntity::Comparator {
+                    output_strength: node.output_power,
+                };
+                world.set_block_entity(pos, block_entity);
+            }
+            write_block(world, pos, &mut block, node);
+        }
+    }
+
+    fn update_nodes(&mut self, positions: &[BlockPos]) {
+        for pos in positions {
+            let Some(&node_id) = self.pos_map.get(pos) else {
+                continue;
+            };
+            update::update_node(
+                &mut self.scheduler,
+                &mut self.events,
+                &mut self.nodes,
+                &mut self.bus_stages,
+                node_id,
+            );
+        }
     }
 
     fn on_use_block(&mut self, pos: BlockPos) {
@@ -232,15 +554,52 @@ impl JITBackend for DirectBackend {
     }
 
     fn tick(&mut self) {
-        let mut queues = self.scheduler.queues_this_tick();
+        if self.trace.is_some() || !self.breakpoints.is_empty() || self.mid_tick.is_some() {
+            // Only a new hit stops the tick, one that was not taken yet is kept instead
+            let had_hit = self.breakpoint_hit.is_some();
+            while !self.tick_priority() {
+                if !had_hit && self.breakpoint_hit.is_some() {
+                    return;
+                }
+            }
+            return;
+        }
 
+        let mut queues = self.scheduler.queues_this_tick();
         for node_id in queues.drain_iter() {
             self.tick_node(node_id);
         }
-
         self.scheduler.end_tick(queues);
     }
 
+    fn step_phase(&mut self) -> bool {
+        self.tick_priority()
+    }
+
+    fn is_mid_tick(&self) -> bool {
+        self.mid_tick.is_some()
+    }
+
+    fn set_breakpoint(&mut self, pos: BlockPos, condition: Option<BreakCondition>) -> bool {
+        let Some(&node) = self.pos_map.get(&pos) else {
+            return false;
+        };
+        self.breakpoints.retain(|breakpoint| breakpoint.pos != pos);
+        if let Some(condition) = condition {
+            self.breakpoints.push(Breakpoint {
+                node,
+                pos,
+                condition,
+                last_state: node_state(&self.nodes[node]),
+            });
+        }
+        true
+    }
+
+    fn take_breakpoint_hit(&mut self) -> Option<BreakpointHit> {
+        self.breakpoint_hit.take()
+    }
+
     fn flush<W: World>(&mut self, world: &mut W, io_only: bool) {
         for event in self.events.drain(..) {
             match event {
@@ -254,19 +613,49 @@ impl JITBackend for DirectBackend {
             let Some((pos, block)) = &mut self.blocks[i] else {
                 continue;
             };
-            if node.changed && (!io_only || node.is_io) {
-                if let Some(powered) = block_powered_mut(block) {
-                    *powered = node.powered
+            if node.changed && (!io_only || node.is_io) && !matches!(node.ty, NodeType::Interface) {
+                write_block(world, *pos, block, node);
+            }
+            node.changed = false;
+        }
+    }
+
+    fn flush_interface<W: World>(
+        &mut self,
+        world: &mut W,
+        positions: &[BlockPos],
+    ) -> Vec<BlockPos> {
+        let mut changed = Vec::new();
+        for &pos in positions {
+            let Some(&node_id) = self.pos_map.get(&pos) else {
+                continue;
+            };
+            let node = &mut self.nodes[node_id];
+            let Some((pos, block)) = &mut self.blocks[node_id.index()] else {
+                continue;
+            };
+            if node.changed {
+                write_block(world, *pos, block, node);
+                node.changed = false;
+                changed.push(*pos);
+            }
+        }
+        changed
+    }
+
+    fn set_interface_power(&mut self, pos: BlockPos, power: u8) {
+        let node_id = self.pos_map[&pos];
+        match self.nodes[node_id].ty {
+            NodeType::Interface => {
+                if let Some(trace) = &mut self.trace {
+                    trace.cause = TraceCause::Interface;
                 }
-                if let Block::RedstoneWire { wire, .. } = block {
-                    wire.power = node.output_power
-                };
-                if let Block::RedstoneRepeater { repeater } = block {
-                    repeater.locked = node.locked;
+                self.set_node(node_id, power > 0, power);
+                if let Some(trace) = &mut self.trace {
+                    trace.cause = TraceCause::Input;
                 }
-                world.set_block(*pos, *block);
             }
-            node.changed = false;
+            ty => warn!("Tried to set interface power for a {:?}", ty),
         }
     }
 
@@ -280,8 +669,57 @@ impl JITBackend for DirectBackend {
         compile::compile(self, graph, ticks, options, monitor);
     }
 
+    fn patch(&mut self, graph: &CompileGraph, patch: &GraphPatch) -> bool {
+        self.finish_tick();
+        compile::patch(self, graph, patch)
+    }
+
     fn has_pending_ticks(&self) -> bool {
-        self.scheduler.has_pending_ticks()
+        self.mid_tick.is_some() || self.scheduler.has_pending_ticks()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            backend: BackendVariant::Direct,
+            parts: vec![self.backend_state()],
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        match &snapshot.parts[..] {
+            [state] if snapshot.backend == BackendVariant::Direct && self.fits(state) => {
+                self.restore_state(state);
+                Ok(())
+            }
+            _ => Err(SnapshotError::Mismatch),
+        }
+    }
+
+    fn node_state(&self, pos: BlockPos) -> Option<NodeState> {
+        let node_id = *self.pos_map.get(&pos)?;
+        Some(node_state(&self.nodes[node_id]))
+    }
+}
+
+/// Writes the current state of the node into its block in the world.
+fn write_block<W: World>(world: &mut W, pos: BlockPos, block: &mut Block, node: &Node) {
+    if let Some(powered) = block_powered_mut(block) {
+        *powered = node.powered
+    }
+    if let Block::RedstoneWire { wire, .. } = block {
+        wire.power = node.output_power
+    };
+    if let Block::RedstoneRepeater { repeater } = block {
+        repeater.locked = node.locked;
+    }
+    world.set_block(pos, *block);
+}
+
+fn node_state(node: &Node) -> NodeState {
+    NodeState {
+        powered: node.powered,
+        locked: node.locked,
+        power: node.output_power,
     }
 }
 
@@ -297,6 +735,18 @@ fn set_node_locked(node: &mut Node, locked: bool) {
     node.changed = true;
 }
 
+/// Schedules a pulse on every observer watching the node. This has to be called whenever the
+/// block state of the node changes.
+fn notify_observers(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId) {
+    for i in 0..nodes[node_id].observers.len() {
+        let observer_id = nodes[node_id].observers[i];
+        let observer = &mut nodes[observer_id];
+        if !observer.powered && !observer.pending_tick {
+            schedule_tick(scheduler, observer_id, observer, 1, TickPriority::Normal);
+        }
+    }
+}
+
 fn schedule_tick(
     scheduler: &mut TickScheduler,
     node_id: NodeId,
@@ -331,23 +781,26 @@ fn last_index_positive(array: &[u8; 16]) -> u32 {
 }
 
 fn get_all_input(node: &Node) -> (u8, u8) {
-    let input_power = last_index_positive(&node.default_inputs.ss_counts) as u8;
+    let mut input_power = last_index_positive(&node.default_inputs.ss_counts) as u8;
+
+    let mut side_input_power = last_index_positive(&node.side_inputs.ss_counts) as u8;
 
-    let side_input_power = last_index_positive(&node.side_inputs.ss_counts) as u8;
+    if let Some(high) = &node.high_inputs {
+        let max = |inputs: &[u8]| inputs.iter().copied().max().unwrap_or(0);
+        input_power = input_power.max(max(&high.default));
+        side_input_power = side_input_power.max(max(&high.side));
+    }
 
     (input_power, side_input_power)
 }
 
-// This function is optimized for input values from 0 to 15 and does not work correctly outside that range
 fn calculate_comparator_output(mode: ComparatorMode, input_strength: u8, power_on_sides: u8) -> u8 {
-    let difference = input_strength.wrapping_sub(power_on_sides);
-    if difference <= 15 {
-        match mode {
-            ComparatorMode::Compare => input_strength,
-            ComparatorMode::Subtract => difference,
-        }
-    } else {
-        0
+    if input_strength < power_on_sides {
+        return 0;
+    }
+    match mode {
+        ComparatorMode::Compare => input_strength,
+        ComparatorMode::Subtract => input_strength - power_on_sides,
     }
 }
 
@@ -376,6 +829,10 @@ impl fmt::Display for DirectBackend {
                 NodeType::Wire => format!("Wire"),
                 NodeType::Constant => format!("Constant({})", node.output_power),
                 NodeType::NoteBlock { .. } => format!("NoteBlock"),
+                NodeType::Observer => format!("Observer"),
+                NodeType::Interface => format!("Interface"),
+                NodeType::BusLane { stage, lane } => format!("BusLane({}, {})", stage, lane),
+                NodeType::BusStage { stage } => format!("BusStage({})", stage),
             };
             let pos = if let Some((pos, _)) = self.blocks[id] {
                 format!("{}, {}, {}", pos.x, pos.y, pos.z)
@@ -393,6 +850,14 @@ impl fmt::Display for DirectBackend {
                     id, out_index, distance, color
                 )?;
             }
+            for observer in node.observers.iter() {
+                writeln!(
+                    f,
+                    "    n{} -> n{} [ style = \"dashed\" ];",
+                    id,
+                    observer.index()
+                )?;
+            }
         }
         writeln!(f, "}}")
     }
@@ -1,3 +1,4 @@
+use crate::snapshot::NodeSnapshot;
 use mchprs_blocks::blocks::ComparatorMode;
 use smallvec::SmallVec;
 use std::num::NonZeroU8;
@@ -71,22 +72,29 @@ pub struct ForwardLink {
 }
 
 impl ForwardLink {
-    pub fn new(id: NodeId, side: bool, ss: u8) -> Self {
-        assert!(id.index() < (1 << 27));
+    pub fn new(id: NodeId, side: bool, ss: u8, through_wire: bool) -> Self {
+        assert!(id.index() < (1 << 26));
         // the clamp_weights compile pass should ensure ss < 15
         assert!(ss < 15);
         Self {
-            data: (id.index() as u32) << 5 | if side { 1 << 4 } else { 0 } | ss as u32,
+            data: (id.index() as u32) << 6
+                | if through_wire { 1 << 5 } else { 0 }
+                | if side { 1 << 4 } else { 0 }
+                | ss as u32,
         }
     }
 
     pub fn node(self) -> NodeId {
         unsafe {
             // safety: ForwardLink is constructed using a NodeId
-            NodeId::from_index((self.data >> 5) as usize)
+            NodeId::from_index((self.data >> 6) as usize)
         }
     }
 
+    pub fn through_wire(self) -> bool {
+        self.data & (1 << 5) != 0
+    }
+
     pub fn side(self) -> bool {
         self.data & (1 << 4) != 0
     }
@@ -94,6 +102,17 @@ impl ForwardLink {
     pub fn ss(self) -> u8 {
         (self.data & 0b1111) as u8
     }
+
+    /// The signal strength the linked node receives when this node outputs `power`.
+    #[inline(always)]
+    pub fn received(self, power: u8) -> u8 {
+        let power = if self.through_wire() {
+            power.min(15)
+        } else {
+            power
+        };
+        power.saturating_sub(self.ss())
+    }
 }
 
 impl std::fmt::Debug for ForwardLink {
@@ -102,6 +121,7 @@ impl std::fmt::Debug for ForwardLink {
             .field("node", &self.node())
             .field("side", &self.side())
             .field("ss", &self.ss())
+            .field("through_wire", &self.through_wire())
             .finish()
     }
 }
@@ -128,6 +148,18 @@ pub enum NodeType {
     NoteBlock {
         noteblock_id: u16,
     },
+    Observer,
+    /// Power coming from a block in the fallback region
+    Interface,
+    /// A repeater or torch that is ticked together with the rest of its bus stage
+    BusLane {
+        stage: u32,
+        lane: u8,
+    },
+    /// Ticks the lanes of a bus stage, it does not have a block of its own
+    BusStage {
+        stage: u32,
+    },
 }
 
 #[repr(align(16))]
@@ -136,6 +168,32 @@ pub struct NodeInput {
     pub ss_counts: [u8; 16],
 }
 
+/// The exact signal strengths above 15 a node receives, which are only counted as 15 in the
+/// `ss_counts` of its inputs. Nodes only get these with high signal strength enabled.
+#[derive(Debug, Clone, Default)]
+pub struct HighInputs {
+    pub default: SmallVec<[u8; 2]>,
+    pub side: SmallVec<[u8; 2]>,
+}
+
+impl HighInputs {
+    /// Replaces one input of `old` with `new`, either of which may be 15 or lower.
+    pub fn replace(&mut self, side: bool, old: u8, new: u8) {
+        let inputs = if side {
+            &mut self.side
+        } else {
+            &mut self.default
+        };
+        if old > 15 {
+            let idx = inputs.iter().position(|&ss| ss == old).unwrap();
+            inputs.swap_remove(idx);
+        }
+        if new > 15 {
+            inputs.push(new);
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct NonMaxU8(NonZeroU8);
 
@@ -154,7 +212,10 @@ pub struct Node {
     pub ty: NodeType,
     pub default_inputs: NodeInput,
     pub side_inputs: NodeInput,
+    pub high_inputs: Option<Box<HighInputs>>,
     pub updates: SmallVec<[ForwardLink; 10]>,
+    /// Observers that pulse when the block state of this node changes
+    pub observers: Box<[NodeId]>,
     pub is_io: bool,
 
     /// Powered or lit
@@ -165,3 +226,37 @@ pub struct Node {
     pub changed: bool,
     pub pending_tick: bool,
 }
+
+impl Node {
+    pub fn snapshot(&self) -> NodeSnapshot {
+        NodeSnapshot {
+            powered: self.powered,
+            locked: self.locked,
+            output_power: self.output_power,
+            pending_tick: self.pending_tick,
+            default_inputs: self.default_inputs.ss_counts,
+            side_inputs: self.side_inputs.ss_counts,
+            high_inputs: self
+                .high_inputs
+                .as_ref()
+                .map(|high| (high.default.to_vec(), high.side.to_vec())),
+        }
+    }
+
+    /// Takes the state of the snapshot, marking the node as changed so its block gets written.
+    pub fn restore(&mut self, snapshot: &NodeSnapshot) {
+        self.powered = snapshot.powered;
+        self.locked = snapshot.locked;
+        self.output_power = snapshot.output_power;
+        self.pending_tick = snapshot.pending_tick;
+        self.default_inputs.ss_counts = snapshot.default_inputs;
+        self.side_inputs.ss_counts = snapshot.side_inputs;
+        self.high_inputs = snapshot.high_inputs.as_ref().map(|(default, side)| {
+            Box::new(HighInputs {
+                default: SmallVec::from_slice(default),
+                side: SmallVec::from_slice(side),
+            })
+        });
+        self.changed = true;
+    }
+}
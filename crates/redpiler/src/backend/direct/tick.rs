@@ -60,6 +60,16 @@ impl DirectBackend {
                     self.set_node(node_id, false, 0);
                 }
             }
+            NodeType::Observer => {
+                if node.powered {
+                    self.set_node(node_id, false, 0);
+                } else {
+                    self.set_node(node_id, true, 15);
+                    let node = &mut self.nodes[node_id];
+                    schedule_tick(&mut self.scheduler, node_id, node, 1, TickPriority::Normal);
+                }
+            }
+            NodeType::BusStage { stage } => self.tick_stage(stage as usize),
             _ => {} //unreachable!("Node {:?} should not be ticked!", node.ty),
         }
     }
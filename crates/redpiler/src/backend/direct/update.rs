@@ -8,9 +8,10 @@ pub(super) fn update_node(
     scheduler: &mut TickScheduler,
     events: &mut Vec<Event>,
     nodes: &mut Nodes,
+    buses: &mut [BusStage],
     node_id: NodeId,
 ) {
-    let node = &mut nodes[node_id];
+    let mut node = &mut nodes[node_id];
 
     match node.ty {
         NodeType::Repeater {
@@ -20,6 +21,8 @@ pub(super) fn update_node(
             let should_be_locked = get_bool_side(node);
             if should_be_locked != node.locked {
                 set_node_locked(node, should_be_locked);
+                notify_observers(scheduler, nodes, node_id);
+                node = &mut nodes[node_id];
             }
             if node.locked || node.pending_tick {
                 return;
@@ -78,12 +81,14 @@ pub(super) fn update_node(
                 schedule_tick(scheduler, node_id, node, 2, TickPriority::Normal);
             } else if !lit && should_be_lit {
                 set_node(node, true);
+                notify_observers(scheduler, nodes, node_id);
             }
         }
         NodeType::Trapdoor => {
             let should_be_powered = get_bool_input(node);
             if node.powered != should_be_powered {
                 set_node(node, should_be_powered);
+                notify_observers(scheduler, nodes, node_id);
             }
         }
         NodeType::Wire => {
@@ -91,6 +96,7 @@ pub(super) fn update_node(
             if node.output_power != input_power {
                 node.output_power = input_power;
                 node.changed = true;
+                notify_observers(scheduler, nodes, node_id);
             }
         }
         NodeType::NoteBlock { noteblock_id } => {
@@ -100,8 +106,14 @@ pub(super) fn update_node(
                 if should_be_powered {
                     events.push(Event::NoteBlockPlay { noteblock_id });
                 }
+                notify_observers(scheduler, nodes, node_id);
             }
         }
+        NodeType::BusLane { stage, lane } => {
+            let input = get_bool_input(node) as u64;
+            let stage = &mut buses[stage as usize];
+            bus::update_lanes(scheduler, stage, 1 << lane, input << lane);
+        }
         _ => {} // unreachable!("Node {:?} should not be updated!", node.ty),
     }
 }
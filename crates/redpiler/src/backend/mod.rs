@@ -1,10 +1,16 @@
+pub mod cranelift;
 pub mod direct;
+pub mod parallel;
 
 use std::sync::Arc;
 
 use super::compile_graph::CompileGraph;
 use super::task_monitor::TaskMonitor;
 use super::CompilerOptions;
+use crate::breakpoint::{BreakCondition, BreakpointHit};
+use crate::incremental::GraphPatch;
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::trace::NodeState;
 use enum_dispatch::enum_dispatch;
 use mchprs_blocks::BlockPos;
 use mchprs_world::{TickEntry, World};
@@ -18,19 +24,70 @@ pub trait JITBackend {
         options: &CompilerOptions,
         monitor: Arc<TaskMonitor>,
     );
+    /// Applies the changes made to `graph` since it was compiled while keeping the state of the
+    /// nodes. Returns false if the backend has to be compiled again instead.
+    fn patch(&mut self, _graph: &CompileGraph, _patch: &GraphPatch) -> bool {
+        false
+    }
+    /// Runs a game tick, or the rest of it if it was stopped halfway. Stops after the priority a
+    /// breakpoint was hit in.
     fn tick(&mut self);
+    /// Runs the ticks of the next priority. Returns true if that finished the game tick. Backends
+    /// that cannot stop halfway through a tick run all of it.
+    fn step_phase(&mut self) -> bool {
+        self.tick();
+        true
+    }
+    /// Returns true if the current game tick was stopped halfway, by a breakpoint or
+    /// [`JITBackend::step_phase`].
+    fn is_mid_tick(&self) -> bool {
+        false
+    }
+    /// Attaches a breakpoint to the node at `pos`, or removes it if `condition` is `None`.
+    /// Returns false if there is no such node or the backend does not support breakpoints.
+    fn set_breakpoint(&mut self, _pos: BlockPos, _condition: Option<BreakCondition>) -> bool {
+        false
+    }
+    /// Returns the breakpoint hit since this was last called. Until then, other hits are ignored.
+    fn take_breakpoint_hit(&mut self) -> Option<BreakpointHit> {
+        None
+    }
+    /// Lets the nodes at `positions` react to their inputs, like a block update would. Positions
+    /// without a node are skipped.
+    fn update_nodes(&mut self, positions: &[BlockPos]);
     fn on_use_block(&mut self, pos: BlockPos);
     fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool);
     fn flush<W: World>(&mut self, world: &mut W, io_only: bool);
     fn reset<W: World>(&mut self, world: &mut W, io_only: bool);
+    /// Writes the state of every node into `world`, and schedules the ticks that are pending in
+    /// it, without changing anything. The current game tick must not have been stopped halfway.
+    fn copy_into<W: World>(&self, world: &mut W);
+    /// Writes the nodes at `positions` that changed since the last flush into the world and
+    /// returns their positions.
+    fn flush_interface<W: World>(&mut self, world: &mut W, positions: &[BlockPos])
+        -> Vec<BlockPos>;
+    /// Sets the output power of the interface node at `pos`.
+    fn set_interface_power(&mut self, pos: BlockPos, power: u8);
     fn has_pending_ticks(&self) -> bool;
+    /// Captures the runtime state of every node and the ticks that are pending.
+    fn snapshot(&self) -> Snapshot;
+    /// Restores the state captured by [`JITBackend::snapshot`] and marks every node as changed.
+    /// Nothing is changed if the snapshot was taken of some other graph.
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError>;
+    /// Returns the current state of the node at `pos`, without flushing it into the world.
+    fn node_state(&self, pos: BlockPos) -> Option<NodeState>;
     /// Inspect block for debugging
     fn inspect(&mut self, pos: BlockPos);
 }
 
+use cranelift::CraneliftBackend;
 use direct::DirectBackend;
+use parallel::ParallelBackend;
 
 #[enum_dispatch(JITBackend)]
+#[allow(clippy::enum_variant_names)]
 pub enum BackendDispatcher {
     DirectBackend,
+    CraneliftBackend,
+    ParallelBackend,
 }
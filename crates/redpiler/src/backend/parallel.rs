@@ -0,0 +1,308 @@
+//! The parallel backend splits the graph into islands that have no links between each other and
+//! runs every island on its own [`DirectBackend`]. Islands are ticked on a worker pool, but they all
+//! advance together one game tick at a time, so the result is the same as ticking them in order.
+
+use super::direct::DirectBackend;
+use super::JITBackend;
+use crate::compile_graph::{CompileGraph, NodeIdx};
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::task_monitor::TaskMonitor;
+use crate::trace::NodeState;
+use crate::{BackendVariant, CompilerOptions};
+use mchprs_blocks::BlockPos;
+use mchprs_world::{TickEntry, World};
+use petgraph::unionfind::UnionFind;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences, NodeIndexable};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+use tracing::{debug, trace, warn};
+
+#[derive(Default)]
+pub struct ParallelBackend {
+    islands: Vec<DirectBackend>,
+    /// Maps the position of every node to the island it ended up in
+    pos_map: FxHashMap<BlockPos, usize>,
+    pool: Option<ThreadPool>,
+}
+
+impl ParallelBackend {
+    fn pool(&mut self) -> &ThreadPool {
+        self.pool.get_or_insert_with(|| {
+            ThreadPoolBuilder::new()
+                .thread_name(|i| format!("redpiler-worker-{}", i))
+                .build()
+                .expect("failed to create redpiler worker pool")
+        })
+    }
+
+    fn island_at(&mut self, pos: BlockPos) -> Option<&mut DirectBackend> {
+        let island = *self.pos_map.get(&pos)?;
+        Some(&mut self.islands[island])
+    }
+}
+
+/// Finds the weakly connected components of the graph and packs them into at most `max_islands`
+/// islands, trying to keep the number of nodes in each island balanced. Returns the island of
+/// every node.
+fn partition(graph: &CompileGraph, max_islands: usize) -> FxHashMap<NodeIdx, usize> {
+    let mut components = UnionFind::new(graph.node_bound());
+    for edge in graph.edge_references() {
+        components.union(edge.source().index(), edge.target().index());
+    }
+
+    let mut component_sizes: FxHashMap<usize, usize> = FxHashMap::default();
+    for node in graph.node_indices() {
+        *component_sizes
+            .entry(components.find(node.index()))
+            .or_default() += 1;
+    }
+
+    // Place the largest components first, each into the island that currently has the least nodes
+    let mut component_sizes: Vec<_> = component_sizes.into_iter().collect();
+    component_sizes.sort_unstable_by_key(|&(root, size)| (std::cmp::Reverse(size), root));
+    let mut island_sizes = vec![0; max_islands.clamp(1, component_sizes.len().max(1))];
+    let mut component_islands = FxHashMap::default();
+    for (root, size) in component_sizes {
+        let (island, island_size) = island_sizes
+            .iter_mut()
+            .enumerate()
+            .min_by_key(|(_, island_size)| **island_size)
+            .unwrap();
+        *island_size += size;
+        component_islands.insert(root, island);
+    }
+
+    graph
+        .node_indices()
+        .map(|node| (node, component_islands[&components.find(node.index())]))
+        .collect()
+}
+
+/// Moves the nodes and links of the graph into one graph per island.
+fn split_graph(
+    mut graph: CompileGraph,
+    node_islands: &FxHashMap<NodeIdx, usize>,
+    num_islands: usize,
+) -> Vec<CompileGraph> {
+    let mut islands: Vec<CompileGraph> = (0..num_islands).map(|_| Default::default()).collect();
+
+    let edges: Vec<_> = graph.edge_indices().collect();
+    let edges: Vec<_> = edges
+        .into_iter()
+        .map(|edge| {
+            let (source, target) = graph.edge_endpoints(edge).unwrap();
+            (source, target, graph.remove_edge(edge).unwrap())
+        })
+        .collect();
+
+    let mut node_map = FxHashMap::default();
+    let nodes: Vec<_> = graph.node_indices().collect();
+    for node in nodes {
+        let island = node_islands[&node];
+        let weight = graph.remove_node(node).unwrap();
+        node_map.insert(node, islands[island].add_node(weight));
+    }
+
+    for (source, target, link) in edges {
+        let island = node_islands[&source];
+        islands[island].add_edge(node_map[&source], node_map[&target], link);
+    }
+
+    islands
+}
+
+impl JITBackend for ParallelBackend {
+    fn inspect(&mut self, pos: BlockPos) {
+        match self.pos_map.get(&pos) {
+            Some(&island) => {
+                debug!("Node is in island {}", island);
+                self.islands[island].inspect(pos);
+            }
+            None => debug!("could not find node at pos {}", pos),
+        }
+    }
+
+    fn reset<W: World>(&mut self, world: &mut W, io_only: bool) {
+        for mut island in self.islands.drain(..) {
+            island.reset(world, io_only);
+        }
+        self.pos_map.clear();
+    }
+
+    fn copy_into<W: World>(&self, world: &mut W) {
+        for island in &self.islands {
+            island.copy_into(world);
+        }
+    }
+
+    fn update_nodes(&mut self, positions: &[BlockPos]) {
+        for &pos in positions {
+            if let Some(island) = self.island_at(pos) {
+                island.update_nodes(&[pos]);
+            }
+        }
+    }
+
+    fn on_use_block(&mut self, pos: BlockPos) {
+        match self.island_at(pos) {
+            Some(island) => island.on_use_block(pos),
+            None => warn!(
+                "Tried to use a block that is not part of any island at {}",
+                pos
+            ),
+        }
+    }
+
+    fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool) {
+        match self.island_at(pos) {
+            Some(island) => island.set_pressure_plate(pos, powered),
+            None => warn!(
+                "Tried to set a pressure plate that is not part of any island at {}",
+                pos
+            ),
+        }
+    }
+
+    fn tick(&mut self) {
+        let Some(pool) = &self.pool else {
+            return;
+        };
+        if let [island] = self.islands.as_mut_slice() {
+            island.tick();
+            return;
+        }
+        // An island without any pending ticks cannot change until it is used, so there is no need
+        // to advance its scheduler at all.
+        pool.install(|| {
+            self.islands
+                .par_iter_mut()
+                .filter(|island| island.has_pending_ticks())
+                .for_each(|island| island.tick())
+        });
+    }
+
+    fn flush<W: World>(&mut self, world: &mut W, io_only: bool) {
+        for island in &mut self.islands {
+            island.flush(world, io_only);
+        }
+    }
+
+    fn flush_interface<W: World>(
+        &mut self,
+        world: &mut W,
+        positions: &[BlockPos],
+    ) -> Vec<BlockPos> {
+        let mut island_positions = vec![Vec::new(); self.islands.len()];
+        for &pos in positions {
+            if let Some(&island) = self.pos_map.get(&pos) {
+                island_positions[island].push(pos);
+            }
+        }
+        self.islands
+            .iter_mut()
+            .zip(island_positions)
+            .flat_map(|(island, positions)| island.flush_interface(world, &positions))
+            .collect()
+    }
+
+    fn set_interface_power(&mut self, pos: BlockPos, power: u8) {
+        match self.island_at(pos) {
+            Some(island) => island.set_interface_power(pos, power),
+            None => warn!(
+                "Tried to set an interface node that is not part of any island at {}",
+                pos
+            ),
+        }
+    }
+
+    fn compile(
+        &mut self,
+        graph: CompileGraph,
+        ticks: Vec<TickEntry>,
+        options: &CompilerOptions,
+        monitor: Arc<TaskMonitor>,
+    ) {
+        let max_islands = self.pool().current_num_threads();
+        let node_islands = partition(&graph, max_islands);
+        let num_islands = node_islands.values().max().map_or(0, |island| island + 1);
+
+        for (&node, &island) in &node_islands {
+            if let Some((pos, _)) = graph[node].block {
+                self.pos_map.insert(pos, island);
+            }
+        }
+        let mut island_ticks = vec![Vec::new(); num_islands];
+        for entry in ticks {
+            if let Some(&island) = self.pos_map.get(&entry.pos) {
+                island_ticks[island].push(entry);
+            }
+        }
+
+        let mut options = options.clone();
+        if options.export_dot_graph && num_islands > 1 {
+            warn!("Exporting a dot graph is not supported for more than one island");
+            options.export_dot_graph = false;
+        }
+        if options.trace && num_islands > 1 {
+            warn!("Tracing is not supported for more than one island");
+            options.trace = false;
+        }
+
+        let graphs = split_graph(graph, &node_islands, num_islands);
+        for (i, graph) in graphs.iter().enumerate() {
+            trace!("Island {} has {} nodes", i, graph.node_count());
+        }
+        let mut islands: Vec<DirectBackend> = graphs.iter().map(|_| Default::default()).collect();
+        self.pool().install(|| {
+            islands
+                .par_iter_mut()
+                .zip(graphs)
+                .zip(island_ticks)
+                .for_each(|((island, graph), ticks)| {
+                    island.compile(graph, ticks, &options, monitor.clone())
+                })
+        });
+        self.islands = islands;
+        debug!("Compiled {} islands", self.islands.len());
+    }
+
+    fn has_pending_ticks(&self) -> bool {
+        self.islands.iter().any(|island| island.has_pending_ticks())
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            backend: BackendVariant::Parallel,
+            parts: self
+                .islands
+                .iter()
+                .map(DirectBackend::backend_state)
+                .collect(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        // Every island is checked first, so none of them are changed if one does not fit
+        let fits = snapshot.backend == BackendVariant::Parallel
+            && snapshot.parts.len() == self.islands.len()
+            && self
+                .islands
+                .iter()
+                .zip(&snapshot.parts)
+                .all(|(island, state)| island.fits(state));
+        if !fits {
+            return Err(SnapshotError::Mismatch);
+        }
+        for (island, state) in self.islands.iter_mut().zip(&snapshot.parts) {
+            island.restore_state(state);
+        }
+        Ok(())
+    }
+
+    fn node_state(&self, pos: BlockPos) -> Option<NodeState> {
+        let island = *self.pos_map.get(&pos)?;
+        self.islands[island].node_state(pos)
+    }
+}
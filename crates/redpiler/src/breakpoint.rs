@@ -0,0 +1,76 @@
+//! Breakpoints pause ticking once a node reaches some state, so a misbehaving circuit can be
+//! stepped through from the tick it went wrong. Only the direct backend supports them.
+
+use crate::trace::NodeState;
+use mchprs_blocks::BlockPos;
+use mchprs_world::TickPriority;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakCondition {
+    /// The node gets powered
+    On,
+    /// The node stops being powered
+    Off,
+    /// Any part of the state of the node changes, including its output power
+    Change,
+}
+
+impl BreakCondition {
+    pub(crate) fn is_met(self, old: NodeState, new: NodeState) -> bool {
+        match self {
+            BreakCondition::On => !old.powered && new.powered,
+            BreakCondition::Off => old.powered && !new.powered,
+            BreakCondition::Change => old != new,
+        }
+    }
+}
+
+impl FromStr for BreakCondition {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "on" => BreakCondition::On,
+            "off" => BreakCondition::Off,
+            "change" => BreakCondition::Change,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl fmt::Display for BreakCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BreakCondition::On => "on",
+            BreakCondition::Off => "off",
+            BreakCondition::Change => "change",
+        })
+    }
+}
+
+/// The breakpoint that stopped ticking, see [`Compiler::take_breakpoint_hit`](crate::Compiler::take_breakpoint_hit).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakpointHit {
+    pub pos: BlockPos,
+    pub condition: BreakCondition,
+    /// The state of the node after the change that met the condition
+    pub state: NodeState,
+    /// Ticking stopped right after the ticks of this priority
+    pub priority: TickPriority,
+}
+
+impl fmt::Display for BreakpointHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Breakpoint at {} ({}) hit after the {:?} priority ticks, the node is now {} with power {}",
+            self.pos,
+            self.condition,
+            self.priority,
+            if self.state.powered { "on" } else { "off" },
+            self.state.power
+        )
+    }
+}
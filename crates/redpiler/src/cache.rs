@@ -0,0 +1,216 @@
+//! Compiled graphs are kept on disk, so that compiling a plot that has not changed since it was
+//! last compiled can skip all of the passes.
+//!
+//! Graphs are stored in the `redpiler_graph` format, named after a hash of everything the passes
+//! look at: the blocks being compiled, the fallback region and the options that change the graph.
+
+use crate::compile_graph::CompileGraph;
+use crate::fallback::FallbackRegion;
+use crate::headless::import_graph;
+use crate::passes::export_nodes;
+use crate::CompilerOptions;
+use mchprs_blocks::BlockPos;
+use mchprs_world::World;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{debug, warn};
+
+/// Changing the passes or the graph format has to change this too, or stale graphs would be used
+const CACHE_VERSION: u32 = 3;
+/// Once there are more graphs than this, the ones that were used the longest time ago are removed
+const MAX_ENTRIES: usize = 32;
+
+pub struct CompileCache {
+    dir: PathBuf,
+}
+
+impl CompileCache {
+    pub fn new(dir: impl Into<PathBuf>) -> CompileCache {
+        CompileCache { dir: dir.into() }
+    }
+
+    /// Returns the key the graph for the given input is stored under, or `None` if the world does
+    /// not have chunks to hash or the graph should not be cached at all.
+    pub fn key<W: World>(
+        &self,
+        world: &W,
+        bounds: (BlockPos, BlockPos),
+        fallback: &FallbackRegion,
+        options: &CompilerOptions,
+    ) -> Option<String> {
+        // The export pass has to run for the graph file to be written
+        if options.export {
+            return None;
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(CACHE_VERSION.to_le_bytes());
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update([
+            options.optimize as u8,
+            options.io_only as u8,
+            options.wire_dot_out as u8,
+            options.high_signal_strength as u8,
+        ]);
+        hash_pos(&mut hasher, bounds.0);
+        hash_pos(&mut hasher, bounds.1);
+
+        let mut fallback_blocks: Vec<_> = fallback.iter().collect();
+        fallback_blocks.sort_by_key(|pos| (pos.x, pos.y, pos.z));
+        hasher.update((fallback_blocks.len() as u64).to_le_bytes());
+        for pos in fallback_blocks {
+            hash_pos(&mut hasher, pos);
+        }
+        match fallback.selection() {
+            Some((first, second)) => {
+                hasher.update([1]);
+                hash_pos(&mut hasher, first);
+                hash_pos(&mut hasher, second);
+            }
+            None => hasher.update([0]),
+        }
+
+        // Blocks right outside of the bounds can still be linked to through the fallback region
+        let (first, second) = bounds;
+        let (min_x, max_x) = (
+            (first.x.min(second.x) - 1) >> 4,
+            (first.x.max(second.x) + 1) >> 4,
+        );
+        let (min_z, max_z) = (
+            (first.z.min(second.z) - 1) >> 4,
+            (first.z.max(second.z) + 1) >> 4,
+        );
+        let mut hashed_any = false;
+        for chunk_x in min_x..=max_x {
+            for chunk_z in min_z..=max_z {
+                let Some(chunk) = world.get_chunk(chunk_x, chunk_z) else {
+                    continue;
+                };
+                // Worlds may hand out some other chunk for coordinates they do not have
+                if chunk.x != chunk_x || chunk.z != chunk_z {
+                    continue;
+                }
+                hashed_any = true;
+                hasher.update(chunk_x.to_le_bytes());
+                hasher.update(chunk_z.to_le_bytes());
+                for section in &chunk.sections {
+                    if section.block_count() == 0 {
+                        hasher.update([0]);
+                        continue;
+                    }
+                    hasher.update([1]);
+                    // Blocks set since the section was last flushed are not part of its data yet
+                    for y in 0..16 {
+                        for z in 0..16 {
+                            for x in 0..16 {
+                                let id = section.get_block(x, y, z) as u16;
+                                hasher.update(id.to_le_bytes());
+                            }
+                        }
+                    }
+                }
+
+                let mut block_entities: Vec<_> = chunk.block_entities.iter().collect();
+                block_entities.sort_by_key(|(pos, _)| (pos.x, pos.y, pos.z));
+                for (pos, block_entity) in block_entities {
+                    hash_pos(&mut hasher, *pos);
+                    hasher.update(serde_json::to_vec(block_entity).ok()?);
+                }
+            }
+        }
+        if !hashed_any {
+            return None;
+        }
+
+        let hash = hasher.finalize();
+        Some(hash.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.bc", key))
+    }
+
+    /// Returns the graph stored under `key`, if there is one.
+    pub fn load(&self, key: &str) -> Option<CompileGraph> {
+        let path = self.path(key);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("Could not open cached graph {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        let nodes = match redpiler_graph::deserialize(&bytes) {
+            Ok(nodes) => nodes,
+            Err(err) => {
+                warn!("Could not read cached graph {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        let graph = match import_graph(&nodes) {
+            Ok(graph) => graph,
+            Err(err) => {
+                warn!("Cached graph {} is invalid: {}", path.display(), err);
+                return None;
+            }
+        };
+        // Keeps the graph from being the first to be removed
+        let touched = File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(err) = touched {
+            debug!("Could not touch cached graph {}: {}", path.display(), err);
+        }
+        Some(graph)
+    }
+
+    /// Stores `graph` under `key`, removing old graphs if there are too many.
+    pub fn store(&self, key: &str, graph: &CompileGraph) {
+        if let Err(err) = self.try_store(key, graph) {
+            warn!("Could not cache compiled graph: {}", err);
+        }
+    }
+
+    fn try_store(&self, key: &str, graph: &CompileGraph) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Written to a temporary file first, so a graph is never read while it is being written
+        let tmp_path = self.dir.join(format!("{}.tmp", key));
+        let writer = BufWriter::new(File::create(&tmp_path)?);
+        redpiler_graph::serialize_into(writer, &export_nodes(graph))
+            .map_err(|err| io::Error::other(err))?;
+        fs::rename(&tmp_path, self.path(key))?;
+        remove_oldest(&self.dir, MAX_ENTRIES)
+    }
+}
+
+fn hash_pos(hasher: &mut Sha256, pos: BlockPos) {
+    hasher.update(pos.x.to_le_bytes());
+    hasher.update(pos.y.to_le_bytes());
+    hasher.update(pos.z.to_le_bytes());
+}
+
+/// Removes the graphs that were used the longest time ago, until only `max_entries` are left.
+fn remove_oldest(dir: &Path, max_entries: usize) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "bc") {
+            entries.push((entry.metadata()?.modified()?, path));
+        }
+    }
+    if entries.len() <= max_entries {
+        return Ok(());
+    }
+    entries.sort();
+    for (_, path) in &entries[..entries.len() - max_entries] {
+        debug!("Removing cached graph {}", path.display());
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
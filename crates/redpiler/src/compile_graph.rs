@@ -27,6 +27,12 @@ pub enum NodeType {
         instrument: Instrument,
         note: u32,
     },
+    /// The only input of an observer is the node it is looking at. It pulses whenever the block
+    /// state of that node changes, rather than when its output power does.
+    Observer,
+    /// Stands in for a block in a fallback region, which is simulated outside of redpiler. Its
+    /// output power is set from the outside after every tick.
+    Interface,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -69,10 +75,21 @@ impl NodeState {
     }
 }
 
-#[derive(Debug, Default)]
-pub struct Annotations {}
+/// The place of a node within a bus found by the `DetectBuses` pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusLane {
+    pub bus: u32,
+    /// Stages are numbered in the order signals travel through them
+    pub stage: u32,
+    pub lane: u32,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
+pub struct Annotations {
+    pub bus: Option<BusLane>,
+}
+
+#[derive(Debug, Clone)]
 pub struct CompileNode {
     pub ty: NodeType,
     pub block: Option<(BlockPos, u32)>,
@@ -87,6 +104,18 @@ impl CompileNode {
     pub fn is_removable(&self) -> bool {
         !self.is_input && !self.is_output
     }
+
+    /// Caps the output strength and the far input of comparators at `max`.
+    pub fn clamp_signal_strength(&mut self, max: u8) {
+        self.state.output_strength = self.state.output_strength.min(max);
+        if let NodeType::Comparator {
+            far_input: Some(far_input),
+            ..
+        } = &mut self.ty
+        {
+            *far_input = (*far_input).min(max);
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -95,29 +124,38 @@ pub enum LinkType {
     Side,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CompileLink {
     pub ty: LinkType,
     pub ss: u8,
+    /// The signal travels through redstone wire, which never carries more than 15
+    pub through_wire: bool,
 }
 
 impl CompileLink {
     pub fn new(ty: LinkType, ss: u8) -> CompileLink {
-        CompileLink { ty, ss }
-    }
-
-    pub fn default(ss: u8) -> CompileLink {
         CompileLink {
-            ty: LinkType::Default,
+            ty,
             ss,
+            through_wire: false,
         }
     }
 
+    pub fn default(ss: u8) -> CompileLink {
+        CompileLink::new(LinkType::Default, ss)
+    }
+
     pub fn side(ss: u8) -> CompileLink {
-        CompileLink {
-            ty: LinkType::Side,
-            ss,
-        }
+        CompileLink::new(LinkType::Side, ss)
+    }
+
+    /// The signal strength seen at the end of this link when its source outputs `output`.
+    pub fn received(&self, output: u8) -> u8 {
+        let output = match self.through_wire {
+            true => output.min(15),
+            false => output,
+        };
+        output.saturating_sub(self.ss)
     }
 }
 
@@ -0,0 +1,339 @@
+//! Fallback regions let redpiler compile plots containing blocks that it cannot model. Those blocks
+//! are left in the world and keep being simulated by `mchprs_redstone`, while everything else is
+//! compiled as usual.
+//!
+//! Both sides exchange signals once per tick through interface nodes:
+//! - Every block in the region is represented in the graph by an [`NodeType::Interface`] node. After
+//!   the vanilla engine has ticked, the host sets its output power to whatever the block provides
+//!   to its neighbors.
+//! - Every compiled node close enough to the region to affect a block in it is kept as an output.
+//!   Whenever one of them changes, its block is written into the world straight away and the
+//!   blocks of the region around it are updated.
+//!
+//! When only a selection is compiled, everything outside of it is part of the region as well.
+//! Only the blocks right next to the selection get interface nodes, since nothing further away can
+//! power a compiled block.
+//!
+//! [`NodeType::Interface`]: crate::compile_graph::NodeType::Interface
+
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockFacing, BlockPos};
+use mchprs_redstone::piston::PUSH_LIMIT;
+use mchprs_world::storage::Chunk;
+use mchprs_world::{for_each_block_optimized, TickPriority, World};
+use rustc_hash::FxHashSet;
+
+/// How far away from a block in the region a compiled node can be while still affecting it.
+/// Pistons can be powered through a solid block next to the block above them.
+const INTERFACE_RADIUS: i32 = 3;
+
+/// Returns true if redpiler is unable to compile the block.
+fn is_unsupported(block: Block) -> bool {
+    matches!(
+        block,
+        Block::Piston { .. } | Block::PistonHead { .. } | Block::MovingPiston { .. }
+    )
+}
+
+fn piston_facing(block: Block) -> Option<BlockFacing> {
+    match block {
+        Block::Piston { facing, .. }
+        | Block::PistonHead { facing, .. }
+        | Block::MovingPiston { facing, .. } => Some(facing),
+        _ => None,
+    }
+}
+
+fn is_in_bounds(pos: BlockPos, (first, second): (BlockPos, BlockPos)) -> bool {
+    (first.x.min(second.x)..=first.x.max(second.x)).contains(&pos.x)
+        && (first.y.min(second.y)..=first.y.max(second.y)).contains(&pos.y)
+        && (first.z.min(second.z)..=first.z.max(second.z)).contains(&pos.z)
+}
+
+fn normalize((first, second): (BlockPos, BlockPos)) -> (BlockPos, BlockPos) {
+    (first.min(second), first.max(second))
+}
+
+/// Returns the positions right outside of `selection`, including the ones diagonal to it.
+fn selection_border(selection: (BlockPos, BlockPos)) -> impl Iterator<Item = BlockPos> {
+    let (min, max) = selection;
+    (min.x - 1..=max.x + 1).flat_map(move |x| {
+        (min.y - 1..=max.y + 1).flat_map(move |y| {
+            let inside = (min.x..=max.x).contains(&x) && (min.y..=max.y).contains(&y);
+            let zs = match inside {
+                true => vec![min.z - 1, max.z + 1],
+                false => (min.z - 1..=max.z + 1).collect(),
+            };
+            zs.into_iter().map(move |z| BlockPos::new(x, y, z))
+        })
+    })
+}
+
+/// Grows `selection` until no wire crosses its border. The two halves of such a wire would keep
+/// powering each other through the interface nodes, so the wire has to be compiled as a whole.
+fn expand_selection<W: World>(
+    world: &W,
+    bounds: (BlockPos, BlockPos),
+    selection: (BlockPos, BlockPos),
+) -> (BlockPos, BlockPos) {
+    let is_wire = |pos| matches!(world.get_block(pos), Block::RedstoneWire { .. });
+    let (mut min, mut max) = selection;
+    loop {
+        let (old_min, old_max) = (min, max);
+        for pos in selection_border((old_min, old_max)) {
+            if !is_in_bounds(pos, bounds) || !is_wire(pos) {
+                continue;
+            }
+            let touches_wire = (-1..=1)
+                .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| (x, y, z))))
+                .map(|(x, y, z)| BlockPos::new(pos.x + x, pos.y + y, pos.z + z))
+                .any(|inner| is_in_bounds(inner, (old_min, old_max)) && is_wire(inner));
+            if touches_wire {
+                min = min.min(pos);
+                max = max.max(pos);
+            }
+        }
+        if (min, max) == (old_min, old_max) {
+            return (min, max);
+        }
+    }
+}
+
+/// The blocks that are left to the default redstone implementation while redpiler is running.
+#[derive(Default)]
+pub struct FallbackRegion {
+    blocks: FxHashSet<BlockPos>,
+    /// Positions outside of the region that are within [`INTERFACE_RADIUS`] of it
+    near: FxHashSet<BlockPos>,
+    /// The compiled selection, if only part of the world is compiled. Everything outside of it
+    /// belongs to the region.
+    selection: Option<(BlockPos, BlockPos)>,
+}
+
+impl FallbackRegion {
+    /// Finds every unsupported block within `bounds`, along with every block a piston could move.
+    pub fn find<W: World>(world: &W, bounds: (BlockPos, BlockPos)) -> FallbackRegion {
+        let mut blocks = FxHashSet::default();
+        for_each_block_optimized(world, bounds.0, bounds.1, |pos| {
+            let block = world.get_block(pos);
+            if !is_unsupported(block) {
+                return;
+            }
+            blocks.insert(pos);
+            if let Some(facing) = piston_facing(block) {
+                // One more than the push limit, because the last block can destroy the one after it
+                for distance in 1..=PUSH_LIMIT as i32 + 1 {
+                    let pos = facing.offset_pos(pos, distance);
+                    if is_in_bounds(pos, bounds) {
+                        blocks.insert(pos);
+                    }
+                }
+            }
+        });
+
+        let mut near = FxHashSet::default();
+        for pos in &blocks {
+            let r = INTERFACE_RADIUS;
+            for x in -r..=r {
+                for y in -r..=r {
+                    for z in -r..=r {
+                        let near_pos = BlockPos::new(pos.x + x, pos.y + y, pos.z + z);
+                        if !blocks.contains(&near_pos) && is_in_bounds(near_pos, bounds) {
+                            near.insert(near_pos);
+                        }
+                    }
+                }
+            }
+        }
+
+        FallbackRegion {
+            blocks,
+            near,
+            selection: None,
+        }
+    }
+
+    /// Like [`FallbackRegion::find`], but only `selection` is compiled and the rest of `bounds`
+    /// joins the region. The selection is grown so that no wire crosses its border, so the
+    /// selection that is actually compiled is returned as well.
+    pub fn find_in_selection<W: World>(
+        world: &W,
+        bounds: (BlockPos, BlockPos),
+        selection: (BlockPos, BlockPos),
+    ) -> (FallbackRegion, (BlockPos, BlockPos)) {
+        let bounds = normalize(bounds);
+        let (min, max) = normalize(selection);
+        let selection = (bounds.0.max(min), bounds.1.min(max));
+        let selection = expand_selection(world, bounds, selection);
+
+        let mut region = FallbackRegion::find(world, selection);
+        for pos in selection_border(selection) {
+            if is_in_bounds(pos, bounds) && !matches!(world.get_block(pos), Block::Air {}) {
+                region.blocks.insert(pos);
+            }
+        }
+        region.selection = Some(selection);
+        (region, selection)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.selection.is_none()
+    }
+
+    pub fn contains(&self, pos: BlockPos) -> bool {
+        self.blocks.contains(&pos)
+            || self
+                .selection
+                .is_some_and(|selection| !is_in_bounds(pos, selection))
+    }
+
+    /// Returns the compiled selection, if only part of the world is compiled.
+    pub fn selection(&self) -> Option<(BlockPos, BlockPos)> {
+        self.selection
+    }
+
+    /// Returns true if a compiled node at `pos` can affect the blocks in the region.
+    pub fn is_near(&self, pos: BlockPos) -> bool {
+        if self.near.contains(&pos) {
+            return true;
+        }
+        let Some((min, max)) = self.selection else {
+            return false;
+        };
+        let distance = [
+            pos.x - min.x,
+            max.x - pos.x,
+            pos.y - min.y,
+            max.y - pos.y,
+            pos.z - min.z,
+            max.z - pos.z,
+        ]
+        .into_iter()
+        .min()
+        .unwrap();
+        distance < INTERFACE_RADIUS
+    }
+
+    /// Returns the blocks in the region that get interface nodes. Blocks outside of a compiled
+    /// selection are only included if they are right next to it.
+    pub fn iter(&self) -> impl Iterator<Item = BlockPos> + '_ {
+        self.blocks.iter().copied()
+    }
+
+    /// Returns the positions in the region that can be affected by a change at `pos`.
+    pub(crate) fn affected_by(&self, pos: BlockPos) -> impl Iterator<Item = BlockPos> + '_ {
+        let r = INTERFACE_RADIUS;
+        (-r..=r)
+            .flat_map(move |x| (-r..=r).flat_map(move |y| (-r..=r).map(move |z| (x, y, z))))
+            .map(move |(x, y, z)| BlockPos::new(pos.x + x, pos.y + y, pos.z + z))
+            .filter(|pos| self.contains(*pos))
+    }
+
+    /// The power the block at `pos` provides to the compiled blocks around it. This is what the
+    /// output power of its interface node is set to.
+    pub(crate) fn interface_power<W: World>(&self, world: &W, pos: BlockPos) -> u8 {
+        let block = world.get_block(pos);
+        let mut power = 0;
+        for face in BlockFace::values() {
+            if self.contains(pos.offset(face)) {
+                continue;
+            }
+            let side = face.opposite();
+            // A solid block must not pass on power that comes from the wire it powers itself
+            let face_power = if block.is_solid() {
+                mchprs_redstone::get_redstone_power_no_dust(block, world, pos, side)
+            } else {
+                mchprs_redstone::get_redstone_power(block, world, pos, side)
+            };
+            power = power.max(face_power);
+        }
+        power
+    }
+}
+
+/// A view of the world for running the default redstone implementation on a fallback region.
+/// Blocks and ticks outside of the region belong to redpiler, so any changes to them are dropped.
+pub struct FallbackWorld<'a, W: World> {
+    world: &'a mut W,
+    region: &'a FallbackRegion,
+}
+
+impl<'a, W: World> FallbackWorld<'a, W> {
+    pub fn new(world: &'a mut W, region: &'a FallbackRegion) -> FallbackWorld<'a, W> {
+        FallbackWorld { world, region }
+    }
+
+    pub fn region(&self) -> &FallbackRegion {
+        self.region
+    }
+}
+
+impl<W: World> World for FallbackWorld<'_, W> {
+    fn get_block_raw(&self, pos: BlockPos) -> u32 {
+        self.world.get_block_raw(pos)
+    }
+
+    fn set_block(&mut self, pos: BlockPos, block: Block) -> bool {
+        self.region.contains(pos) && self.world.set_block(pos, block)
+    }
+
+    fn set_block_raw(&mut self, pos: BlockPos, block: u32) -> bool {
+        self.region.contains(pos) && self.world.set_block_raw(pos, block)
+    }
+
+    fn delete_block_entity(&mut self, pos: BlockPos) {
+        if self.region.contains(pos) {
+            self.world.delete_block_entity(pos);
+        }
+    }
+
+    fn get_block_entity(&self, pos: BlockPos) -> Option<&BlockEntity> {
+        self.world.get_block_entity(pos)
+    }
+
+    fn set_block_entity(&mut self, pos: BlockPos, block_entity: BlockEntity) {
+        if self.region.contains(pos) {
+            self.world.set_block_entity(pos, block_entity);
+        }
+    }
+
+    fn get_chunk(&self, x: i32, z: i32) -> Option<&Chunk> {
+        self.world.get_chunk(x, z)
+    }
+
+    /// Whole chunks can't be limited to the region, so none are handed out.
+    fn get_chunk_mut(&mut self, _x: i32, _z: i32) -> Option<&mut Chunk> {
+        None
+    }
+
+    fn schedule_tick(&mut self, pos: BlockPos, delay: u32, priority: TickPriority) {
+        if self.region.contains(pos) {
+            self.world.schedule_tick(pos, delay, priority);
+        }
+    }
+
+    fn pending_tick_at(&mut self, pos: BlockPos) -> bool {
+        self.world.pending_tick_at(pos)
+    }
+
+    fn cancel_ticks(&mut self, pos: BlockPos) {
+        self.world.cancel_ticks(pos);
+    }
+
+    fn is_cursed(&self) -> bool {
+        self.world.is_cursed()
+    }
+
+    fn play_sound(
+        &mut self,
+        pos: BlockPos,
+        sound_id: i32,
+        sound_category: i32,
+        volume: f32,
+        pitch: f32,
+    ) {
+        self.world
+            .play_sound(pos, sound_id, sound_category, volume, pitch);
+    }
+}
@@ -0,0 +1,462 @@
+//! Running graphs exported with `--export` without the world they were compiled from.
+//!
+//! The exported graph only keeps what the backends need, so ticks that were pending at the time
+//! of the export are lost on the way.
+
+use crate::compile_graph::{
+    Annotations, BusLane, CompileGraph, CompileLink, CompileNode, LinkType, NodeState, NodeType,
+};
+use crate::{block_powered_mut, Compiler, CompilerOptions};
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::{Block, ComparatorMode, Instrument};
+use mchprs_blocks::BlockPos;
+use mchprs_world::storage::Chunk;
+use mchprs_world::{TickPriority, World};
+use redpiler_graph::{
+    ComparatorMode as GComparatorMode, LinkType as GLinkType, Node, NodeId, NodeType as GNodeType,
+};
+use rustc_hash::FxHashMap;
+use std::path::Path;
+use std::{fs, io};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GraphImportError {
+    #[error("redpiler graph deserialization error")]
+    Deserialize(#[from] redpiler_graph::Error),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("node {node} has an input from node {from}, which does not exist")]
+    InvalidLink { node: NodeId, from: NodeId },
+
+    #[error("repeater node {node} has an invalid delay of {delay}")]
+    InvalidDelay { node: NodeId, delay: u8 },
+
+    #[error("note block node {node} has an invalid instrument id of {instrument}")]
+    InvalidInstrument { node: NodeId, instrument: u32 },
+}
+
+#[derive(Error, Debug)]
+pub enum NodeError {
+    #[error("{0:?} is not a node of the graph")]
+    NoNode(NodeRef),
+
+    #[error("the block at {pos} is not a {expected}")]
+    WrongBlock {
+        pos: BlockPos,
+        expected: &'static str,
+    },
+}
+
+/// Rebuilds the `CompileGraph` from the nodes of an exported graph. Node ids are kept, so the node
+/// with id `n` ends up at node index `n`.
+pub(crate) fn import_graph(nodes: &[Node]) -> Result<CompileGraph, GraphImportError> {
+    let mut graph = CompileGraph::with_capacity(nodes.len(), 0);
+    for (id, node) in nodes.iter().enumerate() {
+        let ty = match node.ty {
+            GNodeType::Repeater(delay) => {
+                if !(1..=4).contains(&delay) {
+                    return Err(GraphImportError::InvalidDelay { node: id, delay });
+                }
+                NodeType::Repeater {
+                    delay,
+                    facing_diode: node.facing_diode,
+                }
+            }
+            GNodeType::Torch => NodeType::Torch,
+            GNodeType::Comparator(mode) => NodeType::Comparator {
+                mode: match mode {
+                    GComparatorMode::Compare => ComparatorMode::Compare,
+                    GComparatorMode::Subtract => ComparatorMode::Subtract,
+                },
+                far_input: node.comparator_far_input,
+                facing_diode: node.facing_diode,
+            },
+            GNodeType::Lamp => NodeType::Lamp,
+            GNodeType::Button => NodeType::Button,
+            GNodeType::Lever => NodeType::Lever,
+            GNodeType::PressurePlate => NodeType::PressurePlate,
+            GNodeType::Trapdoor => NodeType::Trapdoor,
+            GNodeType::Wire => NodeType::Wire,
+            GNodeType::Constant => NodeType::Constant,
+            GNodeType::NoteBlock { instrument, note } => {
+                if instrument > Instrument::Piglin.get_id() {
+                    return Err(GraphImportError::InvalidInstrument {
+                        node: id,
+                        instrument,
+                    });
+                }
+                NodeType::NoteBlock {
+                    instrument: Instrument::from_id(instrument),
+                    note,
+                }
+            }
+            GNodeType::Observer => NodeType::Observer,
+            GNodeType::Interface => NodeType::Interface,
+        };
+        graph.add_node(CompileNode {
+            ty,
+            block: node
+                .block
+                .map(|(pos, id)| (BlockPos::new(pos.x, pos.y, pos.z), id)),
+            state: NodeState {
+                powered: node.state.powered,
+                repeater_locked: node.state.repeater_locked,
+                output_strength: node.state.output_strength,
+            },
+            is_input: node.is_input,
+            is_output: node.is_output,
+            annotations: Annotations {
+                bus: node.bus.map(|lane| BusLane {
+                    bus: lane.bus,
+                    stage: lane.stage,
+                    lane: lane.lane,
+                }),
+            },
+        });
+    }
+
+    let indices: Vec<_> = graph.node_indices().collect();
+    for (id, node) in nodes.iter().enumerate() {
+        for link in &node.inputs {
+            let Some(&from) = indices.get(link.to) else {
+                return Err(GraphImportError::InvalidLink {
+                    node: id,
+                    from: link.to,
+                });
+            };
+            // Same as the `ClampWeights` pass, these links can never carry any power
+            if link.weight >= 15 {
+                continue;
+            }
+            let ty = match link.ty {
+                GLinkType::Default => LinkType::Default,
+                GLinkType::Side => LinkType::Side,
+            };
+            let link = CompileLink {
+                through_wire: link.through_wire,
+                ..CompileLink::new(ty, link.weight)
+            };
+            graph.add_edge(from, indices[id], link);
+        }
+    }
+    Ok(graph)
+}
+
+/// The blocks of the nodes in an imported graph. Only the backend changes them, so nothing is ever
+/// ticked here.
+#[derive(Default)]
+struct HeadlessWorld {
+    blocks: FxHashMap<BlockPos, u32>,
+    block_entities: FxHashMap<BlockPos, BlockEntity>,
+}
+
+impl World for HeadlessWorld {
+    fn get_block_raw(&self, pos: BlockPos) -> u32 {
+        self.blocks.get(&pos).copied().unwrap_or(0)
+    }
+
+    fn set_block_raw(&mut self, pos: BlockPos, block: u32) -> bool {
+        self.blocks.insert(pos, block) != Some(block)
+    }
+
+    fn delete_block_entity(&mut self, pos: BlockPos) {
+        self.block_entities.remove(&pos);
+    }
+
+    fn get_block_entity(&self, pos: BlockPos) -> Option<&BlockEntity> {
+        self.block_entities.get(&pos)
+    }
+
+    fn set_block_entity(&mut self, pos: BlockPos, block_entity: BlockEntity) {
+        self.block_entities.insert(pos, block_entity);
+    }
+
+    fn get_chunk(&self, _: i32, _: i32) -> Option<&Chunk> {
+        None
+    }
+
+    fn get_chunk_mut(&mut self, _: i32, _: i32) -> Option<&mut Chunk> {
+        None
+    }
+
+    fn schedule_tick(&mut self, _: BlockPos, _: u32, _: TickPriority) {}
+
+    fn pending_tick_at(&mut self, _: BlockPos) -> bool {
+        false
+    }
+
+    fn cancel_ticks(&mut self, _: BlockPos) {}
+}
+
+/// Refers to a node of an imported graph, either by its id in the graph file or by the position
+/// of its block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeRef {
+    Id(NodeId),
+    Pos(BlockPos),
+}
+
+impl From<NodeId> for NodeRef {
+    fn from(id: NodeId) -> NodeRef {
+        NodeRef::Id(id)
+    }
+}
+
+impl From<BlockPos> for NodeRef {
+    fn from(pos: BlockPos) -> NodeRef {
+        NodeRef::Pos(pos)
+    }
+}
+
+/// Runs an exported graph on a backend, without a world.
+pub struct HeadlessRunner {
+    compiler: Compiler,
+    world: HeadlessWorld,
+    /// The position of every node, indexed by its id in the graph file
+    positions: Vec<Option<BlockPos>>,
+}
+
+impl HeadlessRunner {
+    pub fn new(
+        nodes: &[Node],
+        options: CompilerOptions,
+    ) -> Result<HeadlessRunner, GraphImportError> {
+        let mut graph = import_graph(nodes)?;
+        for node in graph.node_weights_mut() {
+            node.clamp_signal_strength(options.max_signal_strength());
+        }
+
+        let mut world = HeadlessWorld::default();
+        let mut positions = Vec::with_capacity(nodes.len());
+        for node in graph.node_weights() {
+            if let Some((pos, id)) = node.block {
+                world.set_block_raw(pos, id);
+            }
+            positions.push(node.block.map(|(pos, _)| pos));
+        }
+
+        let mut compiler = Compiler::default();
+        compiler.compile_graph(
+            graph,
+            Vec::new(),
+            options,
+            Default::default(),
+            Default::default(),
+        );
+        Ok(HeadlessRunner {
+            compiler,
+            world,
+            positions,
+        })
+    }
+
+    /// Loads a graph file written by the `ExportGraph` pass.
+    pub fn load(
+        path: impl AsRef<Path>,
+        options: CompilerOptions,
+    ) -> Result<HeadlessRunner, GraphImportError> {
+        let bytes = fs::read(path)?;
+        let nodes = redpiler_graph::deserialize(&bytes)?;
+        HeadlessRunner::new(&nodes, options)
+    }
+
+    /// Returns the position of the block of a node, if it has one.
+    pub fn node_pos(&self, node: impl Into<NodeRef>) -> Option<BlockPos> {
+        match node.into() {
+            NodeRef::Id(id) => self.positions.get(id).copied().flatten(),
+            NodeRef::Pos(pos) => Some(pos),
+        }
+    }
+
+    /// Returns the position of the block of a node, checking that there is a node there.
+    fn block_pos(&self, node: NodeRef) -> Result<BlockPos, NodeError> {
+        match self.node_pos(node) {
+            Some(pos) if self.world.blocks.contains_key(&pos) => Ok(pos),
+            _ => Err(NodeError::NoNode(node)),
+        }
+    }
+
+    /// Returns the position of an input node, checking that it was compiled and that its block is
+    /// one `is_input` accepts.
+    fn input_pos(
+        &self,
+        node: NodeRef,
+        is_input: fn(Block) -> bool,
+        expected: &'static str,
+    ) -> Result<BlockPos, NodeError> {
+        let pos = self.block_pos(node)?;
+        if self.compiler.node_state(pos).is_none() {
+            return Err(NodeError::NoNode(node));
+        }
+        if !is_input(self.world.get_block(pos)) {
+            return Err(NodeError::WrongBlock { pos, expected });
+        }
+        Ok(pos)
+    }
+
+    /// Runs the graph for `ticks` redstone ticks.
+    pub fn run(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.compiler.tick();
+        }
+        self.compiler.flush(&mut self.world);
+    }
+
+    pub fn tick(&mut self) {
+        self.run(1);
+    }
+
+    /// Flips a lever or presses a button.
+    pub fn use_block(&mut self, node: impl Into<NodeRef>) -> Result<(), NodeError> {
+        let pos = self.input_pos(
+            node.into(),
+            |block| matches!(block, Block::Lever { .. } | Block::StoneButton { .. }),
+            "lever or button",
+        )?;
+        self.compiler.on_use_block(pos);
+        self.compiler.flush(&mut self.world);
+        Ok(())
+    }
+
+    pub fn set_pressure_plate(
+        &mut self,
+        node: impl Into<NodeRef>,
+        powered: bool,
+    ) -> Result<(), NodeError> {
+        let pos = self.input_pos(
+            node.into(),
+            |block| matches!(block, Block::StonePressurePlate { .. }),
+            "pressure plate",
+        )?;
+        self.compiler.set_pressure_plate(pos, powered);
+        self.compiler.flush(&mut self.world);
+        Ok(())
+    }
+
+    /// Returns the current block of a node. Unless `io_only` is set, this is kept up to date for
+    /// every node.
+    pub fn block(&self, node: impl Into<NodeRef>) -> Result<Block, NodeError> {
+        Ok(self.world.get_block(self.block_pos(node.into())?))
+    }
+
+    /// Returns whether a node is powered, or `None` if its block cannot be powered.
+    pub fn is_powered(&self, node: impl Into<NodeRef>) -> Result<Option<bool>, NodeError> {
+        let mut block = self.block(node)?;
+        Ok(block_powered_mut(&mut block).map(|powered| *powered))
+    }
+
+    pub fn has_pending_ticks(&mut self) -> bool {
+        self.compiler.has_pending_ticks()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mchprs_blocks::blocks::{Lever, RedstoneRepeater};
+    use redpiler_graph::{BlockPos as GBlockPos, Link, NodeState as GNodeState};
+
+    fn node(ty: GNodeType, x: i32, block: Block, inputs: Vec<Link>) -> Node {
+        Node {
+            ty,
+            block: Some((GBlockPos { x, y: 0, z: 0 }, block.get_id())),
+            state: GNodeState {
+                powered: false,
+                repeater_locked: false,
+                output_strength: 0,
+            },
+            facing_diode: false,
+            comparator_far_input: None,
+            is_input: matches!(ty, GNodeType::Lever),
+            is_output: matches!(ty, GNodeType::Lamp),
+            bus: None,
+            inputs,
+            updates: Vec::new(),
+        }
+    }
+
+    fn link(to: NodeId) -> Link {
+        Link {
+            ty: GLinkType::Default,
+            weight: 0,
+            through_wire: false,
+            to,
+        }
+    }
+
+    /// A lever powering a lamp through a repeater with a delay of 2
+    fn lever_repeater_lamp() -> Vec<Node> {
+        let repeater = RedstoneRepeater {
+            delay: 2,
+            ..Default::default()
+        };
+        vec![
+            node(
+                GNodeType::Lever,
+                0,
+                Block::Lever {
+                    lever: Lever::default(),
+                },
+                vec![],
+            ),
+            node(
+                GNodeType::Repeater(2),
+                1,
+                Block::RedstoneRepeater { repeater },
+                vec![link(0)],
+            ),
+            node(
+                GNodeType::Lamp,
+                2,
+                Block::RedstoneLamp { lit: false },
+                vec![link(1)],
+            ),
+        ]
+    }
+
+    #[test]
+    fn run_imported_graph() {
+        let mut runner = HeadlessRunner::new(&lever_repeater_lamp(), Default::default()).unwrap();
+        let lamp = BlockPos::new(2, 0, 0);
+
+        assert!(matches!(
+            runner.use_block(BlockPos::new(5, 0, 0)),
+            Err(NodeError::NoNode(_))
+        ));
+        assert!(matches!(
+            runner.use_block(1),
+            Err(NodeError::WrongBlock { .. })
+        ));
+        runner.use_block(0).unwrap();
+        assert_eq!(runner.is_powered(0).unwrap(), Some(true));
+        runner.run(1);
+        assert_eq!(runner.is_powered(lamp).unwrap(), Some(false));
+        runner.run(1);
+        assert_eq!(runner.is_powered(lamp).unwrap(), Some(true));
+        assert_eq!(runner.is_powered(2).unwrap(), Some(true));
+    }
+
+    #[test]
+    fn read_missing_node() {
+        let runner = HeadlessRunner::new(&lever_repeater_lamp(), Default::default()).unwrap();
+
+        assert!(matches!(runner.block(99), Err(NodeError::NoNode(_))));
+        assert!(matches!(
+            runner.is_powered(BlockPos::new(5, 0, 0)),
+            Err(NodeError::NoNode(_))
+        ));
+    }
+
+    #[test]
+    fn reject_invalid_link() {
+        let mut nodes = lever_repeater_lamp();
+        nodes[2].inputs.push(link(3));
+        assert!(matches!(
+            HeadlessRunner::new(&nodes, Default::default()),
+            Err(GraphImportError::InvalidLink { node: 2, from: 3 })
+        ));
+    }
+}
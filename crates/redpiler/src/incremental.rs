@@ -0,0 +1,317 @@
+//! Patching a compiled graph after blocks were edited, instead of compiling everything again.
+//!
+//! Every block whose node might have changed is identified again, and every node that might have
+//! found different inputs gets its links searched again. A component only looks a couple of blocks
+//! around itself for inputs, except through redstone wire, so whole wire networks next to the edit
+//! are taken into account as well.
+//!
+//! The graph that gets patched is the one from before the optimization passes, since those merge
+//! and remove nodes, after which there is no way to tell which nodes a block ended up in. A graph
+//! that was not optimized is patched in the backend in place. For an optimized one, the
+//! optimization passes run again on the patched graph and the backend is compiled anew from it,
+//! which still skips searching the whole world for nodes and their inputs.
+
+use crate::compile_graph::{CompileGraph, CompileNode, NodeIdx};
+use crate::fallback::FallbackRegion;
+use crate::passes::{identify_node, pos_map, search_inputs};
+use crate::{BackendVariant, CompilerOptions};
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockPos};
+use mchprs_world::storage::Chunk;
+use mchprs_world::{TickEntry, TickPriority, World};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// How far from the edited block other blocks may have changed, for example wires that connect
+/// to a newly placed component.
+const EDIT_RADIUS: i32 = 2;
+/// How far a component looks for inputs, at most through a solid block next to it.
+const SEARCH_RADIUS: i32 = 2;
+
+/// The changes made to the graph, for the backend to apply to its own nodes.
+#[derive(Debug, Default)]
+pub struct GraphPatch {
+    /// Positions of the nodes that no longer exist
+    pub removed: Vec<BlockPos>,
+    /// Nodes that did not exist before
+    pub added: Vec<NodeIdx>,
+    /// Nodes whose incoming links were searched again, including all added nodes
+    pub relinked: Vec<NodeIdx>,
+    /// Positions of the nodes that linked to removed or relinked nodes before the edit
+    pub unlinked: Vec<BlockPos>,
+}
+
+pub struct IncrementalGraph {
+    graph: CompileGraph,
+    pos_map: FxHashMap<BlockPos, NodeIdx>,
+    bounds: (BlockPos, BlockPos),
+}
+
+impl IncrementalGraph {
+    pub fn new(graph: CompileGraph, bounds: (BlockPos, BlockPos)) -> IncrementalGraph {
+        IncrementalGraph {
+            pos_map: pos_map(&graph),
+            graph,
+            bounds,
+        }
+    }
+
+    /// Returns true if a graph compiled with `options` can be patched. With `io_only`, the state of
+    /// the nodes in between is never written back to the world, so it could not be carried over
+    /// to a new backend.
+    pub fn is_supported(options: &CompilerOptions, fallback: &FallbackRegion) -> bool {
+        !options.io_only && !options.trace && !options.high_signal_strength && fallback.is_empty()
+    }
+
+    /// Returns true if the backend can apply a [`GraphPatch`] to its nodes, instead of having to
+    /// be compiled again.
+    pub fn can_patch_in_place(options: &CompilerOptions) -> bool {
+        !options.optimize && options.backend_variant == BackendVariant::Direct
+    }
+
+    pub fn graph(&self) -> &CompileGraph {
+        &self.graph
+    }
+
+    pub fn bounds(&self) -> (BlockPos, BlockPos) {
+        self.bounds
+    }
+
+    /// Reads the state of every node from its block again, after the backend wrote its state
+    /// back to the world.
+    pub fn refresh_states<W: World>(&mut self, world: &W, options: &CompilerOptions) {
+        let fallback = FallbackRegion::default();
+        for node in self.graph.node_weights_mut() {
+            let Some((pos, _)) = node.block else {
+                continue;
+            };
+            if let Some(new) = identify_node(world, pos, options, &fallback) {
+                node.state = new.state;
+            }
+        }
+    }
+
+    fn in_bounds(&self, pos: BlockPos) -> bool {
+        let (first, second) = self.bounds;
+        (first.x..=second.x).contains(&pos.x)
+            && (first.y..=second.y).contains(&pos.y)
+            && (first.z..=second.z).contains(&pos.z)
+    }
+
+    /// Brings the graph up to date with the blocks around `pos`, which was just edited.
+    pub fn update<W: World>(
+        &mut self,
+        world: &W,
+        options: &CompilerOptions,
+        pos: BlockPos,
+    ) -> GraphPatch {
+        let fallback = FallbackRegion::default();
+        let mut patch = GraphPatch::default();
+        // Kept as positions, since removed node indices can be reused by added nodes
+        let mut dirty = FxHashSet::default();
+        let mut changed = Vec::new();
+
+        for pos in area(pos, EDIT_RADIUS + SEARCH_RADIUS) {
+            if !self.in_bounds(pos) {
+                continue;
+            }
+            let new = identify_node(world, pos, options, &fallback);
+            let old = self.pos_map.get(&pos).copied();
+            match (old, new) {
+                (None, None) => continue,
+                (Some(idx), Some(node)) if is_same_node(&self.graph[idx], &node) => {
+                    // Keep the state, but the block may have been rotated or reshaped
+                    self.graph[idx].block = node.block;
+                    continue;
+                }
+                (old, new) => {
+                    if let Some(idx) = old {
+                        for target in self.graph.neighbors_directed(idx, Direction::Outgoing) {
+                            dirty.insert(self.graph[target].block.unwrap().0);
+                        }
+                        for source in self.graph.neighbors_directed(idx, Direction::Incoming) {
+                            patch.unlinked.push(self.graph[source].block.unwrap().0);
+                        }
+                        self.graph.remove_node(idx);
+                        self.pos_map.remove(&pos);
+                        patch.removed.push(pos);
+                    }
+                    if let Some(node) = new {
+                        let idx = self.graph.add_node(node);
+                        self.pos_map.insert(pos, idx);
+                        patch.added.push(idx);
+                    }
+                    changed.push(pos);
+                }
+            }
+        }
+
+        let mut affected: FxHashSet<BlockPos> = area(pos, EDIT_RADIUS).collect();
+        affected.extend(changed.iter().copied());
+        let wires = connected_wires(world, affected.iter().copied());
+        affected.extend(wires);
+        for &affected_pos in &affected {
+            for pos in area(affected_pos, SEARCH_RADIUS) {
+                if self.pos_map.contains_key(&pos) {
+                    dirty.insert(pos);
+                }
+            }
+        }
+
+        patch.relinked = dirty
+            .into_iter()
+            .filter_map(|pos| self.pos_map.get(&pos).copied())
+            .collect();
+        for &idx in &patch.relinked {
+            let edges: Vec<_> = self
+                .graph
+                .edges_directed(idx, Direction::Incoming)
+                .map(|edge| (edge.id(), edge.source()))
+                .collect();
+            for (edge, source) in edges {
+                patch.unlinked.push(self.graph[source].block.unwrap().0);
+                self.graph.remove_edge(edge);
+            }
+        }
+        search_inputs(
+            world,
+            &fallback,
+            &mut self.graph,
+            &self.pos_map,
+            &patch.relinked,
+        );
+        // Same as the clamp weights pass
+        for &idx in &patch.relinked {
+            let edges: Vec<_> = self
+                .graph
+                .edges_directed(idx, Direction::Incoming)
+                .filter(|edge| edge.weight().ss >= 15)
+                .map(|edge| edge.id())
+                .collect();
+            for edge in edges {
+                self.graph.remove_edge(edge);
+            }
+        }
+
+        patch
+    }
+}
+
+/// Keeps the ticks a backend schedules while it is reset, so they can be handed to the backend
+/// that is compiled next. Everything else goes to the world.
+pub struct TickCapture<'a, W: World> {
+    world: &'a mut W,
+    pub ticks: Vec<TickEntry>,
+}
+
+impl<'a, W: World> TickCapture<'a, W> {
+    pub fn new(world: &'a mut W) -> TickCapture<'a, W> {
+        TickCapture {
+            world,
+            ticks: Vec::new(),
+        }
+    }
+}
+
+impl<W: World> World for TickCapture<'_, W> {
+    fn get_block_raw(&self, pos: BlockPos) -> u32 {
+        self.world.get_block_raw(pos)
+    }
+
+    fn set_block_raw(&mut self, pos: BlockPos, block: u32) -> bool {
+        self.world.set_block_raw(pos, block)
+    }
+
+    fn delete_block_entity(&mut self, pos: BlockPos) {
+        self.world.delete_block_entity(pos);
+    }
+
+    fn get_block_entity(&self, pos: BlockPos) -> Option<&BlockEntity> {
+        self.world.get_block_entity(pos)
+    }
+
+    fn set_block_entity(&mut self, pos: BlockPos, block_entity: BlockEntity) {
+        self.world.set_block_entity(pos, block_entity);
+    }
+
+    fn get_chunk(&self, x: i32, z: i32) -> Option<&Chunk> {
+        self.world.get_chunk(x, z)
+    }
+
+    fn get_chunk_mut(&mut self, x: i32, z: i32) -> Option<&mut Chunk> {
+        self.world.get_chunk_mut(x, z)
+    }
+
+    fn schedule_tick(&mut self, pos: BlockPos, delay: u32, priority: TickPriority) {
+        self.ticks.push(TickEntry {
+            ticks_left: delay,
+            tick_priority: priority,
+            pos,
+        });
+    }
+
+    fn pending_tick_at(&mut self, pos: BlockPos) -> bool {
+        self.ticks.iter().any(|entry| entry.pos == pos)
+    }
+
+    fn cancel_ticks(&mut self, pos: BlockPos) {
+        self.ticks.retain(|entry| entry.pos != pos);
+    }
+}
+
+/// Nodes are only patched in place if the backend can keep treating them the same way.
+fn is_same_node(old: &CompileNode, new: &CompileNode) -> bool {
+    let name = |node: &CompileNode| Block::from_id(node.block.unwrap().1).get_name();
+    old.ty == new.ty
+        && old.is_input == new.is_input
+        && old.is_output == new.is_output
+        && name(old) == name(new)
+}
+
+/// Every position within `radius` blocks of `center` on each axis.
+fn area(center: BlockPos, radius: i32) -> impl Iterator<Item = BlockPos> {
+    (-radius..=radius).flat_map(move |x| {
+        (-radius..=radius).flat_map(move |y| {
+            (-radius..=radius).map(move |z| BlockPos::new(center.x + x, center.y + y, center.z + z))
+        })
+    })
+}
+
+/// Finds every wire that is connected to a wire within `SEARCH_RADIUS` of `positions`. This may
+/// find a few more wires than are actually connected, which is fine.
+fn connected_wires<W: World>(
+    world: &W,
+    positions: impl Iterator<Item = BlockPos>,
+) -> FxHashSet<BlockPos> {
+    let is_wire = |pos| matches!(world.get_block(pos), Block::RedstoneWire { .. });
+    let mut found = FxHashSet::default();
+    let mut queue = Vec::new();
+    for pos in positions {
+        for pos in area(pos, SEARCH_RADIUS) {
+            if is_wire(pos) && found.insert(pos) {
+                queue.push(pos);
+            }
+        }
+    }
+    while let Some(pos) = queue.pop() {
+        for side in BlockFace::values() {
+            if !side.is_horizontal() {
+                continue;
+            }
+            let side_pos = pos.offset(side);
+            // Wires also connect one block up or down
+            for neighbor in [
+                side_pos,
+                side_pos.offset(BlockFace::Top),
+                side_pos.offset(BlockFace::Bottom),
+            ] {
+                if is_wire(neighbor) && found.insert(neighbor) {
+                    queue.push(neighbor);
+                }
+            }
+        }
+    }
+    found
+}
@@ -1,20 +1,44 @@
 mod backend;
+mod breakpoint;
+mod cache;
 mod compile_graph;
+mod fallback;
+mod headless;
+mod incremental;
 mod task_monitor;
+mod tick;
+mod trace;
+mod verify;
 // mod debug_graph;
 mod passes;
+mod report;
+mod snapshot;
 
 use backend::{BackendDispatcher, JITBackend};
+use cache::CompileCache;
+use compile_graph::{CompileGraph, NodeType};
+use incremental::{IncrementalGraph, TickCapture};
 use mchprs_blocks::blocks::Block;
 use mchprs_blocks::BlockPos;
 use mchprs_world::TickEntry;
 use mchprs_world::{for_each_block_mut_optimized, World};
-use passes::make_default_pass_manager;
+use passes::{make_build_pass_manager, make_optimize_pass_manager};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Instant;
 use tracing::{debug, error, trace, warn};
 
+pub use breakpoint::{BreakCondition, BreakpointHit};
+pub use fallback::{FallbackRegion, FallbackWorld};
+pub use headless::{GraphImportError, HeadlessRunner, NodeError, NodeRef};
+pub use report::{CompileReport, GraphStats, PassReport};
+pub use snapshot::{Snapshot, SnapshotError};
 pub use task_monitor::TaskMonitor;
+pub use tick::{step_world_phase, tick_world, PendingTicks};
+pub use trace::{NodeState, Trace, TraceCause, TraceChange, TraceError, TraceNode};
+pub use verify::{verify_configurations, Divergence, Verifier, VerifyError, VerifyResult};
 
 fn block_powered_mut(block: &mut Block) -> Option<&mut bool> {
     Some(match block {
@@ -28,6 +52,7 @@ fn block_powered_mut(block: &mut Block) -> Option<&mut bool> {
         Block::RedstoneLamp { lit } => lit,
         Block::IronTrapdoor { powered, .. } => powered,
         Block::NoteBlock { powered, .. } => powered,
+        Block::Observer { powered, .. } => powered,
         _ => return None,
     })
 }
@@ -46,14 +71,37 @@ pub struct CompilerOptions {
     pub export_dot_graph: bool,
     /// Consider a redstone dot to be an output block (for color screens)
     pub wire_dot_out: bool,
+    /// Record every node state change to `redpiler_trace.bin` (direct backend only)
+    pub trace: bool,
+    /// Only compile the WorldEdit selection, the rest keeps running on the default redstone
+    /// implementation. Compiling the selection is up to the caller, see [`Compiler::compile_selection`].
+    pub selection: bool,
+    /// Let comparators output signal strengths above 15, such as container overrides.
+    /// Not supported by the cranelift backend.
+    pub high_signal_strength: bool,
     /// The backend variant to be used after compilation
     pub backend_variant: BackendVariant,
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub enum BackendVariant {
     #[default]
     Direct,
+    Cranelift,
+    Parallel,
+}
+
+impl FromStr for BackendVariant {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "direct" => BackendVariant::Direct,
+            "cranelift" => BackendVariant::Cranelift,
+            "parallel" => BackendVariant::Parallel,
+            _ => return Err(()),
+        })
+    }
 }
 
 impl CompilerOptions {
@@ -61,7 +109,13 @@ impl CompilerOptions {
         let mut co: CompilerOptions = Default::default();
         let options = str.split_whitespace();
         for option in options {
-            if option.starts_with("--") {
+            if let Some(variant) = option.strip_prefix("--backend=") {
+                match variant.parse() {
+                    Ok(variant) => co.backend_variant = variant,
+                    // FIXME: use actual error handling
+                    Err(()) => warn!("Unrecognized backend: {}", variant),
+                }
+            } else if option.starts_with("--") {
                 match option {
                     "--optimize" => co.optimize = true,
                     "--export" => co.export = true,
@@ -69,6 +123,9 @@ impl CompilerOptions {
                     "--update" => co.update = true,
                     "--export-dot" => co.export_dot_graph = true,
                     "--wire-dot-out" => co.wire_dot_out = true,
+                    "--trace" => co.trace = true,
+                    "--selection" => co.selection = true,
+                    "--hss" => co.high_signal_strength = true,
                     // FIXME: use actual error handling
                     _ => warn!("Unrecognized option: {}", option),
                 }
@@ -92,6 +149,14 @@ impl CompilerOptions {
         }
         co
     }
+
+    /// The highest signal strength a node can output with these options.
+    pub(crate) fn max_signal_strength(&self) -> u8 {
+        match self.high_signal_strength {
+            true => u8::MAX,
+            false => 15,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -99,6 +164,17 @@ pub struct Compiler {
     is_active: bool,
     jit: Option<BackendDispatcher>,
     options: CompilerOptions,
+    fallback: FallbackRegion,
+    /// Positions of the compiled nodes that can affect the fallback region
+    interface_outputs: Vec<BlockPos>,
+    /// Positions of the interface nodes along with the power they were last set to
+    interface_inputs: Vec<(BlockPos, u8)>,
+    /// The graph the backend was compiled from, if it can be patched after block edits
+    incremental: Option<IncrementalGraph>,
+    cache: Option<CompileCache>,
+    report: Option<CompileReport>,
+    /// Kept across compiles, so they don't have to be set again after every edit
+    breakpoints: Vec<(BlockPos, BreakCondition)>,
 }
 
 impl Compiler {
@@ -119,6 +195,12 @@ impl Compiler {
         self.jit = Some(jit);
     }
 
+    /// Keep compiled graphs in `dir`, so compiling blocks that were compiled before with the same
+    /// options can skip the passes.
+    pub fn use_cache(&mut self, dir: impl Into<PathBuf>) {
+        self.cache = Some(CompileCache::new(dir));
+    }
+
     pub fn compile<W: World>(
         &mut self,
         world: &W,
@@ -126,48 +208,197 @@ impl Compiler {
         options: CompilerOptions,
         ticks: Vec<TickEntry>,
         monitor: Arc<TaskMonitor>,
+    ) {
+        let fallback = FallbackRegion::find(world, bounds);
+        self.compile_region(world, bounds, fallback, options, ticks, monitor);
+    }
+
+    /// Compiles only `selection`, leaving the rest of `bounds` to the default redstone
+    /// implementation. The selection may be grown a bit to keep wires from being cut in half.
+    pub fn compile_selection<W: World>(
+        &mut self,
+        world: &W,
+        bounds: (BlockPos, BlockPos),
+        selection: (BlockPos, BlockPos),
+        options: CompilerOptions,
+        ticks: Vec<TickEntry>,
+        monitor: Arc<TaskMonitor>,
+    ) {
+        let (fallback, selection) = FallbackRegion::find_in_selection(world, bounds, selection);
+        debug!(
+            "Compiling selection from {} to {}",
+            selection.0, selection.1
+        );
+        self.compile_region(world, selection, fallback, options, ticks, monitor);
+    }
+
+    fn compile_region<W: World>(
+        &mut self,
+        world: &W,
+        bounds: (BlockPos, BlockPos),
+        fallback: FallbackRegion,
+        options: CompilerOptions,
+        ticks: Vec<TickEntry>,
+        monitor: Arc<TaskMonitor>,
     ) {
         debug!("Starting compile");
         let start = Instant::now();
 
-        let input = CompilerInput { world, bounds };
-        let pass_manager = make_default_pass_manager::<W>();
-        let graph = pass_manager.run_passes(&options, &input, monitor.clone());
+        if !fallback.is_empty() {
+            debug!("Found {} blocks to fall back on", fallback.iter().count());
+        }
+        let input = CompilerInput {
+            world,
+            bounds,
+            fallback: &fallback,
+        };
+        let cache_key = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.key(world, bounds, &fallback, &options));
+        let mut report = CompileReport::default();
+        let cached = match (&self.cache, &cache_key) {
+            (Some(cache), Some(key)) => cache.load(key),
+            _ => None,
+        };
+        let incremental = IncrementalGraph::is_supported(&options, &fallback);
+        // The graph before the optimization passes, which is the one that gets patched
+        let mut unoptimized = None;
+        let graph = match cached {
+            Some(graph) => {
+                debug!("Using cached graph");
+                // Only the backend is left to compile
+                monitor.set_max_progress(1);
+                report.cached = true;
+                if incremental && !options.optimize {
+                    unoptimized = Some(graph.clone());
+                }
+                graph
+            }
+            None => {
+                let build = make_build_pass_manager::<W>();
+                let optimize = make_optimize_pass_manager::<W>();
+                // Add one for the backend compile step
+                monitor.set_max_progress(build.pass_count() + optimize.pass_count() + 1);
+                let graph = build.run_passes(
+                    CompileGraph::new(),
+                    &options,
+                    &input,
+                    monitor.clone(),
+                    &mut report,
+                );
+                if incremental {
+                    unoptimized = Some(graph.clone());
+                }
+                let graph =
+                    optimize.run_passes(graph, &options, &input, monitor.clone(), &mut report);
+                if monitor.cancelled() {
+                    return;
+                }
+                if let (Some(cache), Some(key)) = (&self.cache, &cache_key) {
+                    cache.store(key, &graph);
+                }
+                graph
+            }
+        };
+
+        self.interface_outputs.clear();
+        self.interface_inputs.clear();
+        for node in graph.node_weights() {
+            // Constants made by the passes have no block
+            let Some((pos, _)) = node.block else {
+                continue;
+            };
+            if node.ty == NodeType::Interface {
+                self.interface_inputs
+                    .push((pos, node.state.output_strength));
+            } else if fallback.is_near(pos) {
+                self.interface_outputs.push(pos);
+            }
+        }
+        // Ticks in the fallback region are left to the default redstone implementation
+        let ticks = ticks
+            .into_iter()
+            .filter(|entry| !fallback.contains(entry.pos))
+            .collect();
+        self.incremental = unoptimized.map(|graph| IncrementalGraph::new(graph, bounds));
+        self.fallback = fallback;
 
-        if monitor.cancelled() {
-            return;
+        self.compile_graph(graph, ticks, options, monitor, report);
+        if let Some(report) = &mut self.report {
+            report.total_duration = start.elapsed();
+        }
+        debug!("Compile completed in {:?}", start.elapsed());
+    }
+
+    /// Compiles an already built graph with the backend selected in `options`.
+    fn compile_graph(
+        &mut self,
+        graph: CompileGraph,
+        ticks: Vec<TickEntry>,
+        mut options: CompilerOptions,
+        monitor: Arc<TaskMonitor>,
+        mut report: CompileReport,
+    ) {
+        if options.high_signal_strength && options.backend_variant == BackendVariant::Cranelift {
+            warn!("High signal strength is not supported by the cranelift backend, using direct instead");
+            options.backend_variant = BackendVariant::Direct;
         }
 
         let replace_jit = match self.jit {
             Some(BackendDispatcher::DirectBackend(_)) => {
                 options.backend_variant != BackendVariant::Direct
             }
+            Some(BackendDispatcher::CraneliftBackend(_)) => {
+                options.backend_variant != BackendVariant::Cranelift
+            }
+            Some(BackendDispatcher::ParallelBackend(_)) => {
+                options.backend_variant != BackendVariant::Parallel
+            }
             None => true,
         };
         if replace_jit {
             debug!("Switching jit backend to {:?}", options.backend_variant);
             let jit = match options.backend_variant {
                 BackendVariant::Direct => BackendDispatcher::DirectBackend(Default::default()),
+                BackendVariant::Cranelift => {
+                    BackendDispatcher::CraneliftBackend(Default::default())
+                }
+                BackendVariant::Parallel => BackendDispatcher::ParallelBackend(Default::default()),
             };
             self.use_jit(jit);
         }
 
+        if options.trace && options.backend_variant == BackendVariant::Cranelift {
+            warn!("Tracing is not supported by the cranelift backend");
+        }
+
         if let Some(jit) = &mut self.jit {
             trace!("Compiling backend");
             monitor.set_message("Compiling backend".to_string());
             let start = Instant::now();
 
+            report.graph = GraphStats::of(&graph);
             jit.compile(graph, ticks, &options, monitor.clone());
+            for &(pos, condition) in &self.breakpoints {
+                jit.set_breakpoint(pos, Some(condition));
+            }
 
             monitor.inc_progress();
-            trace!("Backend compiled in {:?}", start.elapsed());
+            report.backend_duration = start.elapsed();
+            trace!("Backend compiled in {:?}", report.backend_duration);
         } else {
             error!("Cannot compile without JIT variant selected");
         }
 
         self.options = options;
+        self.report = Some(report);
         self.is_active = true;
-        debug!("Compile completed in {:?}", start.elapsed());
+    }
+
+    /// Returns the report of the last compile, which is kept after redpiler is reset.
+    pub fn report(&self) -> Option<&CompileReport> {
+        self.report.as_ref()
     }
 
     pub fn reset<W: World>(&mut self, world: &mut W, bounds: (BlockPos, BlockPos)) {
@@ -177,6 +408,10 @@ impl Compiler {
                 jit.reset(world, self.options.io_only)
             }
         }
+        self.fallback = Default::default();
+        self.interface_outputs.clear();
+        self.interface_inputs.clear();
+        self.incremental = None;
 
         if self.options.update {
             let (first_pos, second_pos) = bounds;
@@ -188,6 +423,91 @@ impl Compiler {
         self.options = Default::default();
     }
 
+    /// Returns true if blocks can be edited without having to reset redpiler, as long as
+    /// [`Compiler::recompile_around`] is called after every edit.
+    pub fn can_recompile(&self) -> bool {
+        self.is_active && self.incremental.is_some()
+    }
+
+    /// Recompiles the blocks around `pos` after it was edited, keeping the state of all other
+    /// nodes. Ticks the default redstone implementation scheduled for compiled blocks during the
+    /// edit should be discarded. Returns false if redpiler has to be reset instead.
+    pub fn recompile_around<W: World>(&mut self, world: &mut W, pos: BlockPos) -> bool {
+        if !self.is_active {
+            return false;
+        }
+        let (Some(incremental), Some(jit)) = (&mut self.incremental, &mut self.jit) else {
+            return false;
+        };
+        let start = Instant::now();
+        let patch = incremental.update(world, &self.options, pos);
+        let in_place = IncrementalGraph::can_patch_in_place(&self.options)
+            && jit.patch(incremental.graph(), &patch);
+        if in_place {
+            // Nodes that were removed took their breakpoints with them, and may be back now
+            for &(pos, condition) in &self.breakpoints {
+                jit.set_breakpoint(pos, Some(condition));
+            }
+        } else {
+            let positions: Vec<BlockPos> = patch
+                .relinked
+                .iter()
+                .map(|&idx| incremental.graph()[idx].block.unwrap().0)
+                .collect();
+            self.rebuild(world);
+            if let Some(jit) = &mut self.jit {
+                // Like the patch does, this lets the nodes react to their new inputs
+                jit.update_nodes(&positions);
+            }
+        }
+        debug!(
+            "Recompiled {} nodes around {} in {:?}",
+            patch.relinked.len(),
+            pos,
+            start.elapsed()
+        );
+        self.flush(world);
+        true
+    }
+
+    /// Compiles the backend again from the patched graph, running the optimization passes on it
+    /// first. The state of the nodes is carried over through the world.
+    fn rebuild<W: World>(&mut self, world: &mut W) {
+        let (Some(incremental), Some(jit)) = (&mut self.incremental, &mut self.jit) else {
+            return;
+        };
+        let mut capture = TickCapture::new(world);
+        jit.reset(&mut capture, false);
+        let ticks = capture.ticks;
+        incremental.refresh_states(world, &self.options);
+
+        let input = CompilerInput {
+            world: &*world,
+            bounds: incremental.bounds(),
+            fallback: &self.fallback,
+        };
+        let monitor = Arc::new(TaskMonitor::default());
+        // The report of the last full compile is kept
+        let mut report = CompileReport::default();
+        // The graph is only exported when it is compiled from scratch, not after every edit
+        let options = CompilerOptions {
+            export: false,
+            export_dot_graph: false,
+            ..self.options.clone()
+        };
+        let graph = make_optimize_pass_manager::<W>().run_passes(
+            incremental.graph().clone(),
+            &options,
+            &input,
+            monitor.clone(),
+            &mut report,
+        );
+        jit.compile(graph, ticks, &options, monitor);
+        for &(pos, condition) in &self.breakpoints {
+            jit.set_breakpoint(pos, Some(condition));
+        }
+    }
+
     fn backend(&mut self) -> &mut BackendDispatcher {
         assert!(
             self.is_active,
@@ -200,10 +520,49 @@ impl Compiler {
         }
     }
 
+    /// Runs a game tick, or the rest of one that was stopped halfway. Stops halfway if a
+    /// breakpoint is hit, see [`Compiler::take_breakpoint_hit`].
     pub fn tick(&mut self) {
         self.backend().tick();
     }
 
+    /// Runs the ticks of the next tick priority. Returns true if that finished the game tick.
+    pub fn step_phase(&mut self) -> bool {
+        self.backend().step_phase()
+    }
+
+    /// Returns true if the current game tick was stopped halfway by a breakpoint or
+    /// [`Compiler::step_phase`]. The rest of the tick runs on the next [`Compiler::tick`].
+    pub fn is_mid_tick(&self) -> bool {
+        self.is_active && self.jit.as_ref().is_some_and(|jit| jit.is_mid_tick())
+    }
+
+    /// Sets a breakpoint on the node at `pos`, or removes it if `condition` is `None`. Returns
+    /// true if the breakpoint is attached to a compiled node right now, which needs the direct
+    /// backend. Otherwise it is attached once the node is compiled.
+    pub fn set_breakpoint(&mut self, pos: BlockPos, condition: Option<BreakCondition>) -> bool {
+        self.breakpoints.retain(|&(other, _)| other != pos);
+        if let Some(condition) = condition {
+            self.breakpoints.push((pos, condition));
+        }
+        match (&mut self.jit, self.is_active) {
+            (Some(jit), true) => jit.set_breakpoint(pos, condition) && condition.is_some(),
+            _ => false,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[(BlockPos, BreakCondition)] {
+        &self.breakpoints
+    }
+
+    /// Returns the breakpoint that stopped ticking since this was last called.
+    pub fn take_breakpoint_hit(&mut self) -> Option<BreakpointHit> {
+        match (&mut self.jit, self.is_active) {
+            (Some(jit), true) => jit.take_breakpoint_hit(),
+            _ => None,
+        }
+    }
+
     pub fn on_use_block(&mut self, pos: BlockPos) {
         self.backend().on_use_block(pos);
     }
@@ -213,10 +572,79 @@ impl Compiler {
     }
 
     pub fn flush<W: World>(&mut self, world: &mut W) {
+        // Otherwise the fallback region would never find out about these changes
+        self.flush_interface(world);
         let io_only = self.options.io_only;
         self.backend().flush(world, io_only);
     }
 
+    /// Writes the state of the compiled nodes into `world`, along with the ticks that are pending
+    /// for them, while leaving redpiler running. This is meant for a copy of the world, as
+    /// redpiler keeps on ticking the nodes afterwards. Does nothing if redpiler is inactive or the
+    /// current game tick was stopped halfway.
+    pub fn copy_into<W: World>(&self, world: &mut W) {
+        match &self.jit {
+            Some(jit) if self.is_active && !jit.is_mid_tick() => jit.copy_into(world),
+            _ => {}
+        }
+    }
+
+    /// Returns the region that is simulated by the default redstone implementation while redpiler
+    /// is running, if there is one.
+    pub fn fallback_region(&self) -> Option<&FallbackRegion> {
+        match self.is_active && !self.fallback.is_empty() {
+            true => Some(&self.fallback),
+            false => None,
+        }
+    }
+
+    /// Returns true if the block at `pos` is part of the fallback region.
+    pub fn is_fallback(&self, pos: BlockPos) -> bool {
+        self.fallback_region()
+            .is_some_and(|region| region.contains(pos))
+    }
+
+    /// Writes the compiled nodes that can affect the fallback region into the world and updates
+    /// the blocks of the region around the ones that changed. This should be done right after
+    /// ticking the backend, before the fallback region ticks.
+    pub fn flush_interface<W: World>(&mut self, world: &mut W) {
+        if self.fallback_region().is_none() {
+            return;
+        }
+        let outputs = std::mem::take(&mut self.interface_outputs);
+        let changed = self.backend().flush_interface(world, &outputs);
+        self.interface_outputs = outputs;
+
+        let mut world = FallbackWorld::new(world, &self.fallback);
+        for pos in changed {
+            mchprs_redstone::observer::on_observed_change(&mut world, pos);
+            for pos in self.fallback.affected_by(pos) {
+                let block = world.get_block(pos);
+                mchprs_redstone::update(block, &mut world, pos);
+            }
+        }
+    }
+
+    /// Sets the interface nodes to the power the blocks of the fallback region currently provide.
+    /// This should be done after the fallback region has ticked.
+    pub fn read_interface<W: World>(&mut self, world: &W) {
+        if self.fallback_region().is_none() {
+            return;
+        }
+        let mut inputs = std::mem::take(&mut self.interface_inputs);
+        for (pos, last_power) in &mut inputs {
+            let power = self
+                .fallback
+                .interface_power(world, *pos)
+                .min(self.options.max_signal_strength());
+            if power != *last_power {
+                *last_power = power;
+                self.backend().set_interface_power(*pos, power);
+            }
+        }
+        self.interface_inputs = inputs;
+    }
+
     pub fn inspect(&mut self, pos: BlockPos) {
         if let Some(backend) = &mut self.jit {
             backend.inspect(pos);
@@ -225,6 +653,41 @@ impl Compiler {
         }
     }
 
+    /// Returns the current state of the compiled node at `pos`. Blocks of the fallback region are
+    /// kept up to date in the world instead, so there is no state for them.
+    pub fn node_state(&self, pos: BlockPos) -> Option<NodeState> {
+        if !self.is_active || self.fallback.contains(pos) {
+            return None;
+        }
+        self.jit.as_ref()?.node_state(pos)
+    }
+
+    /// Captures the state of every compiled node along with the ticks they have pending.
+    pub fn snapshot(&self) -> Result<Snapshot, SnapshotError> {
+        match (&self.jit, self.is_active) {
+            (Some(jit), true) => Ok(jit.snapshot()),
+            _ => Err(SnapshotError::NotActive),
+        }
+    }
+
+    /// Rewinds the compiled nodes to the state captured in `snapshot`, which has to be taken of
+    /// the same graph. The blocks are written into the world on the next flush. Blocks of the
+    /// fallback region are left as they are.
+    pub fn restore_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let (Some(jit), true) = (&mut self.jit, self.is_active) else {
+            return Err(SnapshotError::NotActive);
+        };
+        jit.restore(snapshot)?;
+        // Otherwise the interface nodes would not be set again if the fallback region provides
+        // the power they had before restoring
+        for (pos, last_power) in &mut self.interface_inputs {
+            if let Some(state) = jit.node_state(*pos) {
+                *last_power = state.power;
+            }
+        }
+        Ok(())
+    }
+
     pub fn has_pending_ticks(&mut self) -> bool {
         self.backend().has_pending_ticks()
     }
@@ -233,6 +696,7 @@ impl Compiler {
 pub struct CompilerInput<'w, W: World> {
     pub world: &'w W,
     pub bounds: (BlockPos, BlockPos),
+    pub fallback: &'w FallbackRegion,
 }
 
 #[cfg(test)]
@@ -249,10 +713,20 @@ mod tests {
             update: true,
             export_dot_graph: false,
             wire_dot_out: false,
+            trace: false,
+            selection: false,
+            high_signal_strength: false,
             backend_variant: BackendVariant::default(),
         };
         let options = CompilerOptions::parse(input);
 
         assert_eq!(options, expected_options);
     }
+
+    #[test]
+    fn parse_backend_option() {
+        let options = CompilerOptions::parse("-o --backend=cranelift");
+        assert!(options.optimize);
+        assert_eq!(options.backend_variant, BackendVariant::Cranelift);
+    }
 }
@@ -90,12 +90,16 @@ impl<W: World> Pass<W> for AnalogRepeaters {
                 graph.remove_node(idx);
             }
 
-            let state = graph[start_idx].state.clone();
+            // The repeaters never output more than 15, even if the start comparator does
+            let mut state = graph[start_idx].state.clone();
+            state.output_strength = state.output_strength.min(15);
             let new_comparator = graph.add_node(CompileNode {
                 ty: NodeType::Comparator {
                     mode: ComparatorMode::Compare,
                     far_input: None,
-                    facing_diode: false,
+                    // Ticks before other comparators like the repeaters did, so a change of the
+                    // start comparator in the same tick is not seen early
+                    facing_diode: true,
                 },
                 block: None,
                 state,
@@ -104,7 +108,11 @@ impl<W: World> Pass<W> for AnalogRepeaters {
                 annotations: Annotations::default(),
             });
 
-            graph.add_edge(start_idx, new_comparator, CompileLink::default(0));
+            let link = CompileLink {
+                through_wire: true,
+                ..CompileLink::default(0)
+            };
+            graph.add_edge(start_idx, new_comparator, link);
             graph.add_edge(new_comparator, end_idx, CompileLink::default(0));
         }
     }
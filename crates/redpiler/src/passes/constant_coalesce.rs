@@ -22,17 +22,15 @@ impl<W: World> Pass<W> for ConstantCoalesce {
             }
         }
 
+        // Collected up front, as the constants added below can take the slots of removed nodes
+        let constants: Vec<NodeIdx> = graph
+            .node_indices()
+            .filter(|&idx| graph[idx].ty == NodeType::Constant && graph[idx].is_removable())
+            .collect();
+
         let mut constant_nodes = FxHashMap::default();
-        for i in 0..graph.node_bound() {
-            let idx = NodeIdx::new(i);
-            if !graph.contains_node(idx) {
-                continue;
-            }
-            let node = &graph[idx];
-            if node.ty != NodeType::Constant || !node.is_removable() {
-                continue;
-            }
-            let ss = node.state.output_strength;
+        for idx in constants {
+            let ss = graph[idx].state.output_strength;
 
             let mut neighbors = graph.neighbors_directed(idx, Direction::Outgoing).detach();
             while let Some((edge, dest)) = neighbors.next(graph) {
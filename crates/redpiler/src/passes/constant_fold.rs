@@ -44,20 +44,12 @@ fn fold(graph: &mut CompileGraph) -> usize {
 
             match edge.weight().ty {
                 LinkType::Default => {
-                    default_power = default_power.max(
-                        constant
-                            .state
-                            .output_strength
-                            .saturating_sub(edge.weight().ss),
-                    )
+                    default_power =
+                        default_power.max(edge.weight().received(constant.state.output_strength))
                 }
                 LinkType::Side => {
-                    side_power = side_power.max(
-                        constant
-                            .state
-                            .output_strength
-                            .saturating_sub(edge.weight().ss),
-                    )
+                    side_power =
+                        side_power.max(edge.weight().received(constant.state.output_strength))
                 }
             }
         }
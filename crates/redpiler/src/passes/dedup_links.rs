@@ -3,7 +3,8 @@
 //! This pass removes duplicate edges from the graph, or parallel edges that have higher weight.
 //!
 //! For example, if two nodes are connected with two links of weights 13 and 15, the link with
-//! weight 15 is removed.
+//! weight 15 is removed. A link through wire is only removed in favor of another link through
+//! wire, or a link that does not go through wire, since wire caps the signal at 15.
 
 use super::Pass;
 use crate::compile_graph::{CompileGraph, NodeIdx};
@@ -33,6 +34,7 @@ impl<W: World> Pass<W> for DedupLinks {
                         && other_edge.source() == source_idx
                         && other_edge.weight().ty == edge.ty
                         && other_edge.weight().ss <= edge.ss
+                        && (edge.through_wire || !other_edge.weight().through_wire)
                     {
                         should_remove = true;
                         break;
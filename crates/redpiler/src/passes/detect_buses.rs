@@ -0,0 +1,213 @@
+//! # [`DetectBuses`]
+//!
+//! This pass finds buses: lanes of repeaters or torches that run next to each other, where every
+//! component in a lane only powers the next one. Datapaths of computers are mostly made out of
+//! these. The nodes of a bus are annotated with their place in it, which lets the direct backend
+//! tick every lane of a stage at once.
+
+use super::Pass;
+use crate::compile_graph::{BusLane, CompileGraph, LinkType, NodeIdx, NodeType};
+use crate::{CompilerInput, CompilerOptions};
+use itertools::Itertools;
+use mchprs_blocks::BlockPos;
+use mchprs_world::World;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::BTreeMap;
+use tracing::trace;
+
+/// Fewer lanes than this are not worth treating as a bus
+const MIN_WIDTH: usize = 4;
+/// The backend keeps the lanes of a stage in a `u64`, wider buses are split up
+const MAX_WIDTH: usize = 64;
+
+pub struct DetectBuses;
+
+impl<W: World> Pass<W> for DetectBuses {
+    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
+        for node in graph.node_weights_mut() {
+            node.annotations.bus = None;
+        }
+
+        // Lanes can only form a bus with lanes of the same shape
+        let mut shapes: FxHashMap<_, Vec<Vec<NodeIdx>>> = FxHashMap::default();
+        for lane in find_lanes(graph) {
+            let types = lane.iter().map(|&idx| graph[idx].ty.clone()).collect_vec();
+            let steps = lane
+                .iter()
+                .tuple_windows()
+                .map(|(&a, &b)| offset(pos(graph, a), pos(graph, b)))
+                .collect_vec();
+            shapes.entry((types, steps)).or_default().push(lane);
+        }
+        // Sorted so that buses are numbered the same way every time
+        let mut shapes = shapes.into_values().collect_vec();
+        shapes.sort_by_key(|lanes| lanes[0][0]);
+
+        let mut num_buses = 0;
+        for mut lanes in shapes {
+            lanes.sort_by_key(|lane| {
+                let pos = pos(graph, lane[0]);
+                (pos.x, pos.y, pos.z)
+            });
+            for bus in split_evenly_spaced(graph, lanes) {
+                for chunk in bus.chunks(MAX_WIDTH) {
+                    if chunk.len() < MIN_WIDTH {
+                        continue;
+                    }
+                    for (lane, nodes) in chunk.iter().enumerate() {
+                        for (stage, &idx) in nodes.iter().enumerate() {
+                            graph[idx].annotations.bus = Some(BusLane {
+                                bus: num_buses,
+                                stage: stage as u32,
+                                lane: lane as u32,
+                            });
+                        }
+                    }
+                    num_buses += 1;
+                }
+            }
+        }
+        trace!("Found {} buses", num_buses);
+    }
+
+    fn status_message(&self) -> &'static str {
+        "Detecting buses"
+    }
+}
+
+fn pos(graph: &CompileGraph, idx: NodeIdx) -> BlockPos {
+    graph[idx].block.unwrap().0
+}
+
+fn offset(from: BlockPos, to: BlockPos) -> (i32, i32, i32) {
+    (to.x - from.x, to.y - from.y, to.z - from.z)
+}
+
+/// Returns true if the node can be part of a lane.
+fn is_lane_node(graph: &CompileGraph, idx: NodeIdx) -> bool {
+    let node = &graph[idx];
+    matches!(node.ty, NodeType::Repeater { .. } | NodeType::Torch)
+        && node.is_removable()
+        && node.block.is_some()
+        && !node.state.repeater_locked
+        && graph
+            .edges_directed(idx, Direction::Incoming)
+            .all(|edge| edge.weight().ty == LinkType::Default)
+        && graph
+            .neighbors_directed(idx, Direction::Outgoing)
+            .all(|target| graph[target].ty != NodeType::Observer)
+}
+
+/// Returns the node after `idx` in its lane, if `idx` only powers a single node that nothing
+/// else powers.
+fn next_in_lane(graph: &CompileGraph, idx: NodeIdx) -> Option<NodeIdx> {
+    let edge = graph
+        .edges_directed(idx, Direction::Outgoing)
+        .exactly_one()
+        .ok()?;
+    let next = edge.target();
+    let only_input = graph.edges_directed(next, Direction::Incoming).count() == 1;
+    (next != idx && only_input && is_lane_node(graph, next)).then_some(next)
+}
+
+/// Finds every lane, from the node that starts it to the node that ends it.
+fn find_lanes(graph: &CompileGraph) -> Vec<Vec<NodeIdx>> {
+    let lane_nodes = graph
+        .node_indices()
+        .filter(|&idx| is_lane_node(graph, idx))
+        .collect_vec();
+    let continued: FxHashSet<_> = lane_nodes
+        .iter()
+        .filter_map(|&idx| next_in_lane(graph, idx))
+        .collect();
+
+    // Rings of nodes have no start, so they are left out
+    let mut lanes = Vec::new();
+    for &start in lane_nodes.iter().filter(|idx| !continued.contains(idx)) {
+        let mut lane = vec![start];
+        while let Some(next) = next_in_lane(graph, *lane.last().unwrap()) {
+            lane.push(next);
+        }
+        lanes.push(lane);
+    }
+    lanes
+}
+
+/// Splits lanes of the same shape into runs in which each lane is the same distance away from
+/// the one before it, like the bits of a bus are.
+fn split_evenly_spaced(graph: &CompileGraph, lanes: Vec<Vec<NodeIdx>>) -> Vec<Vec<Vec<NodeIdx>>> {
+    let mut buses: Vec<Vec<Vec<NodeIdx>>> = Vec::new();
+    let mut step = None;
+    for lane in lanes {
+        let Some(bus) = buses.last_mut() else {
+            buses.push(vec![lane]);
+            continue;
+        };
+        let last = pos(graph, bus.last().unwrap()[0]);
+        let lane_step = offset(last, pos(graph, lane[0]));
+        if bus.len() == 1 || step == Some(lane_step) {
+            step = Some(lane_step);
+            bus.push(lane);
+        } else {
+            step = None;
+            buses.push(vec![lane]);
+        }
+    }
+    buses
+}
+
+/// Returns the stages of every bus that is still intact, each stage with one node per lane.
+/// Passes that run after this one may have broken buses up, and imported graphs cannot be trusted.
+pub(crate) fn bus_stages(graph: &CompileGraph) -> Vec<Vec<Vec<NodeIdx>>> {
+    let mut buses: BTreeMap<u32, BTreeMap<u32, BTreeMap<u32, NodeIdx>>> = BTreeMap::new();
+    for idx in graph.node_indices() {
+        if let Some(lane) = graph[idx].annotations.bus {
+            buses
+                .entry(lane.bus)
+                .or_default()
+                .entry(lane.stage)
+                .or_default()
+                .insert(lane.lane, idx);
+        }
+    }
+
+    buses
+        .into_values()
+        .filter_map(|stages| {
+            let stages = stages
+                .into_iter()
+                .enumerate()
+                .map(|(stage, (number, lanes))| {
+                    let lanes_in_order =
+                        lanes.keys().enumerate().all(|(i, &lane)| i as u32 == lane);
+                    (stage as u32 == number && lanes_in_order)
+                        .then(|| lanes.into_values().collect_vec())
+                })
+                .collect::<Option<Vec<_>>>()?;
+            is_intact(graph, &stages).then_some(stages)
+        })
+        .collect()
+}
+
+fn is_intact(graph: &CompileGraph, stages: &[Vec<NodeIdx>]) -> bool {
+    let width = stages[0].len();
+    if width > MAX_WIDTH || stages.iter().any(|lanes| lanes.len() != width) {
+        return false;
+    }
+    for (stage, lanes) in stages.iter().enumerate() {
+        let ty = &graph[lanes[0]].ty;
+        for (lane, &idx) in lanes.iter().enumerate() {
+            if !is_lane_node(graph, idx) || graph[idx].ty != *ty {
+                return false;
+            }
+            if let Some(next_stage) = stages.get(stage + 1) {
+                if next_in_lane(graph, idx) != Some(next_stage[lane]) {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
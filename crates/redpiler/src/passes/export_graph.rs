@@ -7,7 +7,7 @@ use mchprs_world::World;
 use petgraph::visit::EdgeRef;
 use petgraph::Direction;
 use redpiler_graph::{
-    serialize, BlockPos, ComparatorMode, Link, LinkType, Node, NodeState, NodeType,
+    serialize, BlockPos, BusLane, ComparatorMode, Link, LinkType, Node, NodeState, NodeType,
 };
 use rustc_hash::FxHashMap;
 use std::fs;
@@ -29,6 +29,7 @@ fn convert_node(
                 CLinkType::Side => LinkType::Side,
             },
             weight: weight.ss,
+            through_wire: weight.through_wire,
             to: idx,
         });
     }
@@ -65,7 +66,12 @@ fn convert_node(
             CNodeType::Trapdoor => NodeType::Trapdoor,
             CNodeType::Wire => NodeType::Wire,
             CNodeType::Constant => NodeType::Constant,
-            CNodeType::NoteBlock { .. } => NodeType::NoteBlock,
+            CNodeType::NoteBlock { instrument, note } => NodeType::NoteBlock {
+                instrument: instrument.get_id(),
+                note,
+            },
+            CNodeType::Observer => NodeType::Observer,
+            CNodeType::Interface => NodeType::Interface,
         },
         block: node.block.map(|(pos, id)| {
             (
@@ -84,6 +90,13 @@ fn convert_node(
         },
         comparator_far_input,
         facing_diode,
+        is_input: node.is_input,
+        is_output: node.is_output,
+        bus: node.annotations.bus.map(|lane| BusLane {
+            bus: lane.bus,
+            stage: lane.stage,
+            lane: lane.lane,
+        }),
         inputs,
         updates,
     }
@@ -91,19 +104,23 @@ fn convert_node(
 
 pub struct ExportGraph;
 
-impl<W: World> Pass<W> for ExportGraph {
-    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
-        let mut nodes_map =
-            FxHashMap::with_capacity_and_hasher(graph.node_count(), Default::default());
-        for node in graph.node_indices() {
-            nodes_map.insert(node, nodes_map.len());
-        }
+/// Converts the graph into the nodes of the `redpiler_graph` format, numbered in the order of
+/// their node indices.
+pub(crate) fn export_nodes(graph: &CompileGraph) -> Vec<Node> {
+    let mut nodes_map = FxHashMap::with_capacity_and_hasher(graph.node_count(), Default::default());
+    for node in graph.node_indices() {
+        nodes_map.insert(node, nodes_map.len());
+    }
 
-        let nodes = graph
-            .node_indices()
-            .map(|idx| convert_node(graph, idx, &nodes_map))
-            .collect_vec();
+    graph
+        .node_indices()
+        .map(|idx| convert_node(graph, idx, &nodes_map))
+        .collect_vec()
+}
 
+impl<W: World> Pass<W> for ExportGraph {
+    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
+        let nodes = export_nodes(graph);
         fs::write("redpiler_graph.bc", serialize(nodes.as_slice()).unwrap()).unwrap();
     }
 
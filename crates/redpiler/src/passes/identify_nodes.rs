@@ -5,10 +5,13 @@
 //!
 //! If `optimize` is set in [`CompilerOptions`], redstone wires will not be added to the graph.
 //!
+//! Blocks in the fallback region are not identified. Each of them gets an interface node instead.
+//!
 //! There are no requirements for this pass.
 
 use super::Pass;
 use crate::compile_graph::{Annotations, CompileGraph, CompileNode, NodeIdx, NodeState, NodeType};
+use crate::fallback::FallbackRegion;
 use crate::{CompilerInput, CompilerOptions};
 use itertools::Itertools;
 use mchprs_blocks::block_entities::BlockEntity;
@@ -29,7 +32,6 @@ impl<W: World> Pass<W> for IdentifyNodes {
         options: &CompilerOptions,
         input: &CompilerInput<'_, W>,
     ) {
-        let ignore_wires = options.optimize;
         let plot = input.world;
 
         let mut first_pass = FxHashMap::default();
@@ -42,13 +44,29 @@ impl<W: World> Pass<W> for IdentifyNodes {
                 graph,
                 &mut first_pass,
                 &mut second_pass,
-                ignore_wires,
-                options.wire_dot_out,
+                options,
+                input.fallback,
                 plot,
                 pos,
             );
         });
 
+        for pos in input.fallback.iter() {
+            let power = input
+                .fallback
+                .interface_power(plot, pos)
+                .min(options.max_signal_strength());
+            graph.add_node(CompileNode {
+                ty: NodeType::Interface,
+                block: Some((pos, plot.get_block_raw(pos))),
+                state: NodeState::ss(power),
+
+                is_input: true,
+                is_output: false,
+                annotations: Annotations::default(),
+            });
+        }
+
         for pos in second_pass {
             apply_annotations(graph, options, &first_pass, plot, pos);
         }
@@ -68,22 +86,39 @@ fn for_pos<W: World>(
     graph: &mut CompileGraph,
     first_pass: &mut FxHashMap<BlockPos, NodeIdx>,
     second_pass: &mut FxHashSet<BlockPos>,
-    ignore_wires: bool,
-    wire_dot_out: bool,
+    options: &CompilerOptions,
+    fallback: &FallbackRegion,
     world: &W,
     pos: BlockPos,
 ) {
-    let id = world.get_block_raw(pos);
-    let block = Block::from_id(id);
+    if fallback.contains(pos) {
+        return;
+    }
 
-    if matches!(block, Block::Sign { .. } | Block::WallSign { .. }) {
+    if matches!(
+        world.get_block(pos),
+        Block::Sign { .. } | Block::WallSign { .. }
+    ) {
         second_pass.insert(pos);
         return;
     }
 
-    let Some((ty, state)) = identify_block(block, pos, world) else {
-        return;
-    };
+    if let Some(node) = identify_node(world, pos, options, fallback) {
+        let node_idx = graph.add_node(node);
+        first_pass.insert(pos, node_idx);
+    }
+}
+
+/// Returns the node for the block at `pos`, or `None` if the block is not part of the graph.
+pub(crate) fn identify_node<W: World>(
+    world: &W,
+    pos: BlockPos,
+    options: &CompilerOptions,
+    fallback: &FallbackRegion,
+) -> Option<CompileNode> {
+    let id = world.get_block_raw(pos);
+    let block = Block::from_id(id);
+    let (ty, state) = identify_block(block, pos, world)?;
 
     let is_input = matches!(
         ty,
@@ -92,13 +127,15 @@ fn for_pos<W: World>(
     let is_output = matches!(
         ty,
         NodeType::Trapdoor | NodeType::Lamp | NodeType::NoteBlock { .. }
-    ) || matches!(block, Block::RedstoneWire { wire } if wire_dot_out && wire::is_dot(wire));
+    ) || matches!(block, Block::RedstoneWire { wire } if options.wire_dot_out && wire::is_dot(wire))
+        || fallback.is_near(pos);
 
-    if ignore_wires && ty == NodeType::Wire && !(is_input | is_output) {
-        return;
+    let ignore_wires = options.optimize;
+    if ignore_wires && ty == NodeType::Wire && !(is_input | is_output) && !is_observed(world, pos) {
+        return None;
     }
 
-    let node_idx = graph.add_node(CompileNode {
+    let mut node = CompileNode {
         ty,
         block: Some((pos, id)),
         state,
@@ -106,8 +143,10 @@ fn for_pos<W: World>(
         is_input,
         is_output,
         annotations: Annotations::default(),
-    });
-    first_pass.insert(pos, node_idx);
+    };
+    // Containers and the comparators reading them can go above 15
+    node.clamp_signal_strength(options.max_signal_strength());
+    Some(node)
 }
 
 fn identify_block<W: World>(
@@ -156,6 +195,9 @@ fn identify_block<W: World>(
         }
         Block::IronTrapdoor { powered, .. } => (NodeType::Trapdoor, NodeState::simple(powered)),
         Block::RedstoneBlock {} => (NodeType::Constant, NodeState::ss(15)),
+        // Without tripwire, nothing can change the state of a hook while redpiler is running
+        Block::TripwireHook { powered, .. } => (NodeType::Constant, NodeState::simple(powered)),
+        Block::Observer { powered, .. } => (NodeType::Observer, NodeState::simple(powered)),
         Block::NoteBlock {
             instrument: _,
             note,
@@ -176,6 +218,14 @@ fn identify_block<W: World>(
     Some((ty, state))
 }
 
+/// Returns true if there is an observer looking at the block at `pos`.
+fn is_observed<W: World>(world: &W, pos: BlockPos) -> bool {
+    BlockFace::values().into_iter().any(|face| {
+        let observer_pos = pos.offset(face);
+        matches!(world.get_block(observer_pos), Block::Observer { facing, .. } if observer_pos.offset(facing.block_face()) == pos)
+    })
+}
+
 fn apply_annotations<W: World>(
     graph: &mut CompileGraph,
     options: &CompilerOptions,
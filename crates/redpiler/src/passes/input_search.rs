@@ -2,9 +2,13 @@
 //!
 //! This pass populates the graph with edges.
 //! This pass is *mandatory*. Without it, there would be no links between nodes.
+//!
+//! Any component next to a block in the fallback region is linked to the interface node of that
+//! block, no matter what the block currently is, since it may be replaced while redpiler is running.
 
 use super::Pass;
-use crate::compile_graph::{CompileGraph, CompileLink, LinkType, NodeIdx};
+use crate::compile_graph::{CompileGraph, CompileLink, LinkType, NodeIdx, NodeType};
+use crate::fallback::FallbackRegion;
 use crate::{CompilerInput, CompilerOptions};
 use mchprs_blocks::blocks::{Block, ButtonFace, LeverFace};
 use mchprs_blocks::{BlockDirection, BlockFace, BlockPos};
@@ -23,7 +27,8 @@ impl<W: World> Pass<W> for InputSearch {
         _: &CompilerOptions,
         input: &CompilerInput<'_, W>,
     ) {
-        let mut state = InputSearchState::new(input.world, graph);
+        let pos_map = pos_map(graph);
+        let mut state = InputSearchState::new(input.world, input.fallback, graph, &pos_map);
         state.search();
     }
 
@@ -37,22 +42,50 @@ impl<W: World> Pass<W> for InputSearch {
     }
 }
 
+/// Maps the position of every node in the graph to its index.
+pub(crate) fn pos_map(graph: &CompileGraph) -> FxHashMap<BlockPos, NodeIdx> {
+    let mut pos_map = FxHashMap::default();
+    for id in graph.node_indices() {
+        let (pos, _) = graph[id].block.unwrap();
+        pos_map.insert(pos, id);
+    }
+    pos_map
+}
+
+/// Adds the links into `nodes`, which must not have any incoming links yet.
+pub(crate) fn search_inputs<W: World>(
+    world: &W,
+    fallback: &FallbackRegion,
+    graph: &mut CompileGraph,
+    pos_map: &FxHashMap<BlockPos, NodeIdx>,
+    nodes: &[NodeIdx],
+) {
+    let mut state = InputSearchState::new(world, fallback, graph, pos_map);
+    for &idx in nodes {
+        let node = &state.graph[idx];
+        if node.ty != NodeType::Interface {
+            state.search_node(idx, node.block.unwrap());
+        }
+    }
+}
+
 struct InputSearchState<'a, W: World> {
     world: &'a W,
+    fallback: &'a FallbackRegion,
     graph: &'a mut CompileGraph,
-    pos_map: FxHashMap<BlockPos, NodeIdx>,
+    pos_map: &'a FxHashMap<BlockPos, NodeIdx>,
 }
 
 impl<'a, W: World> InputSearchState<'a, W> {
-    fn new(world: &'a W, graph: &'a mut CompileGraph) -> InputSearchState<'a, W> {
-        let mut pos_map = FxHashMap::default();
-        for id in graph.node_indices() {
-            let (pos, _) = graph[id].block.unwrap();
-            pos_map.insert(pos, id);
-        }
-
+    fn new(
+        world: &'a W,
+        fallback: &'a FallbackRegion,
+        graph: &'a mut CompileGraph,
+        pos_map: &'a FxHashMap<BlockPos, NodeIdx>,
+    ) -> InputSearchState<'a, W> {
         InputSearchState {
             world,
+            fallback,
             graph,
             pos_map,
         }
@@ -66,10 +99,12 @@ impl<'a, W: World> InputSearchState<'a, W> {
             Block::Lever { .. } => true,
             Block::StoneButton { .. } => true,
             Block::StonePressurePlate { .. } => true,
+            Block::TripwireHook { .. } => true,
             Block::RedstoneRepeater { repeater } if repeater.facing.block_face() == side => true,
             Block::RedstoneComparator { comparator } if comparator.facing.block_face() == side => {
                 true
             }
+            Block::Observer { facing, .. } if facing.block_face() == side => true,
             _ => false,
         }
     }
@@ -79,6 +114,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
             Block::RedstoneTorch { .. } if side == BlockFace::Bottom => true,
             Block::RedstoneWallTorch { .. } if side == BlockFace::Bottom => true,
             Block::StonePressurePlate { .. } if side == BlockFace::Top => true,
+            Block::TripwireHook { direction, .. } => direction.block_face() == side,
             Block::Lever { lever } => match side {
                 BlockFace::Top => lever.face == LeverFace::Floor,
                 BlockFace::Bottom => lever.face == LeverFace::Ceiling,
@@ -91,6 +127,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
             },
             Block::RedstoneRepeater { .. } => self.provides_weak_power(block, side),
             Block::RedstoneComparator { .. } => self.provides_weak_power(block, side),
+            Block::Observer { .. } => self.provides_weak_power(block, side),
             _ => false,
         }
     }
@@ -107,16 +144,23 @@ impl<'a, W: World> InputSearchState<'a, W> {
         start_node: NodeIdx,
         search_wire: bool,
     ) {
-        if block.is_solid() {
+        // Links found while searching a wire carry the power of that wire
+        let link = CompileLink {
+            through_wire: !search_wire,
+            ..CompileLink::new(link_ty, distance)
+        };
+        if self.fallback.contains(pos) {
+            // Blocks outside of a compiled selection only have interface nodes if they are not air
+            if let Some(&node) = self.pos_map.get(&pos) {
+                self.graph.add_edge(node, start_node, link);
+            }
+        } else if block.is_solid() {
             for side in &BlockFace::values() {
                 let pos = pos.offset(*side);
                 let block = self.world.get_block(pos);
                 if self.provides_strong_power(block, *side) {
-                    self.graph.add_edge(
-                        self.pos_map[&pos],
-                        start_node,
-                        CompileLink::new(link_ty, distance),
-                    );
+                    self.graph
+                        .add_edge(self.pos_map[&pos], start_node, link.clone());
                 }
 
                 if let Block::RedstoneWire { wire } = block {
@@ -144,11 +188,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
                 }
             }
         } else if self.provides_weak_power(block, side) {
-            self.graph.add_edge(
-                self.pos_map[&pos],
-                start_node,
-                CompileLink::new(link_ty, distance),
-            );
+            self.graph.add_edge(self.pos_map[&pos], start_node, link);
         } else if let Block::RedstoneWire { wire } = block {
             match side {
                 BlockFace::Top => self.search_wire(start_node, pos, link_ty, distance),
@@ -169,6 +209,12 @@ impl<'a, W: World> InputSearchState<'a, W> {
         }
     }
 
+    /// Wires in the fallback region are not part of the graph, so a wire search ends there.
+    fn is_wire(&self, pos: BlockPos) -> bool {
+        matches!(self.world.get_block(pos), Block::RedstoneWire { .. })
+            && !self.fallback.contains(pos)
+    }
+
     fn search_wire(
         &mut self,
         start_node: NodeIdx,
@@ -203,7 +249,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
                     false,
                 );
 
-                if is_wire(self.world, neighbor_pos) && !discovered.contains_key(&neighbor_pos) {
+                if self.is_wire(neighbor_pos) && !discovered.contains_key(&neighbor_pos) {
                     queue.push_back(neighbor_pos);
                     discovered.insert(neighbor_pos, discovered[&pos] + 1);
                 }
@@ -211,7 +257,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
                 if side.is_horizontal() {
                     if !up_block.is_solid() && !neighbor.is_transparent() {
                         let neighbor_up_pos = neighbor_pos.offset(BlockFace::Top);
-                        if is_wire(self.world, neighbor_up_pos)
+                        if self.is_wire(neighbor_up_pos)
                             && !discovered.contains_key(&neighbor_up_pos)
                         {
                             queue.push_back(neighbor_up_pos);
@@ -221,7 +267,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
 
                     if !neighbor.is_solid() {
                         let neighbor_down_pos = neighbor_pos.offset(BlockFace::Bottom);
-                        if is_wire(self.world, neighbor_down_pos)
+                        if self.is_wire(neighbor_down_pos)
                             && !discovered.contains_key(&neighbor_down_pos)
                         {
                             queue.push_back(neighbor_down_pos);
@@ -325,6 +371,14 @@ impl<'a, W: World> InputSearchState<'a, W> {
             Block::RedstoneWire { .. } => {
                 self.search_wire(id, pos, LinkType::Default, 0);
             }
+            Block::Observer { facing, .. } => {
+                // Observers are not powered by the block they are looking at, so this link only
+                // tells the backend which node's state changes to watch for.
+                let observed_pos = pos.offset(facing.block_face());
+                if let Some(&observed) = self.pos_map.get(&observed_pos) {
+                    self.graph.add_edge(observed, id, CompileLink::default(0));
+                }
+            }
             Block::RedstoneLamp { .. } | Block::IronTrapdoor { .. } | Block::NoteBlock { .. } => {
                 for face in &BlockFace::values() {
                     let neighbor_pos = pos.offset(*face);
@@ -351,11 +405,10 @@ impl<'a, W: World> InputSearchState<'a, W> {
                 continue;
             }
             let node = &self.graph[idx];
+            if node.ty == NodeType::Interface {
+                continue;
+            }
             self.search_node(idx, node.block.unwrap());
         }
     }
 }
-
-fn is_wire(world: &impl World, pos: BlockPos) -> bool {
-    matches!(world.get_block(pos), Block::RedstoneWire { .. })
-}
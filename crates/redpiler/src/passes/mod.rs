@@ -4,6 +4,7 @@ mod coalesce;
 mod constant_coalesce;
 mod constant_fold;
 mod dedup_links;
+mod detect_buses;
 mod export_graph;
 mod identify_nodes;
 mod input_search;
@@ -12,18 +13,32 @@ mod unreachable_output;
 
 use mchprs_world::World;
 
+pub(crate) use detect_buses::bus_stages;
+pub(crate) use export_graph::export_nodes;
+pub(crate) use identify_nodes::identify_node;
+pub(crate) use input_search::{pos_map, search_inputs};
+
 use super::compile_graph::CompileGraph;
+use super::report::{CompileReport, GraphStats, PassReport};
 use super::task_monitor::TaskMonitor;
 use super::{CompilerInput, CompilerOptions};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tracing::trace;
 
-pub const fn make_default_pass_manager<'w, W: World>() -> PassManager<'w, W> {
+/// The passes that build the graph from the world. These run for every build.
+pub const fn make_build_pass_manager<'w, W: World>() -> PassManager<'w, W> {
     PassManager::new(&[
         &identify_nodes::IdentifyNodes,
         &input_search::InputSearch,
         &clamp_weights::ClampWeights,
+    ])
+}
+
+/// The passes that run on a graph built by [`make_build_pass_manager`], without looking at the
+/// world again.
+pub const fn make_optimize_pass_manager<'w, W: World>() -> PassManager<'w, W> {
+    PassManager::new(&[
         &dedup_links::DedupLinks,
         &analog_repeaters::AnalogRepeaters,
         &constant_fold::ConstantFold,
@@ -31,6 +46,7 @@ pub const fn make_default_pass_manager<'w, W: World>() -> PassManager<'w, W> {
         &constant_coalesce::ConstantCoalesce,
         &coalesce::Coalesce,
         &prune_orphans::PruneOrphans,
+        &detect_buses::DetectBuses,
         &export_graph::ExportGraph,
     ])
 }
@@ -44,21 +60,32 @@ impl<'p, W: World> PassManager<'p, W> {
         Self { passes }
     }
 
+    pub fn pass_count(&self) -> usize {
+        self.passes.len()
+    }
+
+    /// Runs the passes on `graph`. The progress of `monitor` goes up by one for every pass.
     pub fn run_passes(
         &self,
+        mut graph: CompileGraph,
         options: &CompilerOptions,
         input: &CompilerInput<'_, W>,
         monitor: Arc<TaskMonitor>,
+        report: &mut CompileReport,
     ) -> CompileGraph {
-        let mut graph = CompileGraph::new();
-
-        // Add one for the backend compile step
-        monitor.set_max_progress(self.passes.len() + 1);
-
+        let mut stats = GraphStats::of(&graph);
         for &pass in self.passes {
+            let name = pass.name().rsplit("::").next().unwrap();
             if !pass.should_run(options) {
                 trace!("Skipping pass: {}", pass.name());
                 monitor.inc_progress();
+                report.passes.push(PassReport {
+                    name,
+                    ran: false,
+                    duration: Duration::ZERO,
+                    before: stats.clone(),
+                    after: stats.clone(),
+                });
                 continue;
             }
 
@@ -72,9 +99,18 @@ impl<'p, W: World> PassManager<'p, W> {
 
             pass.run_pass(&mut graph, options, input);
 
-            trace!("Completed pass in {:?}", start.elapsed());
+            let duration = start.elapsed();
+            trace!("Completed pass in {:?}", duration);
             trace!("node_count: {}", graph.node_count());
             trace!("edge_count: {}", graph.edge_count());
+            let after = GraphStats::of(&graph);
+            report.passes.push(PassReport {
+                name,
+                ran: true,
+                duration,
+                before: std::mem::replace(&mut stats, after.clone()),
+                after,
+            });
             monitor.inc_progress();
         }
 
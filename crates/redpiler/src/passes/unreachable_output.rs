@@ -19,7 +19,12 @@ use petgraph::Direction;
 pub struct UnreachableOutput;
 
 impl<W: World> Pass<W> for UnreachableOutput {
-    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
+    fn run_pass(
+        &self,
+        graph: &mut CompileGraph,
+        options: &CompilerOptions,
+        _: &CompilerInput<'_, W>,
+    ) {
         for i in 0..graph.node_bound() {
             let idx = NodeIdx::new(i);
             if !graph.contains_node(idx) {
@@ -36,9 +41,9 @@ impl<W: World> Pass<W> for UnreachableOutput {
                 continue;
             }
 
-            // For simiplicity, we always use 15 here. A more complex implementation in the future
-            // might want to properly calculate this.
-            let max_input: u8 = 15;
+            // For simiplicity, we always use the highest signal strength here. A more complex
+            // implementation in the future might want to properly calculate this.
+            let max_input = options.max_signal_strength();
 
             let mut side_inputs = graph
                 .edges_directed(idx, Direction::Incoming)
@@ -58,7 +63,10 @@ impl<W: World> Pass<W> for UnreachableOutput {
                 continue;
             }
 
-            let constant = graph[constant_idx].state.output_strength;
+            // The side sees the constant weakened by the distance it travels
+            let constant = constant_edge
+                .weight()
+                .received(graph[constant_idx].state.output_strength);
             let max_output = max_input.saturating_sub(constant);
 
             // Now we can go through all the outgoing nodes and remove the ones with a weight that
@@ -0,0 +1,165 @@
+//! Statistics collected while compiling, to find out why a build compiles slowly or why it was
+//! not optimized as well as expected.
+
+use crate::compile_graph::{CompileGraph, NodeType};
+use itertools::Itertools;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+fn as_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+fn type_name(ty: &NodeType) -> &'static str {
+    match ty {
+        NodeType::Repeater { .. } => "repeater",
+        NodeType::Torch => "torch",
+        NodeType::Comparator { .. } => "comparator",
+        NodeType::Lamp => "lamp",
+        NodeType::Button => "button",
+        NodeType::Lever => "lever",
+        NodeType::PressurePlate => "pressure_plate",
+        NodeType::Trapdoor => "trapdoor",
+        NodeType::Wire => "wire",
+        NodeType::Constant => "constant",
+        NodeType::NoteBlock { .. } => "note_block",
+        NodeType::Observer => "observer",
+        NodeType::Interface => "interface",
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GraphStats {
+    pub nodes: usize,
+    pub links: usize,
+    /// Amount of nodes of every type that is in the graph
+    pub node_types: BTreeMap<&'static str, usize>,
+    /// Amount of nodes that are part of a bus
+    pub bus_lanes: usize,
+}
+
+impl GraphStats {
+    pub(crate) fn of(graph: &CompileGraph) -> GraphStats {
+        let mut node_types = BTreeMap::new();
+        for node in graph.node_weights() {
+            *node_types.entry(type_name(&node.ty)).or_default() += 1;
+        }
+        GraphStats {
+            nodes: graph.node_count(),
+            links: graph.edge_count(),
+            node_types,
+            bus_lanes: graph
+                .node_weights()
+                .filter(|node| node.annotations.bus.is_some())
+                .count(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PassReport {
+    pub name: &'static str,
+    /// Passes that are not needed for the options that were used are skipped
+    pub ran: bool,
+    #[serde(rename = "duration_ms", serialize_with = "as_millis")]
+    pub duration: Duration,
+    pub before: GraphStats,
+    pub after: GraphStats,
+}
+
+impl PassReport {
+    /// Returns true if the pass made any change to the amount of nodes or links.
+    pub fn changed_graph(&self) -> bool {
+        self.before != self.after
+    }
+
+    /// Describes how the amount of every type of node changed, such as "-12 wire, +3 constant".
+    fn describe_changes(&self) -> String {
+        let mut changes = Vec::new();
+        let mut push = |name: &str, before: usize, after: usize| {
+            if before != after {
+                changes.push(format!("{:+} {}", after as i64 - before as i64, name));
+            }
+        };
+        push("nodes", self.before.nodes, self.after.nodes);
+        push("links", self.before.links, self.after.links);
+        push("bus lanes", self.before.bus_lanes, self.after.bus_lanes);
+        let types: BTreeSet<_> = self
+            .before
+            .node_types
+            .keys()
+            .chain(self.after.node_types.keys())
+            .collect();
+        for &ty in types {
+            let count = |stats: &GraphStats| stats.node_types.get(ty).copied().unwrap_or(0);
+            push(ty, count(&self.before), count(&self.after));
+        }
+        changes.join(", ")
+    }
+}
+
+/// Everything that happened during the last compile.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompileReport {
+    /// The graph was read from the compile cache, so no passes were run
+    pub cached: bool,
+    pub passes: Vec<PassReport>,
+    /// The graph the backend was compiled from
+    pub graph: GraphStats,
+    #[serde(rename = "backend_duration_ms", serialize_with = "as_millis")]
+    pub backend_duration: Duration,
+    #[serde(rename = "total_duration_ms", serialize_with = "as_millis")]
+    pub total_duration: Duration,
+}
+
+impl CompileReport {
+    /// Returns the passes that ran and changed the graph.
+    pub fn fired_passes(&self) -> impl Iterator<Item = &PassReport> {
+        self.passes
+            .iter()
+            .filter(|pass| pass.ran && pass.changed_graph())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// A short summary of the report, one line per entry.
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.push(format!(
+            "Compiled {} nodes and {} links in {:.1?} ({:.1?} in the backend)",
+            self.graph.nodes, self.graph.links, self.total_duration, self.backend_duration
+        ));
+        if self.cached {
+            lines.push("The graph was read from the compile cache.".to_string());
+        }
+        for pass in self.passes.iter().filter(|pass| pass.ran) {
+            lines.push(format!(
+                "{}: {:.1?}, {} -> {} nodes, {} -> {} links",
+                pass.name,
+                pass.duration,
+                pass.before.nodes,
+                pass.after.nodes,
+                pass.before.links,
+                pass.after.links
+            ));
+        }
+        let mut fired = self.fired_passes().peekable();
+        if fired.peek().is_some() {
+            lines.push("Changes:".to_string());
+        }
+        for pass in fired {
+            lines.push(format!("  {}: {}", pass.name, pass.describe_changes()));
+        }
+        let types = self
+            .graph
+            .node_types
+            .iter()
+            .map(|(ty, count)| format!("{} {}", count, ty))
+            .join(", ");
+        lines.push(format!("Nodes: {}", types));
+        lines
+    }
+}
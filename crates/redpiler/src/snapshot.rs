@@ -0,0 +1,164 @@
+//! Snapshots of the runtime state of a compiled backend, so a circuit can be rewound to some
+//! earlier state without compiling it again.
+//!
+//! A snapshot only fits the graph it was taken of, which is checked using the position of every
+//! node and a hash of the node types and the links between them. Compiling the same blocks with
+//! the same options gives the same graph, so snapshots saved to a file can still be restored after
+//! the plot was unloaded.
+//!
+//! The file starts with the magic `RPSNAP\0\0` and a version byte, followed by the snapshot
+//! encoded with bincode.
+
+use crate::compile_graph::{CompileGraph, LinkType};
+use crate::BackendVariant;
+use itertools::Itertools;
+use mchprs_blocks::BlockPos;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use rustc_hash::{FxHashMap, FxHasher};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+
+const MAGIC: &[u8; 8] = b"RPSNAP\0\0";
+const VERSION: u8 = 2;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("redpiler is not running")]
+    NotActive,
+
+    #[error("the snapshot was taken of a different circuit or backend")]
+    Mismatch,
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("not a redpiler snapshot")]
+    InvalidMagic,
+
+    #[error("unsupported snapshot version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("snapshot deserialization error")]
+    Deserialize(#[from] bincode::Error),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub(crate) backend: BackendVariant,
+    /// One for every direct backend the parallel backend runs, otherwise just one
+    pub(crate) parts: Vec<BackendState>,
+}
+
+impl Snapshot {
+    pub fn load(path: impl AsRef<Path>) -> Result<Snapshot, SnapshotError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        let mut version = [0];
+        reader.read_exact(&mut version)?;
+        if version[0] != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version[0]));
+        }
+        Ok(bincode::deserialize_from(reader)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        bincode::serialize_into(&mut out, self)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// The state of a single backend, with nodes and ticks referred to by their index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BackendState {
+    /// The position of every node, which tells whether the snapshot fits the compiled graph
+    pub positions: Vec<Option<BlockPos>>,
+    /// See [`graph_hash`]
+    pub graph_hash: u64,
+    pub nodes: Vec<NodeSnapshot>,
+    pub ticks: TickSnapshot,
+    /// Only used by the direct backend
+    pub buses: Vec<BusSnapshot>,
+}
+
+impl BackendState {
+    /// Returns true if the snapshot was taken of nodes at these positions, in a graph with this
+    /// hash.
+    pub fn fits(
+        &self,
+        positions: impl ExactSizeIterator<Item = Option<BlockPos>>,
+        graph_hash: u64,
+    ) -> bool {
+        self.graph_hash == graph_hash
+            && self.nodes.len() == positions.len()
+            && positions.eq(self.positions.iter().copied())
+    }
+}
+
+/// Hashes the type of every node and the links into it. Blocks can be swapped for others at the
+/// same positions, like a repeater for a comparator, so the positions alone don't tell whether a
+/// snapshot fits.
+pub(crate) fn graph_hash(graph: &CompileGraph) -> u64 {
+    let ids: FxHashMap<_, _> = graph
+        .node_indices()
+        .enumerate()
+        .map(|(id, idx)| (idx, id))
+        .collect();
+    let mut hasher = FxHasher::default();
+    for idx in graph.node_indices() {
+        graph[idx].ty.hash(&mut hasher);
+        let links = graph
+            .edges_directed(idx, Direction::Incoming)
+            .map(|edge| {
+                let link = edge.weight();
+                (ids[&edge.source()], link.ty == LinkType::Side, link.ss)
+            })
+            .sorted_unstable();
+        for link in links {
+            link.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct NodeSnapshot {
+    pub powered: bool,
+    pub locked: bool,
+    pub output_power: u8,
+    pub pending_tick: bool,
+    /// Backends that keep track of inputs instead of reading the outputs linking into the node
+    /// store the number of inputs with every signal strength here
+    pub default_inputs: [u8; 16],
+    pub side_inputs: [u8; 16],
+    /// Inputs above 15, for high signal strength
+    pub high_inputs: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// The queues of the tick scheduler, by slot and then by priority.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct TickSnapshot {
+    pub pos: usize,
+    pub queues: Vec<Vec<Vec<u32>>>,
+    /// The queues of a tick stopped halfway, along with the priority that runs next
+    pub mid_tick: Option<(Vec<Vec<u32>>, usize)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BusSnapshot {
+    pub powered: u64,
+    pub pending: u64,
+    pub scheduled: Vec<[u64; 4]>,
+}
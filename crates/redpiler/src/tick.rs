@@ -0,0 +1,82 @@
+//! The game tick of a world that redpiler may be compiled for. Plots, the verifier and the tests all
+//! tick through here, so the default redstone implementation runs the same way everywhere.
+
+use crate::{Compiler, FallbackWorld};
+use mchprs_world::{TickEntry, World};
+
+/// A world that keeps its own scheduled ticks.
+pub trait PendingTicks: World {
+    fn pending_ticks(&mut self) -> &mut Vec<TickEntry>;
+}
+
+/// Runs a single game tick, on redpiler if it is active. If redpiler stopped halfway through the
+/// last tick, the rest of it is run instead. Returns false if the tick was stopped halfway again.
+///
+/// Changes made by redpiler are not flushed to the world.
+pub fn tick_world<W: PendingTicks>(world: &mut W, redpiler: &mut Compiler) -> bool {
+    if !redpiler.is_mid_tick() {
+        start_tick(world);
+    }
+    if redpiler.is_active() {
+        redpiler.tick();
+        if redpiler.is_mid_tick() {
+            return false;
+        }
+    }
+    finish_tick(world, redpiler);
+    true
+}
+
+/// Runs the ticks of the next tick priority on redpiler, or a whole game tick if it is not
+/// active. Returns true if that finished the tick.
+pub fn step_world_phase<W: PendingTicks>(world: &mut W, redpiler: &mut Compiler) -> bool {
+    if !redpiler.is_active() {
+        return tick_world(world, redpiler);
+    }
+    if !redpiler.is_mid_tick() {
+        start_tick(world);
+    }
+    if !redpiler.step_phase() {
+        return false;
+    }
+    finish_tick(world, redpiler);
+    true
+}
+
+fn start_tick<W: PendingTicks>(world: &mut W) {
+    // Ticks scheduled by the compiled blocks changing during the tick happen during this tick, so
+    // they must not count towards their delay yet
+    for pending in world.pending_ticks() {
+        pending.ticks_left = pending.ticks_left.saturating_sub(1);
+    }
+}
+
+/// Runs the part of a tick that comes after redpiler has ticked.
+fn finish_tick<W: PendingTicks>(world: &mut W, redpiler: &mut Compiler) {
+    if redpiler.is_active() {
+        if redpiler.fallback_region().is_none() {
+            return;
+        }
+        redpiler.flush_interface(world);
+    }
+
+    world
+        .pending_ticks()
+        .sort_by_key(|e| (e.ticks_left, e.tick_priority));
+    // Ticks scheduled with no delay while processing this tick still run at the end of it
+    while let Some(i) = world.pending_ticks().iter().position(|e| e.ticks_left == 0) {
+        let entry = world.pending_ticks().remove(i);
+        let block = world.get_block(entry.pos);
+        match redpiler.fallback_region() {
+            Some(region) => {
+                let mut world = FallbackWorld::new(world, region);
+                mchprs_redstone::tick(block, &mut world, entry.pos);
+            }
+            None => mchprs_redstone::tick(block, world, entry.pos),
+        }
+    }
+
+    if redpiler.is_active() {
+        redpiler.read_interface(world);
+    }
+}
@@ -0,0 +1,443 @@
+//! Recording every state change the direct backend makes, enabled with `--trace`.
+//!
+//! The trace starts with the initial state of every node, followed by one record per change. A
+//! record written at tick `n` describes a change made after `n` ticks have passed, so changes made
+//! by the `n`th tick and inputs done right after it share the same tick number.
+//!
+//! All numbers are little endian. The header is the magic `RPTRACE\0`, a version byte and the
+//! node count as a `u32`, followed by every node:
+//! - `u8` 1 if the node has a position, followed by its `i32` x, y and z
+//! - `u8` length of the block name, followed by the name
+//! - `u8` flags (1 = powered, 2 = locked) and `u8` output power
+//!
+//! The rest of the file is a list of records, each starting with a tag byte. A tag of 0 starts a
+//! new tick and is followed by the tick number as a `u64`. Any other tag is a change, with the
+//! cause in the lower 4 bits and the tick priority it happened in (plus one, 0 if none) in the
+//! upper 4 bits. It is followed by the `u32` node id, the flags, the output power and for changes
+//! caused by an update the `u32` id of the node that sent it.
+
+use mchprs_blocks::BlockPos;
+use mchprs_world::TickPriority;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+use tracing::error;
+
+/// Where the trace is written to, next to the exported graph
+pub(crate) const TRACE_PATH: &str = "redpiler_trace.bin";
+
+const MAGIC: &[u8; 8] = b"RPTRACE\0";
+const VERSION: u8 = 1;
+
+const TAG_TICK: u8 = 0;
+const CAUSE_TICK: u8 = 1;
+const CAUSE_INPUT: u8 = 2;
+const CAUSE_INTERFACE: u8 = 3;
+const CAUSE_UPDATE: u8 = 4;
+
+const FLAG_POWERED: u8 = 1;
+const FLAG_LOCKED: u8 = 2;
+
+#[derive(Error, Debug)]
+pub enum TraceError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("not a redpiler trace")]
+    InvalidMagic,
+
+    #[error("unsupported trace version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("invalid record tag {0}")]
+    InvalidTag(u8),
+}
+
+/// What made a node change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceCause {
+    /// The node ran a scheduled tick.
+    Tick,
+    /// A lever, button or pressure plate was used.
+    Input,
+    /// The fallback region changed the power of an interface node.
+    Interface,
+    /// The output of another node changed.
+    Update { from: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeState {
+    pub powered: bool,
+    pub locked: bool,
+    pub power: u8,
+}
+
+impl NodeState {
+    fn flags(self) -> u8 {
+        (self.powered as u8 * FLAG_POWERED) | (self.locked as u8 * FLAG_LOCKED)
+    }
+
+    fn from_flags(flags: u8, power: u8) -> NodeState {
+        NodeState {
+            powered: flags & FLAG_POWERED != 0,
+            locked: flags & FLAG_LOCKED != 0,
+            power,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceNode {
+    pub pos: Option<BlockPos>,
+    /// The name of the block the node was compiled from
+    pub block: String,
+    pub initial: NodeState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceChange {
+    pub tick: u64,
+    pub node: u32,
+    pub cause: TraceCause,
+    /// The priority of the ticks that were being run, if the change happened during a tick
+    pub priority: Option<TickPriority>,
+    pub state: NodeState,
+}
+
+/// A trace read back into memory.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub nodes: Vec<TraceNode>,
+    /// Every change in the order it happened
+    pub changes: Vec<TraceChange>,
+}
+
+fn priority_code(priority: Option<TickPriority>) -> u8 {
+    priority.map_or(0, |priority| priority as u8 + 1)
+}
+
+fn priority_from_code(code: u8) -> Option<Option<TickPriority>> {
+    Some(match code {
+        0 => None,
+        1 => Some(TickPriority::Highest),
+        2 => Some(TickPriority::Higher),
+        3 => Some(TickPriority::High),
+        4 => Some(TickPriority::Normal),
+        _ => return None,
+    })
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_i32(reader: &mut impl Read) -> io::Result<i32> {
+    Ok(read_u32(reader)? as i32)
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Reads the rest of the record starting with `tag`. Returns `None` for the start of a new tick.
+fn read_record(
+    reader: &mut impl Read,
+    tag: u8,
+    tick: &mut u64,
+) -> Result<Option<TraceChange>, TraceError> {
+    if tag == TAG_TICK {
+        *tick = read_u64(reader)?;
+        return Ok(None);
+    }
+    let priority = priority_from_code(tag >> 4).ok_or(TraceError::InvalidTag(tag))?;
+    let node = read_u32(reader)?;
+    let flags = read_u8(reader)?;
+    let power = read_u8(reader)?;
+    let cause = match tag & 0xF {
+        CAUSE_TICK => TraceCause::Tick,
+        CAUSE_INPUT => TraceCause::Input,
+        CAUSE_INTERFACE => TraceCause::Interface,
+        CAUSE_UPDATE => TraceCause::Update {
+            from: read_u32(reader)?,
+        },
+        _ => return Err(TraceError::InvalidTag(tag)),
+    };
+    Ok(Some(TraceChange {
+        tick: *tick,
+        node,
+        cause,
+        priority,
+        state: NodeState::from_flags(flags, power),
+    }))
+}
+
+impl Trace {
+    pub fn load(path: impl AsRef<Path>) -> Result<Trace, TraceError> {
+        Trace::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read(mut reader: impl Read) -> Result<Trace, TraceError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(TraceError::InvalidMagic);
+        }
+        let version = read_u8(&mut reader)?;
+        if version != VERSION {
+            return Err(TraceError::UnsupportedVersion(version));
+        }
+
+        let node_count = read_u32(&mut reader)?;
+        let mut nodes = Vec::with_capacity(node_count as usize);
+        for _ in 0..node_count {
+            let pos = match read_u8(&mut reader)? {
+                0 => None,
+                _ => Some(BlockPos::new(
+                    read_i32(&mut reader)?,
+                    read_i32(&mut reader)?,
+                    read_i32(&mut reader)?,
+                )),
+            };
+            let mut block = vec![0; read_u8(&mut reader)? as usize];
+            reader.read_exact(&mut block)?;
+            let flags = read_u8(&mut reader)?;
+            let power = read_u8(&mut reader)?;
+            nodes.push(TraceNode {
+                pos,
+                block: String::from_utf8_lossy(&block).into_owned(),
+                initial: NodeState::from_flags(flags, power),
+            });
+        }
+
+        let mut changes = Vec::new();
+        let mut tick = 0;
+        loop {
+            // The trace may have been cut short, so anything after the last full record is ignored
+            let tag = match read_u8(&mut reader) {
+                Ok(tag) => tag,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            };
+            match read_record(&mut reader, tag, &mut tick) {
+                Ok(Some(change)) => changes.push(change),
+                Ok(None) => {}
+                Err(TraceError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Trace { nodes, changes })
+    }
+
+    /// Returns the ids of the nodes compiled from the block at `pos`.
+    pub fn nodes_at(&self, pos: BlockPos) -> impl Iterator<Item = u32> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(move |(_, node)| node.pos == Some(pos))
+            .map(|(id, _)| id as u32)
+    }
+
+    /// Returns every change made at `tick`.
+    pub fn changes_at(&self, tick: u64) -> &[TraceChange] {
+        let start = self.changes.partition_point(|change| change.tick < tick);
+        let end = self.changes.partition_point(|change| change.tick <= tick);
+        &self.changes[start..end]
+    }
+
+    /// Returns every change of `node`.
+    pub fn history(&self, node: u32) -> impl Iterator<Item = &TraceChange> + '_ {
+        self.changes
+            .iter()
+            .filter(move |change| change.node == node)
+    }
+
+    /// Returns the first tick at which `node` was powered, which is 0 if it started out powered.
+    pub fn first_powered(&self, node: u32) -> Option<u64> {
+        if self.nodes.get(node as usize)?.initial.powered {
+            return Some(0);
+        }
+        self.history(node)
+            .find(|change| change.state.powered)
+            .map(|change| change.tick)
+    }
+
+    /// Returns the last tick at which anything changed.
+    pub fn last_tick(&self) -> u64 {
+        self.changes.last().map_or(0, |change| change.tick)
+    }
+}
+
+/// Writes the trace while the backend is running. Once writing fails the error is logged and the
+/// rest of the trace is dropped.
+pub(crate) struct TraceWriter {
+    out: Option<BufWriter<File>>,
+    tick: u64,
+    written_tick: u64,
+    pub(crate) cause: TraceCause,
+    pub(crate) priority: Option<TickPriority>,
+}
+
+impl TraceWriter {
+    pub(crate) fn create(
+        path: impl AsRef<Path>,
+        nodes: impl ExactSizeIterator<Item = TraceNode>,
+    ) -> io::Result<TraceWriter> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&(nodes.len() as u32).to_le_bytes())?;
+        for node in nodes {
+            match node.pos {
+                Some(pos) => {
+                    out.write_all(&[1])?;
+                    for coord in [pos.x, pos.y, pos.z] {
+                        out.write_all(&coord.to_le_bytes())?;
+                    }
+                }
+                None => out.write_all(&[0])?,
+            }
+            let name = &node.block.as_bytes()[..node.block.len().min(u8::MAX as usize)];
+            out.write_all(&[name.len() as u8])?;
+            out.write_all(name)?;
+            out.write_all(&[node.initial.flags(), node.initial.power])?;
+        }
+        Ok(TraceWriter {
+            out: Some(out),
+            tick: 0,
+            written_tick: 0,
+            cause: TraceCause::Input,
+            priority: None,
+        })
+    }
+
+    pub(crate) fn next_tick(&mut self) {
+        self.tick += 1;
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let Some(out) = &mut self.out else {
+            return;
+        };
+        if let Err(err) = out.write_all(bytes) {
+            error!("Error writing redpiler trace: {}", err);
+            self.out = None;
+        }
+    }
+
+    pub(crate) fn record(&mut self, node: u32, cause: TraceCause, state: NodeState) {
+        if self.tick != self.written_tick {
+            self.written_tick = self.tick;
+            let mut record = [TAG_TICK; 9];
+            record[1..].copy_from_slice(&self.tick.to_le_bytes());
+            self.write(&record);
+        }
+        let (cause_code, from) = match cause {
+            TraceCause::Tick => (CAUSE_TICK, None),
+            TraceCause::Input => (CAUSE_INPUT, None),
+            TraceCause::Interface => (CAUSE_INTERFACE, None),
+            TraceCause::Update { from } => (CAUSE_UPDATE, Some(from)),
+        };
+        let mut record = [0; 11];
+        record[0] = cause_code | priority_code(self.priority) << 4;
+        record[1..5].copy_from_slice(&node.to_le_bytes());
+        record[5] = state.flags();
+        record[6] = state.power;
+        let len = match from {
+            Some(from) => {
+                record[7..11].copy_from_slice(&from.to_le_bytes());
+                11
+            }
+            None => 7,
+        };
+        self.write(&record[..len]);
+    }
+
+    pub(crate) fn finish(&mut self) {
+        if let Some(mut out) = self.out.take() {
+            if let Err(err) = out.flush() {
+                error!("Error writing redpiler trace: {}", err);
+            }
+        }
+    }
+}
+
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_trace() {
+        let path = std::env::temp_dir().join(format!("redpiler_trace_{}.bin", std::process::id()));
+        let off = NodeState {
+            powered: false,
+            locked: false,
+            power: 0,
+        };
+        let on = NodeState {
+            powered: true,
+            locked: false,
+            power: 15,
+        };
+        let nodes = [
+            TraceNode {
+                pos: Some(BlockPos::new(0, 1, 0)),
+                block: "lever".to_string(),
+                initial: off,
+            },
+            TraceNode {
+                pos: None,
+                block: String::new(),
+                initial: off,
+            },
+        ];
+        let mut writer = TraceWriter::create(&path, nodes.clone().into_iter()).unwrap();
+        writer.next_tick();
+        writer.record(0, TraceCause::Input, on);
+        writer.next_tick();
+        writer.next_tick();
+        writer.priority = Some(TickPriority::High);
+        writer.record(1, TraceCause::Update { from: 0 }, on);
+        writer.finish();
+
+        let trace = Trace::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(trace.nodes, nodes);
+        assert_eq!(
+            trace.nodes_at(BlockPos::new(0, 1, 0)).collect::<Vec<_>>(),
+            [0]
+        );
+        assert_eq!(trace.first_powered(0), Some(1));
+        assert_eq!(trace.first_powered(1), Some(3));
+        assert!(trace.changes_at(2).is_empty());
+        assert_eq!(
+            trace.changes_at(3),
+            [TraceChange {
+                tick: 3,
+                node: 1,
+                cause: TraceCause::Update { from: 0 },
+                priority: Some(TickPriority::High),
+                state: on,
+            }]
+        );
+        assert_eq!(trace.last_tick(), 3);
+    }
+}
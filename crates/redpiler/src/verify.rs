@@ -0,0 +1,402 @@
+//! Runs a plot on the default redstone implementation and on redpiler side by side, to find the
+//! first tick and block at which they stop agreeing. Optimization passes are meant to keep the
+//! behavior of a build the same, and this is how to find out whether they did.
+//!
+//! Only outputs are compared, since the optimization passes are free to remove everything else.
+
+use crate::{tick_world, BackendVariant, Compiler, CompilerOptions, FallbackWorld, PendingTicks};
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use mchprs_world::storage::Chunk;
+use mchprs_world::{
+    for_each_block_mut_optimized, for_each_block_optimized, TickEntry, TickPriority, World,
+};
+use rustc_hash::FxHashMap;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    #[error("the block at {0} is not a lever or a button")]
+    NotAnInput(BlockPos),
+}
+
+/// Returns the state of a block that counts as an output, like the `IdentifyNodes` pass decides.
+fn output_state(block: Block) -> Option<bool> {
+    match block {
+        Block::RedstoneLamp { lit } => Some(lit),
+        Block::IronTrapdoor { powered, .. } | Block::NoteBlock { powered, .. } => Some(powered),
+        _ => None,
+    }
+}
+
+/// A copy of the chunks of a plot with ticks of its own, so the plot is not touched while
+/// verifying.
+#[derive(Clone)]
+struct VerifyWorld {
+    chunks: FxHashMap<(i32, i32), Chunk>,
+    to_be_ticked: Vec<TickEntry>,
+}
+
+impl VerifyWorld {
+    fn copy<W: World>(world: &W, bounds: (BlockPos, BlockPos), ticks: Vec<TickEntry>) -> Self {
+        let (first, second) = bounds;
+        let mut chunks = FxHashMap::default();
+        for chunk_x in first.x.min(second.x) >> 4..=first.x.max(second.x) >> 4 {
+            for chunk_z in first.z.min(second.z) >> 4..=first.z.max(second.z) >> 4 {
+                match world.get_chunk(chunk_x, chunk_z) {
+                    Some(chunk) if chunk.x == chunk_x && chunk.z == chunk_z => {
+                        chunks.insert((chunk_x, chunk_z), chunk.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        VerifyWorld {
+            chunks,
+            to_be_ticked: ticks,
+        }
+    }
+
+    fn chunk_at(&self, pos: BlockPos) -> Option<&Chunk> {
+        self.chunks.get(&(pos.x >> 4, pos.z >> 4))
+    }
+
+    fn chunk_at_mut(&mut self, pos: BlockPos) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&(pos.x >> 4, pos.z >> 4))
+    }
+}
+
+fn local_pos(pos: BlockPos) -> BlockPos {
+    BlockPos::new(pos.x & 0xF, pos.y, pos.z & 0xF)
+}
+
+impl World for VerifyWorld {
+    fn get_block_raw(&self, pos: BlockPos) -> u32 {
+        match self.chunk_at(pos) {
+            Some(chunk) if pos.y >= 0 => {
+                chunk.get_block((pos.x & 0xF) as u32, pos.y as u32, (pos.z & 0xF) as u32)
+            }
+            _ => 0,
+        }
+    }
+
+    fn set_block_raw(&mut self, pos: BlockPos, block: u32) -> bool {
+        let Some(chunk) = self.chunk_at_mut(pos) else {
+            return false;
+        };
+        if pos.y < 0 || pos.y as usize >= chunk.sections.len() * 16 {
+            return false;
+        }
+        chunk.set_block(
+            (pos.x & 0xF) as u32,
+            pos.y as u32,
+            (pos.z & 0xF) as u32,
+            block,
+        )
+    }
+
+    fn delete_block_entity(&mut self, pos: BlockPos) {
+        if let Some(chunk) = self.chunk_at_mut(pos) {
+            chunk.delete_block_entity(local_pos(pos));
+        }
+    }
+
+    fn get_block_entity(&self, pos: BlockPos) -> Option<&BlockEntity> {
+        self.chunk_at(pos)?.get_block_entity(local_pos(pos))
+    }
+
+    fn set_block_entity(&mut self, pos: BlockPos, block_entity: BlockEntity) {
+        if let Some(chunk) = self.chunk_at_mut(pos) {
+            chunk.set_block_entity(local_pos(pos), block_entity);
+        }
+    }
+
+    fn get_chunk(&self, x: i32, z: i32) -> Option<&Chunk> {
+        self.chunks.get(&(x, z))
+    }
+
+    fn get_chunk_mut(&mut self, x: i32, z: i32) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&(x, z))
+    }
+
+    fn schedule_tick(&mut self, pos: BlockPos, delay: u32, priority: TickPriority) {
+        self.to_be_ticked.push(TickEntry {
+            pos,
+            ticks_left: delay,
+            tick_priority: priority,
+        });
+    }
+
+    fn pending_tick_at(&mut self, pos: BlockPos) -> bool {
+        self.to_be_ticked.iter().any(|e| e.pos == pos)
+    }
+
+    fn cancel_ticks(&mut self, pos: BlockPos) {
+        self.to_be_ticked.retain(|e| e.pos != pos);
+    }
+}
+
+impl PendingTicks for VerifyWorld {
+    fn pending_ticks(&mut self) -> &mut Vec<TickEntry> {
+        &mut self.to_be_ticked
+    }
+}
+
+/// Runs a tick the same way a plot does. A compiler that is not active leaves everything to the
+/// default redstone implementation.
+fn tick(world: &mut VerifyWorld, redpiler: &mut Compiler) {
+    tick_world(world, redpiler);
+    if redpiler.is_active() {
+        redpiler.flush(world);
+    }
+}
+
+fn use_block(world: &mut VerifyWorld, redpiler: &mut Compiler, pos: BlockPos) {
+    let block = world.get_block(pos);
+    if !redpiler.is_active() {
+        mchprs_redstone::on_use(block, world, pos);
+        return;
+    }
+    if let Some(region) = redpiler
+        .fallback_region()
+        .filter(|region| region.contains(pos))
+    {
+        let mut fallback_world = FallbackWorld::new(world, region);
+        mchprs_redstone::on_use(block, &mut fallback_world, pos);
+        redpiler.read_interface(world);
+    } else {
+        redpiler.on_use_block(pos);
+    }
+    redpiler.flush(world);
+}
+
+/// The first output that ended up different from the reference, which is the default redstone
+/// implementation unless [`Verifier::compare`] was used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Ticks that had passed, 0 being right after compiling
+    pub tick: u64,
+    pub pos: BlockPos,
+    /// The state of the output on the reference
+    pub expected: bool,
+    pub actual: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyResult {
+    pub options: CompilerOptions,
+    pub divergence: Option<Divergence>,
+}
+
+impl fmt::Display for VerifyResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let variant = match self.options.backend_variant {
+            BackendVariant::Direct => "direct",
+            BackendVariant::Cranelift => "cranelift",
+            BackendVariant::Parallel => "parallel",
+        };
+        write!(f, "{}", variant)?;
+        if self.options.optimize {
+            write!(f, " --optimize")?;
+        }
+        if self.options.io_only {
+            write!(f, " --io-only")?;
+        }
+        match &self.divergence {
+            None => write!(f, ": ok"),
+            Some(divergence) => write!(
+                f,
+                ": the output at {} was {} instead of {} after {} ticks",
+                divergence.pos,
+                if divergence.actual { "on" } else { "off" },
+                if divergence.expected { "on" } else { "off" },
+                divergence.tick
+            ),
+        }
+    }
+}
+
+/// Returns the options of every backend, with and without optimizations.
+pub fn verify_configurations() -> Vec<CompilerOptions> {
+    let variants = [
+        BackendVariant::Direct,
+        BackendVariant::Cranelift,
+        BackendVariant::Parallel,
+    ];
+    variants
+        .into_iter()
+        .flat_map(|backend_variant| {
+            [false, true].map(|optimize| CompilerOptions {
+                optimize,
+                backend_variant,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Compares redpiler to the default redstone implementation on a copy of a plot, while using the
+/// same inputs at the same ticks on both.
+pub struct Verifier {
+    world: VerifyWorld,
+    bounds: (BlockPos, BlockPos),
+    /// Positions of the outputs that are compared
+    outputs: Vec<BlockPos>,
+    /// Blocks to use, along with the tick to use them before
+    inputs: Vec<(u64, BlockPos)>,
+}
+
+impl Verifier {
+    /// Copies the blocks within `bounds`, along with the ticks that are pending in them.
+    pub fn new<W: World>(world: &W, bounds: (BlockPos, BlockPos), ticks: Vec<TickEntry>) -> Self {
+        let world = VerifyWorld::copy(world, bounds, ticks);
+        let mut outputs = Vec::new();
+        for_each_block_optimized(&world, bounds.0, bounds.1, |pos| {
+            if output_state(world.get_block(pos)).is_some() {
+                outputs.push(pos);
+            }
+        });
+        Verifier {
+            world,
+            bounds,
+            outputs,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Takes the state of the nodes compiled by `compiler` into the copy, so it picks up where
+    /// the running redpiler is at.
+    pub fn copy_compiler_state(&mut self, compiler: &Compiler) {
+        compiler.copy_into(&mut self.world);
+    }
+
+    /// Updates every block of the copy and runs it on the default redstone implementation for
+    /// `ticks` ticks. Blocks placed without caring about their state, like in generated circuits,
+    /// end up in the state they would be in after being placed by a player.
+    pub fn settle(&mut self, ticks: u64) {
+        let (first, second) = self.bounds;
+        for_each_block_mut_optimized(&mut self.world, first, second, |world, pos| {
+            let block = world.get_block(pos);
+            mchprs_redstone::update(block, world, pos);
+        });
+        let mut redpiler = Compiler::default();
+        for _ in 0..ticks {
+            tick(&mut self.world, &mut redpiler);
+        }
+    }
+
+    /// Uses the lever or button at `pos` right before `tick` is run.
+    pub fn use_block(&mut self, tick: u64, pos: BlockPos) -> Result<(), VerifyError> {
+        match self.world.get_block(pos) {
+            Block::Lever { .. } | Block::StoneButton { .. } => {
+                self.inputs.push((tick, pos));
+                // Inputs used before the same tick keep the order they were added in
+                self.inputs.sort_by_key(|&(tick, _)| tick);
+                Ok(())
+            }
+            _ => Err(VerifyError::NotAnInput(pos)),
+        }
+    }
+
+    fn outputs(&self, world: &VerifyWorld) -> Vec<bool> {
+        self.outputs
+            .iter()
+            .map(|&pos| output_state(world.get_block(pos)).unwrap_or(false))
+            .collect()
+    }
+
+    /// Runs the plot for `ticks` ticks on redpiler, or on the default redstone implementation if
+    /// there are no `options`. `on_tick` is called with the state of every output after compiling
+    /// and after every tick, until it returns false.
+    fn run(
+        &self,
+        options: Option<&CompilerOptions>,
+        ticks: u64,
+        mut on_tick: impl FnMut(u64, Vec<bool>) -> bool,
+    ) {
+        let mut world = self.world.clone();
+        let mut redpiler = Compiler::default();
+        if let Some(options) = options {
+            let ticks = world.to_be_ticked.clone();
+            redpiler.compile(
+                &world,
+                self.bounds,
+                options.clone(),
+                ticks,
+                Default::default(),
+            );
+            world
+                .to_be_ticked
+                .retain(|entry| redpiler.is_fallback(entry.pos));
+        }
+
+        let mut inputs = self.inputs.iter().peekable();
+        if !on_tick(0, self.outputs(&world)) {
+            return;
+        }
+        for tick_num in 1..=ticks {
+            while let Some((_, pos)) = inputs.next_if(|(tick, _)| *tick < tick_num) {
+                use_block(&mut world, &mut redpiler, *pos);
+            }
+            tick(&mut world, &mut redpiler);
+            if !on_tick(tick_num, self.outputs(&world)) {
+                return;
+            }
+        }
+    }
+
+    /// Runs the plot for `ticks` ticks on the default redstone implementation, then with each of
+    /// `configurations`, and returns where each of them first diverged.
+    pub fn verify(&self, configurations: &[CompilerOptions], ticks: u64) -> Vec<VerifyResult> {
+        self.compare_to(None, configurations, ticks)
+    }
+
+    /// Like [`Verifier::verify`], but compares to redpiler compiled with `reference` instead.
+    pub fn compare(
+        &self,
+        reference: &CompilerOptions,
+        configurations: &[CompilerOptions],
+        ticks: u64,
+    ) -> Vec<VerifyResult> {
+        self.compare_to(Some(reference), configurations, ticks)
+    }
+
+    fn compare_to(
+        &self,
+        reference: Option<&CompilerOptions>,
+        configurations: &[CompilerOptions],
+        ticks: u64,
+    ) -> Vec<VerifyResult> {
+        let mut expected = Vec::new();
+        self.run(reference, ticks, |_, outputs| {
+            expected.push(outputs);
+            true
+        });
+
+        configurations
+            .iter()
+            .map(|options| {
+                let mut divergence = None;
+                self.run(Some(options), ticks, |tick, outputs| {
+                    let expected = &expected[tick as usize];
+                    let Some(idx) = (0..outputs.len()).find(|&i| outputs[i] != expected[i]) else {
+                        return true;
+                    };
+                    divergence = Some(Divergence {
+                        tick,
+                        pos: self.outputs[idx],
+                        expected: expected[idx],
+                        actual: outputs[idx],
+                    });
+                    false
+                });
+                VerifyResult {
+                    options: options.clone(),
+                    divergence,
+                }
+            })
+            .collect()
+    }
+}
@@ -1,4 +1,6 @@
 use bincode::{BincodeRead, Result};
+
+pub use bincode::Error;
 use serde::{Deserialize, Serialize};
 
 pub type NodeId = usize;
@@ -26,6 +28,9 @@ pub enum ComparatorMode {
 pub struct Link {
     pub ty: LinkType,
     pub weight: u8,
+    /// The link goes through redstone wire, so the signal is capped at 15 before the weight is
+    /// subtracted
+    pub through_wire: bool,
     pub to: NodeId,
 }
 
@@ -41,7 +46,13 @@ pub enum NodeType {
     Trapdoor,
     Wire,
     Constant,
-    NoteBlock,
+    NoteBlock {
+        /// Protocol id of the instrument, which depends on the block below the note block
+        instrument: u32,
+        note: u32,
+    },
+    Observer,
+    Interface,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
@@ -51,6 +62,15 @@ pub struct NodeState {
     pub output_strength: u8,
 }
 
+/// Repeaters and torches that are part of a bus: lanes that run next to each other, in which
+/// every component only powers the one after it.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct BusLane {
+    pub bus: u32,
+    pub stage: u32,
+    pub lane: u32,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 pub struct Node {
     pub ty: NodeType,
@@ -60,6 +80,11 @@ pub struct Node {
 
     pub facing_diode: bool,
     pub comparator_far_input: Option<u8>,
+    /// Inputs can be changed from the outside, such as levers or interface nodes
+    pub is_input: bool,
+    /// Outputs have to be kept up to date in the world, even when only io is flushed
+    pub is_output: bool,
+    pub bus: Option<BusLane>,
 
     pub inputs: Vec<Link>,
     pub updates: Vec<NodeId>,
@@ -4,6 +4,8 @@
 
 pub mod comparator;
 pub mod noteblock;
+pub mod observer;
+pub mod piston;
 pub mod repeater;
 pub mod wire;
 
@@ -34,11 +36,16 @@ fn get_weak_power(
         Block::StonePressurePlate { powered: true } => 15,
         Block::Lever { lever } if lever.powered => 15,
         Block::StoneButton { button } if button.powered => 15,
+        Block::TripwireHook { powered: true, .. } => 15,
         Block::RedstoneRepeater { repeater }
             if repeater.facing.block_face() == side && repeater.powered =>
         {
             15
         }
+        Block::Observer {
+            facing,
+            powered: true,
+        } if facing.block_face() == side => 15,
         Block::RedstoneComparator { comparator } if comparator.facing.block_face() == side => {
             if let Some(BlockEntity::Comparator { output_strength }) = world.get_block_entity(pos) {
                 *output_strength
@@ -92,9 +99,15 @@ fn get_strong_power(
             } && button.powered,
         ),
         Block::StonePressurePlate { powered: true } if side == BlockFace::Top => 15,
+        Block::TripwireHook {
+            direction,
+            powered: true,
+            ..
+        } if direction.block_face() == side => 15,
         Block::RedstoneWire { .. } => get_weak_power(block, world, pos, side, dust_power),
         Block::RedstoneRepeater { .. } => get_weak_power(block, world, pos, side, dust_power),
         Block::RedstoneComparator { .. } => get_weak_power(block, world, pos, side, dust_power),
+        Block::Observer { .. } => get_weak_power(block, world, pos, side, dust_power),
         _ => 0,
     }
 }
@@ -127,7 +140,7 @@ pub fn get_redstone_power(
     }
 }
 
-fn get_redstone_power_no_dust(
+pub fn get_redstone_power_no_dust(
     block: Block,
     world: &impl World,
     pos: BlockPos,
@@ -183,6 +196,16 @@ fn diode_get_input_strength(world: &impl World, pos: BlockPos, facing: BlockDire
     power
 }
 
+/// Sets the block at `pos`. Unlike [`World::set_block`], this lets observers know about the change.
+/// Returns true if the block was changed.
+pub fn set_block(world: &mut impl World, pos: BlockPos, block: Block) -> bool {
+    let changed = world.set_block(pos, block);
+    if changed {
+        observer::on_observed_change(world, pos);
+    }
+    changed
+}
+
 pub fn update(block: Block, world: &mut impl World, pos: BlockPos) {
     match block {
         Block::RedstoneWire { wire } => {
@@ -209,7 +232,7 @@ pub fn update(block: Block, world: &mut impl World, pos: BlockPos) {
             if lit && !should_be_lit {
                 world.schedule_tick(pos, 2, TickPriority::Normal);
             } else if !lit && should_be_lit {
-                world.set_block(pos, Block::RedstoneLamp { lit: true });
+                set_block(world, pos, Block::RedstoneLamp { lit: true });
             }
         }
         Block::IronTrapdoor {
@@ -224,7 +247,7 @@ pub fn update(block: Block, world: &mut impl World, pos: BlockPos) {
                     half,
                     powered: should_be_powered,
                 };
-                world.set_block(pos, new_block);
+                set_block(world, pos, new_block);
             }
         }
         Block::NoteBlock {
@@ -250,9 +273,14 @@ pub fn update(block: Block, world: &mut impl World, pos: BlockPos) {
                 if should_be_powered && noteblock::is_noteblock_unblocked(world, pos) {
                     noteblock::play_note(world, pos, instrument, note);
                 }
-                world.set_block(pos, new_block);
+                set_block(world, pos, new_block);
             }
         }
+        Block::Piston {
+            extended, facing, ..
+        } => {
+            piston::update(extended, facing, world, pos);
+        }
         _ => {}
     }
 }
@@ -265,36 +293,49 @@ pub fn tick(block: Block, world: &mut impl World, pos: BlockPos) {
         Block::RedstoneComparator { comparator } => {
             comparator::tick(comparator, world, pos);
         }
+        Block::Observer { facing, powered } => {
+            observer::tick(facing, powered, world, pos);
+        }
+        Block::Piston {
+            sticky,
+            extended,
+            facing,
+        } => {
+            piston::tick(sticky, extended, facing, world, pos);
+        }
+        Block::MovingPiston { .. } => {
+            piston::finalize(world, pos);
+        }
         Block::RedstoneTorch { lit } => {
             let should_be_off = torch_should_be_off(world, pos);
             if lit && should_be_off {
-                world.set_block(pos, Block::RedstoneTorch { lit: false });
+                set_block(world, pos, Block::RedstoneTorch { lit: false });
                 update_surrounding_blocks(world, pos);
             } else if !lit && !should_be_off {
-                world.set_block(pos, Block::RedstoneTorch { lit: true });
+                set_block(world, pos, Block::RedstoneTorch { lit: true });
                 update_surrounding_blocks(world, pos);
             }
         }
         Block::RedstoneWallTorch { lit, facing } => {
             let should_be_off = wall_torch_should_be_off(world, pos, facing);
             if lit && should_be_off {
-                world.set_block(pos, Block::RedstoneWallTorch { lit: false, facing });
+                set_block(world, pos, Block::RedstoneWallTorch { lit: false, facing });
                 update_surrounding_blocks(world, pos);
             } else if !lit && !should_be_off {
-                world.set_block(pos, Block::RedstoneWallTorch { lit: true, facing });
+                set_block(world, pos, Block::RedstoneWallTorch { lit: true, facing });
                 update_surrounding_blocks(world, pos);
             }
         }
         Block::RedstoneLamp { lit } => {
             let should_be_lit = redstone_lamp_should_be_lit(world, pos);
             if lit && !should_be_lit {
-                world.set_block(pos, Block::RedstoneLamp { lit: false });
+                set_block(world, pos, Block::RedstoneLamp { lit: false });
             }
         }
         Block::StoneButton { mut button } => {
             if button.powered {
                 button.powered = false;
-                world.set_block(pos, Block::StoneButton { button });
+                set_block(world, pos, Block::StoneButton { button });
                 update_surrounding_blocks(world, pos);
                 match button.face {
                     ButtonFace::Ceiling => {
@@ -361,19 +402,19 @@ pub fn on_use(block: Block, world: &mut impl World, pos: BlockPos) -> bool {
             if repeater.delay > 4 {
                 repeater.delay -= 4;
             }
-            world.set_block(pos, Block::RedstoneRepeater { repeater });
+            set_block(world, pos, Block::RedstoneRepeater { repeater });
             true
         }
         Block::RedstoneComparator { comparator } => {
             let mut comparator = comparator;
             comparator.mode = comparator.mode.toggle();
             comparator::tick(comparator, world, pos);
-            world.set_block(pos, Block::RedstoneComparator { comparator });
+            set_block(world, pos, Block::RedstoneComparator { comparator });
             true
         }
         Block::Lever { mut lever } => {
             lever.powered = !lever.powered;
-            world.set_block(pos, Block::Lever { lever });
+            set_block(world, pos, Block::Lever { lever });
             update_surrounding_blocks(world, pos);
             match lever.face {
                 LeverFace::Ceiling => {
@@ -392,7 +433,7 @@ pub fn on_use(block: Block, world: &mut impl World, pos: BlockPos) -> bool {
         Block::StoneButton { mut button } => {
             if !button.powered {
                 button.powered = true;
-                world.set_block(pos, Block::StoneButton { button });
+                set_block(world, pos, Block::StoneButton { button });
                 world.schedule_tick(pos, 10, TickPriority::Normal);
                 update_surrounding_blocks(world, pos);
                 match button.face {
@@ -420,7 +461,7 @@ pub fn on_use(block: Block, world: &mut impl World, pos: BlockPos) -> bool {
                 new_wire.power = wire.power;
                 new_wire = wire::get_regulated_sides(new_wire, world, pos);
                 if wire != new_wire {
-                    world.set_block(pos, Block::RedstoneWire { wire: new_wire });
+                    set_block(world, pos, Block::RedstoneWire { wire: new_wire });
                     update_wire_neighbors(world, pos);
                     return true;
                 }
@@ -431,7 +472,8 @@ pub fn on_use(block: Block, world: &mut impl World, pos: BlockPos) -> bool {
             let note = (note + 1) % 25;
             let instrument = noteblock::get_noteblock_instrument(world, pos);
 
-            world.set_block(
+            set_block(
+                world,
                 pos,
                 Block::NoteBlock {
                     instrument,
@@ -0,0 +1,50 @@
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockFacing, BlockPos};
+use mchprs_world::{TickPriority, World};
+
+/// Schedules a pulse for every observer that is looking at the block at `pos`. This has to be
+/// called whenever the state of a block changes.
+pub fn on_observed_change(world: &mut impl World, pos: BlockPos) {
+    for face in &BlockFace::values() {
+        let observer_pos = pos.offset(*face);
+        if let Block::Observer {
+            facing,
+            powered: false,
+        } = world.get_block(observer_pos)
+        {
+            if observer_pos.offset(facing.block_face()) == pos
+                && !world.pending_tick_at(observer_pos)
+            {
+                world.schedule_tick(observer_pos, 1, TickPriority::Normal);
+            }
+        }
+    }
+}
+
+fn on_state_change(facing: BlockFacing, world: &mut impl World, pos: BlockPos) {
+    let back_pos = pos.offset(facing.opposite().block_face());
+    let back_block = world.get_block(back_pos);
+    super::update(back_block, world, back_pos);
+    for face in &BlockFace::values() {
+        let neighbor_pos = back_pos.offset(*face);
+        if neighbor_pos != pos {
+            let block = world.get_block(neighbor_pos);
+            super::update(block, world, neighbor_pos);
+        }
+    }
+}
+
+pub fn tick(facing: BlockFacing, powered: bool, world: &mut impl World, pos: BlockPos) {
+    super::set_block(
+        world,
+        pos,
+        Block::Observer {
+            facing,
+            powered: !powered,
+        },
+    );
+    if !powered {
+        world.schedule_tick(pos, 1, TickPriority::Normal);
+    }
+    on_state_change(facing, world, pos);
+}
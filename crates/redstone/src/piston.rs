@@ -0,0 +1,277 @@
+//! Pistons and sticky pistons.
+//!
+//! A piston does not move anything straight away when it gets powered. Instead it schedules a
+//! tick with a delay of 0, which is processed after all other ticks of the current tick, just
+//! like block events in vanilla. Every block that moves is turned into a moving piston holding the
+//! original block in its block entity, and is put back in place 2 game ticks later.
+
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockFacing, BlockPos};
+use mchprs_world::{TickPriority, World};
+
+/// The maximum amount of blocks a single piston can push
+pub const PUSH_LIMIT: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PushReaction {
+    Normal,
+    Destroy,
+    Block,
+}
+
+fn push_reaction(block: Block) -> PushReaction {
+    match block {
+        Block::Piston { extended, .. } => match extended {
+            true => PushReaction::Block,
+            false => PushReaction::Normal,
+        },
+        Block::RedstoneWire { .. }
+        | Block::RedstoneTorch { .. }
+        | Block::RedstoneWallTorch { .. }
+        | Block::RedstoneRepeater { .. }
+        | Block::RedstoneComparator { .. }
+        | Block::Lever { .. }
+        | Block::StoneButton { .. }
+        | Block::StonePressurePlate { .. }
+        | Block::TripwireHook { .. }
+        | Block::Sign { .. }
+        | Block::WallSign { .. }
+        | Block::SeaPickle { .. }
+        | Block::Cake { .. }
+        | Block::Pumpkin { .. } => PushReaction::Destroy,
+        Block::PistonHead { .. } | Block::MovingPiston { .. } | Block::Unknown { .. } => {
+            PushReaction::Block
+        }
+        block if block.can_place_block_in() => PushReaction::Destroy,
+        block if block.has_block_entity() => PushReaction::Block,
+        _ => PushReaction::Normal,
+    }
+}
+
+/// The blocks that are affected when a piston extends
+struct PushStructure {
+    /// The blocks that will be moved, starting with the one right in front of the piston
+    moved: Vec<BlockPos>,
+    /// The block that will be destroyed because it is in the way of the last moved block
+    destroyed: Option<BlockPos>,
+}
+
+fn resolve_push(world: &impl World, pos: BlockPos, facing: BlockFacing) -> Option<PushStructure> {
+    let mut moved = Vec::new();
+    let mut cur = pos.offset(facing.block_face());
+    loop {
+        let block = world.get_block(cur);
+        if let Block::Air {} = block {
+            return Some(PushStructure {
+                moved,
+                destroyed: None,
+            });
+        }
+        match push_reaction(block) {
+            PushReaction::Destroy => {
+                return Some(PushStructure {
+                    moved,
+                    destroyed: Some(cur),
+                })
+            }
+            PushReaction::Block => return None,
+            PushReaction::Normal if moved.len() == PUSH_LIMIT => return None,
+            PushReaction::Normal => moved.push(cur),
+        }
+        cur = cur.offset(facing.block_face());
+    }
+}
+
+pub fn should_extend(world: &impl World, pos: BlockPos, facing: BlockFacing) -> bool {
+    for face in &BlockFace::values() {
+        if *face == facing.block_face() {
+            continue;
+        }
+        let neighbor_pos = pos.offset(*face);
+        let block = world.get_block(neighbor_pos);
+        if super::get_redstone_power(block, world, neighbor_pos, *face) > 0 {
+            return true;
+        }
+    }
+
+    // Quasi-connectivity: pistons are also activated by anything that would power the block
+    // above them.
+    let up_pos = pos.offset(BlockFace::Top);
+    for face in &BlockFace::values() {
+        if *face == BlockFace::Bottom {
+            continue;
+        }
+        let neighbor_pos = up_pos.offset(*face);
+        let block = world.get_block(neighbor_pos);
+        if super::get_redstone_power(block, world, neighbor_pos, *face) > 0 {
+            return true;
+        }
+    }
+    false
+}
+
+pub fn update(extended: bool, facing: BlockFacing, world: &mut impl World, pos: BlockPos) {
+    let should_extend = should_extend(world, pos, facing);
+    if should_extend == extended || world.pending_tick_at(pos) {
+        return;
+    }
+    if should_extend && resolve_push(world, pos, facing).is_none() {
+        return;
+    }
+    world.schedule_tick(pos, 0, TickPriority::Normal);
+}
+
+pub fn tick(
+    sticky: bool,
+    extended: bool,
+    facing: BlockFacing,
+    world: &mut impl World,
+    pos: BlockPos,
+) {
+    let should_extend = should_extend(world, pos, facing);
+    if should_extend && !extended {
+        extend(sticky, facing, world, pos);
+    } else if !should_extend && extended {
+        retract(sticky, facing, world, pos);
+    }
+}
+
+/// Replaces the block at `pos` with a moving piston that turns into `block` after 2 game ticks.
+fn set_moving(
+    world: &mut impl World,
+    pos: BlockPos,
+    facing: BlockFacing,
+    sticky: bool,
+    block: Block,
+    extending: bool,
+) {
+    // Ticks of the block that was here before must not end up ticking the moving piston
+    world.cancel_ticks(pos);
+    world.set_block_entity(
+        pos,
+        BlockEntity::MovingPiston {
+            moved: block.get_id(),
+            facing,
+            extending,
+        },
+    );
+    super::set_block(world, pos, Block::MovingPiston { facing, sticky });
+    world.schedule_tick(pos, 1, TickPriority::Normal);
+}
+
+fn extend(sticky: bool, facing: BlockFacing, world: &mut impl World, pos: BlockPos) {
+    let Some(structure) = resolve_push(world, pos, facing) else {
+        return;
+    };
+    let mut changed = vec![pos];
+
+    if let Some(destroyed_pos) = structure.destroyed {
+        world.cancel_ticks(destroyed_pos);
+        world.delete_block_entity(destroyed_pos);
+        super::set_block(world, destroyed_pos, Block::Air {});
+        changed.push(destroyed_pos);
+    }
+
+    // Move the farthest block first so that no block gets overwritten before it has been moved
+    for &from in structure.moved.iter().rev() {
+        let to = from.offset(facing.block_face());
+        let block = world.get_block(from);
+        set_moving(world, to, facing, false, block, true);
+        changed.push(to);
+    }
+
+    let head_pos = pos.offset(facing.block_face());
+    let head = Block::PistonHead {
+        facing,
+        short: false,
+        sticky,
+    };
+    set_moving(world, head_pos, facing, sticky, head, true);
+    changed.push(head_pos);
+    super::set_block(
+        world,
+        pos,
+        Block::Piston {
+            sticky,
+            extended: true,
+            facing,
+        },
+    );
+
+    for pos in changed {
+        super::update_surrounding_blocks(world, pos);
+    }
+}
+
+fn retract(sticky: bool, facing: BlockFacing, world: &mut impl World, pos: BlockPos) {
+    let head_pos = pos.offset(facing.block_face());
+    // The head might still be on its way out if the piston was only powered for a single tick
+    if let Block::MovingPiston { .. } = world.get_block(head_pos) {
+        finalize(world, head_pos);
+    }
+
+    let base = Block::Piston {
+        sticky,
+        extended: false,
+        facing,
+    };
+    set_moving(world, pos, facing, sticky, base, false);
+    let mut changed = vec![pos, head_pos];
+
+    let mut pulled = false;
+    if sticky {
+        let from = head_pos.offset(facing.block_face());
+        let block = world.get_block(from);
+        match block {
+            Block::MovingPiston { facing: moving, .. }
+                if moving == facing && is_extending(world, from) =>
+            {
+                // A block that is still being pushed by this piston is dropped in place instead of
+                // being pulled back. This is what makes 0-tick pulses spit out blocks.
+                finalize(world, from);
+            }
+            block if block != (Block::Air {}) && push_reaction(block) == PushReaction::Normal => {
+                set_moving(world, head_pos, facing, false, block, false);
+                world.cancel_ticks(from);
+                super::set_block(world, from, Block::Air {});
+                changed.push(from);
+                pulled = true;
+            }
+            _ => {}
+        }
+    }
+    if !pulled {
+        if let Block::PistonHead { .. } = world.get_block(head_pos) {
+            super::set_block(world, head_pos, Block::Air {});
+        }
+    }
+
+    for pos in changed {
+        super::update_surrounding_blocks(world, pos);
+    }
+}
+
+fn is_extending(world: &impl World, pos: BlockPos) -> bool {
+    matches!(
+        world.get_block_entity(pos),
+        Some(BlockEntity::MovingPiston {
+            extending: true,
+            ..
+        })
+    )
+}
+
+/// Puts the block carried by the moving piston at `pos` in place.
+pub fn finalize(world: &mut impl World, pos: BlockPos) {
+    let block = match world.get_block_entity(pos) {
+        Some(BlockEntity::MovingPiston { moved, .. }) => Block::from_id(*moved),
+        _ => Block::Air {},
+    };
+    // The tick that would have finalized this block is no longer needed
+    world.cancel_ticks(pos);
+    world.delete_block_entity(pos);
+    super::set_block(world, pos, block);
+    super::update(block, world, pos);
+    super::update_surrounding_blocks(world, pos);
+}
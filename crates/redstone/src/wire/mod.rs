@@ -77,7 +77,7 @@ pub fn on_neighbor_updated(mut wire: RedstoneWire, world: &mut impl World, pos:
 
     if wire.power != new_power {
         wire.power = new_power;
-        world.set_block(pos, Block::RedstoneWire { wire });
+        crate::set_block(world, pos, Block::RedstoneWire { wire });
         RedstoneWireTurbo::update_surrounding_neighbors(world, pos);
     }
 }
@@ -115,7 +115,7 @@ fn can_connect_to(block: Block, side: BlockDirection) -> bool {
         Block::RedstoneRepeater { repeater } => {
             repeater.facing == side || repeater.facing == side.opposite()
         }
-        Block::Observer { facing } => facing == side.block_facing(),
+        Block::Observer { facing, .. } => facing == side.block_facing(),
         _ => false,
     }
 }
@@ -244,5 +244,6 @@ fn calculate_power(world: &impl World, pos: BlockPos) -> u8 {
         }
     }
 
-    block_power.max(wire_power.saturating_sub(1))
+    // Comparators reading containers can output more than wire is able to carry
+    block_power.min(15).max(wire_power.saturating_sub(1))
 }
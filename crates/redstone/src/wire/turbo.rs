@@ -360,6 +360,8 @@ impl RedstoneWireTurbo {
                 *side,
             ));
         }
+        // Comparators reading containers can output more than wire is able to carry
+        wire_power = wire_power.min(15);
 
         if wire_power < 15 {
             let neighbors = self.nodes[upd.index].neighbors.as_ref().unwrap();
@@ -389,7 +391,7 @@ impl RedstoneWireTurbo {
         }
         if i != j {
             wire.power = j;
-            world.set_block(pos, Block::RedstoneWire { wire });
+            crate::set_block(world, pos, Block::RedstoneWire { wire });
         }
         wire
     }
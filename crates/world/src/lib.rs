@@ -65,6 +65,9 @@ pub trait World {
     /// Returns true if there is a tick entry with `pos`
     fn pending_tick_at(&mut self, pos: BlockPos) -> bool;
 
+    /// Removes all tick entries with `pos`
+    fn cancel_ticks(&mut self, pos: BlockPos);
+
     fn is_cursed(&self) -> bool {
         false
     }
@@ -0,0 +1,282 @@
+//! Runs a plot or schematic without a server and prints the state of its outputs after every tick.
+//!
+//! Actions are given as `<tick> <action> <x>,<y>,<z>`, where the action is one of `use` (flip a
+//! lever or press a button), `press` or `release` (a pressure plate). Actions for a tick are done
+//! right before it runs, so actions at tick 0 are done before the first tick.
+
+use anyhow::{bail, Context, Result};
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use mchprs_core::plot::simulation::Simulation;
+use mchprs_core::plot::worldedit::load_schematic_file;
+use mchprs_redpiler::CompilerOptions;
+use mchprs_save_data::plot_data::PlotData;
+use mchprs_world::{for_each_block_optimized, World};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+const USAGE: &str = "\
+Usage: mchprs-sim <plot file or .schem> --ticks <n> [options]
+
+Options:
+  -t, --ticks <n>          Number of ticks to run
+  -r, --redpiler           Run on redpiler instead of the default redstone implementation
+      --flags <flags>      Redpiler compile flags, such as \"-o --backend=cranelift\" (implies -r)
+  -a, --action <action>    An action to do, as \"<tick> <use|press|release> <x>,<y>,<z>\"
+      --actions <file>     A file with one action per line, lines starting with # are ignored
+  -w, --watch <x>,<y>,<z>  A block to print the state of. Defaults to every lamp, trapdoor and
+                           note block
+  -o, --output <file>      Write the states to a file instead of stdout";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ActionKind {
+    Use,
+    Press,
+    Release,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Action {
+    tick: u64,
+    kind: ActionKind,
+    pos: BlockPos,
+}
+
+fn parse_pos(str: &str) -> Result<BlockPos> {
+    let coords: Vec<i32> = str
+        .split(',')
+        .map(|coord| coord.trim().parse())
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("invalid position: {}", str))?;
+    match coords[..] {
+        [x, y, z] => Ok(BlockPos::new(x, y, z)),
+        _ => bail!("invalid position: {}", str),
+    }
+}
+
+fn parse_action(str: &str) -> Result<Action> {
+    let parts: Vec<&str> = str.split_whitespace().collect();
+    let [tick, kind, pos] = parts[..] else {
+        bail!("invalid action: {}", str);
+    };
+    let tick = tick
+        .parse()
+        .with_context(|| format!("invalid tick in action: {}", str))?;
+    let kind = match kind {
+        "use" => ActionKind::Use,
+        "press" => ActionKind::Press,
+        "release" => ActionKind::Release,
+        _ => bail!("unknown action: {}", kind),
+    };
+    Ok(Action {
+        tick,
+        kind,
+        pos: parse_pos(pos)?,
+    })
+}
+
+struct Args {
+    input: PathBuf,
+    ticks: u64,
+    redpiler: Option<CompilerOptions>,
+    actions: Vec<Action>,
+    watch: Vec<BlockPos>,
+    output: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args> {
+        let mut input = None;
+        let mut ticks = None;
+        let mut redpiler = None;
+        let mut actions = Vec::new();
+        let mut watch = Vec::new();
+        let mut output = None;
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "-t" | "--ticks" => {
+                    ticks = Some(value()?.parse().context("invalid tick count")?);
+                }
+                "-r" | "--redpiler" => {
+                    redpiler.get_or_insert_with(CompilerOptions::default);
+                }
+                "--flags" => redpiler = Some(CompilerOptions::parse(&value()?)),
+                "-a" | "--action" => actions.push(parse_action(&value()?)?),
+                "--actions" => {
+                    let path = value()?;
+                    let file = fs::read_to_string(&path)
+                        .with_context(|| format!("error reading actions from {}", path))?;
+                    for line in file.lines().map(str::trim) {
+                        if !line.is_empty() && !line.starts_with('#') {
+                            actions.push(parse_action(line)?);
+                        }
+                    }
+                }
+                "-w" | "--watch" => watch.push(parse_pos(&value()?)?),
+                "-o" | "--output" => output = Some(value()?.into()),
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ if arg.starts_with('-') => bail!("unknown option: {}", arg),
+                _ if input.is_none() => input = Some(arg.into()),
+                _ => bail!("unexpected argument: {}", arg),
+            }
+        }
+        // Actions for the same tick keep the order they were given in
+        actions.sort_by_key(|action| action.tick);
+        Ok(Args {
+            input: input.context("missing input file")?,
+            ticks: ticks.context("missing tick count")?,
+            redpiler,
+            actions,
+            watch,
+            output,
+        })
+    }
+}
+
+/// Plot files are named after their position, which the coordinates in the plot depend on.
+fn plot_pos(path: &Path) -> (i32, i32) {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    name.strip_prefix('p')
+        .and_then(|pos| pos.split_once(','))
+        .and_then(|(x, z)| Some((x.parse().ok()?, z.parse().ok()?)))
+        .unwrap_or((0, 0))
+}
+
+fn load(path: &Path) -> Result<Simulation> {
+    if path.extension().is_some_and(|ext| ext == "schem") {
+        let clipboard = load_schematic_file(path)
+            .with_context(|| format!("error loading schematic {}", path.display()))?;
+        return Ok(Simulation::from_schematic(&clipboard));
+    }
+    let data = PlotData::load_from_file(path)
+        .with_context(|| format!("error loading plot {}", path.display()))?;
+    let (x, z) = plot_pos(path);
+    Ok(Simulation::from_plot_data(data, x, z))
+}
+
+fn find_outputs(simulation: &Simulation) -> Vec<BlockPos> {
+    let world = simulation.world();
+    let (first, second) = world.get_corners();
+    let mut outputs = Vec::new();
+    for_each_block_optimized(world, first, second, |pos| {
+        if matches!(
+            world.get_block(pos),
+            Block::RedstoneLamp { .. } | Block::IronTrapdoor { .. } | Block::NoteBlock { .. }
+        ) {
+            outputs.push(pos);
+        }
+    });
+    outputs
+}
+
+fn block_state(block: Block) -> String {
+    match block {
+        Block::RedstoneWire { wire } => wire.power.to_string(),
+        Block::RedstoneLamp { lit } => (lit as u8).to_string(),
+        Block::IronTrapdoor { powered, .. }
+        | Block::NoteBlock { powered, .. }
+        | Block::StonePressurePlate { powered }
+        | Block::Observer { powered, .. }
+        | Block::RedstoneTorch { lit: powered }
+        | Block::RedstoneWallTorch { lit: powered, .. } => (powered as u8).to_string(),
+        Block::RedstoneRepeater { repeater } => (repeater.powered as u8).to_string(),
+        Block::RedstoneComparator { comparator } => (comparator.powered as u8).to_string(),
+        Block::Lever { lever } => (lever.powered as u8).to_string(),
+        Block::StoneButton { button } => (button.powered as u8).to_string(),
+        block => block.get_name().to_string(),
+    }
+}
+
+fn write_states(
+    out: &mut impl Write,
+    simulation: &Simulation,
+    tick: u64,
+    watch: &[BlockPos],
+) -> io::Result<()> {
+    write!(out, "{}", tick)?;
+    for &pos in watch {
+        write!(out, ",{}", block_state(simulation.get_block(pos)))?;
+    }
+    writeln!(out)
+}
+
+fn run(args: Args) -> Result<()> {
+    let mut simulation = load(&args.input)?;
+    let watch = match args.watch.is_empty() {
+        true => find_outputs(&simulation),
+        false => args.watch,
+    };
+    if let Some(options) = args.redpiler {
+        simulation.start_redpiler(options);
+    }
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    write!(out, "tick")?;
+    for pos in &watch {
+        write!(out, ",\"{}\"", pos)?;
+    }
+    writeln!(out)?;
+
+    let mut actions = args.actions.iter().peekable();
+    for tick in 0..=args.ticks {
+        while let Some(action) = actions.next_if(|action| action.tick == tick) {
+            let block = simulation.get_block(action.pos);
+            match action.kind {
+                ActionKind::Use => simulation.use_block(action.pos),
+                ActionKind::Press | ActionKind::Release
+                    if !matches!(block, Block::StonePressurePlate { .. }) =>
+                {
+                    bail!(
+                        "cannot press or release the {} at {}, it is not a pressure plate",
+                        block.get_name(),
+                        action.pos
+                    );
+                }
+                ActionKind::Press => simulation.set_pressure_plate(action.pos, true),
+                ActionKind::Release => simulation.set_pressure_plate(action.pos, false),
+            }
+        }
+        write_states(&mut out, &simulation, tick, &watch)?;
+        if tick < args.ticks {
+            simulation.tick();
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let env_filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::WARN.into())
+        .with_env_var("MCHPRS_LOG")
+        .from_env_lossy();
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_env_filter(env_filter)
+        .init();
+
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{:#}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+    run(args)
+}
@@ -0,0 +1,114 @@
+//! Answers questions about a trace recorded by redpiler with the `--trace` flag.
+
+use anyhow::{bail, Context, Result};
+use mchprs_blocks::BlockPos;
+use mchprs_redpiler::{Trace, TraceCause, TraceChange};
+
+const USAGE: &str = "\
+Usage: mchprs-trace <trace file> <command>
+
+Commands:
+  summary                Print the amount of nodes and changes in the trace
+  first <x>,<y>,<z>      Print the first tick at which the block was powered
+  history <x>,<y>,<z>    Print every change of the block
+  tick <n>               Print every change made at tick <n>, in the order they happened";
+
+fn parse_pos(str: &str) -> Result<BlockPos> {
+    let coords: Vec<i32> = str
+        .split(',')
+        .map(|coord| coord.trim().parse())
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("invalid position: {}", str))?;
+    match coords[..] {
+        [x, y, z] => Ok(BlockPos::new(x, y, z)),
+        _ => bail!("invalid position: {}", str),
+    }
+}
+
+fn describe_node(trace: &Trace, id: u32) -> String {
+    match trace.nodes.get(id as usize) {
+        Some(node) => match node.pos {
+            Some(pos) => format!("node {} ({} at {})", id, node.block, pos),
+            None => format!("node {}", id),
+        },
+        None => format!("unknown node {}", id),
+    }
+}
+
+fn describe_change(trace: &Trace, change: &TraceChange) -> String {
+    let cause = match change.cause {
+        TraceCause::Tick => "scheduled tick".to_string(),
+        TraceCause::Input => "input".to_string(),
+        TraceCause::Interface => "fallback region".to_string(),
+        TraceCause::Update { from } => format!("update from {}", describe_node(trace, from)),
+    };
+    let priority = match change.priority {
+        Some(priority) => format!(" ({:?})", priority),
+        None => String::new(),
+    };
+    let state = &change.state;
+    format!(
+        "tick {}{}: {} set to powered={} power={}{} by {}",
+        change.tick,
+        priority,
+        describe_node(trace, change.node),
+        state.powered,
+        state.power,
+        if state.locked { " locked" } else { "" },
+        cause
+    )
+}
+
+/// Every position in the trace maps to a single node, except for blocks that were not compiled.
+fn node_at(trace: &Trace, pos: BlockPos) -> Result<u32> {
+    trace
+        .nodes_at(pos)
+        .next()
+        .with_context(|| format!("there is no node at {}", pos))
+}
+
+fn run(trace: &Trace, command: &str, arg: Option<&str>) -> Result<()> {
+    let arg = || arg.with_context(|| format!("missing argument for {}", command));
+    match command {
+        "summary" => {
+            println!("{} nodes", trace.nodes.len());
+            println!("{} changes", trace.changes.len());
+            println!("last change at tick {}", trace.last_tick());
+        }
+        "first" => {
+            let node = node_at(trace, parse_pos(arg()?)?)?;
+            match trace.first_powered(node) {
+                Some(tick) => println!("{}", tick),
+                None => println!("never"),
+            }
+        }
+        "history" => {
+            let node = node_at(trace, parse_pos(arg()?)?)?;
+            for change in trace.history(node) {
+                println!("{}", describe_change(trace, change));
+            }
+        }
+        "tick" => {
+            let tick = arg()?.parse().context("invalid tick")?;
+            for change in trace.changes_at(tick) {
+                println!("{}", describe_change(trace, change));
+            }
+        }
+        _ => bail!("unknown command: {}", command),
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (path, command, arg) = match &args[..] {
+        [path, command] => (path, command, None),
+        [path, command, arg] => (path, command, Some(arg.as_str())),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let trace = Trace::load(path).with_context(|| format!("error reading trace {}", path))?;
+    run(&trace, command, arg)
+}
@@ -1,7 +1,9 @@
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::Block;
 use mchprs_blocks::BlockPos;
-use mchprs_redpiler::{BackendVariant, Compiler, CompilerOptions};
+use mchprs_redpiler::{
+    tick_world, BackendVariant, Compiler, CompilerOptions, FallbackWorld, PendingTicks,
+};
 use mchprs_world::storage::Chunk;
 use mchprs_world::{TickEntry, TickPriority, World};
 
@@ -120,6 +122,21 @@ impl World for TestWorld {
     fn pending_tick_at(&mut self, pos: BlockPos) -> bool {
         self.to_be_ticked.iter().any(|e| e.pos == pos)
     }
+
+    fn cancel_ticks(&mut self, pos: BlockPos) {
+        self.to_be_ticked.retain(|e| e.pos != pos);
+    }
+}
+
+impl PendingTicks for TestWorld {
+    fn pending_ticks(&mut self) -> &mut Vec<TickEntry> {
+        &mut self.to_be_ticked
+    }
+}
+
+fn world_bounds(world: &TestWorld) -> (BlockPos, BlockPos) {
+    let max = world.size * 16 - 1;
+    (BlockPos::new(0, 0, 0), BlockPos::new(max, max, max))
 }
 
 struct RedpilerInstance {
@@ -128,17 +145,29 @@ struct RedpilerInstance {
 }
 
 impl RedpilerInstance {
-    fn new(world: &TestWorld, variant: BackendVariant) -> RedpilerInstance {
-        let options = CompilerOptions {
-            backend_variant: variant,
-            ..Default::default()
-        };
+    fn new(
+        world: &mut TestWorld,
+        options: CompilerOptions,
+        selection: Option<(BlockPos, BlockPos)>,
+    ) -> RedpilerInstance {
+        let bounds = world_bounds(world);
         let mut compiler = Compiler::default();
-        let max = world.size * 16 - 1;
-        let bounds = (BlockPos::new(0, 0, 0), BlockPos::new(max, max, max));
         let monitor = Default::default();
         let ticks = world.to_be_ticked.clone();
-        compiler.compile(world, bounds, options.clone(), ticks, monitor);
+        match selection {
+            Some(selection) => compiler.compile_selection(
+                world,
+                bounds,
+                selection,
+                options.clone(),
+                ticks,
+                monitor,
+            ),
+            None => compiler.compile(world, bounds, options.clone(), ticks, monitor),
+        }
+        world
+            .to_be_ticked
+            .retain(|entry| compiler.is_fallback(entry.pos));
         RedpilerInstance { options, compiler }
     }
 }
@@ -156,44 +185,122 @@ pub struct BackendRunner {
 
 impl BackendRunner {
     pub fn new(world: TestWorld, backend: TestBackend) -> BackendRunner {
+        BackendRunner::create(world, backend, None, CompilerOptions::default())
+    }
+
+    /// Compiles with `options` when running on redpiler, apart from the backend variant.
+    pub fn with_options(
+        world: TestWorld,
+        backend: TestBackend,
+        options: CompilerOptions,
+    ) -> BackendRunner {
+        BackendRunner::create(world, backend, None, options)
+    }
+
+    /// Only compiles `selection` when running on redpiler.
+    pub fn with_selection(
+        world: TestWorld,
+        backend: TestBackend,
+        selection: (BlockPos, BlockPos),
+    ) -> BackendRunner {
+        BackendRunner::create(world, backend, Some(selection), CompilerOptions::default())
+    }
+
+    fn create(
+        world: TestWorld,
+        backend: TestBackend,
+        selection: Option<(BlockPos, BlockPos)>,
+        options: CompilerOptions,
+    ) -> BackendRunner {
         match backend {
             TestBackend::Redstone => BackendRunner {
                 world,
                 redpiler: None,
             },
-            TestBackend::Redpiler(variant) => BackendRunner {
-                redpiler: Some(RedpilerInstance::new(&world, variant)),
-                world,
-            },
+            TestBackend::Redpiler(variant) => {
+                let mut world = world;
+                let options = CompilerOptions {
+                    backend_variant: variant,
+                    ..options
+                };
+                BackendRunner {
+                    redpiler: Some(RedpilerInstance::new(&mut world, options, selection)),
+                    world,
+                }
+            }
         }
     }
 
     pub fn tick(&mut self) {
-        if let Some(redpiler) = &mut self.redpiler {
-            redpiler.compiler.tick();
-            redpiler.compiler.flush(&mut self.world);
-            return;
+        match &mut self.redpiler {
+            Some(redpiler) => {
+                tick_world(&mut self.world, &mut redpiler.compiler);
+                redpiler.compiler.flush(&mut self.world);
+            }
+            // A compiler that is not active leaves everything to the default redstone
+            // implementation
+            None => {
+                tick_world(&mut self.world, &mut Compiler::default());
+            }
         }
+    }
 
-        self.world
-            .to_be_ticked
-            .sort_by_key(|e| (e.ticks_left, e.tick_priority));
-        for pending in &mut self.world.to_be_ticked {
-            pending.ticks_left = pending.ticks_left.saturating_sub(1);
-        }
-        while self.world.to_be_ticked.first().map_or(1, |e| e.ticks_left) == 0 {
-            let entry = self.world.to_be_ticked.remove(0);
-            mchprs_redstone::tick(self.world.get_block(entry.pos), &mut self.world, entry.pos);
-        }
+    fn fallback_world(&mut self) -> Option<FallbackWorld<'_, TestWorld>> {
+        let region = self.redpiler.as_ref()?.compiler.fallback_region()?;
+        Some(FallbackWorld::new(&mut self.world, region))
     }
 
     pub fn use_block(&mut self, pos: BlockPos) {
+        let block = self.world.get_block(pos);
+        if let Some(mut world) = self.fallback_world() {
+            if world.region().contains(pos) {
+                mchprs_redstone::on_use(block, &mut world, pos);
+                // Compiled blocks next to the region react right away, like they would in vanilla
+                let compiler = &mut self.redpiler.as_mut().unwrap().compiler;
+                compiler.read_interface(&self.world);
+                compiler.flush(&mut self.world);
+                return;
+            }
+        }
         if let Some(redpiler) = &mut self.redpiler {
             redpiler.compiler.on_use_block(pos);
             redpiler.compiler.flush(&mut self.world);
             return;
         }
-        mchprs_redstone::on_use(self.world.get_block(pos), &mut self.world, pos);
+        mchprs_redstone::on_use(block, &mut self.world, pos);
+    }
+
+    /// Returns true if redpiler is running and can be recompiled around edits.
+    pub fn can_recompile(&self) -> bool {
+        self.redpiler
+            .as_ref()
+            .is_some_and(|redpiler| redpiler.compiler.can_recompile())
+    }
+
+    /// Replaces the block at `pos` and updates the blocks around it, like a player building would.
+    /// Redpiler is recompiled around the edit, or compiled all over again if that is not possible.
+    pub fn edit_block(&mut self, pos: BlockPos, block: Block) {
+        let incremental = self.can_recompile();
+        if let Some(redpiler) = &mut self.redpiler {
+            if !incremental {
+                let bounds = world_bounds(&self.world);
+                redpiler.compiler.reset(&mut self.world, bounds);
+            }
+        }
+
+        self.world.set_block(pos, block);
+        mchprs_redstone::update(block, &mut self.world, pos);
+        mchprs_redstone::update_surrounding_blocks(&mut self.world, pos);
+
+        if let Some(redpiler) = &mut self.redpiler {
+            if incremental {
+                self.world.to_be_ticked.clear();
+                assert!(redpiler.compiler.recompile_around(&mut self.world, pos));
+            } else {
+                let options = redpiler.options.clone();
+                *redpiler = RedpilerInstance::new(&mut self.world, options, None);
+            }
+        }
     }
 
     pub fn check_block_powered(&self, pos: BlockPos, powered: bool) {
@@ -233,6 +340,7 @@ fn is_block_powered(block: Block) -> Option<bool> {
         Block::RedstoneLamp { lit } => lit,
         Block::IronTrapdoor { powered, .. } => powered,
         Block::NoteBlock { powered, .. } => powered,
+        Block::Observer { powered, .. } => powered,
         _ => return None,
     })
 }
@@ -244,6 +352,10 @@ macro_rules! test_all_backends {
             fn [< $name _redstone >]() { $name(TestBackend::Redstone) }
             #[test]
             fn [< $name _rp_direct >]() { $name(TestBackend::Redpiler(BackendVariant::Direct)) }
+            #[test]
+            fn [< $name _rp_cranelift >]() { $name(TestBackend::Redpiler(BackendVariant::Cranelift)) }
+            #[test]
+            fn [< $name _rp_parallel >]() { $name(TestBackend::Redpiler(BackendVariant::Parallel)) }
         }
     };
 }
@@ -1,11 +1,17 @@
 mod common;
 
 use common::{test_all_backends, BackendRunner, TestBackend, TestWorld};
-use mchprs_blocks::blocks::{Block, Lever, LeverFace, RedstoneRepeater};
-use mchprs_blocks::{BlockDirection, BlockPos};
-use mchprs_redpiler::BackendVariant;
+use mchprs_blocks::block_entities::{BlockEntity, ContainerType};
+use mchprs_blocks::blocks::{
+    Block, ComparatorMode, Lever, LeverFace, RedstoneComparator, RedstoneRepeater,
+};
+use mchprs_blocks::{BlockDirection, BlockFacing, BlockPos};
+use mchprs_redpiler::{
+    verify_configurations, BackendVariant, BreakCondition, Compiler, CompilerOptions, Snapshot,
+    SnapshotError, Verifier,
+};
 use mchprs_redstone::wire::make_cross;
-use mchprs_world::World;
+use mchprs_world::{TickPriority, World};
 
 fn pos(x: i32, y: i32, z: i32) -> BlockPos {
     BlockPos::new(x, y, z)
@@ -189,3 +195,844 @@ fn repeater_on_off(backend: TestBackend) {
         runner.check_block_powered(trapdoor_pos, false);
     }
 }
+
+test_all_backends!(observer_pulse);
+fn observer_pulse(backend: TestBackend) {
+    let lever_pos = pos(0, 1, 0);
+    let observer_pos = pos(1, 1, 0);
+    let trapdoor_pos = pos(2, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    world.set_block(
+        observer_pos,
+        Block::Observer {
+            facing: BlockFacing::West,
+            powered: false,
+        },
+    );
+    world.set_block(trapdoor_pos, trapdoor());
+
+    let mut runner = BackendRunner::new(world, backend);
+    runner.check_block_powered(trapdoor_pos, false);
+
+    // Both turning the lever on and off should emit a 1 tick pulse after 1 tick
+    for _ in 0..2 {
+        runner.use_block(lever_pos);
+        runner.check_powered_for(trapdoor_pos, false, 1);
+        runner.check_block_powered(observer_pos, true);
+        runner.check_powered_for(trapdoor_pos, true, 1);
+        runner.check_block_powered(trapdoor_pos, false);
+        runner.check_block_powered(observer_pos, false);
+    }
+}
+
+test_all_backends!(constant_sources);
+fn constant_sources(backend: TestBackend) {
+    let lever_pos = pos(0, 1, 0);
+    let trapdoor_pos = pos(1, 1, 0);
+
+    let sources = [
+        // Powers the trapdoor directly
+        vec![(pos(2, 1, 0), Block::RedstoneBlock {})],
+        // Strongly powers the block the hook is attached to
+        vec![
+            (pos(2, 1, 0), Block::Target {}),
+            (
+                pos(3, 1, 0),
+                Block::TripwireHook {
+                    attached: true,
+                    direction: BlockDirection::East,
+                    powered: true,
+                },
+            ),
+        ],
+    ];
+    for blocks in sources {
+        let mut world = TestWorld::new(1);
+        make_lever(&mut world, lever_pos);
+        world.set_block(
+            trapdoor_pos,
+            Block::IronTrapdoor {
+                facing: Default::default(),
+                half: Default::default(),
+                powered: true,
+            },
+        );
+        for (pos, block) in blocks {
+            world.set_block(pos, block);
+        }
+
+        let mut runner = BackendRunner::new(world, backend);
+        runner.use_block(lever_pos);
+        runner.check_powered_for(trapdoor_pos, true, 2);
+        runner.use_block(lever_pos);
+        runner.check_powered_for(trapdoor_pos, true, 2);
+    }
+}
+
+test_all_backends!(independent_circuits);
+fn independent_circuits(backend: TestBackend) {
+    let mut world = TestWorld::new(1);
+    let mut circuits = Vec::new();
+    for (i, delay) in [1, 3].into_iter().enumerate() {
+        let z = i as i32 * 4;
+        let lever_pos = pos(0, 2, z);
+        let trapdoor_pos = pos(2, 1, z);
+        make_lever(&mut world, lever_pos);
+        place_on_block(
+            &mut world,
+            pos(1, 1, z),
+            Block::RedstoneRepeater {
+                repeater: RedstoneRepeater {
+                    facing: BlockDirection::West,
+                    delay,
+                    ..Default::default()
+                },
+            },
+        );
+        world.set_block(trapdoor_pos, trapdoor());
+        circuits.push((lever_pos, trapdoor_pos));
+    }
+    let [(lever_a, trapdoor_a), (lever_b, trapdoor_b)] = circuits[..] else {
+        unreachable!()
+    };
+
+    let mut runner = BackendRunner::new(world, backend);
+    runner.use_block(lever_a);
+    runner.use_block(lever_b);
+    runner.check_powered_for(trapdoor_a, false, 1);
+    runner.check_block_powered(trapdoor_a, true);
+    runner.check_powered_for(trapdoor_b, false, 2);
+    runner.check_block_powered(trapdoor_b, true);
+
+    // Toggling one circuit must not affect the other
+    runner.use_block(lever_a);
+    runner.check_powered_for(trapdoor_b, true, 3);
+    runner.check_block_powered(trapdoor_a, false);
+}
+
+test_all_backends!(sticky_piston_push_pull);
+fn sticky_piston_push_pull(backend: TestBackend) {
+    let lever_pos = pos(0, 1, 0);
+    let piston_pos = pos(1, 1, 0);
+    let lamp_pos = pos(4, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    world.set_block(
+        piston_pos,
+        Block::Piston {
+            sticky: true,
+            extended: false,
+            facing: BlockFacing::East,
+        },
+    );
+    world.set_block(pos(2, 1, 0), Block::RedstoneBlock {});
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mut runner = BackendRunner::new(world, backend);
+    // The piston starts moving 1 tick after being powered and takes 1 more tick to finish
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, false, 2);
+    runner.check_block_powered(lamp_pos, true);
+
+    // The redstone block is pulled away as soon as the piston starts retracting
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, true, 3);
+    runner.check_block_powered(lamp_pos, false);
+}
+
+test_all_backends!(piston_quasi_connectivity);
+fn piston_quasi_connectivity(backend: TestBackend) {
+    // The lever only powers the air above the piston
+    let lever_pos = pos(1, 2, 1);
+    let piston_pos = pos(1, 1, 0);
+    let lamp_pos = pos(4, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    world.set_block(
+        piston_pos,
+        Block::Piston {
+            sticky: false,
+            extended: false,
+            facing: BlockFacing::East,
+        },
+    );
+    world.set_block(pos(2, 1, 0), Block::RedstoneBlock {});
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mut runner = BackendRunner::new(world, backend);
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, false, 2);
+    runner.check_block_powered(lamp_pos, true);
+
+    // A regular piston leaves the block behind
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, true, 5);
+}
+
+test_all_backends!(piston_between_compiled_components);
+fn piston_between_compiled_components(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 5);
+    let repeater_pos = pos(2, 1, 5);
+    let lamp_pos = pos(5, 1, 6);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    for x in 1..=2 {
+        place_on_block(
+            &mut world,
+            pos(x, 1, 5),
+            Block::RedstoneRepeater {
+                repeater: RedstoneRepeater {
+                    facing: BlockDirection::West,
+                    ..Default::default()
+                },
+            },
+        );
+    }
+    world.set_block(
+        pos(3, 1, 5),
+        Block::Piston {
+            sticky: true,
+            extended: false,
+            facing: BlockFacing::East,
+        },
+    );
+    world.set_block(pos(4, 1, 5), Block::RedstoneBlock {});
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mut runner = BackendRunner::new(world, backend);
+    runner.use_block(lever_pos);
+    runner.check_powered_for(repeater_pos, false, 2);
+    runner.check_block_powered(repeater_pos, true);
+    // The redstone block ends up next to the lamp once the piston has finished extending
+    runner.check_powered_for(lamp_pos, false, 1);
+    runner.check_block_powered(lamp_pos, true);
+
+    runner.use_block(lever_pos);
+    runner.check_powered_for(repeater_pos, true, 2);
+    runner.check_block_powered(repeater_pos, false);
+    // The lamp stays lit for 2 more ticks after the block has been pulled away
+    runner.check_powered_for(lamp_pos, true, 2);
+    runner.check_block_powered(lamp_pos, false);
+}
+
+/// Builds a line of repeaters along x, powered by a lever at x = 0, with a lamp at the end.
+fn make_repeater_line(world: &mut TestWorld, len: i32) {
+    make_lever(world, pos(0, 2, 5));
+    for x in 1..=len {
+        place_on_block(
+            world,
+            pos(x, 1, 5),
+            Block::RedstoneRepeater {
+                repeater: RedstoneRepeater {
+                    facing: BlockDirection::West,
+                    ..Default::default()
+                },
+            },
+        );
+    }
+    world.set_block(pos(len + 1, 1, 5), Block::RedstoneLamp { lit: false });
+}
+
+test_all_backends!(selection_boundary);
+fn selection_boundary(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 5);
+    let lamp_pos = pos(6, 1, 5);
+
+    let mut world = TestWorld::new(1);
+    make_repeater_line(&mut world, 5);
+
+    // Only the last three repeaters are compiled, the rest and the lamp run outside of redpiler
+    let selection = (pos(3, 0, 0), pos(5, 15, 15));
+    let mut runner = BackendRunner::with_selection(world, backend, selection);
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, false, 5);
+    runner.check_block_powered(lamp_pos, true);
+
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, true, 7);
+    runner.check_block_powered(lamp_pos, false);
+}
+
+test_all_backends!(selection_wire_crossing);
+fn selection_wire_crossing(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 5);
+    let lamp_pos = pos(6, 1, 5);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    for x in 1..=5 {
+        place_on_block(
+            &mut world,
+            pos(x, 1, 5),
+            Block::RedstoneWire {
+                wire: make_cross(0),
+            },
+        );
+    }
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    // The wire crosses the selection, which has to be grown to include all of it
+    let selection = (pos(4, 0, 0), pos(4, 15, 15));
+    let mut runner = BackendRunner::with_selection(world, backend, selection);
+    runner.use_block(lever_pos);
+    runner.check_block_powered(lamp_pos, true);
+
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, true, 2);
+    runner.check_block_powered(lamp_pos, false);
+}
+
+test_all_backends!(edit_connects_wire);
+fn edit_connects_wire(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 5);
+    let lamp_pos = pos(6, 1, 5);
+    let gap_pos = pos(3, 1, 5);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    for x in 1..=5 {
+        place_on_block(
+            &mut world,
+            pos(x, 1, 5),
+            Block::RedstoneWire {
+                wire: make_cross(0),
+            },
+        );
+    }
+    world.set_block(gap_pos, Block::Air {});
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mut runner = BackendRunner::new(world, backend);
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, false, 2);
+
+    runner.edit_block(
+        gap_pos,
+        Block::RedstoneWire {
+            wire: make_cross(0),
+        },
+    );
+    runner.check_block_powered(lamp_pos, true);
+
+    runner.edit_block(gap_pos, Block::Air {});
+    runner.check_powered_for(lamp_pos, true, 2);
+    runner.check_block_powered(lamp_pos, false);
+}
+
+test_all_backends!(edit_keeps_pending_ticks);
+fn edit_keeps_pending_ticks(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 5);
+    let lamp_pos = pos(5, 1, 5);
+
+    let mut world = TestWorld::new(1);
+    make_repeater_line(&mut world, 4);
+
+    let mut runner = BackendRunner::new(world, backend);
+    runner.use_block(lever_pos);
+    runner.tick();
+    runner.tick();
+
+    // The signal is still on its way while the lamp is replaced
+    runner.edit_block(lamp_pos, Block::Air {});
+    runner.edit_block(lamp_pos, Block::RedstoneLamp { lit: false });
+    runner.check_powered_for(lamp_pos, false, 2);
+    runner.check_block_powered(lamp_pos, true);
+}
+
+test_all_backends!(edit_optimized_graph);
+fn edit_optimized_graph(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 5);
+    let lamp_pos = pos(5, 1, 5);
+
+    let mut world = TestWorld::new(1);
+    make_repeater_line(&mut world, 4);
+
+    let options = CompilerOptions {
+        optimize: true,
+        ..Default::default()
+    };
+    let mut runner = BackendRunner::with_options(world, backend, options);
+    if let TestBackend::Redpiler(_) = backend {
+        assert!(runner.can_recompile());
+    }
+    runner.use_block(lever_pos);
+    runner.tick();
+
+    // The signal is on its way while a repeater further down the line is replaced
+    runner.edit_block(
+        pos(3, 1, 5),
+        Block::RedstoneRepeater {
+            repeater: RedstoneRepeater {
+                facing: BlockDirection::West,
+                delay: 2,
+                ..Default::default()
+            },
+        },
+    );
+    runner.check_powered_for(lamp_pos, false, 4);
+    runner.check_block_powered(lamp_pos, true);
+
+    // The signal takes the slower repeater on its way back as well
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, true, 7);
+    runner.check_block_powered(lamp_pos, false);
+}
+
+#[test]
+fn cached_graph() {
+    let lever_pos = pos(0, 1, 0);
+    let lamp_pos = pos(1, 0, 0);
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+    let cache_dir = std::env::temp_dir().join(format!("redpiler_cache_{}", std::process::id()));
+    let cache_entries = || std::fs::read_dir(&cache_dir).unwrap().count();
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let compile = |world: &TestWorld| {
+        let mut compiler = Compiler::default();
+        compiler.use_cache(&cache_dir);
+        let options = CompilerOptions::default();
+        compiler.compile(world, bounds, options, Vec::new(), Default::default());
+        compiler
+    };
+    compile(&world);
+    assert_eq!(cache_entries(), 1);
+
+    // The second compile gets its graph from the cache
+    let mut compiler = compile(&world);
+    assert_eq!(cache_entries(), 1);
+    compiler.on_use_block(lever_pos);
+    compiler.flush(&mut world);
+    assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+
+    // Now that the lever is on, the plot is different from the one in the cache
+    compiler.reset(&mut world, bounds);
+    compile(&world);
+    assert_eq!(cache_entries(), 2);
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn compile_report() {
+    let lever_pos = pos(0, 1, 0);
+    let lamp_pos = pos(1, 0, 0);
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mut compiler = Compiler::default();
+    let options = CompilerOptions::default();
+    compiler.compile(&world, bounds, options, Vec::new(), Default::default());
+    let report = compiler.report().unwrap();
+
+    assert!(!report.cached);
+    assert_eq!(report.graph.nodes, 2);
+    assert_eq!(report.graph.links, 1);
+    assert_eq!(report.graph.node_types["lever"], 1);
+    assert_eq!(report.graph.node_types["lamp"], 1);
+    let identify = &report.passes[0];
+    assert_eq!(identify.name, "IdentifyNodes");
+    assert!(identify.ran);
+    assert_eq!((identify.before.nodes, identify.after.nodes), (0, 2));
+    // The optimization passes are skipped without --optimize
+    let coalesce = report.passes.iter().find(|pass| pass.name == "Coalesce");
+    assert!(!coalesce.unwrap().ran);
+    assert!(report.fired_passes().any(|pass| pass.name == "InputSearch"));
+}
+
+/// Builds 4 lanes along x, each a lever, three repeaters and a wall torch lighting a lamp.
+fn make_bus(world: &mut TestWorld) {
+    for lane in 0..4 {
+        let z = lane * 2;
+        make_lever(world, pos(0, 2, z));
+        for x in 1..=3 {
+            place_on_block(
+                world,
+                pos(x, 1, z),
+                Block::RedstoneRepeater {
+                    repeater: RedstoneRepeater {
+                        facing: BlockDirection::West,
+                        ..Default::default()
+                    },
+                },
+            );
+        }
+        world.set_block(pos(4, 1, z), Block::Sandstone {});
+        world.set_block(
+            pos(5, 1, z),
+            Block::RedstoneWallTorch {
+                lit: true,
+                facing: BlockDirection::East,
+            },
+        );
+        world.set_block(pos(6, 1, z), Block::RedstoneLamp { lit: true });
+    }
+}
+
+test_all_backends!(bus_lanes);
+fn bus_lanes(backend: TestBackend) {
+    let lever = |lane: i32| pos(0, 2, lane * 2);
+    let lamp = |lane: i32| pos(6, 1, lane * 2);
+
+    let mut world = TestWorld::new(1);
+    make_bus(&mut world);
+    let options = CompilerOptions {
+        optimize: true,
+        ..Default::default()
+    };
+    let mut runner = BackendRunner::with_options(world, backend, options);
+
+    runner.use_block(lever(0));
+    runner.use_block(lever(2));
+    runner.tick();
+    runner.use_block(lever(1));
+    runner.check_powered_for(lamp(0), true, 4);
+    runner.check_block_powered(lamp(1), true);
+    runner.tick();
+    runner.check_block_powered(lamp(0), false);
+    runner.check_block_powered(lamp(2), false);
+    runner.check_block_powered(lamp(1), true);
+    runner.tick();
+    runner.check_block_powered(lamp(1), false);
+    runner.check_block_powered(lamp(3), true);
+
+    // Turning a lane back off while the others stay on
+    runner.use_block(lever(0));
+    runner.check_powered_for(lamp(0), false, 4);
+    runner.check_block_powered(lamp(0), true);
+    runner.check_block_powered(lamp(2), false);
+}
+
+#[test]
+fn bus_detection() {
+    let mut world = TestWorld::new(1);
+    make_bus(&mut world);
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+
+    let mut compiler = Compiler::default();
+    let options = CompilerOptions {
+        optimize: true,
+        ..Default::default()
+    };
+    compiler.compile(&world, bounds, options, Vec::new(), Default::default());
+    let report = compiler.report().unwrap();
+    // Every lane has three repeaters and a torch
+    assert_eq!(report.graph.bus_lanes, 16);
+}
+
+#[test]
+fn verify_backends() {
+    let mut world = TestWorld::new(1);
+    make_bus(&mut world);
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+
+    let mut verifier = Verifier::new(&world, bounds, Vec::new());
+    verifier.use_block(0, pos(0, 2, 0)).unwrap();
+    verifier.use_block(3, pos(0, 2, 2)).unwrap();
+    verifier.use_block(5, pos(0, 2, 0)).unwrap();
+    assert!(verifier.use_block(0, pos(6, 1, 0)).is_err());
+
+    let results = verifier.verify(&verify_configurations(), 20);
+    assert_eq!(results.len(), 6);
+    for result in results {
+        assert_eq!(result.divergence, None, "{}", result);
+    }
+}
+
+/// Builds two comparators reading barrels with overrides of 20 and 18, which a subtracting
+/// comparator compares to light a lamp. A lever powers the side of both through a wire, so they
+/// get updated.
+fn make_high_signal_strength(world: &mut TestWorld) {
+    let comparator = |facing, mode| Block::RedstoneComparator {
+        comparator: RedstoneComparator {
+            facing,
+            mode,
+            powered: false,
+        },
+    };
+    for (barrel_pos, comparator_override) in [(pos(0, 1, 1), 20), (pos(2, 1, 3), 18)] {
+        world.set_block(barrel_pos, Block::Barrel {});
+        world.set_block_entity(
+            barrel_pos,
+            BlockEntity::Container {
+                comparator_override,
+                inventory: Vec::new(),
+                ty: ContainerType::Barrel,
+            },
+        );
+    }
+    world.set_block(
+        pos(1, 1, 1),
+        comparator(BlockDirection::West, ComparatorMode::Compare),
+    );
+    world.set_block(
+        pos(2, 1, 2),
+        comparator(BlockDirection::South, ComparatorMode::Compare),
+    );
+    world.set_block(
+        pos(2, 1, 1),
+        comparator(BlockDirection::West, ComparatorMode::Subtract),
+    );
+    world.set_block(pos(3, 1, 1), Block::RedstoneLamp { lit: false });
+    let wire = Block::RedstoneWire {
+        wire: make_cross(0),
+    };
+    place_on_block(world, pos(1, 1, 2), wire);
+    make_lever(world, pos(0, 1, 2));
+}
+
+// The cranelift backend does not support high signal strength, see
+// `high_signal_strength_cranelift_fallback`
+#[test]
+fn high_signal_strength_redstone() {
+    high_signal_strength(TestBackend::Redstone);
+}
+
+#[test]
+fn high_signal_strength_rp_direct() {
+    high_signal_strength(TestBackend::Redpiler(BackendVariant::Direct));
+}
+
+#[test]
+fn high_signal_strength_rp_parallel() {
+    high_signal_strength(TestBackend::Redpiler(BackendVariant::Parallel));
+}
+
+fn high_signal_strength_options() -> CompilerOptions {
+    CompilerOptions {
+        optimize: true,
+        high_signal_strength: true,
+        ..Default::default()
+    }
+}
+
+fn high_signal_strength(backend: TestBackend) {
+    let mut world = TestWorld::new(1);
+    make_high_signal_strength(&mut world);
+    let options = high_signal_strength_options();
+    let mut runner = BackendRunner::with_options(world, backend, options);
+
+    runner.use_block(pos(0, 1, 2));
+    runner.check_powered_for(pos(3, 1, 1), false, 2);
+    runner.check_block_powered(pos(3, 1, 1), true);
+}
+
+#[test]
+fn high_signal_strength_cranelift_fallback() {
+    let mut world = TestWorld::new(1);
+    make_high_signal_strength(&mut world);
+    let mut compiler = Compiler::default();
+    let options = CompilerOptions {
+        backend_variant: BackendVariant::Cranelift,
+        ..high_signal_strength_options()
+    };
+    compiler.compile(
+        &world,
+        (pos(0, 0, 0), pos(15, 15, 15)),
+        options,
+        Vec::new(),
+        Default::default(),
+    );
+
+    // The direct backend is used instead, and still sees signal strengths above 15
+    let flags = compiler.current_flags().unwrap();
+    assert_eq!(flags.backend_variant, BackendVariant::Direct);
+    assert!(flags.high_signal_strength);
+    compiler.on_use_block(pos(0, 1, 2));
+    compiler.tick();
+    compiler.flush(&mut world);
+    assert_eq!(
+        world.get_block(pos(3, 1, 1)),
+        Block::RedstoneLamp { lit: false }
+    );
+    compiler.tick();
+    compiler.flush(&mut world);
+    assert_eq!(
+        world.get_block(pos(3, 1, 1)),
+        Block::RedstoneLamp { lit: true }
+    );
+}
+
+#[test]
+fn high_signal_strength_capped() {
+    let mut world = TestWorld::new(1);
+    make_high_signal_strength(&mut world);
+    let backend = TestBackend::Redpiler(BackendVariant::Direct);
+    let mut runner = BackendRunner::new(world, backend);
+
+    // Both comparators output 15, which leaves nothing after subtracting
+    runner.use_block(pos(0, 1, 2));
+    runner.check_powered_for(pos(3, 1, 1), false, 4);
+}
+
+#[test]
+fn breakpoint() {
+    let lever_pos = pos(0, 2, 5);
+    let repeater_pos = pos(3, 1, 5);
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+
+    let mut world = TestWorld::new(1);
+    make_repeater_line(&mut world, 3);
+    let mut compiler = Compiler::default();
+    compiler.compile(
+        &world,
+        bounds,
+        Default::default(),
+        Vec::new(),
+        Default::default(),
+    );
+    assert!(compiler.set_breakpoint(repeater_pos, Some(BreakCondition::On)));
+
+    compiler.on_use_block(lever_pos);
+    compiler.tick();
+    compiler.tick();
+    assert_eq!(compiler.take_breakpoint_hit(), None);
+    compiler.tick();
+    let hit = compiler.take_breakpoint_hit().unwrap();
+    assert_eq!(hit.pos, repeater_pos);
+    assert_eq!(hit.priority, TickPriority::High);
+    assert!(hit.state.powered);
+    assert!(compiler.is_mid_tick());
+
+    // The next tick only runs what is left of the stopped one
+    compiler.tick();
+    assert!(!compiler.is_mid_tick());
+    assert_eq!(compiler.take_breakpoint_hit(), None);
+
+    // Turning the line off doesn't meet the condition
+    compiler.on_use_block(lever_pos);
+    for _ in 0..10 {
+        compiler.tick();
+    }
+    assert_eq!(compiler.take_breakpoint_hit(), None);
+}
+
+#[test]
+fn step_phases() {
+    let lever_pos = pos(0, 2, 5);
+    let lamp_pos = pos(2, 1, 5);
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+
+    let mut world = TestWorld::new(1);
+    make_repeater_line(&mut world, 1);
+    let mut compiler = Compiler::default();
+    compiler.compile(
+        &world,
+        bounds,
+        Default::default(),
+        Vec::new(),
+        Default::default(),
+    );
+
+    // A game tick has a phase for each tick priority
+    compiler.on_use_block(lever_pos);
+    for _ in 0..3 {
+        assert!(!compiler.step_phase());
+        assert!(compiler.is_mid_tick());
+    }
+    assert!(compiler.step_phase());
+    assert!(!compiler.is_mid_tick());
+
+    compiler.flush(&mut world);
+    assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+}
+
+#[test]
+fn snapshot_restore() {
+    let lever_pos = pos(0, 2, 5);
+    let lamp_pos = pos(4, 1, 5);
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+    let path = std::env::temp_dir().join(format!("redpiler_snapshot_{}", std::process::id()));
+
+    for backend_variant in [
+        BackendVariant::Direct,
+        BackendVariant::Cranelift,
+        BackendVariant::Parallel,
+    ] {
+        let mut world = TestWorld::new(1);
+        make_repeater_line(&mut world, 3);
+        let options = CompilerOptions {
+            backend_variant,
+            ..Default::default()
+        };
+        let mut compiler = Compiler::default();
+        compiler.compile(&world, bounds, options, Vec::new(), Default::default());
+
+        // Only the first repeater is on, the second one has a tick pending
+        compiler.on_use_block(lever_pos);
+        compiler.tick();
+        compiler.snapshot().unwrap().save(&path).unwrap();
+
+        for _ in 0..5 {
+            compiler.tick();
+        }
+        compiler.flush(&mut world);
+        assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+
+        let snapshot = Snapshot::load(&path).unwrap();
+        compiler.restore_snapshot(&snapshot).unwrap();
+        compiler.flush(&mut world);
+        assert_eq!(
+            world.get_block(lamp_pos),
+            Block::RedstoneLamp { lit: false }
+        );
+        assert!(!compiler.node_state(pos(2, 1, 5)).unwrap().powered);
+        compiler.tick();
+        assert!(compiler.node_state(pos(2, 1, 5)).unwrap().powered);
+        compiler.tick();
+        compiler.flush(&mut world);
+        assert_eq!(world.get_block(lamp_pos), Block::RedstoneLamp { lit: true });
+
+        // A longer line is a different graph
+        compiler.reset(&mut world, bounds);
+        let mut world = TestWorld::new(1);
+        make_repeater_line(&mut world, 4);
+        let options = CompilerOptions {
+            backend_variant,
+            ..Default::default()
+        };
+        compiler.compile(
+            &world,
+            bounds,
+            options.clone(),
+            Vec::new(),
+            Default::default(),
+        );
+        assert!(matches!(
+            compiler.restore_snapshot(&snapshot),
+            Err(SnapshotError::Mismatch)
+        ));
+
+        // So is one with the same positions, but a slower repeater
+        compiler.reset(&mut world, bounds);
+        let mut world = TestWorld::new(1);
+        make_repeater_line(&mut world, 3);
+        world.set_block(
+            pos(2, 1, 5),
+            Block::RedstoneRepeater {
+                repeater: RedstoneRepeater {
+                    facing: BlockDirection::West,
+                    delay: 2,
+                    ..Default::default()
+                },
+            },
+        );
+        compiler.compile(&world, bounds, options, Vec::new(), Default::default());
+        assert!(matches!(
+            compiler.restore_snapshot(&snapshot),
+            Err(SnapshotError::Mismatch)
+        ));
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
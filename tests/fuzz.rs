@@ -0,0 +1,364 @@
+//! Differential fuzzing of the redpiler passes. Random circuits are compiled with and without
+//! optimizations, and the outputs of both have to stay the same while levers are flipped.
+//!
+//! Run with `cargo test --test fuzz`. `PROPTEST_CASES` sets how many circuits are tried. A circuit
+//! that fails is shrunk down and saved as `redpiler_fuzz.schem` in cargo's temporary directory for
+//! integration tests, so it can be loaded with `//load` and looked at in game.
+
+#[allow(dead_code)]
+mod common;
+
+use common::TestWorld;
+use mchprs_blocks::blocks::{
+    Block, ComparatorMode, Lever, LeverFace, RedstoneComparator, RedstoneRepeater,
+};
+use mchprs_blocks::{BlockDirection, BlockFacing, BlockPos};
+use mchprs_core::plot::worldedit::{create_clipboard, save_schematic_file};
+use mchprs_redpiler::{Compiler, CompilerOptions, Verifier};
+use mchprs_redstone::wire;
+use mchprs_world::World;
+use proptest::prelude::*;
+use proptest::sample::{select, subsequence, Index};
+use proptest::strategy::ValueTree;
+use proptest::test_runner::TestRunner;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+/// Width of the random part of a circuit
+const WIDTH: i32 = 6;
+/// Depth of the random part of a circuit, the rows are repeated along z
+const DEPTH: i32 = 4;
+/// Enough copies for the lanes in them to be found as buses
+const MAX_COPIES: i32 = 5;
+const BOUNDS: (BlockPos, BlockPos) = (BlockPos::new(0, 0, 0), BlockPos::new(31, 15, 31));
+/// Ticks the default redstone implementation runs for before the circuit is compiled
+const SETTLE_TICKS: u64 = 40;
+const TICKS: u64 = 200;
+
+fn pos(x: i32, y: i32, z: i32) -> BlockPos {
+    BlockPos::new(x, y, z)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Cell {
+    Air,
+    Wire,
+    Sandstone,
+    RedstoneBlock,
+    Repeater {
+        facing: BlockDirection,
+        delay: u8,
+    },
+    Comparator {
+        facing: BlockDirection,
+        mode: ComparatorMode,
+    },
+    Torch,
+    WallTorch {
+        facing: BlockDirection,
+    },
+    Lamp,
+    Trapdoor,
+    Lever,
+    Observer {
+        facing: BlockFacing,
+    },
+}
+
+impl Cell {
+    fn block(self) -> Block {
+        match self {
+            Cell::Air => Block::Air {},
+            Cell::Wire => Block::RedstoneWire {
+                wire: wire::make_cross(0),
+            },
+            Cell::Sandstone => Block::Sandstone {},
+            Cell::RedstoneBlock => Block::RedstoneBlock {},
+            Cell::Repeater { facing, delay } => Block::RedstoneRepeater {
+                repeater: RedstoneRepeater {
+                    facing,
+                    delay,
+                    ..Default::default()
+                },
+            },
+            Cell::Comparator { facing, mode } => Block::RedstoneComparator {
+                comparator: RedstoneComparator::new(facing, mode, false),
+            },
+            Cell::Torch => Block::RedstoneTorch { lit: true },
+            Cell::WallTorch { facing } => Block::RedstoneWallTorch { lit: true, facing },
+            Cell::Lamp => Block::RedstoneLamp { lit: false },
+            Cell::Trapdoor => Block::IronTrapdoor {
+                facing: Default::default(),
+                half: Default::default(),
+                powered: false,
+            },
+            Cell::Lever => Block::Lever {
+                lever: Lever {
+                    face: LeverFace::Floor,
+                    ..Default::default()
+                },
+            },
+            Cell::Observer { facing } => Block::Observer {
+                facing,
+                powered: false,
+            },
+        }
+    }
+}
+
+fn direction() -> impl Strategy<Value = BlockDirection> {
+    use BlockDirection::*;
+    select(vec![North, South, East, West])
+}
+
+fn facing() -> impl Strategy<Value = BlockFacing> {
+    use BlockFacing::*;
+    select(vec![North, South, East, West, Up, Down])
+}
+
+/// Most repeaters are 1 tick, so that there are ones the passes can treat as the same
+fn delay() -> impl Strategy<Value = u8> {
+    prop_oneof![3 => Just(1), 1 => 2..=4u8]
+}
+
+/// Cells shrink towards air, so a shrunk circuit only keeps what it needs to fail.
+fn cell() -> impl Strategy<Value = Cell> {
+    prop_oneof![
+        4 => Just(Cell::Air),
+        4 => Just(Cell::Wire),
+        2 => Just(Cell::Sandstone),
+        1 => Just(Cell::RedstoneBlock),
+        3 => (direction(), delay()).prop_map(|(facing, delay)| Cell::Repeater { facing, delay }),
+        2 => (direction(), select(vec![ComparatorMode::Compare, ComparatorMode::Subtract]))
+            .prop_map(|(facing, mode)| Cell::Comparator { facing, mode }),
+        1 => Just(Cell::Torch),
+        2 => direction().prop_map(|facing| Cell::WallTorch { facing }),
+        2 => Just(Cell::Lamp),
+        1 => Just(Cell::Trapdoor),
+        2 => Just(Cell::Lever),
+        1 => facing().prop_map(|facing| Cell::Observer { facing }),
+    ]
+}
+
+#[derive(Debug, Clone)]
+struct Circuit {
+    /// `DEPTH` rows of `WIDTH` cells
+    rows: Vec<Vec<Cell>>,
+    copies: i32,
+    gadgets: Vec<Gadget>,
+    /// Levers to flip, by the tick to flip them before
+    toggles: Vec<(u64, Index)>,
+}
+
+fn circuit() -> impl Strategy<Value = Circuit> {
+    (
+        prop::collection::vec(
+            prop::collection::vec(cell(), WIDTH as usize),
+            DEPTH as usize,
+        ),
+        1..=MAX_COPIES,
+        subsequence(Gadget::ALL.to_vec(), 0..=Gadget::ALL.len()),
+        prop::collection::vec((0..TICKS - 50, any::<Index>()), 0..12),
+    )
+        .prop_map(|(rows, copies, gadgets, toggles)| Circuit {
+            rows,
+            copies,
+            gadgets,
+            toggles,
+        })
+}
+
+/// Circuits that passes look for, which random cells are too unlikely to end up as. They are
+/// built next to the random part of the circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Gadget {
+    /// Comparators linked by 15 repeaters, for `AnalogRepeaters`
+    AnalogRepeater,
+    /// A wire longer than a signal reaches, for `ClampWeights`
+    LongWire,
+    /// A lever powering two repeaters, for `Coalesce`
+    FanOut,
+    /// A subtracting comparator with a constant on its side, for `UnreachableOutput`
+    ConstantSubtract,
+}
+
+impl Gadget {
+    const ALL: [Gadget; 4] = [
+        Gadget::AnalogRepeater,
+        Gadget::LongWire,
+        Gadget::FanOut,
+        Gadget::ConstantSubtract,
+    ];
+
+    fn build(self, world: &mut TestWorld) {
+        let row = Gadget::ALL
+            .iter()
+            .position(|&gadget| gadget == self)
+            .unwrap() as i32;
+        let origin = pos(13, 1, 1 + row * 4);
+        let at = |x, z| origin + pos(x, 0, z);
+        let comparator = |facing, mode| Cell::Comparator { facing, mode }.block();
+        let repeater = |facing| Cell::Repeater { facing, delay: 1 }.block();
+        match self {
+            Gadget::AnalogRepeater => {
+                // Read off the first wire at distances 0 to 14, into the second at 14 to 0
+                world.set_block(at(0, 0), Cell::Lever.block());
+                let compare = comparator(BlockDirection::West, ComparatorMode::Compare);
+                world.set_block(at(1, 0), compare);
+                for x in 2..=16 {
+                    world.set_block(at(x, 0), Cell::Wire.block());
+                    world.set_block(at(x, 1), repeater(BlockDirection::North));
+                    world.set_block(at(x, 2), Cell::Wire.block());
+                }
+                world.set_block(at(17, 2), compare);
+                world.set_block(at(18, 2), Cell::Lamp.block());
+            }
+            Gadget::LongWire => {
+                world.set_block(at(0, 0), Cell::Lever.block());
+                for x in 1..=17 {
+                    world.set_block(at(x, 0), Cell::Wire.block());
+                }
+                world.set_block(at(18, 0), Cell::Lamp.block());
+            }
+            Gadget::FanOut => {
+                world.set_block(at(2, 0), Cell::Lever.block());
+                world.set_block(at(1, 0), repeater(BlockDirection::East));
+                world.set_block(at(3, 0), repeater(BlockDirection::West));
+                world.set_block(at(0, 0), Cell::Lamp.block());
+                world.set_block(at(4, 0), Cell::Lamp.block());
+            }
+            Gadget::ConstantSubtract => {
+                world.set_block(at(0, 0), Cell::Lever.block());
+                let subtract = comparator(BlockDirection::West, ComparatorMode::Subtract);
+                world.set_block(at(1, 0), subtract);
+                // Two wires away, so the side is at 14 and the comparator outputs 1
+                world.set_block(at(1, 1), Cell::Wire.block());
+                world.set_block(at(1, 2), Cell::Wire.block());
+                world.set_block(at(1, 3), Cell::RedstoneBlock.block());
+                world.set_block(at(2, 0), Cell::Wire.block());
+                world.set_block(at(3, 0), Cell::Lamp.block());
+            }
+        }
+    }
+}
+
+/// Builds the circuit on a sandstone floor and returns the positions of its levers.
+fn build(circuit: &Circuit) -> (TestWorld, Vec<BlockPos>) {
+    let mut world = TestWorld::new(2);
+    for x in BOUNDS.0.x..=BOUNDS.1.x {
+        for z in BOUNDS.0.z..=BOUNDS.1.z {
+            world.set_block(pos(x, 0, z), Block::Sandstone {});
+        }
+    }
+
+    let mut cells = Vec::new();
+    for copy in 0..circuit.copies {
+        for (row, cells_in_row) in circuit.rows.iter().enumerate() {
+            let z = 1 + copy * (DEPTH + 1) + row as i32;
+            for (column, &cell) in cells_in_row.iter().enumerate() {
+                cells.push((pos(1 + column as i32, 1, z), cell));
+            }
+        }
+    }
+    for &(cell_pos, cell) in &cells {
+        world.set_block(cell_pos, cell.block());
+    }
+    // Wall torches need something to hang on
+    for &(cell_pos, cell) in &cells {
+        if let Cell::WallTorch { facing } = cell {
+            let behind = cell_pos.offset(facing.opposite().block_face());
+            world.set_block(behind, Block::Sandstone {});
+        }
+    }
+
+    for gadget in &circuit.gadgets {
+        gadget.build(&mut world);
+    }
+
+    // Wires connect to whatever ended up next to them
+    let mut levers = Vec::new();
+    for x in BOUNDS.0.x..=BOUNDS.1.x {
+        for z in BOUNDS.0.z..=BOUNDS.1.z {
+            let block_pos = pos(x, 1, z);
+            match world.get_block(block_pos) {
+                Block::RedstoneWire { .. } => {
+                    let wire = wire::get_state_for_placement(&world, block_pos);
+                    world.set_block(block_pos, Block::RedstoneWire { wire });
+                }
+                Block::Lever { .. } => levers.push(block_pos),
+                _ => {}
+            }
+        }
+    }
+    (world, levers)
+}
+
+/// Saves the circuit where it can be found after a test failed, and describes where it went.
+fn save_circuit(world: &TestWorld) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("redpiler_fuzz.schem");
+    let clipboard = create_clipboard(world, BOUNDS.0, BOUNDS.0, BOUNDS.1);
+    match save_schematic_file(&path, &clipboard) {
+        Ok(()) => format!("circuit saved to {}", path.display()),
+        Err(err) => format!("circuit could not be saved: {}", err),
+    }
+}
+
+fn options(optimize: bool, io_only: bool) -> CompilerOptions {
+    CompilerOptions {
+        optimize,
+        io_only,
+        ..Default::default()
+    }
+}
+
+proptest! {
+    #[test]
+    fn optimized_matches_unoptimized(circuit in circuit()) {
+        let (world, levers) = build(&circuit);
+        let mut verifier = Verifier::new(&world, BOUNDS, Vec::new());
+        verifier.settle(SETTLE_TICKS);
+        if !levers.is_empty() {
+            for (tick, lever) in &circuit.toggles {
+                verifier.use_block(*tick, *lever.get(&levers)).unwrap();
+            }
+        }
+
+        let configurations = [options(true, false), options(true, true)];
+        let results = verifier.compare(&options(false, false), &configurations, TICKS);
+        if let Some(result) = results.iter().find(|result| result.divergence.is_some()) {
+            prop_assert!(false, "{}, {}", result, save_circuit(&world));
+        }
+    }
+}
+
+/// Every pass has to change the graph of at least one generated circuit, or the fuzzer would not
+/// be testing it. Some passes only run with `--io-only`.
+#[test]
+fn circuits_cover_passes() {
+    let mut runner = TestRunner::deterministic();
+    let strategy = circuit();
+    let mut fired = BTreeSet::new();
+    let mut all_passes = BTreeSet::new();
+    for _ in 0..256 {
+        let circuit = strategy.new_tree(&mut runner).unwrap().current();
+        let (world, _) = build(&circuit);
+        for io_only in [false, true] {
+            let mut compiler = Compiler::default();
+            compiler.compile(
+                &world,
+                BOUNDS,
+                options(true, io_only),
+                Vec::new(),
+                Default::default(),
+            );
+            let report = compiler.report().unwrap();
+            all_passes.extend(report.passes.iter().map(|pass| pass.name));
+            fired.extend(report.fired_passes().map(|pass| pass.name));
+        }
+    }
+    // Exporting writes a file instead of changing the graph
+    all_passes.remove("ExportGraph");
+
+    let missing: Vec<_> = all_passes.difference(&fired).collect();
+    assert!(missing.is_empty(), "passes that never fired: {:?}", missing);
+}